@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: GPL-2.0
+/*
+ * Test module loaded and unloaded in a loop by the module-stress payload.
+ *
+ * Every load maps fresh module text (exercising instruction cache
+ * maintenance on the new mapping) and touches a set of vmalloc pages
+ * (exercising stage-2 faults on fresh guest memory).
+ */
+#include <linux/module.h>
+#include <linux/vmalloc.h>
+
+static unsigned int pages = 64;
+module_param(pages, uint, 0444);
+MODULE_PARM_DESC(pages, "Number of vmalloc pages to touch on load");
+
+static noinline u32 stress_checksum(const u32 *buf, size_t words)
+{
+	u32 sum = 0;
+	size_t i;
+
+	for (i = 0; i < words; i++)
+		sum = (sum << 1 | sum >> 31) ^ buf[i];
+	return sum;
+}
+
+static int __init stress_mod_init(void)
+{
+	size_t words = (size_t)pages * PAGE_SIZE / sizeof(u32);
+	u32 *buf, expected = 0;
+	size_t i;
+
+	buf = vmalloc(array_size(pages, PAGE_SIZE));
+	if (!buf)
+		return -ENOMEM;
+
+	for (i = 0; i < words; i++) {
+		buf[i] = i * 2654435761u;
+		expected = (expected << 1 | expected >> 31) ^ buf[i];
+	}
+
+	/* Runs from the freshly mapped module text */
+	if (stress_checksum(buf, words) != expected) {
+		vfree(buf);
+		pr_err("stress_mod: checksum mismatch over %u pages\n", pages);
+		return -EIO;
+	}
+
+	vfree(buf);
+	return 0;
+}
+
+static void __exit stress_mod_exit(void)
+{
+}
+
+module_init(stress_mod_init);
+module_exit(stress_mod_exit);
+
+MODULE_DESCRIPTION("Module loading and vmalloc stress test");
+MODULE_LICENSE("GPL");
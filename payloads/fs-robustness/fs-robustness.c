@@ -0,0 +1,200 @@
+/*
+ * Filesystem robustness checker for the scratch disk the harness attaches
+ * with --fs-robustness. The mode comes from the kernel command line:
+ *
+ *   fs_robustness=write       write numbered files forever, printing
+ *                             "fs-robustness: synced <n>" once file n and
+ *                             its directory entry have been fsync'ed
+ *   fs_robustness=verify:<n>  mount (replaying the journal) and check that
+ *                             files 0..n are intact, then unmount cleanly
+ *
+ * The harness kills the guest or yanks the disk while files are being
+ * written, so everything reported as synced must survive the reboot.
+ * Without the parameter the checker does nothing.
+ *
+ * The scratch disk is found by its virtio-blk serial.
+ */
+#include <dirent.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/mount.h>
+#include <sys/stat.h>
+#include <sys/sysmacros.h>
+#include <unistd.h>
+
+#define SCRATCH_SERIAL "fs-scratch"
+#define MOUNT_POINT "/mnt/scratch"
+#define FILE_SIZE (256 * 1024)
+
+static unsigned char buf[FILE_SIZE];
+
+static void fill(unsigned char *p, unsigned int n)
+{
+	for (unsigned int i = 0; i < FILE_SIZE; i++)
+		p[i] = (unsigned char)(i * 31 + n * 7 + 1);
+}
+
+/* Mode parameter from /proc/cmdline, or NULL */
+static char *cmdline_mode(void)
+{
+	static char line[4096];
+	char *param, *end;
+	FILE *f = fopen("/proc/cmdline", "r");
+
+	if (!f)
+		return NULL;
+	if (!fgets(line, sizeof(line), f)) {
+		fclose(f);
+		return NULL;
+	}
+	fclose(f);
+	param = strstr(line, "fs_robustness=");
+	if (!param)
+		return NULL;
+	param += strlen("fs_robustness=");
+	end = strpbrk(param, " \n");
+	if (end)
+		*end = '\0';
+	return param;
+}
+
+/* Name of the block device whose virtio-blk serial matches the scratch disk */
+static int find_scratch(char *name, size_t len)
+{
+	char path[300], serial[64];
+	struct dirent *entry;
+	DIR *dir = opendir("/sys/block");
+	int found = 0;
+
+	if (!dir)
+		return 0;
+	while (!found && (entry = readdir(dir))) {
+		FILE *f;
+
+		snprintf(path, sizeof(path), "/sys/block/%s/serial", entry->d_name);
+		f = fopen(path, "r");
+		if (!f)
+			continue;
+		if (fgets(serial, sizeof(serial), f) &&
+		    !strncmp(serial, SCRATCH_SERIAL, strlen(SCRATCH_SERIAL))) {
+			snprintf(name, len, "%s", entry->d_name);
+			found = 1;
+		}
+		fclose(f);
+	}
+	closedir(dir);
+	return found;
+}
+
+static int mount_scratch(void)
+{
+	char name[256], dev[300];
+
+	if (!find_scratch(name, sizeof(name))) {
+		printf("fs-robustness: no disk with serial " SCRATCH_SERIAL "\n");
+		return -1;
+	}
+	snprintf(dev, sizeof(dev), "/dev/%s", name);
+	mkdir(MOUNT_POINT, 0755);
+	/* The device node may not exist yet when mdev has not run */
+	if (access(dev, F_OK)) {
+		char sysdev[300];
+		unsigned int major, minor;
+		FILE *f;
+
+		snprintf(sysdev, sizeof(sysdev), "/sys/block/%s/dev", name);
+		f = fopen(sysdev, "r");
+		if (!f || fscanf(f, "%u:%u", &major, &minor) != 2) {
+			printf("fs-robustness: no device number for %s\n", dev);
+			return -1;
+		}
+		fclose(f);
+		mknod(dev, S_IFBLK | 0600, makedev(major, minor));
+	}
+	if (mount(dev, MOUNT_POINT, "ext4", 0, NULL)) {
+		printf("fs-robustness: mount %s: %s\n", dev, strerror(errno));
+		return -1;
+	}
+	printf("fs-robustness: mounted %s\n", dev);
+	return 0;
+}
+
+static int write_files(void)
+{
+	char path[64];
+	int dirfd = open(MOUNT_POINT, O_RDONLY | O_DIRECTORY);
+
+	if (dirfd < 0)
+		return 1;
+	setvbuf(stdout, NULL, _IONBF, 0);
+	for (unsigned int n = 0;; n++) {
+		int fd;
+
+		snprintf(path, sizeof(path), MOUNT_POINT "/f%u", n);
+		fd = open(path, O_WRONLY | O_CREAT | O_TRUNC, 0644);
+		if (fd < 0) {
+			printf("fs-robustness: create f%u: %s\n", n, strerror(errno));
+			return 1;
+		}
+		fill(buf, n);
+		if (write(fd, buf, FILE_SIZE) != FILE_SIZE || fsync(fd) || fsync(dirfd)) {
+			/* Expected once the harness yanks the disk */
+			printf("fs-robustness: write f%u: %s\n", n, strerror(errno));
+			return 1;
+		}
+		close(fd);
+		printf("fs-robustness: synced %u\n", n);
+	}
+}
+
+static int verify_files(unsigned int last)
+{
+	static unsigned char expected[FILE_SIZE];
+	char path[64];
+	unsigned int bad = 0;
+
+	for (unsigned int n = 0; n <= last; n++) {
+		int fd;
+		ssize_t got;
+
+		snprintf(path, sizeof(path), MOUNT_POINT "/f%u", n);
+		fd = open(path, O_RDONLY);
+		if (fd < 0) {
+			printf("fs-robustness: f%u missing: %s\n", n, strerror(errno));
+			bad++;
+			continue;
+		}
+		got = read(fd, buf, FILE_SIZE);
+		close(fd);
+		fill(expected, n);
+		if (got != FILE_SIZE || memcmp(buf, expected, FILE_SIZE)) {
+			printf("fs-robustness: f%u corrupted (%zd bytes)\n", n, got);
+			bad++;
+		}
+	}
+	if (umount(MOUNT_POINT)) {
+		printf("fs-robustness: umount: %s\n", strerror(errno));
+		return 1;
+	}
+	printf("fs-robustness: verified %u files, %u bad\n", last + 1, bad);
+	return bad ? 1 : 0;
+}
+
+int main(void)
+{
+	char *mode = cmdline_mode();
+
+	if (!mode)
+		return 0;
+	if (mount_scratch())
+		return 1;
+	if (!strcmp(mode, "write"))
+		return write_files();
+	if (!strncmp(mode, "verify:", 7))
+		return verify_files(strtoul(mode + 7, NULL, 10));
+	printf("fs-robustness: unknown mode %s\n", mode);
+	return 1;
+}
@@ -0,0 +1,211 @@
+/*
+ * IMA/EVM appraisal check: load the build's signing certificate into the
+ * .ima and .evm keyrings, enable EVM signature checking, load the appraisal
+ * policy, then check that signed binaries still run, an unsigned copy is
+ * refused and the measurement list is populated.
+ *
+ * Usage: ima [certificate [policy]]
+ */
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/mount.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define SECURITYFS "/sys/kernel/security"
+#define UNSIGNED "/root/ima-unsigned"
+
+static int failures;
+
+static void check(int ok, const char *what)
+{
+	printf("ima: %s: %s\n", what, ok ? "ok" : "FAIL");
+	if (!ok)
+		failures++;
+}
+
+/* Id of a kernel keyring such as ".ima", from /proc/keys */
+static long find_keyring(const char *name)
+{
+	char line[512], desc[128];
+	unsigned long id;
+	long found = -1;
+	FILE *f;
+
+	f = fopen("/proc/keys", "r");
+	if (!f)
+		return -1;
+	while (fgets(line, sizeof(line), f)) {
+		if (sscanf(line, "%lx %*s %*s %*s %*s %*s %*s %*s %127s", &id, desc) != 2)
+			continue;
+		if (!strncmp(desc, name, strlen(name)) && desc[strlen(name)] == ':') {
+			found = (long)id;
+			break;
+		}
+	}
+	fclose(f);
+	return found;
+}
+
+static int load_cert(const char *keyring, const char *data, size_t len)
+{
+	long id = find_keyring(keyring);
+
+	if (id < 0) {
+		printf("ima: no %s keyring\n", keyring);
+		return 0;
+	}
+	if (syscall(SYS_add_key, "asymmetric", NULL, data, len, id) < 0) {
+		printf("ima: adding the certificate to %s: %s\n", keyring, strerror(errno));
+		return 0;
+	}
+	return 1;
+}
+
+static int write_file(const char *path, const char *data)
+{
+	int fd, ok;
+
+	fd = open(path, O_WRONLY);
+	if (fd < 0) {
+		printf("ima: %s: %s\n", path, strerror(errno));
+		return 0;
+	}
+	ok = write(fd, data, strlen(data)) == (ssize_t)strlen(data);
+	if (!ok)
+		printf("ima: writing %s: %s\n", path, strerror(errno));
+	close(fd);
+	return ok;
+}
+
+static char *read_file(const char *path, size_t *len)
+{
+	struct stat st;
+	char *data;
+	int fd;
+
+	fd = open(path, O_RDONLY);
+	if (fd < 0 || fstat(fd, &st) < 0) {
+		printf("ima: %s: %s\n", path, strerror(errno));
+		if (fd >= 0)
+			close(fd);
+		return NULL;
+	}
+	data = malloc(st.st_size + 1);
+	if (!data || read(fd, data, st.st_size) != st.st_size) {
+		free(data);
+		close(fd);
+		return NULL;
+	}
+	close(fd);
+	*len = st.st_size;
+	return data;
+}
+
+static int copy_file(const char *from, const char *to)
+{
+	char buf[65536];
+	ssize_t n;
+	int in, out, ok = 1;
+
+	in = open(from, O_RDONLY);
+	out = open(to, O_WRONLY | O_CREAT | O_TRUNC, 0755);
+	if (in < 0 || out < 0)
+		ok = 0;
+	while (ok && (n = read(in, buf, sizeof(buf))) > 0)
+		ok = write(out, buf, n) == n;
+	if (in >= 0)
+		close(in);
+	if (out >= 0)
+		close(out);
+	return ok;
+}
+
+/* Exec a program in a child; returns 0 on success or the exec errno */
+static int try_exec(const char *path)
+{
+	int status;
+	pid_t pid;
+
+	pid = fork();
+	if (pid == 0) {
+		execl(path, path, "true", (char *)NULL);
+		_exit(errno);
+	}
+	if (pid < 0 || waitpid(pid, &status, 0) < 0 || !WIFEXITED(status))
+		return -1;
+	return WEXITSTATUS(status);
+}
+
+static int count_measurements(void)
+{
+	char line[1024];
+	int n = 0;
+	FILE *f;
+
+	f = fopen(SECURITYFS "/ima/ascii_runtime_measurements", "r");
+	if (!f)
+		return -1;
+	while (fgets(line, sizeof(line), f))
+		n++;
+	fclose(f);
+	return n;
+}
+
+int main(int argc, char **argv)
+{
+	const char *cert_path = argc > 1 ? argv[1] : "/etc/keys/x509_ima.der";
+	const char *policy = argc > 2 ? argv[2] : "/etc/ima/ima-policy";
+	char *cert;
+	size_t len;
+	int err, n;
+
+	if (access(SECURITYFS "/ima", F_OK) &&
+	    mount("securityfs", SECURITYFS, "securityfs", 0, NULL) && errno != EBUSY) {
+		printf("ima: mounting securityfs: %s\n", strerror(errno));
+		return 1;
+	}
+	if (access(SECURITYFS "/ima/policy", F_OK)) {
+		printf("ima: kernel has no IMA policy interface\n");
+		return 1;
+	}
+
+	cert = read_file(cert_path, &len);
+	if (!cert)
+		return 1;
+	check(load_cert(".ima", cert, len), "certificate in .ima");
+	check(load_cert(".evm", cert, len), "certificate in .evm");
+	free(cert);
+	/* EVM_INIT_X509: verify portable signatures with the .evm keyring */
+	check(write_file(SECURITYFS "/evm", "2"), "EVM enabled");
+	check(write_file(SECURITYFS "/ima/policy", policy), "policy loaded");
+
+	check(try_exec("/bin/busybox") == 0, "signed binary runs");
+	unlink(UNSIGNED);
+	if (!copy_file("/bin/busybox", UNSIGNED)) {
+		printf("ima: copying /bin/busybox: %s\n", strerror(errno));
+		failures++;
+	} else {
+		err = try_exec(UNSIGNED);
+		if (err > 0)
+			printf("ima: unsigned exec: %s\n", strerror(err));
+		check(err == EACCES, "unsigned binary refused");
+		unlink(UNSIGNED);
+	}
+
+	n = count_measurements();
+	printf("ima: %d measurements\n", n);
+	check(n > 1, "measurement list populated");
+
+	if (failures) {
+		printf("ima: FAIL\n");
+		return 1;
+	}
+	printf("ima: OK\n");
+	return 0;
+}
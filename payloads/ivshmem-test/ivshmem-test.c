@@ -0,0 +1,243 @@
+/*
+ * Guest side of the ivshmem test: finds the ivshmem PCI device, maps its
+ * shared-memory BAR and checks it against the host harness.
+ *
+ * Shared memory layout (must match src/ivshmem.rs):
+ *   [0, 16)                    host magic "IVSHMEM-HOST"
+ *   [4096, 8192)               host pattern, byte i = (i * 7 + 3) & 0xff
+ *   [size / 2, +16)            guest magic "IVSHMEM-GUEST"
+ *   [size / 2 + 4096, +4096)   guest pattern, byte i = (i * 13 + 5) & 0xff
+ */
+#include <dirent.h>
+#include <fcntl.h>
+#include <poll.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/stat.h>
+#include <time.h>
+#include <unistd.h>
+
+#define PCI_DEVICES "/sys/bus/pci/devices"
+#define IVSHMEM_VENDOR 0x1af4
+#define IVSHMEM_DEVICE 0x1110
+
+#define PATTERN_OFFSET 4096
+#define PATTERN_SIZE 4096
+#define LATENCY_ITERATIONS 100000
+
+/* BAR0 registers */
+#define REG_INTR_MASK 0
+#define REG_INTR_STATUS 4
+#define REG_IV_POSITION 8
+#define REG_DOORBELL 12
+
+static int read_hex(const char *path, unsigned int *value)
+{
+	FILE *f = fopen(path, "r");
+	int ok;
+
+	if (!f)
+		return -1;
+	ok = fscanf(f, "%x", value) == 1;
+	fclose(f);
+	return ok ? 0 : -1;
+}
+
+/* Find the first ivshmem device and store its sysfs path in dev */
+static int find_device(char *dev, size_t len)
+{
+	DIR *dir = opendir(PCI_DEVICES);
+	struct dirent *ent;
+	char path[512];
+	unsigned int vendor, device;
+
+	if (!dir)
+		return -1;
+	while ((ent = readdir(dir))) {
+		if (ent->d_name[0] == '.')
+			continue;
+		snprintf(path, sizeof(path), PCI_DEVICES "/%s/vendor", ent->d_name);
+		if (read_hex(path, &vendor) || vendor != IVSHMEM_VENDOR)
+			continue;
+		snprintf(path, sizeof(path), PCI_DEVICES "/%s/device", ent->d_name);
+		if (read_hex(path, &device) || device != IVSHMEM_DEVICE)
+			continue;
+		snprintf(dev, len, PCI_DEVICES "/%s", ent->d_name);
+		closedir(dir);
+		return 0;
+	}
+	closedir(dir);
+	return -1;
+}
+
+static volatile uint8_t *map_bar(const char *dev, int bar, size_t *size)
+{
+	char path[600];
+	struct stat st;
+	void *mem;
+	int fd;
+
+	snprintf(path, sizeof(path), "%s/resource%d", dev, bar);
+	fd = open(path, O_RDWR | O_SYNC);
+	if (fd < 0)
+		return NULL;
+	if (fstat(fd, &st) < 0) {
+		close(fd);
+		return NULL;
+	}
+	mem = mmap(NULL, st.st_size, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+	close(fd);
+	if (mem == MAP_FAILED)
+		return NULL;
+	*size = st.st_size;
+	return mem;
+}
+
+static uint64_t now_ns(void)
+{
+	struct timespec ts;
+
+	clock_gettime(CLOCK_MONOTONIC, &ts);
+	return (uint64_t)ts.tv_sec * 1000000000ull + ts.tv_nsec;
+}
+
+static int check_host_pattern(volatile uint8_t *shm)
+{
+	size_t i;
+
+	if (memcmp((const void *)shm, "IVSHMEM-HOST", 12) != 0) {
+		printf("ivshmem: host pattern not present, skipping read check\n");
+		return 0;
+	}
+	for (i = 0; i < PATTERN_SIZE; i++) {
+		if (shm[PATTERN_OFFSET + i] != (uint8_t)(i * 7 + 3)) {
+			printf("ivshmem: host pattern mismatch at byte %zu\n", i);
+			return -1;
+		}
+	}
+	printf("ivshmem: host pattern OK\n");
+	return 0;
+}
+
+static void write_guest_pattern(volatile uint8_t *shm, size_t size)
+{
+	volatile uint8_t *half = shm + size / 2;
+	size_t i;
+
+	for (i = 0; i < PATTERN_SIZE; i++)
+		half[PATTERN_OFFSET + i] = (uint8_t)(i * 13 + 5);
+	for (i = 0; i < 14; i++)
+		half[i] = (uint8_t)"IVSHMEM-GUEST"[i];
+	printf("ivshmem: guest pattern written\n");
+}
+
+static void measure_latency(volatile uint8_t *shm, size_t size)
+{
+	volatile uint64_t *rd = (volatile uint64_t *)shm;
+	volatile uint64_t *wr = (volatile uint64_t *)(shm + size - 64);
+	uint64_t start, read_ns, write_ns, sink = 0;
+	int i;
+
+	start = now_ns();
+	for (i = 0; i < LATENCY_ITERATIONS; i++)
+		sink += *rd;
+	read_ns = now_ns() - start;
+
+	start = now_ns();
+	for (i = 0; i < LATENCY_ITERATIONS; i++)
+		*wr = sink + i;
+	write_ns = now_ns() - start;
+
+	printf("ivshmem: latency read %llu ns/op, write %llu ns/op\n",
+	       (unsigned long long)(read_ns / LATENCY_ITERATIONS),
+	       (unsigned long long)(write_ns / LATENCY_ITERATIONS));
+}
+
+/* Ring our own doorbell and wait for the interrupt through uio_pci_generic */
+static int doorbell_test(const char *dev)
+{
+	volatile uint32_t *regs;
+	char path[600], uio[300] = "";
+	struct pollfd pfd;
+	uint32_t count, enable = 1;
+	size_t regs_size;
+	DIR *dir;
+	struct dirent *ent;
+	FILE *f;
+
+	f = fopen("/sys/bus/pci/drivers/uio_pci_generic/new_id", "w");
+	if (f) {
+		fprintf(f, "%x %x\n", IVSHMEM_VENDOR, IVSHMEM_DEVICE);
+		fclose(f);
+	}
+
+	snprintf(path, sizeof(path), "%s/uio", dev);
+	dir = opendir(path);
+	if (!dir) {
+		printf("ivshmem: uio_pci_generic did not bind, doorbell test failed\n");
+		return -1;
+	}
+	while ((ent = readdir(dir)))
+		if (strncmp(ent->d_name, "uio", 3) == 0)
+			snprintf(uio, sizeof(uio), "/dev/%s", ent->d_name);
+	closedir(dir);
+
+	regs = (volatile uint32_t *)map_bar(dev, 0, &regs_size);
+	pfd.fd = open(uio, O_RDWR);
+	if (!regs || pfd.fd < 0) {
+		printf("ivshmem: cannot access doorbell registers\n");
+		return -1;
+	}
+
+	regs[REG_INTR_MASK / 4] = 0xffffffff;
+	if (write(pfd.fd, &enable, sizeof(enable)) != sizeof(enable)) {
+		printf("ivshmem: cannot enable interrupts\n");
+		return -1;
+	}
+	regs[REG_DOORBELL / 4] = regs[REG_IV_POSITION / 4] << 16;
+
+	pfd.events = POLLIN;
+	if (poll(&pfd, 1, 5000) != 1 || read(pfd.fd, &count, sizeof(count)) != sizeof(count)) {
+		printf("ivshmem: doorbell interrupt not received\n");
+		return -1;
+	}
+	(void)regs[REG_INTR_STATUS / 4];
+	printf("ivshmem: doorbell interrupt received\n");
+	return 0;
+}
+
+int main(void)
+{
+	char dev[300], bar1[320];
+	volatile uint8_t *shm;
+	size_t size;
+	int ret = 0;
+
+	if (find_device(dev, sizeof(dev))) {
+		printf("ivshmem: no ivshmem device found\n");
+		return 1;
+	}
+	printf("ivshmem: device %s\n", dev);
+
+	shm = map_bar(dev, 2, &size);
+	if (!shm) {
+		printf("ivshmem: cannot map shared memory BAR\n");
+		return 1;
+	}
+	printf("ivshmem: shared memory size %zu\n", size);
+
+	if (check_host_pattern(shm))
+		ret = 1;
+	write_guest_pattern(shm, size);
+	measure_latency(shm, size);
+
+	/* Only ivshmem-doorbell has an MSI-X BAR */
+	snprintf(bar1, sizeof(bar1), "%s/resource1", dev);
+	if (access(bar1, F_OK) == 0 && doorbell_test(dev))
+		ret = 1;
+
+	return ret;
+}
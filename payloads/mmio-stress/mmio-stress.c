@@ -0,0 +1,160 @@
+/*
+ * Hammer emulated device registers and report per-access latency.
+ *
+ * Every PCI config space read through sysfs becomes an ECAM MMIO access
+ * (arm64) or a port I/O access (x86) that the hypervisor traps and
+ * emulates; every RTC read hits the PL031/CMOS emulation.
+ *
+ * Usage: mmio-stress [iterations]
+ */
+#include <dirent.h>
+#include <fcntl.h>
+#include <linux/rtc.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/ioctl.h>
+#include <time.h>
+#include <unistd.h>
+
+#define PCI_DEVICES "/sys/bus/pci/devices"
+#define VIRTIO_VENDOR 0x1af4
+#define DEFAULT_ITERATIONS 20000
+
+static uint64_t now_ns(void)
+{
+	struct timespec ts;
+
+	clock_gettime(CLOCK_MONOTONIC, &ts);
+	return (uint64_t)ts.tv_sec * 1000000000ull + ts.tv_nsec;
+}
+
+static int cmp_u64(const void *a, const void *b)
+{
+	uint64_t x = *(const uint64_t *)a, y = *(const uint64_t *)b;
+
+	return x < y ? -1 : x > y;
+}
+
+static void report(const char *name, uint64_t *samples, int n)
+{
+	uint64_t total = 0;
+	int i;
+
+	qsort(samples, n, sizeof(*samples), cmp_u64);
+	for (i = 0; i < n; i++)
+		total += samples[i];
+	printf("mmio-stress: %-28s n=%d min=%lluns avg=%lluns p50=%lluns p99=%lluns max=%lluns\n",
+	       name, n, (unsigned long long)samples[0],
+	       (unsigned long long)(total / n),
+	       (unsigned long long)samples[n / 2],
+	       (unsigned long long)samples[n * 99 / 100],
+	       (unsigned long long)samples[n - 1]);
+}
+
+/* Read the vendor/device dword of a device's config space repeatedly */
+static int stress_pci_config(const char *dev, uint64_t *samples, int n)
+{
+	char path[600], name[300];
+	uint32_t id;
+	uint64_t start;
+	int fd, i;
+
+	snprintf(path, sizeof(path), PCI_DEVICES "/%s/config", dev);
+	fd = open(path, O_RDONLY);
+	if (fd < 0)
+		return -1;
+
+	for (i = 0; i < n; i++) {
+		start = now_ns();
+		if (pread(fd, &id, sizeof(id), 0) != sizeof(id)) {
+			close(fd);
+			return -1;
+		}
+		samples[i] = now_ns() - start;
+	}
+	close(fd);
+
+	snprintf(name, sizeof(name), "pci-config %s", dev);
+	report(name, samples, n);
+	return 0;
+}
+
+static int stress_virtio_pci(uint64_t *samples, int n)
+{
+	DIR *dir = opendir(PCI_DEVICES);
+	struct dirent *ent;
+	char path[600];
+	unsigned int vendor;
+	int found = 0;
+	FILE *f;
+
+	if (!dir)
+		return 0;
+	while ((ent = readdir(dir))) {
+		if (ent->d_name[0] == '.')
+			continue;
+		snprintf(path, sizeof(path), PCI_DEVICES "/%s/vendor", ent->d_name);
+		f = fopen(path, "r");
+		if (!f)
+			continue;
+		if (fscanf(f, "%x", &vendor) == 1 && vendor == VIRTIO_VENDOR &&
+		    stress_pci_config(ent->d_name, samples, n) == 0)
+			found++;
+		fclose(f);
+	}
+	closedir(dir);
+	return found;
+}
+
+static int stress_rtc(uint64_t *samples, int n)
+{
+	struct rtc_time tm;
+	uint64_t start;
+	int fd, i;
+
+	fd = open("/dev/rtc0", O_RDONLY);
+	if (fd < 0)
+		return -1;
+
+	for (i = 0; i < n; i++) {
+		start = now_ns();
+		if (ioctl(fd, RTC_RD_TIME, &tm) < 0) {
+			close(fd);
+			return -1;
+		}
+		samples[i] = now_ns() - start;
+	}
+	close(fd);
+
+	report("rtc-read /dev/rtc0", samples, n);
+	return 0;
+}
+
+int main(int argc, char **argv)
+{
+	int n = argc > 1 ? atoi(argv[1]) : DEFAULT_ITERATIONS;
+	uint64_t *samples;
+	int tested = 0;
+
+	if (n <= 0)
+		n = DEFAULT_ITERATIONS;
+	samples = calloc(n, sizeof(*samples));
+	if (!samples)
+		return 1;
+
+	tested += stress_virtio_pci(samples, n);
+	if (stress_rtc(samples, n) == 0)
+		tested++;
+	else
+		printf("mmio-stress: no usable /dev/rtc0, skipping RTC reads\n");
+
+	free(samples);
+	if (!tested) {
+		printf("mmio-stress: no emulated devices found\n");
+		return 1;
+	}
+	printf("mmio-stress: %d device(s) stressed\n", tested);
+	return 0;
+}
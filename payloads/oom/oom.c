@@ -0,0 +1,40 @@
+/*
+ * Allocate memory and touch every page of it, so the pages are really
+ * faulted in (and, under a hypervisor, backed by host memory).
+ *
+ * With a size, stop once that much is resident and exit 0; without one,
+ * keep allocating until the OOM killer ends the process.
+ *
+ * Usage: oom [MiB]
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+#define CHUNK (1024 * 1024)
+#define REPORT_EVERY 64
+
+int main(int argc, char **argv)
+{
+	long limit = argc > 1 ? atol(argv[1]) : -1;
+	long page = sysconf(_SC_PAGESIZE);
+	long mib, i;
+	char *chunk;
+
+	setvbuf(stdout, NULL, _IOLBF, 0);
+	for (mib = 0; limit < 0 || mib < limit; mib++) {
+		chunk = malloc(CHUNK);
+		if (!chunk) {
+			printf("oom: malloc failed after %ld MiB\n", mib);
+			return 2;
+		}
+		/* Distinct contents, so neither KSM nor zero-page tricks back them for free */
+		for (i = 0; i < CHUNK; i += page)
+			memset(chunk + i, (int)(mib + i / page) | 1, page);
+		if (mib % REPORT_EVERY == 0)
+			printf("oom: %ld MiB resident\n", mib);
+	}
+	printf("oom: %ld MiB resident, done\n", mib);
+	return 0;
+}
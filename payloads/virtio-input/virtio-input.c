@@ -0,0 +1,119 @@
+/*
+ * evtest-like checker for virtio-input: watch every evdev device, print
+ * the events that arrive and wait until the harness-injected key press,
+ * pointer motion and button click have all been seen.
+ *
+ * The harness starts injecting through QMP once "virtio-input: ready" is
+ * printed.
+ *
+ * Usage: virtio-input [timeout-seconds]
+ */
+#include <dirent.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <linux/input.h>
+#include <poll.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+#define INPUT_DIR "/dev/input"
+#define MAX_DEVICES 16
+
+enum {
+	SEEN_KEY_DOWN = 1 << 0,
+	SEEN_KEY_UP = 1 << 1,
+	SEEN_REL = 1 << 2,
+	SEEN_BTN_DOWN = 1 << 3,
+	SEEN_BTN_UP = 1 << 4,
+	SEEN_ALL = (1 << 5) - 1,
+};
+
+static const char *const seen_names[] = {
+	"KEY_A press", "KEY_A release", "pointer motion", "BTN_LEFT press", "BTN_LEFT release",
+};
+
+static int open_devices(struct pollfd *fds)
+{
+	char path[300], name[128];
+	struct dirent *entry;
+	int n = 0, fd;
+	DIR *dir;
+
+	dir = opendir(INPUT_DIR);
+	if (!dir)
+		return 0;
+	while ((entry = readdir(dir)) && n < MAX_DEVICES) {
+		if (strncmp(entry->d_name, "event", 5))
+			continue;
+		snprintf(path, sizeof(path), INPUT_DIR "/%s", entry->d_name);
+		fd = open(path, O_RDONLY | O_NONBLOCK);
+		if (fd < 0)
+			continue;
+		if (ioctl(fd, EVIOCGNAME(sizeof(name)), name) < 0)
+			strcpy(name, "?");
+		printf("virtio-input: %s: %s\n", path, name);
+		fds[n].fd = fd;
+		fds[n].events = POLLIN;
+		n++;
+	}
+	closedir(dir);
+	return n;
+}
+
+static int classify(const struct input_event *ev)
+{
+	if (ev->type == EV_KEY && ev->code == KEY_A)
+		return ev->value ? SEEN_KEY_DOWN : SEEN_KEY_UP;
+	if (ev->type == EV_KEY && ev->code == BTN_LEFT)
+		return ev->value ? SEEN_BTN_DOWN : SEEN_BTN_UP;
+	if (ev->type == EV_REL && ev->value)
+		return SEEN_REL;
+	return 0;
+}
+
+int main(int argc, char **argv)
+{
+	int timeout = argc > 1 ? atoi(argv[1]) : 30;
+	struct pollfd fds[MAX_DEVICES];
+	struct input_event ev;
+	time_t deadline;
+	int n, i, seen = 0;
+
+	n = open_devices(fds);
+	if (!n) {
+		printf("virtio-input: no input devices\n");
+		return 1;
+	}
+
+	printf("virtio-input: ready\n");
+	fflush(stdout);
+	deadline = time(NULL) + timeout;
+	while (seen != SEEN_ALL && time(NULL) < deadline) {
+		if (poll(fds, n, 1000) <= 0)
+			continue;
+		for (i = 0; i < n; i++) {
+			if (!(fds[i].revents & POLLIN))
+				continue;
+			while (read(fds[i].fd, &ev, sizeof(ev)) == sizeof(ev)) {
+				if (ev.type == EV_SYN)
+					continue;
+				printf("virtio-input: event%d type %d code %d value %d\n",
+				       i, ev.type, ev.code, ev.value);
+				seen |= classify(&ev);
+			}
+		}
+	}
+
+	if (seen != SEEN_ALL) {
+		for (i = 0; i < 5; i++)
+			if (!(seen & (1 << i)))
+				printf("virtio-input: missing %s\n", seen_names[i]);
+		printf("virtio-input: FAIL\n");
+		return 1;
+	}
+	printf("virtio-input: OK\n");
+	return 0;
+}
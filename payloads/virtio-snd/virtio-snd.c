@@ -0,0 +1,178 @@
+/*
+ * Play a tone on the first PCM playback device and capture from the first
+ * capture device, talking to ALSA through its ioctl interface directly (as
+ * tinyalsa does) so the binary stays static and dependency free.
+ *
+ * The tone is a 440 Hz triangle wave; the harness checks the host audio
+ * backend received it.
+ *
+ * Usage: virtio-snd [play-seconds]
+ */
+#include <errno.h>
+#include <fcntl.h>
+#include <limits.h>
+#include <sound/asound.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/ioctl.h>
+#include <unistd.h>
+
+#define PLAYBACK_DEVICE "/dev/snd/pcmC0D0p"
+#define CAPTURE_DEVICE "/dev/snd/pcmC0D0c"
+#define RATE 48000
+#define CHANNELS 2
+#define PERIOD_FRAMES 1024
+#define PERIODS 4
+#define TONE_HZ 440
+#define AMPLITUDE 16000
+
+static struct snd_mask *param_mask(struct snd_pcm_hw_params *p, int n)
+{
+	return &p->masks[n - SNDRV_PCM_HW_PARAM_FIRST_MASK];
+}
+
+static struct snd_interval *param_interval(struct snd_pcm_hw_params *p, int n)
+{
+	return &p->intervals[n - SNDRV_PCM_HW_PARAM_FIRST_INTERVAL];
+}
+
+static void set_mask(struct snd_pcm_hw_params *p, int n, unsigned int bit)
+{
+	struct snd_mask *m = param_mask(p, n);
+
+	memset(m->bits, 0, sizeof(m->bits));
+	m->bits[bit >> 5] |= 1u << (bit & 31);
+}
+
+static void set_value(struct snd_pcm_hw_params *p, int n, unsigned int value)
+{
+	struct snd_interval *i = param_interval(p, n);
+
+	i->min = i->max = value;
+	i->integer = 1;
+}
+
+/* Open a PCM device with a fixed S16_LE stereo interleaved configuration */
+static int pcm_open(const char *path)
+{
+	struct snd_pcm_hw_params params;
+	int fd, n;
+
+	fd = open(path, O_RDWR);
+	if (fd < 0) {
+		printf("virtio-snd: open %s: %s\n", path, strerror(errno));
+		return -1;
+	}
+
+	memset(&params, 0, sizeof(params));
+	for (n = SNDRV_PCM_HW_PARAM_FIRST_MASK; n <= SNDRV_PCM_HW_PARAM_LAST_MASK; n++)
+		memset(param_mask(&params, n)->bits, 0xff, sizeof(struct snd_mask));
+	for (n = SNDRV_PCM_HW_PARAM_FIRST_INTERVAL; n <= SNDRV_PCM_HW_PARAM_LAST_INTERVAL; n++)
+		param_interval(&params, n)->max = UINT_MAX;
+	params.rmask = ~0u;
+	params.info = ~0u;
+
+	set_mask(&params, SNDRV_PCM_HW_PARAM_ACCESS, SNDRV_PCM_ACCESS_RW_INTERLEAVED);
+	set_mask(&params, SNDRV_PCM_HW_PARAM_FORMAT, SNDRV_PCM_FORMAT_S16_LE);
+	set_mask(&params, SNDRV_PCM_HW_PARAM_SUBFORMAT, SNDRV_PCM_SUBFORMAT_STD);
+	set_value(&params, SNDRV_PCM_HW_PARAM_CHANNELS, CHANNELS);
+	set_value(&params, SNDRV_PCM_HW_PARAM_RATE, RATE);
+	set_value(&params, SNDRV_PCM_HW_PARAM_PERIOD_SIZE, PERIOD_FRAMES);
+	set_value(&params, SNDRV_PCM_HW_PARAM_PERIODS, PERIODS);
+
+	if (ioctl(fd, SNDRV_PCM_IOCTL_HW_PARAMS, &params) < 0 ||
+	    ioctl(fd, SNDRV_PCM_IOCTL_PREPARE) < 0) {
+		printf("virtio-snd: configure %s: %s\n", path, strerror(errno));
+		close(fd);
+		return -1;
+	}
+	return fd;
+}
+
+/* Move frames in or out, recovering from under/overruns */
+static int transfer(int fd, unsigned long request, int16_t *buf, int frames)
+{
+	struct snd_xferi x = { .buf = buf, .frames = frames };
+
+	while (ioctl(fd, request, &x) < 0) {
+		if (errno != EPIPE || ioctl(fd, SNDRV_PCM_IOCTL_PREPARE) < 0)
+			return -1;
+	}
+	return x.result;
+}
+
+static int play(int seconds)
+{
+	int16_t buf[PERIOD_FRAMES * CHANNELS];
+	long frame = 0, total = (long)seconds * RATE;
+	int fd, i, c;
+
+	fd = pcm_open(PLAYBACK_DEVICE);
+	if (fd < 0)
+		return -1;
+
+	while (frame < total) {
+		for (i = 0; i < PERIOD_FRAMES; i++, frame++) {
+			/* Triangle wave: 0 -> +A -> 0 -> -A over one period */
+			long phase = frame * TONE_HZ * 4 % (RATE * 4);
+			long v = phase < RATE ? phase :
+				 phase < RATE * 3 ? 2 * RATE - phase : phase - 4 * RATE;
+
+			for (c = 0; c < CHANNELS; c++)
+				buf[i * CHANNELS + c] = (int16_t)(v * AMPLITUDE / RATE);
+		}
+		if (transfer(fd, SNDRV_PCM_IOCTL_WRITEI_FRAMES, buf, PERIOD_FRAMES) < 0) {
+			printf("virtio-snd: playback write: %s\n", strerror(errno));
+			close(fd);
+			return -1;
+		}
+	}
+	ioctl(fd, SNDRV_PCM_IOCTL_DRAIN);
+	close(fd);
+	printf("virtio-snd: played %ld frames of %d Hz\n", total, TONE_HZ);
+	return 0;
+}
+
+static int capture(void)
+{
+	int16_t buf[PERIOD_FRAMES * CHANNELS];
+	long frames = 0;
+	int fd, n;
+
+	fd = pcm_open(CAPTURE_DEVICE);
+	if (fd < 0)
+		return -1;
+	if (ioctl(fd, SNDRV_PCM_IOCTL_START) < 0 && errno != EBADFD) {
+		printf("virtio-snd: capture start: %s\n", strerror(errno));
+		close(fd);
+		return -1;
+	}
+
+	/* Half a second; the host backend may well deliver silence */
+	while (frames < RATE / 2) {
+		n = transfer(fd, SNDRV_PCM_IOCTL_READI_FRAMES, buf, PERIOD_FRAMES);
+		if (n < 0) {
+			printf("virtio-snd: capture read: %s\n", strerror(errno));
+			close(fd);
+			return -1;
+		}
+		frames += n;
+	}
+	close(fd);
+	printf("virtio-snd: captured %ld frames\n", frames);
+	return 0;
+}
+
+int main(int argc, char **argv)
+{
+	int seconds = argc > 1 ? atoi(argv[1]) : 2;
+
+	if (play(seconds) < 0 || capture() < 0) {
+		printf("virtio-snd: FAIL\n");
+		return 1;
+	}
+	printf("virtio-snd: OK\n");
+	return 0;
+}
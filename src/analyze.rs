@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Debugger a memory dump is opened with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AnalyzeTool {
+    /// crash(8), which must be built for the guest arch
+    Crash,
+    /// drgn, scriptable in Python
+    Drgn,
+}
+
+/// crash commands printing the panic message and the backtraces of all CPUs
+const CRASH_SUMMARY: &str = "set scroll off\nsys\nbt -a\nexit\n";
+
+/// drgn script printing the panic message and the backtraces of all CPUs
+const DRGN_SUMMARY: &str = r#"from drgn.helpers.linux.cpumask import for_each_online_cpu
+from drgn.helpers.linux.printk import get_printk_records
+from drgn.helpers.linux.sched import cpu_curr
+
+panics = [r.text for r in get_printk_records(prog) if b"Kernel panic" in r.text]
+print("Panic:", panics[-1].decode(errors="replace") if panics else "none in the kernel log")
+for cpu in for_each_online_cpu(prog):
+    print(f"\nCPU {cpu}:")
+    print(prog.stack_trace(cpu_curr(prog, cpu)))
+"#;
+
+/// Kernel with debug info and the symbol map of a built configuration
+fn kernel_files(config_name: &str) -> Result<(PathBuf, Option<PathBuf>), String> {
+    let build_dir = PathBuf::from("build").join(config_name);
+    let vmlinux = build_dir.join("linux").join("vmlinux");
+    if !vmlinux.exists() {
+        return Err(format!(
+            "{} has no {}; build it first",
+            config_name,
+            vmlinux.display()
+        ));
+    }
+    // crash and drgn need types, not just symbols
+    let options = crate::kconfig::read_config(&build_dir.join("linux").join(".config"));
+    if !crate::kconfig::is_enabled(&options, "CONFIG_DEBUG_INFO") {
+        eprintln!(
+            "Warning: {} was built without CONFIG_DEBUG_INFO, the analysis will be limited",
+            config_name
+        );
+    }
+    let system_map = [
+        build_dir.join("System.map"),
+        build_dir.join("linux").join("System.map"),
+    ]
+    .into_iter()
+    .find(|path| path.exists());
+    Ok((vmlinux, system_map))
+}
+
+/// Open a memory dump of a configuration's guest in crash or drgn, interactively or
+/// printing a summary of the panic and the backtraces of all CPUs
+pub fn analyze(config_name: &str, dump: &Path, tool: AnalyzeTool, summary: bool) -> bool {
+    if !dump.exists() {
+        eprintln!("Memory dump {} not found", dump.display());
+        return false;
+    }
+    let (vmlinux, system_map) = match kernel_files(config_name) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+
+    let script = if summary {
+        let (name, content) = match tool {
+            AnalyzeTool::Crash => ("summary.crash", CRASH_SUMMARY),
+            AnalyzeTool::Drgn => ("summary.py", DRGN_SUMMARY),
+        };
+        let path = PathBuf::from("build").join(config_name).join(name);
+        if let Err(e) = fs::write(&path, content) {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            return false;
+        }
+        Some(path)
+    } else {
+        None
+    };
+
+    let mut cmd = match tool {
+        AnalyzeTool::Crash => {
+            let mut cmd = Command::new("crash");
+            if let Some(script) = &script {
+                cmd.arg("-s").arg("-i").arg(script);
+            }
+            cmd.args(&system_map).arg(&vmlinux).arg(dump);
+            cmd
+        }
+        AnalyzeTool::Drgn => {
+            let mut cmd = Command::new("drgn");
+            cmd.arg("-c").arg(dump).arg("-s").arg(&vmlinux);
+            cmd.args(&script);
+            cmd
+        }
+    };
+
+    println!("Running {:?}", cmd);
+    match cmd.status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            let program = cmd.get_program().to_string_lossy().into_owned();
+            eprintln!("Failed to run {}: {}", program, e);
+            false
+        }
+    }
+}
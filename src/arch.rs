@@ -0,0 +1,284 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{Console, parse_config_name};
+
+/// Per-architecture defaults for building and booting guests
+pub struct Arch {
+    /// Name used for `config/<arch>/` and in config names
+    pub name: &'static str,
+    /// Kernel `ARCH=` value
+    pub kernel_arch: &'static str,
+    /// `uname -m` values of hosts that build this arch natively
+    pub native_hosts: &'static [&'static str],
+    /// `CROSS_COMPILE=` prefix used on other hosts
+    pub cross_prefix: &'static str,
+    /// Build with the host compiler when the cross compiler is missing, e.g. a multilib
+    /// gcc that targets the arch as well
+    pub native_fallback: bool,
+    /// Make target producing the kernel image
+    pub kernel_target: &'static str,
+    /// Kernel image relative to the kernel build directory
+    pub kernel_image: &'static str,
+    /// Image some configs build instead, exported under the name of `kernel_image`
+    pub alt_kernel_image: Option<&'static str>,
+    /// Consoles the QEMU machine provides, the first being the default
+    pub consoles: &'static [Console],
+    /// Root block device for `-drive` on the QEMU machine
+    pub root_device: &'static str,
+    /// Whether the guest hardware is described by a devicetree
+    pub devicetree: bool,
+    /// Whether the interrupt controller is a GIC with a selectable version
+    pub gic: bool,
+    pub qemu_binary: &'static str,
+    pub qemu_machine: &'static str,
+    pub qemu_cpu: &'static str,
+    /// Rust target guest binaries are built for (static musl)
+    pub rust_target: &'static str,
+    /// iPXE build chainloaded by the arch's firmware for network boot
+    pub ipxe_binary: &'static str,
+    /// Suffix of QEMU virtio devices on the machine's default transport (`device` or `pci`)
+    pub virtio_suffix: &'static str,
+}
+
+/// All supported architectures
+pub const ARCHES: &[Arch] = &[
+    Arch {
+        name: "arm64",
+        kernel_arch: "arm64",
+        native_hosts: &["aarch64", "arm64"],
+        cross_prefix: "aarch64-linux-gnu-",
+        native_fallback: false,
+        kernel_target: "Image",
+        kernel_image: "arch/arm64/boot/Image",
+        alt_kernel_image: None,
+        consoles: &[Console::Pl011, Console::Virtio],
+        // virt attaches -drive as virtio-blk
+        root_device: "/dev/vda",
+        devicetree: true,
+        gic: true,
+        qemu_binary: "qemu-system-aarch64",
+        qemu_machine: "virt,virtualization=true",
+        qemu_cpu: "cortex-a72",
+        rust_target: "aarch64-unknown-linux-musl",
+        ipxe_binary: "snp.efi",
+        virtio_suffix: "device",
+    },
+    Arch {
+        name: "x86",
+        kernel_arch: "x86_64",
+        native_hosts: &["x86_64", "i686"],
+        cross_prefix: "x86_64-linux-gnu-",
+        native_fallback: true,
+        kernel_target: "bzImage",
+        kernel_image: "arch/x86/boot/bzImage",
+        alt_kernel_image: None,
+        consoles: &[Console::Uart8250, Console::Virtio],
+        // q35 attaches -drive to the AHCI controller
+        root_device: "/dev/sda",
+        devicetree: false,
+        gic: false,
+        qemu_binary: "qemu-system-x86_64",
+        qemu_machine: "q35",
+        qemu_cpu: "qemu64",
+        rust_target: "x86_64-unknown-linux-musl",
+        ipxe_binary: "undionly.kpxe",
+        virtio_suffix: "pci",
+    },
+    Arch {
+        name: "riscv64",
+        kernel_arch: "riscv",
+        native_hosts: &["riscv64"],
+        cross_prefix: "riscv64-linux-gnu-",
+        native_fallback: false,
+        kernel_target: "Image",
+        kernel_image: "arch/riscv/boot/Image",
+        alt_kernel_image: None,
+        // virt has an ns16550a UART; OpenSBI is loaded as the default firmware
+        consoles: &[Console::Uart8250, Console::Virtio],
+        // virt attaches -drive as virtio-blk
+        root_device: "/dev/vda",
+        devicetree: true,
+        gic: false,
+        qemu_binary: "qemu-system-riscv64",
+        qemu_machine: "virt",
+        qemu_cpu: "rv64",
+        rust_target: "riscv64gc-unknown-linux-musl",
+        ipxe_binary: "snp.efi",
+        virtio_suffix: "device",
+    },
+    Arch {
+        name: "loongarch64",
+        kernel_arch: "loongarch",
+        native_hosts: &["loongarch64"],
+        cross_prefix: "loongarch64-linux-gnu-",
+        native_fallback: false,
+        // The default target builds the EFI image the config asks for
+        kernel_target: "all",
+        kernel_image: "arch/loongarch/boot/vmlinux.efi",
+        // Compressed instead with CONFIG_EFI_ZBOOT
+        alt_kernel_image: Some("arch/loongarch/boot/vmlinuz.efi"),
+        consoles: &[Console::Uart8250, Console::Virtio],
+        // virt attaches -drive as virtio-blk
+        root_device: "/dev/vda",
+        // The firmware tables QEMU generates are ACPI
+        devicetree: false,
+        gic: false,
+        qemu_binary: "qemu-system-loongarch64",
+        qemu_machine: "virt",
+        qemu_cpu: "la464",
+        rust_target: "loongarch64-unknown-linux-musl",
+        ipxe_binary: "snp.efi",
+        virtio_suffix: "pci",
+    },
+];
+
+impl Arch {
+    /// Default console of the arch
+    pub fn default_console(&self) -> Console {
+        self.consoles[0]
+    }
+
+    /// Name of the kernel image in `build/<config>/`
+    pub fn image_name(&self) -> &'static str {
+        self.kernel_image.rsplit('/').next().unwrap()
+    }
+
+    /// Kernel image a build produced in `build_dir`, the newest if both images exist
+    pub fn built_kernel_image(&self, build_dir: &Path) -> PathBuf {
+        let modified = |path: &PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+        std::iter::once(self.kernel_image)
+            .chain(self.alt_kernel_image)
+            .map(|image| build_dir.join(image))
+            .filter(|path| path.exists())
+            .max_by_key(modified)
+            .unwrap_or_else(|| build_dir.join(self.kernel_image))
+    }
+
+    /// Cross-compile prefix needed on the given host, `None` when building natively
+    pub fn cross_compile_prefix(&self, host_arch: &str) -> Option<String> {
+        if self.native_hosts.contains(&host_arch) {
+            return None;
+        }
+
+        let gcc = format!("{}gcc", self.cross_prefix);
+        let found = Command::new("which")
+            .arg(&gcc)
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        if !found {
+            if self.native_fallback {
+                eprintln!("Warning: {} not found, trying native compilation", gcc);
+                return None;
+            }
+            eprintln!("Warning: {} not found, cross-compilation may fail", gcc);
+        }
+        Some(self.cross_prefix.to_string())
+    }
+}
+
+/// Look up an architecture by name
+pub fn find_arch(name: &str) -> Option<&'static Arch> {
+    ARCHES.iter().find(|a| a.name == name)
+}
+
+/// Look up the architecture of a config name
+pub fn arch_of(config_name: &str) -> Option<&'static Arch> {
+    find_arch(&parse_config_name(config_name).0)
+}
+
+/// Print all supported architectures and their capabilities
+pub fn list_arches() {
+    println!("Supported architectures:");
+    println!(
+        "  {:<12} {:<10} {:<12} {:<24} {:<24} {:<4} consoles",
+        "name", "ARCH", "image", "qemu", "cross prefix", "dt"
+    );
+    for arch in ARCHES {
+        let consoles: Vec<&str> = arch.consoles.iter().map(|c| c.name()).collect();
+        println!(
+            "  {:<12} {:<10} {:<12} {:<24} {:<24} {:<4} {}",
+            arch.name,
+            arch.kernel_arch,
+            arch.image_name(),
+            arch.qemu_binary,
+            arch.cross_prefix,
+            if arch.devicetree { "yes" } else { "no" },
+            consoles.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_arches_by_name() {
+        assert_eq!(find_arch("arm64").unwrap().kernel_arch, "arm64");
+        assert_eq!(find_arch("x86").unwrap().kernel_arch, "x86_64");
+        assert_eq!(find_arch("loongarch64").unwrap().kernel_arch, "loongarch");
+        assert!(find_arch("x86_64").is_none());
+        assert!(find_arch("").is_none());
+    }
+
+    #[test]
+    fn arch_of_config_names() {
+        assert_eq!(arch_of("arm64-qemu").unwrap().name, "arm64");
+        assert_eq!(arch_of("x86-qemu").unwrap().name, "x86");
+        assert_eq!(arch_of("riscv64-qemu").unwrap().name, "riscv64");
+        assert!(arch_of("sparc-qemu").is_none());
+    }
+
+    #[test]
+    fn image_names() {
+        assert_eq!(find_arch("arm64").unwrap().image_name(), "Image");
+        assert_eq!(find_arch("x86").unwrap().image_name(), "bzImage");
+        assert_eq!(
+            find_arch("loongarch64").unwrap().image_name(),
+            "vmlinux.efi"
+        );
+    }
+
+    #[test]
+    fn built_kernel_image_prefers_the_newest() {
+        let arch = find_arch("loongarch64").unwrap();
+        let dir = std::env::temp_dir().join(format!("arch-test-{}", std::process::id()));
+        let boot = dir.join("arch/loongarch/boot");
+        fs::create_dir_all(&boot).unwrap();
+
+        // Nothing built: the primary image
+        assert_eq!(arch.built_kernel_image(&dir), dir.join(arch.kernel_image));
+
+        let image = boot.join("vmlinux.efi");
+        let zboot = boot.join("vmlinuz.efi");
+        fs::write(&image, "").unwrap();
+        assert_eq!(arch.built_kernel_image(&dir), image);
+
+        fs::write(&zboot, "").unwrap();
+        let older = std::time::SystemTime::UNIX_EPOCH;
+        fs::File::options()
+            .write(true)
+            .open(&image)
+            .unwrap()
+            .set_modified(older)
+            .unwrap();
+        assert_eq!(arch.built_kernel_image(&dir), zboot);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_cross_prefix_on_native_hosts() {
+        assert_eq!(
+            find_arch("arm64").unwrap().cross_compile_prefix("aarch64"),
+            None
+        );
+        assert_eq!(
+            find_arch("x86").unwrap().cross_compile_prefix("x86_64"),
+            None
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::download::sha256_file;
+use crate::manifest::{artifact_paths, manifest_path, read_manifest};
+
+/// How build provenance is signed and verified
+pub enum Signer {
+    /// A local PEM key: the private key for signing, the public key for verifying
+    Key(PathBuf),
+    /// Sigstore keyless flow through cosign; verification pins the signer identity
+    Keyless {
+        identity: Option<String>,
+        issuer: Option<String>,
+    },
+}
+
+/// Get the signature file stored next to a signed file
+fn signature_path(file: &Path, signer: &Signer) -> PathBuf {
+    let suffix = match signer {
+        Signer::Key(_) => "sig",
+        Signer::Keyless { .. } => "sigstore.json",
+    };
+    PathBuf::from(format!("{}.{}", file.display(), suffix))
+}
+
+/// Run a signing tool, reporting failures
+fn run(cmd: &mut Command, what: &str) -> bool {
+    match cmd.output() {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            eprintln!(
+                "{} failed: {}",
+                what,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run {}: {}", what, e);
+            false
+        }
+    }
+}
+
+/// Sign a single file
+fn sign_file(file: &Path, signer: &Signer) -> bool {
+    let signature = signature_path(file, signer);
+    let mut cmd = match signer {
+        Signer::Key(key) => {
+            let mut cmd = Command::new("openssl");
+            cmd.args(["dgst", "-sha256", "-sign"])
+                .arg(key)
+                .arg("-out")
+                .arg(&signature)
+                .arg(file);
+            cmd
+        }
+        Signer::Keyless { .. } => {
+            let mut cmd = Command::new("cosign");
+            cmd.args(["sign-blob", "--yes", "--bundle"])
+                .arg(&signature)
+                .arg(file);
+            cmd
+        }
+    };
+    if !run(&mut cmd, "signing") {
+        return false;
+    }
+    println!("  signed {} -> {}", file.display(), signature.display());
+    true
+}
+
+/// Verify a single file against its signature
+fn verify_file(file: &Path, signer: &Signer) -> bool {
+    let signature = signature_path(file, signer);
+    let mut cmd = match signer {
+        Signer::Key(key) => {
+            let mut cmd = Command::new("openssl");
+            cmd.args(["dgst", "-sha256", "-verify"])
+                .arg(key)
+                .arg("-signature")
+                .arg(&signature)
+                .arg(file);
+            cmd
+        }
+        Signer::Keyless { identity, issuer } => {
+            let (Some(identity), Some(issuer)) = (identity, issuer) else {
+                eprintln!("Keyless verification needs the expected signer identity and issuer");
+                return false;
+            };
+            let mut cmd = Command::new("cosign");
+            cmd.args(["verify-blob", "--bundle"])
+                .arg(&signature)
+                .args(["--certificate-identity", identity])
+                .args(["--certificate-oidc-issuer", issuer])
+                .arg(file);
+            cmd
+        }
+    };
+    run(&mut cmd, &format!("verification of {}", file.display()))
+}
+
+/// Sign the build manifest of a configuration, and its artifacts if requested
+pub fn sign(config_name: &str, signer: &Signer, artifacts: bool) -> bool {
+    let manifest = manifest_path(config_name);
+    if !manifest.exists() {
+        eprintln!(
+            "No build manifest for {}, build it first: {}",
+            config_name,
+            manifest.display()
+        );
+        return false;
+    }
+
+    println!("Signing build provenance of {}...", config_name);
+    let mut files = vec![manifest];
+    if artifacts {
+        files.extend(artifact_paths(config_name));
+    }
+    files.iter().all(|file| sign_file(file, signer))
+}
+
+/// Verify the signed provenance of a configuration before it is consumed
+///
+/// The manifest signature is mandatory. Each artifact must then match the SHA-256 the
+/// signed manifest records for it, or carry a valid signature of its own; an artifact
+/// with neither fails, so swapping a file and deleting its signature is caught.
+pub fn verify(config_name: &str, signer: &Signer) -> bool {
+    let manifest = manifest_path(config_name);
+    if !signature_path(&manifest, signer).exists() {
+        eprintln!("{} is not signed", manifest.display());
+        return false;
+    }
+    if !verify_file(&manifest, signer) {
+        println!("  FAIL     {}", manifest.display());
+        eprintln!("Provenance verification failed for {}", config_name);
+        return false;
+    }
+    println!("  ok       {}", manifest.display());
+
+    let output_dir = PathBuf::from("build").join(config_name);
+    let recorded = read_manifest(config_name)
+        .map(|manifest| manifest.artifacts)
+        .unwrap_or_default();
+    let mut ok = true;
+    for file in artifact_paths(config_name) {
+        let relative = file.strip_prefix(&output_dir).unwrap_or(&file);
+        let artifact = recorded.iter().find(|a| Path::new(&a.path) == relative);
+        let problem = if signature_path(&file, signer).exists() {
+            (!verify_file(&file, signer)).then(|| "bad signature".to_string())
+        } else {
+            match artifact {
+                None => Some("neither signed nor recorded in the manifest".to_string()),
+                Some(artifact) => match sha256_file(&file) {
+                    Err(e) => Some(e),
+                    Ok(sha256) if sha256 != artifact.sha256 => {
+                        Some(format!("sha256 {} instead of {}", sha256, artifact.sha256))
+                    }
+                    Ok(_) => None,
+                },
+            }
+        };
+        match problem {
+            None => println!("  ok       {}", file.display()),
+            Some(problem) => {
+                println!("  FAIL     {}: {}", file.display(), problem);
+                ok = false;
+            }
+        }
+    }
+
+    if ok {
+        println!("Provenance of {} verified", config_name);
+    } else {
+        eprintln!("Provenance verification failed for {}", config_name);
+    }
+    ok
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+/// File names that should never reach a guest image
+const SENSITIVE_NAMES: &[&str] = &[
+    ".ssh",
+    ".gnupg",
+    ".aws",
+    ".netrc",
+    ".git-credentials",
+    ".bash_history",
+    "id_rsa",
+    "id_ecdsa",
+    "id_ed25519",
+];
+
+/// `/root/.ssh` as the build generates it for `ssh = true`: only the authorized key of
+/// the generated key pair, which is not a leaked credential
+fn generated_ssh_dir(guest_path: &Path, path: &Path) -> bool {
+    guest_path == Path::new("/root/.ssh")
+        && fs::read_dir(path).is_ok_and(|entries| {
+            entries
+                .flatten()
+                .all(|entry| entry.file_name() == crate::ssh::AUTHORIZED_KEYS)
+        })
+}
+
+/// A policy violation in a staged rootfs
+pub struct Finding {
+    /// Path inside the guest, e.g. `/bin/su`
+    pub path: String,
+    pub problem: String,
+}
+
+/// Host directories whose contents showing up in the guest means a copy went wrong
+fn host_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    // A home of /root is also a regular guest directory
+    if let Some(home) = std::env::var_os("HOME").map(PathBuf::from)
+        && home.starts_with("/home")
+    {
+        paths.push(home);
+    }
+    if let Ok(dir) = std::env::current_dir() {
+        paths.push(dir);
+    }
+    paths
+}
+
+/// Check a symlink target against the rootfs it lives in
+fn check_symlink(rootfs_dir: &Path, guest_path: &Path, target: &Path) -> Option<String> {
+    if target.is_absolute() {
+        // Resolved against the guest root at runtime; a target missing from the
+        // rootfs most likely names something on the build host
+        let inside = rootfs_dir.join(target.strip_prefix("/").unwrap_or(target));
+        if fs::symlink_metadata(&inside).is_err() {
+            return Some(format!(
+                "absolute symlink to {} outside the rootfs",
+                target.display()
+            ));
+        }
+        return None;
+    }
+
+    let mut depth = guest_path.components().count().saturating_sub(2) as i64;
+    for component in target.components() {
+        match component {
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return Some(format!(
+                "symlink {} climbs out of the rootfs",
+                target.display()
+            ));
+        }
+    }
+    None
+}
+
+/// Audit a staged rootfs for setuid/setgid files, world-writable files, symlinks
+/// escaping the rootfs and files copied from the build host
+pub fn audit_rootfs(rootfs_dir: &Path) -> Vec<Finding> {
+    let host_paths = host_paths();
+    let mut findings = Vec::new();
+    let mut pending = vec![rootfs_dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            let relative = path.strip_prefix(rootfs_dir).unwrap_or(&path);
+            let guest_path = Path::new("/").join(relative);
+            let mut report = |problem: String| {
+                findings.push(Finding {
+                    path: guest_path.display().to_string(),
+                    problem,
+                })
+            };
+
+            let name = entry.file_name();
+            if SENSITIVE_NAMES.iter().any(|n| name == *n) && !generated_ssh_dir(&guest_path, &path)
+            {
+                report("credentials or shell history from a developer machine".to_string());
+            }
+            if let Some(host) = host_paths.iter().find(|h| guest_path == **h) {
+                report(format!(
+                    "host directory {} copied into the rootfs",
+                    host.display()
+                ));
+                continue;
+            }
+
+            if metadata.file_type().is_symlink() {
+                if let Ok(target) = fs::read_link(&path)
+                    && let Some(problem) = check_symlink(rootfs_dir, &guest_path, &target)
+                {
+                    report(problem);
+                }
+                continue;
+            }
+
+            let mode = metadata.permissions().mode();
+            if mode & 0o4000 != 0 {
+                report(format!("setuid (owner uid {})", metadata.uid()));
+            }
+            if mode & 0o2000 != 0 && !metadata.is_dir() {
+                report(format!("setgid (owner gid {})", metadata.gid()));
+            }
+            // Sticky directories such as /tmp are meant to be shared
+            if mode & 0o002 != 0 && !(metadata.is_dir() && mode & 0o1000 != 0) {
+                report(format!("world-writable (mode {:o})", mode & 0o7777));
+            }
+
+            if metadata.is_dir() {
+                pending.push(path);
+            }
+        }
+    }
+
+    findings.sort_by(|a, b| a.path.cmp(&b.path));
+    findings
+}
+
+/// Print the audit findings of a rootfs; returns false if the build should stop
+pub fn check_rootfs(rootfs_dir: &Path, strict: bool) -> bool {
+    println!("Auditing rootfs contents...");
+    let findings = audit_rootfs(rootfs_dir);
+    if findings.is_empty() {
+        println!("Rootfs audit: no findings");
+        return true;
+    }
+
+    let level = if strict { "error" } else { "warning" };
+    for finding in &findings {
+        eprintln!("  {}: {}: {}", level, finding.path, finding.problem);
+    }
+    eprintln!("Rootfs audit: {} finding(s)", findings.len());
+    if strict {
+        eprintln!("Refusing to build the image in --strict mode");
+        return false;
+    }
+    true
+}
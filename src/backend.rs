@@ -0,0 +1,465 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cmdline::kernel_cmdline;
+use crate::config::{ConfigManifest, Console, load_manifest, parse_config_name};
+use crate::cpu_hotplug::BOOT_CPUS;
+use crate::memory::memory_size;
+use crate::qemu::{
+    LaunchOptions, initramfs_path, kernel_image_path, qemu_command, rootfs_image_path,
+};
+
+/// Hypervisor frontend `run` and `test` boot guests with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackendKind {
+    /// `qemu-system-*`, or a QEMU-compatible `--hypervisor-bin`
+    #[default]
+    Qemu,
+    /// cloud-hypervisor, booting the `vmm_artifacts` of a configuration
+    CloudHypervisor,
+    /// crosvm, booting the `vmm_artifacts` of a configuration
+    Crosvm,
+    /// An external script given as `--hypervisor-bin` (see README)
+    Script,
+}
+
+/// Features beyond booting a kernel with a console and a root disk
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// QMP monitor, for input injection, virtio-mem resizing, vCPU hotplug and screendumps
+    pub qmp: bool,
+    /// User-mode networking with host port forwards, for httpd, ssh and NFS roots
+    pub user_net: bool,
+    /// Devices other than console, disk and network: extra disks, TPM, sound, displays, ...
+    pub devices: bool,
+    /// Booting from an initramfs instead of the rootfs image
+    pub initramfs: bool,
+}
+
+impl Capabilities {
+    /// Names used by `capabilities` output of script backends and in error messages
+    const NAMES: [&'static str; 4] = ["qmp", "user-net", "devices", "initramfs"];
+
+    fn flags(&self) -> [bool; 4] {
+        [self.qmp, self.user_net, self.devices, self.initramfs]
+    }
+
+    fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut caps = Capabilities::default();
+        for name in names {
+            match name.trim() {
+                "qmp" => caps.qmp = true,
+                "user-net" => caps.user_net = true,
+                "devices" => caps.devices = true,
+                "initramfs" => caps.initramfs = true,
+                _ => {}
+            }
+        }
+        caps
+    }
+
+    /// Capabilities in `self` that `offered` lacks
+    fn missing(&self, offered: &Capabilities) -> Vec<&'static str> {
+        Self::NAMES
+            .into_iter()
+            .zip(self.flags().into_iter().zip(offered.flags()))
+            .filter(|(_, (needed, offered))| *needed && !offered)
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+/// A way of booting built configurations with the console on stdio
+pub trait RunBackend {
+    fn name(&self) -> &'static str;
+    /// What the backend can do; script backends are asked at run time
+    fn capabilities(&self) -> Result<Capabilities, String>;
+    /// Version of the installed hypervisor, recorded with test results
+    fn version(&self, config_name: &str) -> Option<String>;
+    /// Command that boots the configuration
+    fn command(&self, config_name: &str, options: &LaunchOptions) -> Result<Command, String>;
+}
+
+/// Pick the backend of a launch
+pub fn select(options: &LaunchOptions) -> Result<Box<dyn RunBackend>, String> {
+    Ok(match options.backend {
+        BackendKind::Qemu => Box::new(Qemu {
+            binary: options.binary.clone(),
+        }),
+        BackendKind::CloudHypervisor => Box::new(CloudHypervisor {
+            binary: options
+                .binary
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("cloud-hypervisor")),
+        }),
+        BackendKind::Crosvm => Box::new(Crosvm {
+            binary: options
+                .binary
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("crosvm")),
+        }),
+        BackendKind::Script => Box::new(Script {
+            path: options
+                .binary
+                .clone()
+                .ok_or("the script backend needs --hypervisor-bin")?,
+        }),
+    })
+}
+
+/// Capabilities a configuration and launch need from the backend
+fn requirements(arch: &str, manifest: &ConfigManifest, options: &LaunchOptions) -> Capabilities {
+    let has_profile = |name: &str| manifest.profiles.iter().any(|p| p == name);
+    Capabilities {
+        qmp: options.qmp.is_some()
+            || has_profile("virtio-input")
+            || has_profile("virtio-mem")
+            || has_profile("oom")
+            || (has_profile("cpu-hotplug") && crate::cpu_hotplug::qmp_hotplug(arch)),
+        user_net: manifest.httpd || manifest.ssh || manifest.nfsroot || has_profile("dma-io"),
+        devices: !crate::disks::declared(manifest).is_empty()
+            || manifest.tpm.is_some()
+            || manifest.display.is_some()
+            || !manifest.qemu_args.is_empty()
+            || ["virtio-snd", "virtio-input", "ivshmem"]
+                .into_iter()
+                .any(has_profile),
+        initramfs: manifest.initramfs,
+    }
+}
+
+/// Command booting a configuration with the launch's backend, if it can run it
+pub fn command(config_name: &str, options: &LaunchOptions) -> Result<Command, String> {
+    let backend = select(options)?;
+    let (arch, _) = parse_config_name(config_name);
+    let manifest = load_manifest(config_name)?;
+    let missing = requirements(&arch, &manifest, options).missing(&backend.capabilities()?);
+    if !missing.is_empty() {
+        return Err(format!(
+            "{} cannot run {}: no support for {}",
+            backend.name(),
+            config_name,
+            missing.join(", ")
+        ));
+    }
+    backend.command(config_name, options)
+}
+
+/// First line a binary prints for `args`, e.g. its version
+fn first_line(binary: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new(binary).args(args).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().next()?.trim().to_string();
+    (output.status.success() && !line.is_empty()).then_some(line)
+}
+
+/// What a non-QEMU backend boots: kernel, root and machine size
+struct BootSpec {
+    kernel: PathBuf,
+    root: Root,
+    cmdline: String,
+    memory: u64,
+    vcpus: u32,
+    console: Console,
+    firmware: Option<crate::firmware::FirmwareFiles>,
+}
+
+enum Root {
+    Disk {
+        image: PathBuf,
+        readonly: bool,
+    },
+    Initramfs(PathBuf),
+    /// An initramfs built into the kernel image
+    Embedded,
+}
+
+impl BootSpec {
+    /// Boot the configuration the way QEMU would, with its own console and root device
+    fn new(config_name: &str, options: &LaunchOptions) -> Result<Self, String> {
+        let (arch, _) = parse_config_name(config_name);
+        let manifest = load_manifest(config_name)?;
+        let kernel = match &options.kernel {
+            Some(kernel) => kernel.clone(),
+            None => kernel_image_path(config_name)
+                .ok_or_else(|| format!("unsupported architecture {}", arch))?,
+        };
+        let cmdline = kernel_cmdline(config_name, &manifest);
+        Self::with(
+            config_name,
+            options,
+            &manifest,
+            kernel,
+            cmdline,
+            manifest.console(&arch),
+        )
+    }
+
+    /// Boot the configuration's reference-VMM artifacts (`vmm_artifacts = true`)
+    fn vmm(config_name: &str, options: &LaunchOptions) -> Result<Self, String> {
+        let manifest = load_manifest(config_name)?;
+        let kernel = match &options.kernel {
+            Some(kernel) => kernel.clone(),
+            None => crate::vmm::kernel_path(config_name).ok_or_else(|| {
+                format!(
+                    "{} has no reference VMM kernel, build it with vmm_artifacts = true",
+                    config_name
+                )
+            })?,
+        };
+        let cmdline = crate::vmm::cmdline(config_name, &manifest);
+        Self::with(
+            config_name,
+            options,
+            &manifest,
+            kernel,
+            cmdline,
+            Console::Virtio,
+        )
+    }
+
+    fn with(
+        config_name: &str,
+        options: &LaunchOptions,
+        manifest: &ConfigManifest,
+        kernel: PathBuf,
+        mut cmdline: String,
+        console: Console,
+    ) -> Result<Self, String> {
+        let root = if manifest.initramfs_embed {
+            Root::Embedded
+        } else if manifest.initramfs {
+            Root::Initramfs(initramfs_path(config_name))
+        } else {
+            Root::Disk {
+                image: rootfs_image_path(config_name),
+                readonly: manifest.readonly_root,
+            }
+        };
+        for param in &options.extra_cmdline {
+            cmdline.push(' ');
+            cmdline.push_str(param);
+        }
+        Ok(BootSpec {
+            kernel,
+            root,
+            cmdline,
+            memory: memory_size(manifest)?,
+            vcpus: manifest.vcpus.unwrap_or(BOOT_CPUS),
+            console,
+            firmware: crate::firmware::files(config_name, manifest),
+        })
+    }
+}
+
+/// QEMU, the default backend
+struct Qemu {
+    binary: Option<PathBuf>,
+}
+
+impl RunBackend for Qemu {
+    fn name(&self) -> &'static str {
+        "qemu"
+    }
+
+    fn capabilities(&self) -> Result<Capabilities, String> {
+        Ok(Capabilities {
+            qmp: true,
+            user_net: true,
+            devices: true,
+            initramfs: true,
+        })
+    }
+
+    fn version(&self, config_name: &str) -> Option<String> {
+        let binary = match &self.binary {
+            Some(binary) => binary.clone(),
+            None => PathBuf::from(crate::arch::arch_of(config_name)?.qemu_binary),
+        };
+        first_line(&binary, &["--version"])
+    }
+
+    fn command(&self, config_name: &str, options: &LaunchOptions) -> Result<Command, String> {
+        qemu_command(config_name, options)
+            .ok_or_else(|| format!("no QEMU command for {}", config_name))
+    }
+}
+
+/// cloud-hypervisor, booting the kernel directly with the console on stdio
+struct CloudHypervisor {
+    binary: PathBuf,
+}
+
+impl RunBackend for CloudHypervisor {
+    fn name(&self) -> &'static str {
+        "cloud-hypervisor"
+    }
+
+    fn capabilities(&self) -> Result<Capabilities, String> {
+        Ok(Capabilities {
+            initramfs: true,
+            ..Default::default()
+        })
+    }
+
+    fn version(&self, _config_name: &str) -> Option<String> {
+        first_line(&self.binary, &["--version"])
+    }
+
+    fn command(&self, config_name: &str, options: &LaunchOptions) -> Result<Command, String> {
+        let (arch, _) = parse_config_name(config_name);
+        if arch != "x86" && arch != "arm64" {
+            return Err(format!("cloud-hypervisor does not run {} guests", arch));
+        }
+        let spec = BootSpec::vmm(config_name, options)?;
+
+        let mut cmd = Command::new(&self.binary);
+        cmd.arg("--kernel")
+            .arg(&spec.kernel)
+            .args(["--cmdline", &spec.cmdline])
+            .args(["--cpus", &format!("boot={}", spec.vcpus)])
+            .args(["--memory", &format!("size={}", spec.memory)]);
+        match &spec.root {
+            Root::Disk { image, readonly } => {
+                let mut disk = format!("path={}", image.display());
+                if *readonly {
+                    disk.push_str(",readonly=on");
+                }
+                cmd.args(["--disk", &disk]);
+            }
+            Root::Initramfs(initramfs) => {
+                cmd.arg("--initramfs").arg(initramfs);
+            }
+            Root::Embedded => {}
+        }
+        // The kernel writes to hvc0, so the virtio console goes to stdio
+        cmd.args(["--console", "tty", "--serial", "off"]);
+        cmd.args(&options.extra_args);
+        Ok(cmd)
+    }
+}
+
+/// crosvm, booting the same artifacts as cloud-hypervisor
+struct Crosvm {
+    binary: PathBuf,
+}
+
+impl RunBackend for Crosvm {
+    fn name(&self) -> &'static str {
+        "crosvm"
+    }
+
+    fn capabilities(&self) -> Result<Capabilities, String> {
+        Ok(Capabilities {
+            initramfs: true,
+            ..Default::default()
+        })
+    }
+
+    fn version(&self, _config_name: &str) -> Option<String> {
+        first_line(&self.binary, &["version"])
+    }
+
+    fn command(&self, config_name: &str, options: &LaunchOptions) -> Result<Command, String> {
+        let (arch, _) = parse_config_name(config_name);
+        if arch != "x86" && arch != "arm64" {
+            return Err(format!("crosvm does not run {} guests", arch));
+        }
+        let spec = BootSpec::vmm(config_name, options)?;
+
+        let mut cmd = Command::new(&self.binary);
+        // The sandbox needs minijail policies installed, which development hosts lack
+        cmd.args(["run", "--disable-sandbox"])
+            .args(["--cpus", &spec.vcpus.to_string()])
+            .args(["--mem", &(spec.memory >> 20).to_string()])
+            .args(["--params", &spec.cmdline])
+            .args([
+                "--serial",
+                "type=stdout,hardware=virtio-console,console=true,stdin=true",
+            ]);
+        match &spec.root {
+            Root::Disk { image, readonly } => {
+                let mut block = format!("path={}", image.display());
+                if *readonly {
+                    block.push_str(",ro=true");
+                }
+                cmd.args(["--block", &block]);
+            }
+            Root::Initramfs(initramfs) => {
+                cmd.arg("--initrd").arg(initramfs);
+            }
+            Root::Embedded => {}
+        }
+        cmd.args(&options.extra_args).arg(&spec.kernel);
+        Ok(cmd)
+    }
+}
+
+/// An external script that boots the guest from `GUEST_TEST_*` variables
+struct Script {
+    path: PathBuf,
+}
+
+impl RunBackend for Script {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    fn capabilities(&self) -> Result<Capabilities, String> {
+        let output = Command::new(&self.path)
+            .arg("capabilities")
+            .output()
+            .map_err(|e| format!("failed to run {}: {}", self.path.display(), e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} capabilities failed with {}",
+                self.path.display(),
+                output.status
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(Capabilities::from_names(stdout.lines()))
+    }
+
+    fn version(&self, _config_name: &str) -> Option<String> {
+        first_line(&self.path, &["version"])
+    }
+
+    fn command(&self, config_name: &str, options: &LaunchOptions) -> Result<Command, String> {
+        let (arch, _) = parse_config_name(config_name);
+        let spec = BootSpec::new(config_name, options)?;
+
+        let mut cmd = Command::new(&self.path);
+        cmd.arg("boot")
+            .args(&options.extra_args)
+            .env("GUEST_TEST_CONFIG", config_name)
+            .env("GUEST_TEST_ARCH", &arch)
+            .env("GUEST_TEST_KERNEL", &spec.kernel)
+            .env("GUEST_TEST_CMDLINE", &spec.cmdline)
+            .env("GUEST_TEST_MEMORY", spec.memory.to_string())
+            .env("GUEST_TEST_VCPUS", spec.vcpus.to_string())
+            .env("GUEST_TEST_CONSOLE", spec.console.name());
+        match &spec.root {
+            Root::Disk { image, readonly } => {
+                cmd.env("GUEST_TEST_ROOTFS", image);
+                if *readonly {
+                    cmd.env("GUEST_TEST_ROOTFS_READONLY", "1");
+                }
+            }
+            Root::Initramfs(initramfs) => {
+                cmd.env("GUEST_TEST_INITRD", initramfs);
+            }
+            Root::Embedded => {}
+        }
+        if let Some(firmware) = &spec.firmware {
+            cmd.env("GUEST_TEST_FIRMWARE", &firmware.code);
+            if let Some(vars) = &firmware.vars {
+                cmd.env("GUEST_TEST_FIRMWARE_VARS", vars);
+            }
+        }
+        if let Some(socket) = &options.qmp {
+            cmd.env("GUEST_TEST_QMP", socket);
+        }
+        Ok(cmd)
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::config::ConfigManifest;
+use crate::error::{BuildError, run};
+
+/// Source of the static `/init` of bare configurations
+const BARE_INIT: &str = "init/bare.c";
+
+/// Check that a manifest asks nothing of a bare guest that needs a shell
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    if !manifest.bare {
+        return Ok(());
+    }
+    if !manifest.initramfs {
+        return Err("bare requires initramfs = true".to_string());
+    }
+    if !manifest.profiles.is_empty()
+        || !manifest.payloads.is_empty()
+        || manifest.ssh
+        || manifest.httpd
+    {
+        return Err("bare cannot be combined with profiles, payloads, ssh or httpd".to_string());
+    }
+    Ok(())
+}
+
+/// Compile the bare init and pack it alone into the configuration's initramfs
+pub fn create_initramfs_for_config(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    cross_compile_prefix: &Option<String>,
+) -> Result<(), BuildError> {
+    let rootfs_dir = PathBuf::from("build").join(config_name).join("rootfs");
+    if rootfs_dir.exists() {
+        fs::remove_dir_all(&rootfs_dir)
+            .map_err(BuildError::io("failed to remove existing rootfs directory"))?;
+    }
+    // Mount points for proc and devtmpfs, which provides /dev/console
+    for dir in ["dev", "proc"] {
+        fs::create_dir_all(rootfs_dir.join(dir)).map_err(BuildError::io(format!(
+            "failed to create /{} in rootfs",
+            dir
+        )))?;
+    }
+
+    let cc = format!("{}gcc", cross_compile_prefix.as_deref().unwrap_or(""));
+    println!("Building {} with {}...", BARE_INIT, cc);
+    run(
+        Command::new(&cc)
+            .args(["-static", "-Os", "-Wall", "-s", "-o"])
+            .arg(rootfs_dir.join("init"))
+            .arg(BARE_INIT),
+        "building the bare init",
+    )?;
+
+    crate::rootfs::pack_initramfs(config_name, manifest, &rootfs_dir)
+}
@@ -0,0 +1,694 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::arch::{Arch, arch_of};
+use crate::config::{ConfigManifest, load_manifest, parse_config_name};
+use crate::memory::{format_size, parse_size};
+
+/// Start of the first partition; the space before it is left to raw bootloaders
+const FIRST_PARTITION: u64 = 16 * 1024 * 1024;
+/// Boot partition size when the board does not set one
+const DEFAULT_BOOT_SIZE: u64 = 64 * 1024 * 1024;
+const SECTOR: u64 = 512;
+
+/// A physical board a config targets instead of a QEMU machine, e.g.
+/// `board = { dtb = "rockchip/rk3588-rock-5b.dtb", console = "ttyS2,1500000n8" }`
+///
+/// The build then emits an SD card image with a FAT boot partition U-Boot reads
+/// `extlinux/extlinux.conf` from, followed by the root partition.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Board {
+    /// DTB U-Boot hands the kernel, one of the config's `dtbs`
+    pub dtb: String,
+    /// Kernel console on the board's debug UART, e.g. "ttyS2,1500000n8"
+    pub console: String,
+    /// Root device as the board's kernel sees it, defaults to the card's root partition
+    /// by its PARTUUID, which does not depend on how the board numbers its MMC hosts
+    #[serde(default)]
+    pub root_device: Option<String>,
+    /// Size of the boot partition, defaults to 64M
+    #[serde(default)]
+    pub boot_size: Option<String>,
+    /// Bootloader image next to the config, written raw ahead of the first partition
+    #[serde(default)]
+    pub bootloader: Option<String>,
+    /// Offset of the bootloader on the card, e.g. "32K" for Rockchip's idbloader
+    #[serde(default)]
+    pub bootloader_offset: Option<String>,
+}
+
+/// Check the board settings of a manifest
+pub fn validate(manifest: &ConfigManifest, arch_info: &Arch) -> Result<(), String> {
+    let Some(board) = &manifest.board else {
+        return Ok(());
+    };
+    if !arch_info.devicetree {
+        return Err(format!("boards are not supported on {}", arch_info.name));
+    }
+    if !manifest.dtbs.contains(&board.dtb) {
+        return Err(format!("board dtb {} must be one of dtbs", board.dtb));
+    }
+    if board.console.is_empty() || board.console.contains(char::is_whitespace) {
+        return Err(format!(
+            "board console must be a console= value, got {:?}",
+            board.console
+        ));
+    }
+    // The card holds the plain rootfs image only
+    if manifest.verity || manifest.luks {
+        return Err("verity and luks are not supported on boards".to_string());
+    }
+    if let Some(size) = &board.boot_size
+        && !parse_size(size)?.is_multiple_of(1024 * 1024)
+    {
+        return Err(format!("boot_size must be whole megabytes, got {}", size));
+    }
+    match (&board.bootloader, &board.bootloader_offset) {
+        (Some(bootloader), Some(offset)) => {
+            let path = Path::new("config").join(arch_info.name).join(bootloader);
+            if !path.is_file() {
+                return Err(format!("bootloader {} does not exist", path.display()));
+            }
+            let offset = parse_size(offset)?;
+            let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            if offset < SECTOR || offset + size > FIRST_PARTITION {
+                return Err(format!(
+                    "bootloader must fit between the partition table and {}",
+                    format_size(FIRST_PARTITION)
+                ));
+            }
+        }
+        (None, None) => {}
+        _ => return Err("bootloader and bootloader_offset go together".to_string()),
+    }
+    Ok(())
+}
+
+/// SD card image of a board configuration
+pub fn sdcard_image_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("sdcard.img")
+}
+
+/// MBR disk identifier of a configuration's card, derived from its name so that rebuilds
+/// keep the root partition's PARTUUID
+fn disk_id(config_name: &str) -> u32 {
+    let digest = Sha256::digest(config_name.as_bytes());
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+/// Root device on the kernel command line of a board configuration
+pub fn root_device(config_name: &str, board: &Board) -> String {
+    board
+        .root_device
+        .clone()
+        .unwrap_or_else(|| format!("PARTUUID={:08x}-02", disk_id(config_name)))
+}
+
+/// Run a host tool, failing with its name
+fn run(cmd: &mut Command, what: &str) -> Result<(), String> {
+    let status = cmd
+        .status()
+        .map_err(|e| format!("failed to run {}: {}", what, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed: {}", what, status))
+    }
+}
+
+/// Copy a file into the image at `offset`
+fn copy_into(image: &mut File, offset: u64, source: &Path) -> Result<(), String> {
+    let mut source = File::open(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+    image
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| io::copy(&mut source, image))
+        .map(|_| ())
+        .map_err(|e| format!("failed to write image: {}", e))
+}
+
+/// `extlinux/extlinux.conf` of the boot partition
+fn extlinux_conf(config_name: &str, image: &str, dtb: &str, initrd: bool, cmdline: &str) -> String {
+    let mut conf = format!(
+        "# {}, generated by guest-test-linux\ndefault guest\ntimeout 1\n\nlabel guest\n    kernel /{}\n    fdt /{}\n",
+        config_name, image, dtb
+    );
+    if initrd {
+        conf.push_str("    initrd /initramfs.cpio.gz\n");
+    }
+    conf.push_str(&format!("    append {}\n", cmdline));
+    conf
+}
+
+/// Write the SD card image of a board configuration: the bootloader, a FAT boot
+/// partition with the kernel, DTB and extlinux.conf, then the rootfs image
+pub fn write_image(config_name: &str, manifest: &ConfigManifest) -> Result<(), String> {
+    let Some(board) = &manifest.board else {
+        return Ok(());
+    };
+    let (arch, _) = parse_config_name(config_name);
+    let arch_info = arch_of(config_name).ok_or_else(|| format!("unknown arch {}", arch))?;
+    let output_dir = PathBuf::from("build").join(config_name);
+    let kernel = crate::qemu::kernel_image_path(config_name)
+        .filter(|path| path.is_file())
+        .ok_or("kernel image missing")?;
+    let dtb_name = Path::new(&board.dtb)
+        .file_name()
+        .ok_or("board dtb has no file name")?
+        .to_string_lossy()
+        .to_string();
+    // Overlays merged into the board's DTB take its place
+    let merged = crate::dtb::merged_dtb_path(config_name);
+    let dtb = if merged.is_file() {
+        merged
+    } else {
+        output_dir.join(&dtb_name)
+    };
+    let rootfs = crate::qemu::rootfs_image_path(config_name);
+    let initramfs = crate::qemu::initramfs_path(config_name);
+    let root = rootfs.is_file();
+    if !root && !initramfs.is_file() {
+        return Err("neither a rootfs image nor an initramfs was built".to_string());
+    }
+
+    // Boot partition, populated without mounting it
+    let staging = output_dir.join("sdcard");
+    if staging.exists() {
+        fs::remove_dir_all(&staging).map_err(|e| format!("{}: {}", staging.display(), e))?;
+    }
+    fs::create_dir_all(staging.join("extlinux")).map_err(|e| e.to_string())?;
+    let cmdline = crate::cmdline::kernel_cmdline(config_name, manifest);
+    fs::write(
+        staging.join("extlinux").join("extlinux.conf"),
+        extlinux_conf(
+            config_name,
+            arch_info.image_name(),
+            &dtb_name,
+            !root,
+            &cmdline,
+        ),
+    )
+    .map_err(|e| e.to_string())?;
+    let boot_size = match &board.boot_size {
+        Some(size) => parse_size(size)?,
+        None => DEFAULT_BOOT_SIZE,
+    };
+    let boot_img = output_dir.join("boot.vfat");
+    let _ = fs::remove_file(&boot_img);
+    run(
+        Command::new("mkfs.vfat")
+            .args(["-n", "BOOT", "-C"])
+            .arg(&boot_img)
+            .arg((boot_size / 1024).to_string())
+            .stdout(Stdio::null()),
+        "mkfs.vfat",
+    )?;
+    let mut files = vec![kernel, dtb, staging.join("extlinux")];
+    if !root {
+        files.push(initramfs);
+    }
+    for (file, name) in files.iter().zip([
+        arch_info.image_name(),
+        &dtb_name,
+        "extlinux",
+        "initramfs.cpio.gz",
+    ]) {
+        run(
+            Command::new("mcopy")
+                .args(["-s", "-o", "-i"])
+                .arg(&boot_img)
+                .arg(file)
+                .arg(format!("::{}", name)),
+            "mcopy",
+        )?;
+    }
+
+    // Partition table, bootloader and partitions
+    let root_size = if root {
+        let size = fs::metadata(&rootfs).map_err(|e| e.to_string())?.len();
+        size.div_ceil(SECTOR) * SECTOR
+    } else {
+        0
+    };
+    let image_path = sdcard_image_path(config_name);
+    let mut image =
+        File::create(&image_path).map_err(|e| format!("{}: {}", image_path.display(), e))?;
+    image
+        .set_len(FIRST_PARTITION + boot_size + root_size)
+        .map_err(|e| e.to_string())?;
+    let mut table = format!(
+        "label: dos\nlabel-id: {:#010x}\nstart={}, size={}, type=c, bootable\n",
+        disk_id(config_name),
+        FIRST_PARTITION / SECTOR,
+        boot_size / SECTOR
+    );
+    if root {
+        table.push_str(&format!(
+            "start={}, size={}, type=83\n",
+            (FIRST_PARTITION + boot_size) / SECTOR,
+            root_size / SECTOR
+        ));
+    }
+    let mut sfdisk = Command::new("sfdisk")
+        .args(["--quiet", "--no-reread", "--no-tell-kernel"])
+        .arg(&image_path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run sfdisk: {}", e))?;
+    if let Some(mut stdin) = sfdisk.stdin.take() {
+        stdin
+            .write_all(table.as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+    let status = sfdisk.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("sfdisk failed: {}", status));
+    }
+    if let (Some(bootloader), Some(offset)) = (&board.bootloader, &board.bootloader_offset) {
+        let path = Path::new("config").join(&arch).join(bootloader);
+        copy_into(&mut image, parse_size(offset)?, &path)?;
+    }
+    copy_into(&mut image, FIRST_PARTITION, &boot_img)?;
+    if root {
+        copy_into(&mut image, FIRST_PARTITION + boot_size, &rootfs)?;
+    }
+    let _ = fs::remove_file(&boot_img);
+    let _ = fs::remove_dir_all(&staging);
+
+    println!(
+        "SD card image written: {} ({}), root={}",
+        image_path.display(),
+        format_size(FIRST_PARTITION + boot_size + root_size),
+        root_device(config_name, board)
+    );
+    Ok(())
+}
+
+/// Mount points of the running system; the disks behind them are never flashed
+const SYSTEM_MOUNTS: [&str; 5] = ["/", "/boot", "/boot/efi", "/usr", "/home"];
+/// Chunk size of the write and the readback
+const CHUNK: usize = 4 * 1024 * 1024;
+
+/// What `flash` does besides writing the image
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlashOptions {
+    /// Skip typing the device name to confirm
+    pub yes: bool,
+    /// Also write to disks that are not removable, e.g. loop devices
+    pub allow_fixed: bool,
+    /// Grow the root partition and its filesystem to the end of the card
+    pub expand: bool,
+}
+
+/// `/sys/class/block/<name>`
+fn sys_block(name: &str) -> PathBuf {
+    Path::new("/sys/class/block").join(name)
+}
+
+/// Names of the entries of a sysfs directory
+fn sys_entries(dir: &Path) -> Vec<String> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whole disks under a block device, through partitions, device-mapper and md
+fn disks_of(name: &str) -> Vec<String> {
+    let sys = sys_block(name);
+    let slaves = sys_entries(&sys.join("slaves"));
+    if !slaves.is_empty() {
+        return slaves.iter().flat_map(|slave| disks_of(slave)).collect();
+    }
+    if sys.join("partition").exists()
+        && let Ok(path) = sys.canonicalize()
+        && let Some(parent) = path.parent().and_then(Path::file_name)
+    {
+        return vec![parent.to_string_lossy().to_string()];
+    }
+    vec![name.to_string()]
+}
+
+/// Block device name of a `/dev` path, e.g. `sda1` for `/dev/disk/by-label/root`
+fn device_name(path: &str) -> Option<String> {
+    if !path.starts_with("/dev/") {
+        return None;
+    }
+    let path = Path::new(path).canonicalize().ok()?;
+    Some(path.file_name()?.to_string_lossy().to_string())
+}
+
+/// Devices of the mounted filesystems with their mount points
+fn mounts() -> Vec<(String, String)> {
+    fs::read_to_string("/proc/mounts")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = device_name(fields.next()?)?;
+            Some((device, fields.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// Devices swapped to
+fn swaps() -> Vec<String> {
+    fs::read_to_string("/proc/swaps")
+        .unwrap_or_default()
+        .lines()
+        .skip(1)
+        .filter_map(|line| device_name(line.split_whitespace().next()?))
+        .collect()
+}
+
+/// Disks the running system's root, /boot, /usr, /home and swap live on
+fn system_disks() -> Vec<String> {
+    let mut devices: Vec<String> = mounts()
+        .into_iter()
+        .filter(|(_, target)| SYSTEM_MOUNTS.contains(&target.as_str()))
+        .map(|(device, _)| device)
+        .chain(swaps())
+        .collect();
+    // The root's device number also finds it when /proc/mounts names no device node
+    if let Ok(metadata) = fs::metadata("/") {
+        let dev = metadata.dev();
+        let sys = format!("/sys/dev/block/{}:{}", libc::major(dev), libc::minor(dev));
+        if let Ok(path) = Path::new(&sys).canonicalize()
+            && let Some(name) = path.file_name()
+        {
+            devices.push(name.to_string_lossy().to_string());
+        }
+    }
+    let mut disks: Vec<String> = devices.iter().flat_map(|device| disks_of(device)).collect();
+    disks.sort();
+    disks.dedup();
+    disks
+}
+
+/// Whether a disk takes removable media: flagged removable, an SD/MMC slot or on USB
+fn removable(name: &str) -> bool {
+    let sys = sys_block(name);
+    fs::read_to_string(sys.join("removable")).is_ok_and(|flag| flag.trim() == "1")
+        || name.starts_with("mmcblk")
+        || sys
+            .canonicalize()
+            .is_ok_and(|path| path.to_string_lossy().contains("/usb"))
+}
+
+/// Why a disk cannot be written now: a partition of it is mounted, swapped to or held
+/// by device-mapper or md
+fn in_use(disk: &str) -> Option<String> {
+    let mut devices = vec![disk.to_string()];
+    devices.extend(
+        sys_entries(&sys_block(disk))
+            .into_iter()
+            .filter(|entry| entry.starts_with(disk) && sys_block(entry).join("partition").exists()),
+    );
+    let mounts = mounts();
+    let swaps = swaps();
+    devices.iter().find_map(|device| {
+        if let Some((_, target)) = mounts.iter().find(|(mounted, _)| mounted == device) {
+            return Some(format!(
+                "/dev/{} is mounted on {}; unmount it first",
+                device, target
+            ));
+        }
+        if swaps.contains(device) {
+            return Some(format!("/dev/{} is in use as swap", device));
+        }
+        let holders = sys_entries(&sys_block(device).join("holders"));
+        (!holders.is_empty()).then(|| format!("/dev/{} is held by {}", device, holders.join(", ")))
+    })
+}
+
+/// Device node of a partition of a disk, e.g. `/dev/mmcblk0p2` or `/dev/sdb2`
+fn partition_device(disk: &str, number: u32) -> PathBuf {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        PathBuf::from(format!("/dev/{}p{}", disk, number))
+    } else {
+        PathBuf::from(format!("/dev/{}{}", disk, number))
+    }
+}
+
+/// Copy `size` bytes from `reader` to `writer` in chunks, printing progress on a single
+/// stderr line, and return their SHA-256
+fn copy_hashed(
+    what: &str,
+    reader: &mut impl Read,
+    mut writer: Option<&mut File>,
+    size: u64,
+) -> io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK];
+    let mut reader = reader.take(size);
+    let started = Instant::now();
+    let mut done = 0u64;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        if let Some(writer) = writer.as_mut() {
+            writer.write_all(&buf[..n])?;
+        }
+        done += n as u64;
+        let rate = done as f64 / started.elapsed().as_secs_f64().max(0.001) / 1048576.0;
+        eprint!(
+            "\r  {} {:>3}% {:.1}/{:.1} MiB {:.1} MiB/s   ",
+            what,
+            (done * 100).checked_div(size).unwrap_or(100),
+            done as f64 / 1048576.0,
+            size as f64 / 1048576.0,
+            rate
+        );
+        let _ = io::stderr().flush();
+    }
+    eprintln!();
+    if done < size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("only {} of {} bytes", done, size),
+        ));
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Write the image to the device and read it back, failing when the two differ
+fn write_verified(image: &Path, device: &Path, size: u64) -> Result<(), String> {
+    let mut source = File::open(image).map_err(|e| format!("{}: {}", image.display(), e))?;
+    // Synchronous writes, so that the progress is the card's and not the page cache's
+    let mut target = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_DSYNC)
+        .open(device)
+        .map_err(|e| format!("{}: {}", device.display(), e))?;
+    let written = copy_hashed("write", &mut source, Some(&mut target), size)
+        .map_err(|e| format!("failed to write {}: {}", device.display(), e))?;
+    target.sync_all().map_err(|e| e.to_string())?;
+
+    let mut target = File::open(device).map_err(|e| format!("{}: {}", device.display(), e))?;
+    // Drop the device's cached pages so that the readback comes from the card
+    unsafe {
+        libc::posix_fadvise(target.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+    let read = copy_hashed("verify", &mut target, None, size)
+        .map_err(|e| format!("failed to read back {}: {}", device.display(), e))?;
+    if read != written {
+        return Err(format!(
+            "{} reads back different data than was written; the card may be faulty",
+            device.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Grow the root partition of a flashed card to its end, then its filesystem
+fn expand_root(disk: &str, device: &Path) -> Result<(), String> {
+    let mut sfdisk = Command::new("sfdisk")
+        .args(["--quiet", "-N", "2"])
+        .arg(device)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run sfdisk: {}", e))?;
+    if let Some(mut stdin) = sfdisk.stdin.take() {
+        stdin.write_all(b",+\n").map_err(|e| e.to_string())?;
+    }
+    let status = sfdisk.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("sfdisk failed: {}", status));
+    }
+
+    let partition = partition_device(disk, 2);
+    // The kernel re-reads the table, and udev recreates the node, after sfdisk exits
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !partition.exists() {
+        if Instant::now() > deadline {
+            return Err(format!("{} did not appear", partition.display()));
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    // e2fsck exits with 1 when it corrected something
+    let status = Command::new("e2fsck")
+        .args(["-f", "-p"])
+        .arg(&partition)
+        .status()
+        .map_err(|e| format!("failed to run e2fsck: {}", e))?;
+    if !matches!(status.code(), Some(0 | 1)) {
+        return Err(format!("e2fsck failed: {}", status));
+    }
+    run(Command::new("resize2fs").arg(&partition), "resize2fs")
+}
+
+/// Write a board configuration's SD card image to a block device
+///
+/// The device must be a whole removable disk (unless `allow_fixed`), not back the running
+/// system and not be in use; unless `yes` is set its name has to be typed in to confirm.
+/// What was written is read back and compared.
+pub fn flash(config_name: &str, device: &Path, options: FlashOptions) -> bool {
+    let Ok(manifest) = load_manifest(config_name) else {
+        eprintln!("Invalid manifest for configuration {}", config_name);
+        return false;
+    };
+    if manifest.board.is_none() {
+        eprintln!("{} does not target a board", config_name);
+        return false;
+    }
+    let image = sdcard_image_path(config_name);
+    let Ok(image_size) = fs::metadata(&image).map(|m| m.len()) else {
+        eprintln!(
+            "{} is missing, build {} first",
+            image.display(),
+            config_name
+        );
+        return false;
+    };
+    if options.expand && !crate::qemu::rootfs_image_path(config_name).is_file() {
+        eprintln!("{} has no root partition to expand", config_name);
+        return false;
+    }
+    if options.expand && manifest.image_format.read_only() {
+        eprintln!(
+            "{} root filesystems cannot grow, flash without --expand",
+            manifest.image_format.name()
+        );
+        return false;
+    }
+
+    let device = match device.canonicalize() {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("{}: {}", device.display(), e);
+            return false;
+        }
+    };
+    if !fs::metadata(&device).is_ok_and(|m| m.file_type().is_block_device()) {
+        eprintln!("{} is not a block device", device.display());
+        return false;
+    }
+    let name = device
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let sys = sys_block(&name);
+    if sys.join("partition").exists() {
+        eprintln!(
+            "{} is a partition; flash the whole disk /dev/{}",
+            device.display(),
+            disks_of(&name).join(", /dev/")
+        );
+        return false;
+    }
+    if system_disks().contains(&name) {
+        eprintln!(
+            "{} holds the running system; refusing to overwrite it",
+            device.display()
+        );
+        return false;
+    }
+    if !options.allow_fixed && !removable(&name) {
+        eprintln!(
+            "{} is not a removable disk; pass --allow-fixed if it really is the card",
+            device.display()
+        );
+        return false;
+    }
+    if let Some(reason) = in_use(&name) {
+        eprintln!("{}", reason);
+        return false;
+    }
+    let device_size = fs::read_to_string(sys.join("size"))
+        .ok()
+        .and_then(|sectors| sectors.trim().parse::<u64>().ok())
+        .map(|sectors| sectors * SECTOR);
+    if let Some(device_size) = device_size
+        && device_size < image_size
+    {
+        eprintln!(
+            "{} ({}) is smaller than the image ({})",
+            device.display(),
+            format_size(device_size),
+            format_size(image_size)
+        );
+        return false;
+    }
+
+    if let Err(e) = OpenOptions::new().write(true).open(&device) {
+        eprintln!("Cannot write {}: {}", device.display(), e);
+        return false;
+    }
+    if !options.yes {
+        let model = fs::read_to_string(sys.join("device").join("model")).unwrap_or_default();
+        println!(
+            "This overwrites everything on {}{}{}. Type its name to continue:",
+            device.display(),
+            device_size
+                .map(|size| format!(" ({})", format_size(size)))
+                .unwrap_or_default(),
+            if model.trim().is_empty() {
+                String::new()
+            } else {
+                format!(", {}", model.trim())
+            }
+        );
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer).is_err()
+            || answer.trim().trim_start_matches("/dev/") != name
+        {
+            eprintln!("Not confirmed, nothing written.");
+            return false;
+        }
+    }
+
+    println!("Writing {} to {}...", image.display(), device.display());
+    if let Err(e) = write_verified(&image, &device, image_size) {
+        eprintln!("{}", e);
+        return false;
+    }
+    if options.expand {
+        println!("Expanding the root partition...");
+        if let Err(e) = expand_root(&name, &device) {
+            eprintln!("Failed to expand the root partition: {}", e);
+            return false;
+        }
+    }
+    println!(
+        "{} flashed to {} and verified",
+        config_name,
+        device.display()
+    );
+    true
+}
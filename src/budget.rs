@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigManifest;
+use crate::error::BuildError;
+use crate::memory::parse_size;
+
+/// Size and boot-time limits of a configuration, `[budget]` in its manifest
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Budget {
+    /// Largest kernel image, e.g. "24M"
+    pub kernel_size: Option<String>,
+    /// Largest rootfs: the staged tree, or the initramfs archive for `initramfs = true`
+    pub rootfs_size: Option<String>,
+    /// Longest time from starting the guest to its first test, in seconds
+    pub boot_secs: Option<f64>,
+    /// Fail the build or test run instead of warning when a budget is exceeded
+    pub enforce: bool,
+}
+
+impl Budget {
+    /// Check the declared limits of a manifest
+    pub fn validate(&self) -> Result<(), String> {
+        for size in [&self.kernel_size, &self.rootfs_size].into_iter().flatten() {
+            parse_size(size).map_err(|e| format!("budget: {}", e))?;
+        }
+        if self.boot_secs.is_some_and(|secs| secs <= 0.0) {
+            return Err("budget: boot_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Warn about or, when enforced, fail on the exceeded budgets
+    fn judge(&self, exceeded: Vec<String>) -> Result<(), String> {
+        if exceeded.is_empty() {
+            return Ok(());
+        }
+        let message = format!("over budget: {}", exceeded.join(", "));
+        if self.enforce {
+            Err(message)
+        } else {
+            eprintln!("Warning: {}", message);
+            Ok(())
+        }
+    }
+}
+
+/// Measured sizes and boot time, stored in the build manifest
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Measurements {
+    /// Kernel image size in bytes
+    pub kernel_size: Option<u64>,
+    /// Rootfs size in bytes, measured as for `budget.rootfs_size`
+    pub rootfs_size: Option<u64>,
+    /// Boot time of the latest `test` run in seconds
+    pub boot_secs: Option<f64>,
+}
+
+/// Apparent size of a directory tree in bytes
+pub fn tree_size(dir: &Path) -> Option<u64> {
+    let output = Command::new("du").arg("-sb").arg(dir).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// `what` measured against a size limit, if it is over
+fn size_over(what: &str, measured: Option<u64>, limit: &Option<String>) -> Option<String> {
+    let limit_bytes = parse_size(limit.as_deref()?).ok()?;
+    let measured = measured?;
+    (measured > limit_bytes).then(|| {
+        format!(
+            "{} is {} bytes, budget {}",
+            what,
+            measured,
+            limit.as_deref().unwrap_or_default()
+        )
+    })
+}
+
+/// Measure the built kernel and rootfs, record them in the build manifest and check the budget
+pub fn check_build(config_name: &str, manifest: &ConfigManifest) -> Result<(), BuildError> {
+    let kernel_size = crate::qemu::kernel_image_path(config_name)
+        .and_then(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len());
+    let rootfs_size = if manifest.initramfs {
+        fs::metadata(crate::qemu::initramfs_path(config_name))
+            .ok()
+            .map(|metadata| metadata.len())
+    } else {
+        tree_size(&Path::new("build").join(config_name).join("rootfs"))
+    };
+    for (what, size) in [("Kernel image", kernel_size), ("Rootfs", rootfs_size)] {
+        if let Some(size) = size {
+            println!("{}: {} bytes", what, size);
+        }
+    }
+    crate::manifest::update_manifest(config_name, |build| {
+        build.measurements.kernel_size = kernel_size;
+        build.measurements.rootfs_size = rootfs_size;
+    });
+
+    let budget = &manifest.budget;
+    let exceeded = [
+        size_over("kernel image", kernel_size, &budget.kernel_size),
+        size_over("rootfs", rootfs_size, &budget.rootfs_size),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    budget.judge(exceeded).map_err(BuildError::Step)
+}
+
+/// Record a test run's boot time and check it against the budget, for `TestRun::host_check`
+pub fn check_boot(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    boot_secs: f64,
+) -> Result<String, String> {
+    crate::manifest::update_manifest(config_name, |build| {
+        build.measurements.boot_secs = Some(boot_secs);
+    });
+    let budget = &manifest.budget;
+    let exceeded = budget
+        .boot_secs
+        .filter(|limit| boot_secs > *limit)
+        .map(|limit| format!("boot took {:.1}s, budget {:.1}s", boot_secs, limit));
+    budget.judge(exceeded.into_iter().collect())?;
+    Ok(format!("{:.1}s", boot_secs))
+}
@@ -0,0 +1,396 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::cache::ArtifactCache;
+use crate::download::{KernelSelection, KernelSource};
+use crate::kernel::build_linux_for_config;
+use crate::stages::StageSelection;
+
+/// Prefix of the line `build` prints when a configuration fails
+const FAILED_PREFIX: &str = "Build failed for configuration ";
+
+/// Outcome of building one configuration
+struct BuildOutcome {
+    config: String,
+    duration: Duration,
+    error: Option<String>,
+}
+
+/// What a child build last reported on its output
+#[derive(Default)]
+struct Progress {
+    stage: String,
+    error: Option<String>,
+}
+
+/// A configuration building in a child process
+struct Running {
+    index: usize,
+    child: Child,
+    started: Instant,
+    progress: Arc<Mutex<Progress>>,
+    reader: JoinHandle<()>,
+}
+
+/// Where a configuration is in a parallel `build-all`
+enum State {
+    Pending,
+    Running(Instant),
+    Done(BuildOutcome),
+}
+
+/// Build every configuration, then print a summary; returns false if any failed
+///
+/// A failing configuration does not stop the others, so one run shows every breakage.
+/// With `jobs_per_config`, as many configurations as the CPUs fit at that many jobs
+/// each build at once, each in its own process.
+pub fn build_all(
+    configs: &[String],
+    selection: &KernelSelection,
+    cache: Option<&str>,
+    strict: bool,
+    jobs_per_config: Option<usize>,
+) -> bool {
+    if configs.is_empty() {
+        eprintln!("No configurations to build.");
+        return false;
+    }
+
+    let slots = jobs_per_config.map_or(1, |jobs| (crate::system::num_cpus() / jobs.max(1)).max(1));
+    let outcomes = match jobs_per_config {
+        Some(jobs) if slots > 1 && configs.len() > 1 => {
+            build_parallel(configs, selection, cache, strict, jobs, slots)
+        }
+        _ => build_serial(configs, selection, cache, strict),
+    };
+
+    let width = configs.iter().map(String::len).max().unwrap_or(0).max(6);
+    println!();
+    println!(
+        "{:<width$}  {:<6}  {:>8}  ERROR",
+        "CONFIG", "RESULT", "TIME"
+    );
+    for outcome in &outcomes {
+        println!(
+            "{:<width$}  {:<6}  {:>7.0}s  {}",
+            outcome.config,
+            crate::output::status(outcome.error.is_none(), 6),
+            outcome.duration.as_secs_f64(),
+            outcome.error.as_deref().unwrap_or("")
+        );
+    }
+    let failed = outcomes.iter().filter(|o| o.error.is_some()).count();
+    println!(
+        "{} of {} configurations built, {} failed",
+        outcomes.len() - failed,
+        outcomes.len(),
+        failed
+    );
+    failed == 0
+}
+
+/// Build each configuration in turn in this process
+fn build_serial(
+    configs: &[String],
+    selection: &KernelSelection,
+    cache: Option<&str>,
+    strict: bool,
+) -> Vec<BuildOutcome> {
+    let cache = cache.map(ArtifactCache::from_location);
+    let mut outcomes = Vec::new();
+    for (i, config) in configs.iter().enumerate() {
+        crate::output::heading(&format!(
+            "Building configuration {} ({}/{})",
+            config,
+            i + 1,
+            configs.len()
+        ));
+        let started = Instant::now();
+        let error = build_linux_for_config(
+            config,
+            selection,
+            &StageSelection::default(),
+            cache.as_ref(),
+            strict,
+        )
+        .err()
+        .map(|e| e.to_string());
+        if let Some(e) = &error {
+            eprintln!("Build failed for configuration {}: {}", config, e);
+        }
+        outcomes.push(BuildOutcome {
+            config: config.clone(),
+            duration: started.elapsed(),
+            error,
+        });
+    }
+    outcomes
+}
+
+/// Log a child build's output goes to
+fn build_log(config_name: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("logs")
+        .join("build-all.log")
+}
+
+/// Fetch the kernel refs the configurations use other than the default tree, once each,
+/// so that concurrent builds do not fetch the same ref into the same directory
+fn fetch_kernel_refs(configs: &[String], selection: &KernelSelection) {
+    let mut fetched = HashSet::new();
+    for config in configs {
+        let Ok(manifest) = crate::config::load_manifest(config) else {
+            continue;
+        };
+        let (arch, _) = crate::config::parse_config_name(config);
+        let source = KernelSource::select(selection, &manifest, &arch);
+        if source.is_default() || !fetched.insert(source.dir()) {
+            continue;
+        }
+        // A failed fetch fails the configuration's own build, which reports it
+        let _ = crate::output::stage(&format!("kernel source {}", source.git_ref), || {
+            source.fetch()
+        });
+    }
+}
+
+/// Start `build <config>` as a child process whose output goes to its log, noting its
+/// current stage and failure as they come by
+fn spawn_build(
+    config: &str,
+    selection: &KernelSelection,
+    cache: Option<&str>,
+    strict: bool,
+    jobs: usize,
+) -> io::Result<(Child, Arc<Mutex<Progress>>, JoinHandle<()>)> {
+    let log = build_log(config);
+    if let Some(dir) = log.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = File::create(&log)?;
+
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.args(["build", config]);
+    if let Some(kernel_ref) = &selection.kernel_ref {
+        cmd.args(["--kernel-ref", kernel_ref]);
+    }
+    if let Some(kernel_repo) = &selection.kernel_repo {
+        cmd.args(["--kernel-repo", kernel_repo]);
+    }
+    if strict {
+        cmd.arg("--strict");
+    }
+    if let Some(cache) = cache {
+        cmd.env(crate::cache::CACHE_ENV, cache);
+    }
+    if crate::download::offline() {
+        cmd.env("GUEST_TEST_OFFLINE", "true");
+    }
+    let (reader, writer) = io::pipe()?;
+    let child = cmd
+        .env(crate::system::JOBS_ENV, jobs.to_string())
+        .stdin(Stdio::null())
+        .stdout(writer.try_clone()?)
+        .stderr(writer)
+        .spawn()?;
+    // The command holds the write ends until dropped; the pipe closes when the child exits
+    drop(cmd);
+
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    let shared = Arc::clone(&progress);
+    let reader = thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let _ = writeln!(file, "{}", line);
+            let mut progress = shared.lock().unwrap();
+            if let Some((_, stage)) = line.split_once("==> ") {
+                progress.stage = stage.trim().to_string();
+            } else if let Some(rest) = line.strip_prefix(FAILED_PREFIX)
+                && let Some((_, error)) = rest.split_once(": ")
+            {
+                progress.error = Some(error.to_string());
+            }
+        }
+    });
+    Ok((child, progress, reader))
+}
+
+/// Status line of a configuration
+fn status_line(config: &str, width: usize, state: &State, stage: &str) -> String {
+    match state {
+        State::Pending => format!("  {:<width$}  waiting", config),
+        State::Running(started) => format!(
+            "  {:<width$}  {:<6}  {:>5}s  {}",
+            config,
+            "..",
+            started.elapsed().as_secs(),
+            stage
+        ),
+        State::Done(outcome) => format!(
+            "  {:<width$}  {}  {:>5.0}s  {}",
+            config,
+            crate::output::status(outcome.error.is_none(), 6),
+            outcome.duration.as_secs_f64(),
+            outcome.error.as_deref().unwrap_or("")
+        ),
+    }
+}
+
+/// Build the configurations in child processes, `slots` at a time with `jobs` make jobs
+/// each, showing a live status line per configuration
+///
+/// Each build's output goes to `build/<config>/logs/build-all.log`, next to its stage logs.
+fn build_parallel(
+    configs: &[String],
+    selection: &KernelSelection,
+    cache: Option<&str>,
+    strict: bool,
+    jobs: usize,
+    slots: usize,
+) -> Vec<BuildOutcome> {
+    fetch_kernel_refs(configs, selection);
+    crate::output::heading(&format!(
+        "Building {} configurations, {} at a time with {} jobs each",
+        configs.len(),
+        slots,
+        jobs
+    ));
+
+    let width = configs.iter().map(String::len).max().unwrap_or(0);
+    let interactive = crate::output::interactive();
+    let mut states: Vec<State> = configs.iter().map(|_| State::Pending).collect();
+    let mut running: Vec<Running> = Vec::new();
+    let mut next = 0;
+    // Running builds' lines on the terminal, and the last line printed per configuration
+    let mut drawn = 0;
+    let mut reported = vec![String::new(); configs.len()];
+    loop {
+        while running.len() < slots && next < configs.len() {
+            let config = &configs[next];
+            match spawn_build(config, selection, cache, strict, jobs) {
+                Ok((child, progress, reader)) => {
+                    let started = Instant::now();
+                    states[next] = State::Running(started);
+                    running.push(Running {
+                        index: next,
+                        child,
+                        started,
+                        progress,
+                        reader,
+                    });
+                }
+                Err(e) => {
+                    states[next] = State::Done(BuildOutcome {
+                        config: config.clone(),
+                        duration: Duration::ZERO,
+                        error: Some(format!("failed to start build: {}", e)),
+                    });
+                }
+            }
+            next += 1;
+        }
+
+        let mut i = 0;
+        while i < running.len() {
+            let exited = match running[i].child.try_wait() {
+                Ok(status) => status,
+                Err(e) => {
+                    eprintln!("Failed to wait for {}: {}", configs[running[i].index], e);
+                    let _ = running[i].child.kill();
+                    running[i].child.wait().ok()
+                }
+            };
+            let Some(status) = exited else {
+                i += 1;
+                continue;
+            };
+            let build = running.swap_remove(i);
+            let _ = build.reader.join();
+            let config = &configs[build.index];
+            let error = (!status.success()).then(|| {
+                build
+                    .progress
+                    .lock()
+                    .unwrap()
+                    .error
+                    .take()
+                    .unwrap_or_else(|| format!("{}, see {}", status, build_log(config).display()))
+            });
+            states[build.index] = State::Done(BuildOutcome {
+                config: config.clone(),
+                duration: build.started.elapsed(),
+                error,
+            });
+        }
+
+        let stages: Vec<String> = (0..configs.len())
+            .map(|index| {
+                running
+                    .iter()
+                    .find(|r| r.index == index)
+                    .map(|r| r.progress.lock().unwrap().stage.clone())
+                    .unwrap_or_default()
+            })
+            .collect();
+        // Clear the running builds' lines to redraw them below the new results
+        if drawn > 0 {
+            print!("\x1b[{}A\x1b[J", drawn);
+        }
+        for (index, state) in states.iter().enumerate() {
+            // Without a terminal to redraw on, stage changes are printed as they happen
+            let line = match state {
+                State::Running(_) if !interactive && !stages[index].is_empty() => {
+                    format!("{}: {}", configs[index], stages[index])
+                }
+                State::Done(_) => status_line(&configs[index], width, state, "")
+                    .trim_start()
+                    .to_string(),
+                _ => continue,
+            };
+            if reported[index] != line {
+                println!("{} {}", crate::output::timestamp(), line);
+                reported[index] = line;
+            }
+        }
+        if interactive {
+            drawn = 0;
+            for (index, state) in states.iter().enumerate() {
+                if let State::Running(_) = state {
+                    println!(
+                        "{}",
+                        status_line(&configs[index], width, state, &stages[index])
+                    );
+                    drawn += 1;
+                }
+            }
+            let waiting = configs.len() - next;
+            if waiting > 0 {
+                println!("  {} waiting", waiting);
+                drawn += 1;
+            }
+        }
+        let _ = io::stdout().flush();
+
+        if running.is_empty() && next >= configs.len() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+
+    states
+        .into_iter()
+        .filter_map(|state| match state {
+            State::Done(outcome) => Some(outcome),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,178 @@
+use std::path::PathBuf;
+
+use crate::cache::ArtifactCache;
+use crate::config::{is_valid_config, load_manifest};
+use crate::download::{KernelSelection, SourceMode, SourceOptions, fetch_sources};
+use crate::error::BuildError;
+use crate::kernel::build_linux_for_config;
+use crate::manifest::BuildManifest;
+use crate::stages::{Stage, StageSelection};
+
+/// Build of one configuration, the library counterpart of `build <config>`
+///
+/// ```no_run
+/// let artifacts = guest_test_linux::Builder::new("arm64-qemu")
+///     .kernel_ref("v6.13")
+///     .build()?;
+/// println!("{:?}", artifacts.kernel_image());
+/// # Ok::<(), guest_test_linux::BuildError>(())
+/// ```
+///
+/// Paths are relative to the working directory, which must be the repository root as
+/// for the CLI. Unlike `build`, no script lint runs first.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    config: String,
+    kernel: KernelSelection,
+    sources: SourceOptions,
+    stages: Vec<Stage>,
+    cache: Option<String>,
+    strict: bool,
+}
+
+impl Builder {
+    pub fn new(config: impl Into<String>) -> Self {
+        Builder {
+            config: config.into(),
+            kernel: KernelSelection::default(),
+            sources: SourceOptions::default(),
+            stages: Vec::new(),
+            cache: None,
+            strict: false,
+        }
+    }
+
+    /// Kernel tag, branch or commit instead of the config's `kernel_ref`
+    pub fn kernel_ref(mut self, git_ref: impl Into<String>) -> Self {
+        self.kernel.kernel_ref = Some(git_ref.into());
+        self
+    }
+
+    /// Kernel repository to fetch the ref from instead of the config's `kernel_repo`
+    pub fn kernel_repo(mut self, repo: impl Into<String>) -> Self {
+        self.kernel.kernel_repo = Some(repo.into());
+        self
+    }
+
+    /// Fetch the default sources as release tarballs instead of git clones
+    pub fn tarball_sources(mut self) -> Self {
+        self.sources.source = SourceMode::Tarball;
+        self
+    }
+
+    /// Linux source tree or release tarball to use as `linux/`
+    pub fn kernel_src(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sources.kernel_src = Some(path.into());
+        self
+    }
+
+    /// BusyBox source tree or release tarball to use as `busybox/`
+    pub fn busybox_src(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sources.busybox_src = Some(path.into());
+        self
+    }
+
+    /// Only run these stages, reusing the others' earlier outputs
+    pub fn only(mut self, stages: impl IntoIterator<Item = Stage>) -> Self {
+        self.stages = stages.into_iter().collect();
+        self
+    }
+
+    /// Artifact cache directory or http(s) URL
+    pub fn cache(mut self, location: impl Into<String>) -> Self {
+        self.cache = Some(location.into());
+        self
+    }
+
+    /// Fail when the rootfs audit finds setuid, world-writable or leaked host files
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Fetch the sources the configuration needs and build it
+    pub fn build(self) -> Result<Artifacts, BuildError> {
+        if !is_valid_config(&self.config) {
+            return Err(BuildError::Config(format!(
+                "invalid configuration: {}",
+                self.config
+            )));
+        }
+        let stages = StageSelection::only(self.stages);
+        let bare = load_manifest(&self.config).is_ok_and(|manifest| manifest.bare);
+        if !fetch_sources(&self.sources, !bare && stages.runs(Stage::Busybox)) {
+            return Err(BuildError::Step("failed to fetch sources".to_string()));
+        }
+        let cache = self.cache.as_deref().map(ArtifactCache::from_location);
+        build_linux_for_config(
+            &self.config,
+            &self.kernel,
+            &stages,
+            cache.as_ref(),
+            self.strict,
+        )?;
+        Ok(Artifacts::of(&self.config))
+    }
+}
+
+/// Outputs of a built configuration under `build/<config>/`; the accessors return only
+/// files that exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifacts {
+    config: String,
+}
+
+impl Artifacts {
+    /// Outputs of a configuration built earlier
+    pub fn of(config: &str) -> Self {
+        Artifacts {
+            config: config.to_string(),
+        }
+    }
+
+    pub fn config(&self) -> &str {
+        &self.config
+    }
+
+    /// `build/<config>/`
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from("build").join(&self.config)
+    }
+
+    pub fn kernel_image(&self) -> Option<PathBuf> {
+        crate::qemu::kernel_image_path(&self.config).filter(|path| path.is_file())
+    }
+
+    pub fn rootfs_image(&self) -> Option<PathBuf> {
+        existing(crate::qemu::rootfs_image_path(&self.config))
+    }
+
+    pub fn initramfs(&self) -> Option<PathBuf> {
+        existing(crate::qemu::initramfs_path(&self.config))
+    }
+
+    /// DTB a hypervisor hands the guest: merged overlays, QEMU's DTB or the first kernel DTB
+    pub fn dtb(&self) -> Option<PathBuf> {
+        let manifest = load_manifest(&self.config).ok()?;
+        crate::dtb::guest_dtb_path(&self.config, &manifest)
+    }
+
+    /// SD card image of a board configuration
+    pub fn sdcard_image(&self) -> Option<PathBuf> {
+        existing(crate::board::sdcard_image_path(&self.config))
+    }
+
+    /// Every output `verify` checks, as listed in the build manifest
+    pub fn all(&self) -> Vec<PathBuf> {
+        crate::manifest::artifact_paths(&self.config)
+    }
+
+    /// The build manifest: versions, source commit, checksums and what the guest expects
+    pub fn manifest(&self) -> Option<BuildManifest> {
+        crate::manifest::read_manifest(&self.config)
+    }
+}
+
+fn existing(path: PathBuf) -> Option<PathBuf> {
+    path.is_file().then_some(path)
+}
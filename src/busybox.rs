@@ -1,68 +1,87 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use crate::system::num_cpus;
+use crate::system::make_jobs;
+use crate::cache::ArtifactCache;
+use crate::download::{BUSYBOX_GIT_URL, BUSYBOX_REF};
+use crate::error::{BuildError, run};
 use crate::rootfs::create_rootfs_for_config;
+use crate::stages::{Stage, StageSelection};
 
 /// Download busybox if not exists
-pub fn download_busybox() {
+pub fn download_busybox() -> Result<(), BuildError> {
     let busybox_dir = Path::new("busybox");
     if busybox_dir.exists() {
         println!("Busybox source already exists, skipping download.");
-        return;
+        return crate::sources_lock::verify_checkout("busybox").map_err(BuildError::Step);
     }
 
-    println!("Cloning busybox 1_36_1 source code from Gitee...");
-    let status = Command::new("git")
-        .args([
+    if crate::download::offline() {
+        return Err(BuildError::Step("busybox/ is missing and --offline forbids cloning it; pass --busybox-src".to_string()));
+    }
+
+    println!("Cloning busybox {} source code from Gitee...", BUSYBOX_REF);
+    run(
+        Command::new("git").args([
             "clone",
             "--depth=1",
             "-b",
-            "1_36_1",
-            "https://gitee.com/mirrors_addons/busybox.git",
+            BUSYBOX_REF,
+            BUSYBOX_GIT_URL,
             "busybox",
-        ])
-        .status()
-        .expect("Failed to clone busybox");
-
-    if !status.success() {
-        eprintln!("Failed to clone busybox from Gitee");
-        return;
+        ]),
+        "cloning busybox from Gitee",
+    )?;
+    // Nothing may build from a tree that is not the pinned one
+    if let Err(e) = crate::sources_lock::verify_checkout("busybox") {
+        let _ = fs::remove_dir_all(busybox_dir);
+        return Err(BuildError::Step(e));
     }
 
     println!("Busybox clone completed.");
+    Ok(())
 }
 
 /// Build busybox and create rootfs for a specific configuration
-pub fn build_busybox_and_rootfs_for_config(config_name: &str, arch: &str, kernel_arch: &str, cross_compile_prefix: &Option<String>) {
+pub fn build_busybox_and_rootfs_for_config(config_name: &str, arch: &str, kernel_arch: &str, cross_compile_prefix: &Option<String>, stages: &StageSelection, cache: Option<&ArtifactCache>, strict: bool) -> Result<(), BuildError> {
     println!(
         "Starting busybox build and rootfs creation for configuration: {}",
         config_name
     );
 
-    // Download busybox if not exists
-    download_busybox();
+    if stages.runs(Stage::Busybox) {
+        crate::output::stage("busybox", || {
+            // Download busybox if not exists
+            download_busybox()?;
 
-    // Build busybox
-    build_busybox_for_config(config_name, cross_compile_prefix);
+            // Build busybox
+            build_busybox_for_config(config_name, cross_compile_prefix, cache)
+        })?;
+    }
 
     // Create rootfs
-    create_rootfs_for_config(config_name, arch, kernel_arch, cross_compile_prefix);
+    if stages.runs(Stage::Rootfs) {
+        crate::output::stage("rootfs", || {
+            create_rootfs_for_config(config_name, arch, kernel_arch, cross_compile_prefix, strict)
+        })?;
+    }
+    Ok(())
 }
 
 /// Build busybox for a specific configuration
-fn build_busybox_for_config(config_name: &str, cross_compile_prefix: &Option<String>) {
+fn build_busybox_for_config(config_name: &str, cross_compile_prefix: &Option<String>, cache: Option<&ArtifactCache>) -> Result<(), BuildError> {
     println!("Building busybox for configuration: {}", config_name);
 
     let busybox_dir = "busybox";
     let build_dir = PathBuf::from("build").join(config_name).join("busybox");
 
     // Create build directory
-    fs::create_dir_all(&build_dir).expect("Failed to create busybox build directory");
+    fs::create_dir_all(&build_dir).map_err(BuildError::io("failed to create busybox build directory"))?;
+    let build_dir = build_dir.canonicalize().map_err(BuildError::io("failed to resolve busybox build directory"))?;
 
     // Configure busybox with default config
     let mut make_args = vec![
-        format!("O={}", build_dir.canonicalize().unwrap().display()),
+        format!("O={}", build_dir.display()),
         "defconfig".to_string(),
     ];
 
@@ -71,21 +90,12 @@ fn build_busybox_for_config(config_name: &str, cross_compile_prefix: &Option<Str
     }
 
     println!("Configuring busybox...");
-    let status = Command::new("make")
-        .current_dir(busybox_dir)
-        .args(&make_args)
-        .status()
-        .expect("Failed to configure busybox");
-
-    if !status.success() {
-        eprintln!("Busybox configuration failed for configuration: {}", config_name);
-        return;
-    }
+    run(Command::new("make").current_dir(busybox_dir).args(&make_args).envs(crate::repro::make_env()), "busybox defconfig")?;
 
     // Enable static linking in busybox config
     println!("Enabling static compilation for busybox...");
     let config_path = build_dir.join(".config");
-    let config_content = fs::read_to_string(&config_path).expect("Failed to read busybox config");
+    let config_content = fs::read_to_string(&config_path).map_err(BuildError::io("failed to read busybox config"))?;
 
     // Enable CONFIG_STATIC and disable CONFIG_FEATURE_SHARED_BUSYBOX
     let modified_config = config_content
@@ -111,14 +121,27 @@ fn build_busybox_for_config(config_name: &str, cross_compile_prefix: &Option<Str
         modified_config
     };
 
-    fs::write(&config_path, final_config).expect("Failed to write modified busybox config");
+    fs::write(&config_path, final_config).map_err(BuildError::io("failed to write modified busybox config"))?;
 
     println!("Static compilation enabled for busybox");
 
+    // Reuse a cached busybox install when config, source and toolchain match
+    let install_dir = install_dir(config_name);
+    if install_dir.exists() {
+        fs::remove_dir_all(&install_dir).map_err(BuildError::io("failed to remove old busybox install"))?;
+    }
+    fs::create_dir_all(&install_dir).map_err(BuildError::io("failed to create busybox install directory"))?;
+    let entry = cache.and_then(|_| busybox_cache_entry(&build_dir, cross_compile_prefix));
+    if let (Some(cache), Some(entry)) = (cache, &entry)
+        && cache.restore(entry, &install_dir)
+    {
+        return Ok(());
+    }
+
     // Build busybox
     let mut make_args = vec![
-        format!("O={}", build_dir.canonicalize().unwrap().display()),
-        format!("-j{}", num_cpus()),
+        format!("O={}", build_dir.display()),
+        format!("-j{}", make_jobs()),
     ];
 
     if let Some(prefix) = cross_compile_prefix {
@@ -126,16 +149,41 @@ fn build_busybox_for_config(config_name: &str, cross_compile_prefix: &Option<Str
     }
 
     println!("Building busybox...");
-    let status = Command::new("make")
-        .current_dir(busybox_dir)
-        .args(&make_args)
-        .status()
-        .expect("Failed to build busybox");
-
-    if !status.success() {
-        eprintln!("Busybox build failed for configuration: {}", config_name);
-        return;
-    }
+    run(Command::new("make").current_dir(busybox_dir).args(&make_args).envs(crate::repro::make_env()), "busybox build")?;
 
     println!("Busybox build completed for {}", config_name);
+
+    // Install into a staging directory the rootfs is assembled from
+    let install_dir = install_dir.canonicalize().map_err(BuildError::io("failed to resolve busybox install directory"))?;
+    let mut make_args = vec![
+        format!("O={}", build_dir.display()),
+        format!("CONFIG_PREFIX={}", install_dir.display()),
+        "install".to_string(),
+    ];
+    if let Some(prefix) = cross_compile_prefix {
+        make_args.push(format!("CROSS_COMPILE={}", prefix));
+    }
+    run(Command::new("make").current_dir(busybox_dir).args(&make_args).envs(crate::repro::make_env()), "busybox install")?;
+
+    if let (Some(cache), Some(entry)) = (cache, &entry) {
+        cache.populate(entry, &install_dir, &["."]);
+    }
+    Ok(())
+}
+
+/// Get the staging directory busybox is installed to
+pub fn install_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("busybox-install")
+}
+
+/// Cache entry of the busybox built from the current `.config`, source tree and toolchain
+fn busybox_cache_entry(build_dir: &Path, cross_compile_prefix: &Option<String>) -> Option<String> {
+    let config = fs::read_to_string(build_dir.join(".config")).ok()?;
+    let material = format!(
+        "{}\n{}\n{}",
+        crate::cache::toolchain_id(cross_compile_prefix),
+        crate::cache::source_revision("busybox"),
+        config
+    );
+    crate::cache::cache_entry("busybox", &material, &build_dir.join("cache-key"))
 }
@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use sha2::{Digest, Sha256};
+
+/// Environment variable naming the artifact cache when `--cache` is not given
+pub const CACHE_ENV: &str = "GUEST_TEST_CACHE";
+
+/// Content-addressed store for build outputs, keyed by hashes of their inputs
+pub enum ArtifactCache {
+    /// A local (or network-mounted) directory
+    Dir(PathBuf),
+    /// An HTTP server accepting GET and PUT, e.g. a WebDAV share or bazel-remote
+    Http(String),
+}
+
+impl ArtifactCache {
+    /// Parse a cache location: an `http(s)://` URL or a directory path
+    pub fn from_location(location: &str) -> ArtifactCache {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            ArtifactCache::Http(location.trim_end_matches('/').to_string())
+        } else {
+            ArtifactCache::Dir(PathBuf::from(location))
+        }
+    }
+
+    /// Fetch an entry into `dest`, returning false on a miss
+    fn fetch(&self, entry: &str, dest: &Path) -> bool {
+        match self {
+            ArtifactCache::Dir(dir) => fs::copy(dir.join(entry), dest).is_ok(),
+            ArtifactCache::Http(url) => {
+                crate::download::download(&format!("{}/{}", url, entry), dest, None).is_ok()
+            }
+        }
+    }
+
+    /// Store `src` as an entry
+    fn store(&self, entry: &str, src: &Path) -> bool {
+        match self {
+            ArtifactCache::Dir(dir) => {
+                // Copy then rename so concurrent readers never see a partial entry
+                let tmp = dir.join(format!("{}.tmp{}", entry, std::process::id()));
+                fs::create_dir_all(dir).is_ok()
+                    && fs::copy(src, &tmp).is_ok()
+                    && fs::rename(&tmp, dir.join(entry)).is_ok()
+            }
+            ArtifactCache::Http(url) => Command::new("curl")
+                .args(["-fsS", "-T", &src.to_string_lossy()])
+                .arg(format!("{}/{}", url, entry))
+                .status()
+                .map(|s| s.success())
+                .unwrap_or(false),
+        }
+    }
+
+    /// Unpack a cached entry into `dir`, returning true on a hit
+    pub fn restore(&self, entry: &str, dir: &Path) -> bool {
+        let archive = archive_path(entry, dir);
+        let hit = self.fetch(entry, &archive)
+            && Command::new("tar")
+                .args([
+                    "-xzf",
+                    &archive.to_string_lossy(),
+                    "-C",
+                    &dir.to_string_lossy(),
+                ])
+                .status()
+                .map(|s| s.success())
+                .unwrap_or(false);
+        let _ = fs::remove_file(&archive);
+
+        if hit {
+            println!("Cache hit: {}", entry);
+        } else {
+            println!("Cache miss: {}", entry);
+        }
+        hit
+    }
+
+    /// Pack `files` (relative to `dir`) and store them as an entry
+    pub fn populate(&self, entry: &str, dir: &Path, files: &[&str]) {
+        let archive = archive_path(entry, dir);
+        let packed = Command::new("tar")
+            .args([
+                "-czf",
+                &archive.to_string_lossy(),
+                "-C",
+                &dir.to_string_lossy(),
+            ])
+            .args(files)
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+
+        if packed && self.store(entry, &archive) {
+            println!("Cache populated: {}", entry);
+        } else {
+            eprintln!("Failed to populate cache entry {}", entry);
+        }
+        let _ = fs::remove_file(&archive);
+    }
+}
+
+/// Scratch archive for an entry, kept next to `dir` so packing `.` does not include it
+fn archive_path(entry: &str, dir: &Path) -> PathBuf {
+    dir.parent().unwrap_or(dir).join(format!(".{}", entry))
+}
+
+/// Revision of a git source tree, including a hash of uncommitted changes
+pub fn source_revision(source_dir: impl AsRef<Path>) -> String {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(source_dir.as_ref())
+            .args(args)
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .unwrap_or_default()
+    };
+    format!("{} {}", git(&["rev-parse", "HEAD"]), git(&["diff", "HEAD"]))
+}
+
+/// Identify the compiler and linker a build uses
+pub fn toolchain_id(cross_compile_prefix: &Option<String>) -> String {
+    let prefix = cross_compile_prefix.as_deref().unwrap_or_default();
+    ["gcc", "ld"]
+        .iter()
+        .map(|tool| {
+            Command::new(format!("{}{}", prefix, tool))
+                .arg("--version")
+                .output()
+                .map(|o| {
+                    String::from_utf8_lossy(&o.stdout)
+                        .lines()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Compute the cache entry name of a stage from its key material
+///
+/// The material is kept in `key_file` so a miss can be diffed against an earlier build.
+pub fn cache_entry(stage: &str, material: &str, key_file: &Path) -> Option<String> {
+    fs::write(key_file, material).ok()?;
+    let hash: String = Sha256::digest(material.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Some(format!("{}-{}.tar.gz", stage, hash))
+}
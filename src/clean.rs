@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::stages::Stage;
+
+/// The kernel stage's output the pruned build tree keeps, for `analyze` and symbol lookups
+const KEPT_KERNEL_FILE: &str = "vmlinux";
+
+/// Configurations with a build directory, skipping `.repro` and the like
+fn built_configs() -> Vec<String> {
+    let Ok(entries) = fs::read_dir("build") else {
+        return Vec::new();
+    };
+    let mut configs: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    configs.sort();
+    configs
+}
+
+/// What `clean --stage` removes: the stage's build tree and the outputs exported from it
+fn stage_outputs(config_name: &str, stage: Stage) -> Vec<PathBuf> {
+    let dir = PathBuf::from("build").join(config_name);
+    match stage {
+        Stage::Kernel => {
+            let mut outputs = vec![dir.join("linux")];
+            outputs.extend(crate::qemu::kernel_image_path(config_name));
+            for name in ["System.map", "symbols.json", "orc.elf", "memory.dtsi"] {
+                outputs.push(dir.join(name));
+            }
+            outputs.push(crate::dtb::overlays_dir(config_name));
+            outputs.push(crate::dtb::merged_dtb_path(config_name));
+            outputs.push(crate::dtb::qemu_dtb_path(config_name));
+            if let Ok(manifest) = crate::config::load_manifest(config_name) {
+                outputs.extend(crate::dtb::kernel_dtb_paths(config_name, &manifest));
+            }
+            outputs
+        }
+        Stage::Busybox => vec![
+            dir.join("busybox"),
+            crate::busybox::install_dir(config_name),
+        ],
+        Stage::Rootfs => vec![
+            dir.join("rootfs"),
+            crate::qemu::rootfs_image_path(config_name),
+            crate::qemu::initramfs_path(config_name),
+            crate::rootfs::embedded_initramfs_path(config_name),
+            dir.join("payloads"),
+            dir.join("rust"),
+            dir.join("dropbear"),
+        ],
+    }
+}
+
+/// What `clean --prune` removes: trees the final images were built from, except `vmlinux`
+///
+/// With `nfsroot`, `rootfs/` is what the guest mounts, so it is a final output and stays.
+fn intermediate_trees(config_name: &str) -> Vec<PathBuf> {
+    let dir = PathBuf::from("build").join(config_name);
+    let mut trees: Vec<PathBuf> = fs::read_dir(dir.join("linux"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.file_name() != KEPT_KERNEL_FILE)
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    trees.sort();
+    let nfsroot = crate::config::load_manifest(config_name).is_ok_and(|manifest| manifest.nfsroot);
+    for name in [
+        "busybox",
+        "busybox-install",
+        "rootfs",
+        "payloads",
+        "rust",
+        "dropbear",
+        "sdcard",
+    ] {
+        if name == "rootfs" && nfsroot {
+            continue;
+        }
+        trees.push(dir.join(name));
+    }
+    trees
+}
+
+/// Remove a file or tree, returning the bytes it took
+fn remove(path: &Path) -> Result<u64, String> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(0),
+    };
+    let size = crate::budget::tree_size(path).unwrap_or(0);
+    let removed = if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    removed.map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(size)
+}
+
+/// Clean `build/`: everything, one configuration, the outputs of some stages, or with
+/// `prune` the intermediate trees while keeping the final images
+///
+/// Without a configuration, stages and pruning apply to every built one.
+pub fn clean(config: Option<&str>, stages: &[Stage], prune: bool) -> bool {
+    let build_dir = Path::new("build");
+    if !build_dir.exists() {
+        println!("Build directory does not exist.");
+        return true;
+    }
+    if config.is_none() && stages.is_empty() && !prune {
+        if let Err(e) = fs::remove_dir_all(build_dir) {
+            eprintln!("Failed to remove build directory: {}", e);
+            return false;
+        }
+        println!("Build directory cleaned.");
+        return true;
+    }
+
+    let configs = match config {
+        Some(config) => {
+            if config.is_empty() || config.starts_with('.') || config.contains(['/', '\\']) {
+                eprintln!("invalid configuration: {}", config);
+                return false;
+            }
+            if !build_dir.join(config).is_dir() {
+                println!("{} has not been built.", config);
+                return true;
+            }
+            vec![config.to_string()]
+        }
+        None => built_configs(),
+    };
+
+    let mut freed = 0;
+    let mut ok = true;
+    for config_name in &configs {
+        let mut paths = Vec::new();
+        if stages.is_empty() && !prune {
+            paths.push(build_dir.join(config_name));
+            paths.push(crate::repro::first_build_dir(config_name));
+        }
+        for stage in stages {
+            paths.extend(stage_outputs(config_name, *stage));
+        }
+        if prune {
+            paths.extend(intermediate_trees(config_name));
+        }
+
+        let mut removed = 0;
+        for path in &paths {
+            match remove(path) {
+                Ok(size) => removed += size,
+                Err(e) => {
+                    eprintln!("Failed to remove {}", e);
+                    ok = false;
+                }
+            }
+        }
+        println!(
+            "{}: freed {:.1} MiB",
+            config_name,
+            removed as f64 / (1024.0 * 1024.0)
+        );
+        freed += removed;
+    }
+    if configs.len() > 1 {
+        println!("Freed {:.1} MiB in total", freed as f64 / (1024.0 * 1024.0));
+    }
+    ok
+}
@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::Path;
+
+use clap::Command;
+
+/// Prefix of the environment variable behind each option, e.g. `GUEST_TEST_TIMEOUT` for `--timeout`
+const ENV_PREFIX: &str = "GUEST_TEST_";
+
+/// Per-project defaults, read from the working directory
+const ENV_FILE: &str = ".env";
+
+/// Give every option of a command and its subcommands an environment variable,
+/// keeping the ones options already declare
+pub fn with_env(command: Command) -> Command {
+    command
+        .mut_args(|arg| {
+            if arg.is_positional() || arg.get_env().is_some() {
+                return arg;
+            }
+            let name = format!(
+                "{}{}",
+                ENV_PREFIX,
+                arg.get_id().as_str().to_uppercase().replace('-', "_")
+            );
+            arg.env(name)
+        })
+        .mut_subcommands(with_env)
+}
+
+/// One `KEY=VALUE` line of an env file, `export` prefix and quotes allowed
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    let value = value.trim();
+    let value = ['"', '\'']
+        .iter()
+        .find_map(|quote| {
+            value
+                .strip_prefix(*quote)
+                .and_then(|v| v.strip_suffix(*quote))
+        })
+        .unwrap_or(value);
+    Some((key, value))
+}
+
+/// Load `.env` into the environment; variables that are already set win
+///
+/// Must run before any thread is started, as it modifies the process environment.
+pub fn load_env_file() {
+    let path = Path::new(ENV_FILE);
+    let Ok(content) = fs::read_to_string(path) else {
+        return;
+    };
+    for (number, line) in content.lines().enumerate() {
+        let Some((key, value)) = parse_line(line) else {
+            if !line.trim().is_empty() && !line.trim().starts_with('#') {
+                eprintln!("Warning: ignoring {}:{}: {}", ENV_FILE, number + 1, line);
+            }
+            continue;
+        };
+        if std::env::var_os(key).is_none() {
+            // SAFETY: called at the start of main, before other threads exist
+            unsafe { std::env::set_var(key, value) };
+        }
+    }
+}
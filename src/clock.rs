@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::ConfigManifest;
+
+/// Host directory IANA zone names are looked up in
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// Kernel options for reading the emulated RTC at boot and from userspace
+/// (options for another arch's RTC are dropped by olddefconfig)
+pub const RTC_KERNEL_OPTIONS: &[&str] = &[
+    "CONFIG_RTC_CLASS",
+    "CONFIG_RTC_HCTOSYS",
+    "CONFIG_RTC_INTF_DEV",
+    "CONFIG_RTC_DRV_PL031",
+    "CONFIG_RTC_DRV_CMOS",
+];
+
+/// What the emulated RTC counts in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RtcMode {
+    /// UTC, what Linux expects
+    Utc,
+    /// Local time, as left behind by dual-boot Windows hosts
+    Local,
+}
+
+/// Whether a timezone is a POSIX TZ string (`CET-1CEST,M3.5.0,M10.5.0/3`) rather than a zone name
+fn is_posix_tz(timezone: &str) -> bool {
+    timezone.chars().any(|c| c.is_ascii_digit()) && !timezone.contains('/')
+}
+
+/// Check that a date is `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS`, the forms QEMU accepts
+fn is_valid_date(date: &str) -> bool {
+    let pattern = if date.len() == 10 {
+        "dddd-dd-dd"
+    } else {
+        "dddd-dd-ddTdd:dd:dd"
+    };
+    date.len() == pattern.len()
+        && date.chars().zip(pattern.chars()).all(|(c, p)| match p {
+            'd' => c.is_ascii_digit(),
+            _ => c == p,
+        })
+}
+
+/// Check the clock settings of a manifest
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    if let Some(timezone) = &manifest.timezone
+        && (timezone.is_empty()
+            || timezone.contains(char::is_whitespace)
+            || timezone.contains(".."))
+    {
+        return Err(format!("invalid timezone {:?}", timezone));
+    }
+    if let Some(date) = &manifest.initial_date
+        && !is_valid_date(date)
+    {
+        return Err(format!(
+            "invalid initial_date {}, expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS",
+            date
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the manifest touches the guest's clock at all
+pub fn configured(manifest: &ConfigManifest) -> bool {
+    manifest.rtc.is_some() || manifest.initial_date.is_some()
+}
+
+/// Install the timezone into the rootfs; init exports `/etc/TZ` as `TZ`
+///
+/// Zone names copy the host's zoneinfo file, so the guest needs no tzdata package.
+pub fn install_timezone(rootfs_dir: &Path, timezone: &str) -> bool {
+    let etc = rootfs_dir.join("etc");
+    if !is_posix_tz(timezone) {
+        let source = Path::new(ZONEINFO_DIR).join(timezone);
+        let dest = rootfs_dir
+            .join(ZONEINFO_DIR.trim_start_matches('/'))
+            .join(timezone);
+        if let Err(e) = dest
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::copy(&source, &dest))
+        {
+            eprintln!(
+                "Failed to install timezone {} from {}: {}",
+                timezone,
+                source.display(),
+                e
+            );
+            return false;
+        }
+        let localtime = etc.join("localtime");
+        let _ = fs::remove_file(&localtime);
+        if let Err(e) =
+            std::os::unix::fs::symlink(Path::new(ZONEINFO_DIR).join(timezone), &localtime)
+        {
+            eprintln!("Failed to link /etc/localtime: {}", e);
+            return false;
+        }
+    }
+    if let Err(e) = fs::write(etc.join("TZ"), format!("{}\n", timezone)) {
+        eprintln!("Failed to write /etc/TZ: {}", e);
+        return false;
+    }
+    true
+}
+
+/// QEMU `-rtc` arguments for the manifest's RTC mode and initial date
+pub fn qemu_args(manifest: &ConfigManifest) -> Vec<String> {
+    let base = match (&manifest.initial_date, manifest.rtc) {
+        (Some(date), _) => date.clone(),
+        (None, Some(RtcMode::Local)) => "localtime".to_string(),
+        (None, Some(RtcMode::Utc)) => "utc".to_string(),
+        (None, None) => return Vec::new(),
+    };
+    // A fixed start date only stays reproducible if it advances with the guest
+    let clock = if manifest.initial_date.is_some() {
+        ",clock=vm"
+    } else {
+        ""
+    };
+    vec!["-rtc".to_string(), format!("base={}{}", base, clock)]
+}
+
+/// Kernel parameters telling init how to read the RTC
+pub fn cmdline_params(manifest: &ConfigManifest) -> Vec<String> {
+    match manifest.rtc {
+        Some(RtcMode::Local) => vec!["guest_test.rtc=local".to_string()],
+        _ => Vec::new(),
+    }
+}
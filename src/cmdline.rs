@@ -0,0 +1,182 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::config::{ConfigManifest, parse_config_name};
+
+/// How the generated command line is built into the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuiltinCmdline {
+    /// Whatever the bootloader or hypervisor passes is appended to it
+    Extend,
+    /// Used instead of whatever the bootloader or hypervisor passes
+    Force,
+}
+
+/// Values of the `{name}` placeholders in kernel parameters appended at run time
+#[derive(Debug, Clone)]
+pub struct RunVars {
+    /// Configuration being booted
+    pub config: String,
+    /// Label of this run, e.g. for result directories; defaults to the start time
+    pub tag: String,
+    /// Seed for randomized guest tests; defaults to one derived from the start time
+    pub seed: u64,
+}
+
+impl RunVars {
+    /// Variables for a run, filling in the tag and seed that were not given
+    pub fn new(config_name: &str, tag: Option<String>, seed: Option<u64>) -> RunVars {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        RunVars {
+            config: config_name.to_string(),
+            tag: tag.unwrap_or_else(|| now.as_secs().to_string()),
+            seed: seed.unwrap_or(now.as_nanos() as u64),
+        }
+    }
+
+    /// Value of a placeholder
+    fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "config" => Some(self.config.clone()),
+            "tag" => Some(self.tag.clone()),
+            "seed" => Some(self.seed.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Replace the `{name}` placeholders of a parameter; `{{` and `}}` are literal braces
+    pub fn expand(&self, template: &str) -> Result<String, String> {
+        let mut expanded = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            expanded.push_str(&rest[..start]);
+            let brace = &rest[start..start + 1];
+            if rest[start + 1..].starts_with(brace) {
+                expanded.push_str(brace);
+                rest = &rest[start + 2..];
+                continue;
+            }
+            let end = match (brace, rest[start..].find('}')) {
+                ("{", Some(end)) => start + end,
+                _ => return Err(format!("unbalanced brace in {:?}", template)),
+            };
+            let name = &rest[start + 1..end];
+            let value = self
+                .get(name)
+                .ok_or_else(|| format!("unknown variable {{{}}} in {:?}", name, template))?;
+            expanded.push_str(&value);
+            rest = &rest[end + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+
+    /// Expand `--append` values into kernel parameters
+    pub fn expand_params(&self, appended: &[String]) -> Result<Vec<String>, String> {
+        appended.iter().map(|param| self.expand(param)).collect()
+    }
+}
+
+/// Build the kernel command line for a configuration
+pub fn kernel_cmdline(config_name: &str, manifest: &ConfigManifest) -> String {
+    // A board's kernel boots from its own SD card and UART instead of QEMU's devices
+    if let Some(board) = &manifest.board {
+        let root_device = crate::board::root_device(config_name, board);
+        return kernel_cmdline_with(config_name, manifest, &root_device, &board.console);
+    }
+    let (arch, _) = parse_config_name(config_name);
+    let root_device = crate::arch::find_arch(&arch).map_or("/dev/sda", |a| a.root_device);
+    kernel_cmdline_with(
+        config_name,
+        manifest,
+        root_device,
+        manifest.console(&arch).device(),
+    )
+}
+
+/// Build the kernel command line for a hypervisor that attaches the root disk and console differently
+pub fn kernel_cmdline_with(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    root_device: &str,
+    console: &str,
+) -> String {
+    let (arch, _) = parse_config_name(config_name);
+    let arch = arch.as_str();
+    // The kernel runs /init from an initramfs and never mounts a root device
+    let mut params = if manifest.initramfs {
+        Vec::new()
+    } else if manifest.nfsroot {
+        crate::nfs::cmdline_params(config_name, manifest)
+    } else if manifest.verity {
+        crate::verity::cmdline_params(config_name, root_device)
+    } else if manifest.luks {
+        crate::luks::cmdline_params(config_name, root_device)
+    } else {
+        vec![format!("root={}", root_device)]
+    };
+    if !manifest.initramfs && !manifest.nfsroot && manifest.image_format.read_only() {
+        params.push(format!("rootfstype={}", manifest.image_format.name()));
+    }
+    // init moves a read-only root under a writable overlay
+    if manifest.readonly_root {
+        params.extend(["ro".to_string(), "guest_test.overlay".to_string()]);
+    } else if !manifest.initramfs {
+        params.push("rw".to_string());
+    }
+    params.push("init=/init".to_string());
+    // The last console= becomes /dev/console, so the framebuffer only mirrors the log
+    if manifest.display.is_some() {
+        params.push("console=tty0".to_string());
+    }
+    params.push(format!("console={}", console));
+    params.extend(crate::memory::cmdline_params(arch, manifest));
+    params.extend(crate::pstore::cmdline_params(arch, manifest));
+    // Also covers bootloaders/hypervisors that randomize on behalf of the kernel
+    if manifest.kaslr == Some(false) {
+        params.push("nokaslr".to_string());
+    }
+    params.extend(crate::clock::cmdline_params(manifest));
+    params.extend(crate::tpm::cmdline_params(manifest));
+    params.extend(crate::swap::cmdline_params(manifest));
+    if manifest.module_sig_enforce {
+        params.push("module.sig_enforce=1".to_string());
+    }
+    if let Some(dma) = manifest.dma {
+        params.extend(dma.cmdline().iter().map(|p| p.to_string()));
+    }
+    for profile in crate::profile::enabled_profiles(manifest) {
+        params.extend(profile.cmdline.iter().map(|p| p.to_string()));
+    }
+    // Last, so a config can override any of the generated parameters
+    params.extend(manifest.cmdline.iter().cloned());
+    params.join(" ")
+}
+
+/// Kernel options baking the generated command line into the kernel, with `builtin_cmdline`
+pub fn kernel_options(config_name: &str, manifest: &ConfigManifest) -> Vec<(String, String)> {
+    let Some(mode) = manifest.builtin_cmdline else {
+        return Vec::new();
+    };
+    let (arch, _) = parse_config_name(config_name);
+    let cmdline = kernel_cmdline(config_name, manifest).replace('"', "\\\"");
+    let mut options = vec![("CONFIG_CMDLINE".to_string(), format!("\"{}\"", cmdline))];
+    // x86 always puts the built-in command line in front of the bootloader's
+    let choice = match (arch.as_str(), mode) {
+        ("x86", BuiltinCmdline::Extend) => None,
+        ("x86", BuiltinCmdline::Force) => Some("CONFIG_CMDLINE_OVERRIDE"),
+        (_, BuiltinCmdline::Extend) => Some("CONFIG_CMDLINE_EXTEND"),
+        (_, BuiltinCmdline::Force) => Some("CONFIG_CMDLINE_FORCE"),
+    };
+    if arch == "x86" {
+        options.push(("CONFIG_CMDLINE_BOOL".to_string(), "y".to_string()));
+    }
+    if let Some(choice) = choice {
+        options.push((choice.to_string(), "y".to_string()));
+    }
+    options
+}
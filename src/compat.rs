@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::manifest::read_manifest;
+
+/// Capabilities of a hypervisor build, as described in a JSON file
+#[derive(Debug, Deserialize)]
+pub struct HypervisorCapabilities {
+    pub version: String,
+    /// GIC versions the hypervisor can emulate
+    #[serde(default)]
+    pub gic_versions: Vec<u32>,
+    /// Virtio transports and devices the hypervisor provides
+    #[serde(default)]
+    pub virtio: Vec<String>,
+    /// UART models the hypervisor emulates (e.g. "pl011", "8250")
+    #[serde(default)]
+    pub consoles: Vec<String>,
+}
+
+/// Load a capability description from a JSON path or from `hypervisor/<version>.json`
+fn load_capabilities(hypervisor: &str) -> Option<HypervisorCapabilities> {
+    let path = if Path::new(hypervisor).is_file() {
+        PathBuf::from(hypervisor)
+    } else {
+        PathBuf::from("hypervisor").join(format!("{}.json", hypervisor))
+    };
+
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!(
+                "No capability description for hypervisor {} ({}): {}",
+                hypervisor,
+                path.display(),
+                e
+            );
+            return None;
+        }
+    };
+
+    match serde_json::from_str(&content) {
+        Ok(caps) => Some(caps),
+        Err(e) => {
+            eprintln!("Invalid capability description {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Format GIC versions for display (e.g. "GICv2/GICv3")
+fn gic_list(versions: &[u32]) -> String {
+    versions
+        .iter()
+        .map(|v| format!("GICv{}", v))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Compare a built configuration against a hypervisor, returning true if compatible
+pub fn check_compat(config_name: &str, hypervisor: &str) -> bool {
+    let Some(manifest) = read_manifest(config_name) else {
+        eprintln!("Build {} first to generate its manifest.", config_name);
+        return false;
+    };
+    let Some(caps) = load_capabilities(hypervisor) else {
+        return false;
+    };
+
+    println!(
+        "Checking {} against hypervisor {}...",
+        config_name, caps.version
+    );
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let age_days = now.saturating_sub(manifest.built_at) / (24 * 60 * 60);
+    println!("  image built {} day(s) ago", age_days);
+
+    let mut mismatches = Vec::new();
+
+    let guest_gics = &manifest.hypervisor.gic_versions;
+    if !guest_gics.is_empty() && !guest_gics.iter().any(|v| caps.gic_versions.contains(v)) {
+        mismatches.push(format!(
+            "guest needs {} but hypervisor only emulates {}",
+            gic_list(guest_gics),
+            if caps.gic_versions.is_empty() {
+                "no GIC".to_string()
+            } else {
+                gic_list(&caps.gic_versions)
+            }
+        ));
+    }
+
+    // virtio-console is a virtio device; UARTs are listed separately
+    match manifest.hypervisor.console.as_deref() {
+        Some("virtio") if !caps.virtio.iter().any(|v| v == "console") => {
+            mismatches.push("guest console is virtio-console but hypervisor has none".to_string());
+        }
+        Some(uart) if uart != "virtio" && !caps.consoles.iter().any(|c| c == uart) => {
+            mismatches.push(format!(
+                "guest console is {} but hypervisor does not emulate it",
+                uart
+            ));
+        }
+        _ => {}
+    }
+
+    // Drivers without a matching device are harmless; only the root disk
+    // and console matter when the hypervisor provides no alternative
+    let transports: Vec<&String> = manifest
+        .hypervisor
+        .virtio
+        .iter()
+        .filter(|v| *v == "mmio" || *v == "pci")
+        .collect();
+    if !transports.is_empty() && !transports.iter().any(|t| caps.virtio.contains(t)) {
+        mismatches.push(format!(
+            "guest supports virtio over {} but hypervisor provides none of them",
+            transports
+                .iter()
+                .map(|t| t.as_str())
+                .collect::<Vec<_>>()
+                .join("/")
+        ));
+    }
+
+    for device in &manifest.hypervisor.virtio {
+        if !caps.virtio.contains(device) && !transports.contains(&device) {
+            println!(
+                "  note: guest has a virtio-{} driver the hypervisor does not provide",
+                device
+            );
+        }
+    }
+
+    if mismatches.is_empty() {
+        println!("Compatible.");
+        true
+    } else {
+        for mismatch in &mismatches {
+            eprintln!("  MISMATCH: {}", mismatch);
+        }
+        false
+    }
+}
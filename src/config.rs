@@ -1,28 +1,567 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
+
+use crate::clock::RtcMode;
+use crate::disks::Disk;
+use crate::display::DisplayDevice;
+use crate::dma::DmaMode;
+use crate::dtb::OverlayMode;
+use crate::firmware::FirmwareKind;
+use crate::tpm::TpmInterface;
+
+/// Serial console transport used by the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Console {
+    /// ARM PrimeCell UART, the default on arm64
+    Pl011,
+    /// 16550-compatible UART, the default on x86
+    #[serde(rename = "8250")]
+    Uart8250,
+    /// virtio-console (hvc)
+    Virtio,
+}
+
+impl Console {
+    /// Default console for an architecture
+    pub fn default_for_arch(arch: &str) -> Console {
+        crate::arch::find_arch(arch).map_or(Console::Uart8250, |a| a.default_console())
+    }
+
+    /// Name used in manifests
+    pub fn name(self) -> &'static str {
+        match self {
+            Console::Pl011 => "pl011",
+            Console::Uart8250 => "8250",
+            Console::Virtio => "virtio",
+        }
+    }
+
+    /// Device name passed as `console=` on the kernel command line
+    pub fn device(self) -> &'static str {
+        match self {
+            Console::Pl011 => "ttyAMA0",
+            Console::Uart8250 => "ttyS0",
+            Console::Virtio => "hvc0",
+        }
+    }
+
+    /// Input that makes the next character a sysrq command on this console
+    pub fn sysrq_prefix(self) -> &'static str {
+        match self {
+            // Ctrl-A b makes QEMU's serial mux send a break
+            Console::Pl011 | Console::Uart8250 => "\x01b",
+            // hvc treats Ctrl-O as the sysrq key
+            Console::Virtio => "\x0f",
+        }
+    }
+
+    /// Kernel options needed to drive this console
+    fn kernel_options(self) -> &'static [&'static str] {
+        match self {
+            Console::Pl011 => &[
+                "CONFIG_SERIAL_AMBA_PL011",
+                "CONFIG_SERIAL_AMBA_PL011_CONSOLE",
+            ],
+            Console::Uart8250 => &["CONFIG_SERIAL_8250", "CONFIG_SERIAL_8250_CONSOLE"],
+            Console::Virtio => &["CONFIG_VIRTIO_CONSOLE"],
+        }
+    }
+}
+
+/// Per-config settings read from `config/<arch>/<name>.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigManifest {
+    /// GIC version the guest targets (arm64 only): 2 or 3
+    pub gic: Option<u32>,
+    /// Console transport, defaults to the arch's usual UART
+    pub console: Option<Console>,
+    /// Guest RAM size (e.g. "1G"), defaults to 1G
+    pub memory: Option<String>,
+    /// vCPUs the guest boots with, defaults to 2
+    pub vcpus: Option<u32>,
+    /// Kernel parameters appended to the generated command line
+    pub cmdline: Vec<String>,
+    /// Additional QEMU arguments, e.g. `["-device", "virtio-rng-pci"]`
+    pub qemu_args: Vec<String>,
+    /// Payloads from `payloads/` installed under `/opt/tests` on top of those of the profiles
+    #[serde(alias = "packages")]
+    pub payloads: Vec<String>,
+    /// Restricted guest physical memory map
+    pub memory_map: Vec<MemoryRegion>,
+    /// Devicetree overlay sources (`.dtso`) next to the config
+    pub overlays: Vec<String>,
+    /// Whether overlays are merged into `base_dtb` or shipped separately
+    pub overlay_mode: OverlayMode,
+    /// DTB the overlays are merged into
+    pub base_dtb: Option<String>,
+    /// Kernel DTBs to build with `make dtbs` and copy into `build/<config>/`,
+    /// relative to `arch/<arch>/boot/dts/` (e.g. "arm/fvp-base-revc.dtb")
+    pub dtbs: Vec<String>,
+    /// Dump QEMU's machine DTB into `build/<config>/qemu.dtb`; overlays merge into it
+    /// when `base_dtb` is unset
+    pub qemu_dtb: bool,
+    /// Opt-in test profiles (see `profile.rs`)
+    pub profiles: Vec<String>,
+    /// DMA routing: "swiotlb" (forced bouncing) or "restricted-pool" (arm64 only)
+    pub dma: Option<DmaMode>,
+    /// Randomize the kernel (and module) layout; false gives a stable layout for debugging
+    pub kaslr: Option<bool>,
+    /// Ship gzipped ORC unwind info next to the symbol table (x86 only)
+    pub orc: bool,
+    /// Cargo workspace whose binaries are cross-built and installed into the rootfs
+    pub rust_workspace: Option<String>,
+    /// Rust target for the workspace, defaults to the arch's static musl target
+    pub rust_target: Option<String>,
+    /// Serve a health endpoint and known-content file with busybox httpd
+    pub httpd: bool,
+    /// Host port forwarded to the guest's httpd, defaults to 8080
+    pub httpd_port: Option<u16>,
+    /// Kernel make target generating the base `.config`, e.g. "defconfig"; the config
+    /// file is then a fragment merged on top of it instead of a full `.config`
+    pub base_config: Option<String>,
+    /// Further kconfig fragments next to the config, merged in order after it
+    pub fragments: Vec<String>,
+    /// Kernel tag, branch or commit to build, defaults to the release in `linux/`
+    pub kernel_ref: Option<String>,
+    /// Kernel git repository `kernel_ref` is fetched from, defaults to torvalds/linux
+    pub kernel_repo: Option<String>,
+    /// Commit `kernel_ref` must resolve to, pinning e.g. a vendor BSP branch
+    pub kernel_commit: Option<String>,
+    /// Patches next to the config applied to the kernel tree in order, e.g. vendor fixes
+    pub kernel_patches: Vec<String>,
+    /// Physical board the config targets, which gets an SD card image (see `board.rs`)
+    pub board: Option<crate::board::Board>,
+    /// Also emit cloud-hypervisor/crosvm boot artifacts under `build/<config>/vmm/`
+    pub vmm_artifacts: bool,
+    /// Kernel, rootfs and boot-time limits checked by `build` and `test`
+    pub budget: crate::budget::Budget,
+    /// Console markers `test` waits for and text it must not see
+    pub expect: crate::testing::Expectations,
+    /// Run dropbear so the harness executes commands and copies files over SSH
+    pub ssh: bool,
+    /// Host port forwarded to the guest's dropbear, defaults to 2222
+    pub ssh_port: Option<u16>,
+    /// Mount the root filesystem over NFS instead of the disk image
+    pub nfsroot: bool,
+    /// External NFS root (`<server-ip>:<path>[,<options>]`) instead of the bundled unfsd
+    pub nfs_server: Option<String>,
+    /// Emulated display for framebuffer tests: "virtio-gpu"
+    pub display: Option<DisplayDevice>,
+    /// Additional disks attached next to the root disk
+    pub disks: Vec<Disk>,
+    /// Mount the root disk read-only under a tmpfs overlay so the image is never modified
+    pub readonly_root: bool,
+    /// Pack the rootfs into `initramfs.cpio.gz` and boot from it instead of `rootfs.img`
+    pub initramfs: bool,
+    /// Build the initramfs into the kernel image (`CONFIG_INITRAMFS_SOURCE`)
+    pub initramfs_embed: bool,
+    /// Boot a static `/init` built from init/bare.c instead of BusyBox (requires initramfs)
+    pub bare: bool,
+    /// Append a dm-verity hash tree to the rootfs image and boot through it
+    pub verity: bool,
+    /// Encrypt the rootfs image with LUKS2 and boot through dm-crypt
+    pub luks: bool,
+    /// Passphrase of the LUKS key slot, defaults to "guest-test"
+    pub luks_passphrase: Option<String>,
+    /// Guest timezone: an IANA zone installed from the host (e.g. "Europe/Berlin") or a POSIX TZ string
+    pub timezone: Option<String>,
+    /// What the emulated RTC counts in: "utc" or "local"
+    pub rtc: Option<RtcMode>,
+    /// Date the RTC starts at (e.g. "2024-02-29T23:59:00"), advancing with guest time
+    pub initial_date: Option<String>,
+    /// Sign kernel modules, including out-of-tree test modules
+    pub module_signing: bool,
+    /// PEM with the signing key and certificate, generated under build/<config>/ if unset
+    pub module_signing_key: Option<String>,
+    /// Refuse unsigned modules in the guest (`module.sig_enforce=1`)
+    pub module_sig_enforce: bool,
+    /// Emulated TPM 2.0 backed by swtpm: "tis" or "crb" (x86 only)
+    pub tpm: Option<TpmInterface>,
+    /// Boot through firmware from `firmware/`: "edk2" (UEFI) or "seabios" (x86 only)
+    pub firmware: Option<FirmwareKind>,
+    /// Keep oops/panic logs and the console in ramoops at the top of RAM across guest reboots
+    pub pstore: bool,
+    /// Build the generated command line into the kernel with `CONFIG_CMDLINE`, for
+    /// hypervisors and bootloaders that pass none or a different one
+    pub builtin_cmdline: Option<crate::cmdline::BuiltinCmdline>,
+    /// Filesystem of the rootfs image; squashfs and erofs need `readonly_root`
+    pub image_format: crate::rootfs::ImageFormat,
+    /// Swap file, partition or zram device the guest sets up at boot
+    pub swap: Option<crate::swap::Swap>,
+    /// Variables exported to init, the boot hooks and the guest tests, e.g.
+    /// `TEST_ITERATIONS = "100"`
+    pub env: BTreeMap<String, String>,
+    /// Guest hostname; "guest" by default
+    pub hostname: Option<String>,
+    /// Console device the BusyBox init getty listens on; the console by default
+    pub getty: Option<String>,
+    /// Extra or replacement `/etc/fstab` entries, mounted at boot
+    pub mounts: Vec<crate::etc::Mount>,
+    /// Prune documentation, locales, headers and static libraries from the rootfs and strip
+    /// its binaries
+    pub minimize: Option<crate::minimize::Minimize>,
+    /// Kernel modules the rootfs must ship (or the kernel build in), e.g. `["zram"]`;
+    /// the build fails when one is missing
+    pub modules: Vec<String>,
+}
+
+/// Kernel options for the virtio-net NIC used by httpd and NFS root
+const NIC_KERNEL_OPTIONS: &[&str] = &[
+    "CONFIG_PCI",
+    "CONFIG_PCI_HOST_GENERIC",
+    "CONFIG_VIRTIO_PCI",
+    "CONFIG_NET",
+    "CONFIG_INET",
+    "CONFIG_VIRTIO_NET",
+];
+
+/// A guest physical memory region, e.g. `{ base = "0x40000000", size = "512M" }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryRegion {
+    pub base: String,
+    pub size: String,
+}
+
+impl ConfigManifest {
+    /// Check that the settings make sense for the given architecture
+    fn validate(&self, arch: &str) -> Result<(), String> {
+        let Some(arch_info) = crate::arch::find_arch(arch) else {
+            return Err(format!("unsupported architecture {}", arch));
+        };
+
+        if let Some(gic) = self.gic {
+            if !arch_info.gic {
+                return Err(format!("gic is only supported on arm64, not {}", arch));
+            }
+            if gic != 2 && gic != 3 {
+                return Err(format!("unsupported gic version {}, expected 2 or 3", gic));
+            }
+        }
+
+        crate::memory::memory_regions(self)?;
+        crate::cpu_hotplug::validate(self, arch)?;
+        crate::payload::validate(self)?;
+        crate::bare::validate(self)?;
+        if let Some(param) = self
+            .cmdline
+            .iter()
+            .find(|p| p.is_empty() || p.contains(char::is_whitespace))
+        {
+            return Err(format!(
+                "cmdline entries are single kernel parameters, got {:?}",
+                param
+            ));
+        }
+        crate::disks::validate(self)?;
+        crate::swap::validate(self)?;
+        crate::guest_env::validate(self)?;
+        crate::etc::validate(self)?;
+        crate::minimize::validate(self)?;
+        crate::modules::validate(self)?;
+        if let Some(target) = &self.base_config
+            && (!target.ends_with("config") || target.contains(['/', ' ']))
+        {
+            return Err(format!(
+                "base_config must be a kernel config target such as defconfig, got {:?}",
+                target
+            ));
+        }
+        for fragment in &self.fragments {
+            if !Path::new("config").join(arch).join(fragment).is_file() {
+                return Err(format!(
+                    "config fragment config/{}/{} does not exist",
+                    arch, fragment
+                ));
+            }
+        }
+        for (key, value) in [
+            ("kernel_ref", &self.kernel_ref),
+            ("kernel_repo", &self.kernel_repo),
+        ] {
+            if value
+                .as_ref()
+                .is_some_and(|v| v.is_empty() || v.contains(char::is_whitespace))
+            {
+                return Err(format!("{} must be a non-empty git ref or URL", key));
+            }
+        }
+        if let Some(commit) = &self.kernel_commit
+            && (!(7..=40).contains(&commit.len()) || !commit.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(format!("kernel_commit {} is not a commit hash", commit));
+        }
+        for patch in &self.kernel_patches {
+            if !Path::new("config").join(arch).join(patch).is_file() {
+                return Err(format!(
+                    "kernel patch config/{}/{} does not exist",
+                    arch, patch
+                ));
+            }
+        }
+        self.expect.validate()?;
+        self.budget.validate()?;
+        crate::clock::validate(self)?;
+        crate::tpm::validate(self, arch)?;
+        crate::firmware::validate(self, arch)?;
+        crate::pstore::validate(self, arch)?;
+
+        for profile in &self.profiles {
+            if crate::profile::find_profile(profile).is_none() {
+                return Err(format!("unknown profile {}", profile));
+            }
+        }
+
+        if !self.overlays.is_empty() && !arch_info.devicetree {
+            return Err(format!("devicetree overlays are not supported on {}", arch));
+        }
+        if self.overlay_mode == OverlayMode::Merge && self.base_dtb.is_none() && !self.qemu_dtb {
+            return Err("overlay_mode = \"merge\" requires base_dtb or qemu_dtb".to_string());
+        }
+        crate::dtb::validate(self, arch_info)?;
+        crate::board::validate(self, arch_info)?;
+
+        // The restricted pool is described in the devicetree
+        if self.dma == Some(DmaMode::RestrictedPool) && !arch_info.devicetree {
+            return Err(format!(
+                "dma = \"restricted-pool\" is not supported on {}",
+                arch
+            ));
+        }
+
+        if self.orc && arch != "x86" {
+            return Err(format!("orc unwind info is not available on {}", arch));
+        }
+
+        if let Some(workspace) = &self.rust_workspace
+            && !Path::new(workspace).join("Cargo.toml").is_file()
+        {
+            return Err(format!("rust_workspace {} has no Cargo.toml", workspace));
+        }
+
+        if self.ssh_port.is_some() && !self.ssh {
+            return Err("ssh_port requires ssh = true".to_string());
+        }
+        if self.ssh && self.httpd {
+            let ssh_port = self.ssh_port.unwrap_or(crate::ssh::DEFAULT_SSH_PORT);
+            if ssh_port == self.httpd_port.unwrap_or(crate::httpd::DEFAULT_HTTPD_PORT) {
+                return Err(format!(
+                    "ssh and httpd would both forward host port {}; set ssh_port or httpd_port to another port",
+                    ssh_port
+                ));
+            }
+        }
+        if self.nfs_server.is_some() && !self.nfsroot {
+            return Err("nfs_server requires nfsroot = true".to_string());
+        }
+        if self.readonly_root && self.nfsroot {
+            return Err("readonly_root is not supported with nfsroot".to_string());
+        }
+        // The verity device is read-only, so writes need the overlay
+        if self.verity && !self.readonly_root {
+            return Err("verity requires readonly_root = true".to_string());
+        }
+        if self.image_format.read_only() && !self.readonly_root {
+            return Err(format!(
+                "image_format = \"{}\" requires readonly_root = true",
+                self.image_format.name()
+            ));
+        }
+        if self.initramfs_embed && !self.initramfs {
+            return Err("initramfs_embed requires initramfs = true".to_string());
+        }
+        if self.initramfs && (self.nfsroot || self.readonly_root || self.verity || self.luks) {
+            return Err(
+                "initramfs cannot be combined with nfsroot, readonly_root, verity or luks"
+                    .to_string(),
+            );
+        }
+        if self.luks && (self.verity || self.nfsroot) {
+            return Err("luks cannot be combined with verity or nfsroot".to_string());
+        }
+        if (self.module_signing_key.is_some() || self.module_sig_enforce) && !self.module_signing {
+            return Err(
+                "module_signing_key and module_sig_enforce require module_signing = true"
+                    .to_string(),
+            );
+        }
+        if let Some(key) = &self.module_signing_key
+            && !Path::new(key).is_file()
+        {
+            return Err(format!("module_signing_key {} does not exist", key));
+        }
+        if self.luks_passphrase.is_some() && !self.luks {
+            return Err("luks_passphrase requires luks = true".to_string());
+        }
+        // Their parameters come from the rootfs, which is built after the kernel
+        if self.builtin_cmdline.is_some() && (self.verity || self.luks) {
+            return Err("builtin_cmdline cannot be combined with verity or luks".to_string());
+        }
+
+        // e.g. QEMU's virt machine has no 8250 and x86 has no pl011
+        if let Some(console) = self.console
+            && !arch_info.consoles.contains(&console)
+        {
+            return Err(format!(
+                "console {} is not available on {}",
+                console.name(),
+                arch
+            ));
+        }
+        Ok(())
+    }
+
+    /// Console transport for the given architecture
+    pub fn console(&self, arch: &str) -> Console {
+        self.console
+            .unwrap_or_else(|| Console::default_for_arch(arch))
+    }
+
+    /// Kernel options required by these settings
+    pub fn kernel_options(&self, arch: &str) -> Vec<(String, String)> {
+        let mut options = Vec::new();
+
+        for option in self.console(arch).kernel_options() {
+            options.push((option.to_string(), "y".to_string()));
+        }
+        for option in self.dma.map_or(&[][..], |dma| dma.kernel_options()) {
+            options.push((option.to_string(), "y".to_string()));
+        }
+        // Module placement is randomized along with the kernel image
+        match self.kaslr {
+            Some(true) => options.push(("CONFIG_RANDOMIZE_BASE".to_string(), "y".to_string())),
+            Some(false) => options.push(("CONFIG_RANDOMIZE_BASE".to_string(), "n".to_string())),
+            None => {}
+        }
+        if self.orc {
+            options.push(("CONFIG_UNWINDER_ORC".to_string(), "y".to_string()));
+            options.push(("CONFIG_UNWINDER_FRAME_POINTER".to_string(), "n".to_string()));
+        }
+        for option in self
+            .display
+            .map_or(&[][..], |display| display.kernel_options())
+        {
+            options.push((option.to_string(), "y".to_string()));
+        }
+        for option in crate::disks::kernel_options(self) {
+            options.push((option.to_string(), "y".to_string()));
+        }
+        for option in crate::swap::kernel_options(self) {
+            options.push((option.to_string(), "y".to_string()));
+        }
+        if self.httpd || self.ssh || self.nfsroot {
+            for option in NIC_KERNEL_OPTIONS {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+        if self.vmm_artifacts {
+            for option in crate::vmm::kernel_options(arch) {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+        if crate::clock::configured(self) {
+            for option in crate::clock::RTC_KERNEL_OPTIONS {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+        for option in self.tpm.map_or(&[][..], |tpm| tpm.kernel_options()) {
+            options.push((option.to_string(), "y".to_string()));
+        }
+        for option in self
+            .firmware
+            .map_or(&[][..], |firmware| firmware.kernel_options())
+        {
+            options.push((option.to_string(), "y".to_string()));
+        }
+        if self.pstore {
+            for option in crate::pstore::KERNEL_OPTIONS {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+        if self.initramfs {
+            for option in crate::rootfs::INITRAMFS_KERNEL_OPTIONS {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+        if self.readonly_root {
+            options.push(("CONFIG_TMPFS".to_string(), "y".to_string()));
+            options.push(("CONFIG_OVERLAY_FS".to_string(), "y".to_string()));
+        }
+        for option in self.image_format.kernel_options() {
+            options.push((option.to_string(), "y".to_string()));
+        }
+        if self.verity {
+            for option in crate::verity::VERITY_KERNEL_OPTIONS {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+        if self.luks {
+            for option in crate::luks::LUKS_KERNEL_OPTIONS {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+        if self.nfsroot {
+            for option in crate::nfs::NFSROOT_KERNEL_OPTIONS {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+        for profile in crate::profile::enabled_profiles(self) {
+            for option in profile.kernel_options {
+                options.push((option.to_string(), "y".to_string()));
+            }
+        }
+
+        // arm64 selects both GIC drivers; make sure the targeted one and its
+        // MSI controller stay enabled
+        match self.gic {
+            Some(2) => {
+                options.push(("CONFIG_ARM_GIC".to_string(), "y".to_string()));
+                options.push(("CONFIG_ARM_GIC_V2M".to_string(), "y".to_string()));
+            }
+            Some(3) => {
+                options.push(("CONFIG_ARM_GIC_V3".to_string(), "y".to_string()));
+                options.push(("CONFIG_ARM_GIC_V3_ITS".to_string(), "y".to_string()));
+            }
+            _ => {}
+        }
+
+        options
+    }
+}
+
 /// Check if the given config name is valid and exists
 pub fn is_valid_config(config_name: &str) -> bool {
-    let parts: Vec<&str> = config_name.split('-').collect();
-    if parts.len() < 2 {
+    let (arch, name) = match resolve_config_name(config_name) {
+        Ok(parts) => parts,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+
+    let config_path = PathBuf::from("config").join(&arch).join(&name);
+    if !config_path.is_file() {
         return false;
     }
 
-    let arch = parts[0];
-    let name = parts[1..].join("-");
-
-    let config_path = PathBuf::from("config").join(arch).join(&name);
-    config_path.exists()
+    match split_variant(config_name).1 {
+        Some(variant) => variant_names(&arch, &name).iter().any(|v| v == variant),
+        None => true,
+    }
 }
 
-/// List all available configurations
-pub fn list_configs() {
-    println!("Available configurations:");
+/// List all available configurations, variants included, sorted by name
+pub fn list_configs() -> Vec<String> {
     let config_dir = Path::new("config");
 
     if !config_dir.exists() {
         eprintln!("Config directory does not exist.");
-        return;
+        return Vec::new();
     }
 
     let mut configs = Vec::new();
@@ -40,9 +579,13 @@ pub fn list_configs() {
                 let config_entry = config_entry.expect("Failed to read config entry");
                 let config_path = config_entry.path();
 
-                if config_path.is_file() {
+                // Files with an extension (e.g. `qemu.toml`) are companions of a config
+                if config_path.is_file() && config_path.extension().is_none() {
                     let config_name = config_path.file_name().unwrap().to_str().unwrap();
                     let full_config_name = format!("{}-{}", arch_name, config_name);
+                    for variant in variant_names(arch_name, config_name) {
+                        configs.push(format!("{}@{}", full_config_name, variant));
+                    }
                     configs.push(full_config_name);
                 }
             }
@@ -51,20 +594,244 @@ pub fn list_configs() {
 
     // Sort configurations for consistent output
     configs.sort();
+    configs
+}
 
-    if configs.is_empty() {
-        println!("No configurations found.");
-    } else {
-        for config in configs {
-            println!("  {}", config);
+/// Split a config name into its base name and optional variant (`arm64-qemu@gicv2`)
+pub fn split_variant(config_name: &str) -> (&str, Option<&str>) {
+    match config_name.split_once('@') {
+        Some((base, variant)) => (base, Some(variant)),
+        None => (config_name, None),
+    }
+}
+
+/// Names of the architecture directories under `config/`
+fn config_arches() -> Vec<String> {
+    let Ok(entries) = fs::read_dir("config") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+/// Split a config name against the `config/<arch>/` directories, ignoring any variant
+///
+/// Both arch and config names may contain dashes: the arch is the directory whose
+/// name is a prefix of the config name and which holds the rest as a config.
+/// Without such a config the longest matching directory wins.
+pub fn resolve_config_name(config_name: &str) -> Result<(String, String), String> {
+    resolve_against(config_name, config_arches(), |arch, name| {
+        Path::new("config").join(arch).join(name).is_file()
+    })
+}
+
+/// `resolve_config_name` against the given arch directories, with `exists` telling
+/// whether `config/<arch>/<name>` is a config
+fn resolve_against(
+    config_name: &str,
+    arches: Vec<String>,
+    exists: impl Fn(&str, &str) -> bool,
+) -> Result<(String, String), String> {
+    let (base, _) = split_variant(config_name);
+    let mut candidates: Vec<(String, String)> = arches
+        .into_iter()
+        .filter_map(|arch| {
+            let name = base.strip_prefix(arch.as_str())?.strip_prefix('-')?;
+            (!name.is_empty()).then(|| (arch.clone(), name.to_string()))
+        })
+        .collect();
+    candidates.sort_by_key(|(arch, _)| std::cmp::Reverse(arch.len()));
+
+    let existing: Vec<&(String, String)> = candidates
+        .iter()
+        .filter(|(arch, name)| exists(arch, name))
+        .collect();
+    match (existing.as_slice(), candidates.first()) {
+        ([found], _) => Ok((*found).clone()),
+        ([], Some(longest)) => Ok(longest.clone()),
+        ([], None) => Err(format!(
+            "{} does not start with an architecture from config/ followed by '-'",
+            base
+        )),
+        (found, _) => {
+            let paths: Vec<String> = found
+                .iter()
+                .map(|(arch, name)| format!("config/{}/{}", arch, name))
+                .collect();
+            Err(format!(
+                "{} is ambiguous, it matches {}",
+                base,
+                paths.join(" and ")
+            ))
         }
     }
 }
 
-/// Parse config name into arch and name components
+/// Parse config name into arch and name components, ignoring any variant
+///
+/// Names that do not resolve (see `resolve_config_name`) split at the first '-'.
 pub fn parse_config_name(config_name: &str) -> (String, String) {
-    let parts: Vec<&str> = config_name.split('-').collect();
-    let arch = parts[0].to_string();
-    let name = parts[1..].join("-");
-    (arch, name)
+    resolve_config_name(config_name).unwrap_or_else(|_| {
+        let (base, _) = split_variant(config_name);
+        let (arch, name) = base.split_once('-').unwrap_or((base, ""));
+        (arch.to_string(), name.to_string())
+    })
+}
+
+/// Get the manifest path for a config
+pub fn manifest_file(arch: &str, name: &str) -> PathBuf {
+    PathBuf::from("config")
+        .join(arch)
+        .join(format!("{}.toml", name))
+}
+
+/// Read the raw manifest table of a config, if it has one
+fn read_manifest_table(arch: &str, name: &str) -> Result<toml::Table, String> {
+    let path = manifest_file(arch, name);
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+
+    let content = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    content
+        .parse::<toml::Table>()
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// List the variants declared in a config manifest
+fn variant_names(arch: &str, name: &str) -> Vec<String> {
+    match read_manifest_table(arch, name) {
+        Ok(table) => match table.get("variants") {
+            Some(toml::Value::Table(variants)) => variants.keys().cloned().collect(),
+            _ => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Recursively merge `overlay` into `base`, with `overlay` winning
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Load the manifest of a config, applying the variant's overrides if one is named
+pub fn load_manifest(config_name: &str) -> Result<ConfigManifest, String> {
+    let (arch, name) = parse_config_name(config_name);
+    let mut table = read_manifest_table(&arch, &name)?;
+
+    let variants = table.remove("variants");
+    if let Some(variant) = split_variant(config_name).1 {
+        let overrides = match variants {
+            Some(toml::Value::Table(mut variants)) => variants.remove(variant),
+            _ => None,
+        };
+        match overrides {
+            Some(toml::Value::Table(overrides)) => merge_tables(&mut table, overrides),
+            _ => return Err(format!("unknown variant {} of {}", variant, config_name)),
+        }
+    }
+
+    let manifest: ConfigManifest = toml::Value::Table(table)
+        .try_into()
+        .map_err(|e| format!("{}: {}", manifest_file(&arch, &name).display(), e))?;
+    manifest.validate(&arch)?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arches(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn resolve(config_name: &str, configs: &[(&str, &str)]) -> Result<(String, String), String> {
+        resolve_against(
+            config_name,
+            arches(&["arm64", "x86_64", "x86_64-v2"]),
+            |arch, name| configs.contains(&(arch, name)),
+        )
+    }
+
+    fn split(arch: &str, name: &str) -> Result<(String, String), String> {
+        Ok((arch.to_string(), name.to_string()))
+    }
+
+    fn check(manifest: &str) -> Result<(), String> {
+        toml::from_str::<ConfigManifest>(manifest)
+            .unwrap()
+            .validate("x86")
+    }
+
+    #[test]
+    fn forwarded_ports_differ() {
+        assert!(check("ssh = true\nhttpd = true").is_ok());
+        assert!(check("ssh = true\nhttpd = true\nhttpd_port = 2222").is_err());
+        assert!(check("ssh = true\nhttpd = true\nssh_port = 8080").is_err());
+        assert!(check("ssh = true\nhttpd = true\nssh_port = 9000\nhttpd_port = 9000").is_err());
+        assert!(check("ssh = true\nhttpd = true\nssh_port = 2200\nhttpd_port = 2222").is_ok());
+    }
+
+    #[test]
+    fn dashed_arch_names() {
+        assert_eq!(
+            resolve("x86_64-v2-foo", &[("x86_64-v2", "foo")]),
+            split("x86_64-v2", "foo")
+        );
+        // The config decides which arch a dashed name belongs to
+        assert_eq!(
+            resolve("x86_64-v2-foo", &[("x86_64", "v2-foo")]),
+            split("x86_64", "v2-foo")
+        );
+    }
+
+    #[test]
+    fn longest_prefix_without_a_config() {
+        assert_eq!(resolve("x86_64-v2-foo", &[]), split("x86_64-v2", "foo"));
+        assert_eq!(resolve("arm64-qemu", &[]), split("arm64", "qemu"));
+    }
+
+    #[test]
+    fn ambiguous_names() {
+        let err = resolve(
+            "x86_64-v2-foo",
+            &[("x86_64", "v2-foo"), ("x86_64-v2", "foo")],
+        )
+        .unwrap_err();
+        assert!(err.contains("ambiguous"), "{}", err);
+        assert!(err.contains("config/x86_64/v2-foo"), "{}", err);
+        assert!(err.contains("config/x86_64-v2/foo"), "{}", err);
+    }
+
+    #[test]
+    fn variants_are_ignored() {
+        assert_eq!(
+            resolve("arm64-qemu@zram", &[("arm64", "qemu")]),
+            split("arm64", "qemu")
+        );
+        assert_eq!(
+            resolve("x86_64-v2-foo@x", &[("x86_64-v2", "foo")]),
+            split("x86_64-v2", "foo")
+        );
+    }
+
+    #[test]
+    fn names_without_an_arch() {
+        assert!(resolve("sparc-qemu", &[]).is_err());
+        assert!(resolve("arm64", &[]).is_err());
+        assert!(resolve("arm64-", &[]).is_err());
+    }
 }
@@ -0,0 +1,55 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::qmp::Qmp;
+
+/// Console line of a kernel panic; a guest that panicked will not report its test results
+pub const PANIC_SIGNATURE: &str = "Kernel panic - not syncing";
+
+/// Kernel build tree holding `vmlinux`; the guest state goes next to it, ready for
+/// `crash vmlinux vmcore`
+fn state_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("linux")
+}
+
+/// Stop the guest and capture its vCPU registers and memory over QMP
+///
+/// Writes `registers.txt` and the ELF `vmcore` next to `vmlinux`, returning the files
+/// that were written. Whatever cannot be captured is reported and skipped.
+pub fn capture(config_name: &str, qmp_socket: &Path) -> Vec<PathBuf> {
+    let dir = state_dir(config_name);
+    let mut qmp = match Qmp::connect(qmp_socket, Duration::from_secs(5)) {
+        Ok(qmp) => qmp,
+        Err(e) => {
+            eprintln!("Failed to capture the guest state: {}", e);
+            return Vec::new();
+        }
+    };
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Failed to create {}: {}", dir.display(), e);
+        return Vec::new();
+    }
+    // A stopped guest keeps its registers consistent with the memory dump
+    if let Err(e) = qmp.execute("stop", serde_json::json!({})) {
+        eprintln!("Warning: could not stop the guest: {}", e);
+    }
+
+    let mut captured = Vec::new();
+    let registers = dir.join("registers.txt");
+    match qmp
+        .human_monitor_command("info registers -a")
+        .and_then(|output| fs::write(&registers, output).map_err(|e| e.to_string()))
+    {
+        Ok(()) => captured.push(registers),
+        Err(e) => eprintln!("Failed to capture the vCPU registers: {}", e),
+    }
+
+    let vmcore = dir.join("vmcore");
+    println!("Dumping guest memory to {}...", vmcore.display());
+    match qmp.dump_guest_memory(&vmcore) {
+        Ok(()) => captured.push(vmcore),
+        Err(e) => eprintln!("Failed to dump guest memory: {}", e),
+    }
+    captured
+}
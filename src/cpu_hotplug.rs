@@ -0,0 +1,84 @@
+use serde_json::{Value, json};
+
+use crate::config::ConfigManifest;
+use crate::qmp::Qmp;
+
+/// vCPUs the guest boots with unless the config manifest sets `vcpus`
+pub const BOOT_CPUS: u32 = 2;
+
+/// vCPUs the guest can have once the harness plugged the rest
+pub const MAX_CPUS: u32 = 4;
+
+/// Printed by payloads/cpu-hotplug once its workers run and it waits for new vCPUs
+pub const READY_MARKER: &str = "cpu-hotplug: ready";
+
+/// Printed by payloads/cpu-hotplug once the added vCPUs are online and used
+pub const ADDED_MARKER: &str = "cpu-hotplug: added";
+
+/// Prefix of the QOM ids given to plugged vCPUs
+const ID_PREFIX: &str = "hotcpu";
+
+/// Whether QEMU can hot-add vCPUs on the arch's machine
+///
+/// Elsewhere (arm64's virt) the guest offlines and onlines its own vCPUs instead,
+/// which goes through the hypervisor's PSCI CPU_OFF/CPU_ON path.
+pub fn qmp_hotplug(arch: &str) -> bool {
+    arch == "x86"
+}
+
+/// Check the vCPU count of a manifest; hotplug needs free slots above it
+pub fn validate(manifest: &ConfigManifest, arch: &str) -> Result<(), String> {
+    let hotplug = manifest.profiles.iter().any(|p| p == "cpu-hotplug");
+    match manifest.vcpus {
+        Some(0) => Err("vcpus must be at least 1".to_string()),
+        Some(vcpus) if hotplug && qmp_hotplug(arch) && vcpus >= MAX_CPUS => Err(format!(
+            "the cpu-hotplug profile needs vcpus below {}",
+            MAX_CPUS
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// `-smp` value for a configuration
+pub fn smp_arg(arch: &str, manifest: &ConfigManifest) -> String {
+    let vcpus = manifest.vcpus.unwrap_or(BOOT_CPUS);
+    if manifest.profiles.iter().any(|p| p == "cpu-hotplug") && qmp_hotplug(arch) {
+        format!("{},maxcpus={}", vcpus, MAX_CPUS)
+    } else {
+        vcpus.to_string()
+    }
+}
+
+/// Plug every vCPU slot QEMU reports as empty; returns the ids of the new devices
+pub fn add_cpus(qmp: &mut Qmp) -> Result<Vec<String>, String> {
+    let slots = qmp.execute("query-hotpluggable-cpus", json!({}))?;
+    let mut ids = Vec::new();
+    for slot in slots.as_array().into_iter().flatten() {
+        if slot.get("qom-path").is_some() {
+            continue;
+        }
+        let id = format!("{}{}", ID_PREFIX, ids.len());
+        let mut arguments = slot["props"].clone();
+        let Value::Object(fields) = &mut arguments else {
+            return Err("query-hotpluggable-cpus returned no props".to_string());
+        };
+        fields.insert("driver".to_string(), slot["type"].clone());
+        fields.insert("id".to_string(), json!(id));
+        println!("Adding vCPU {} ({})...", id, slot["props"]);
+        qmp.execute("device_add", arguments)?;
+        ids.push(id);
+    }
+    if ids.is_empty() {
+        return Err("no free vCPU slots".to_string());
+    }
+    Ok(ids)
+}
+
+/// Request removal of plugged vCPUs; the guest ejects them through ACPI
+pub fn remove_cpus(qmp: &mut Qmp, ids: &[String]) -> Result<(), String> {
+    for id in ids {
+        println!("Removing vCPU {}...", id);
+        qmp.execute("device_del", json!({ "id": id }))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+
+use crate::config::{load_manifest, parse_config_name};
+use crate::qemu::{LaunchOptions, is_built, launch};
+use crate::smoke::INIT_MARKER;
+
+/// What the hypervisor is configured to do when the guest panics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PanicPolicy {
+    /// Reset the guest, which must boot back to init
+    Restart,
+    /// Stop the guest for good
+    Halt,
+    /// Report the panic to the host through pvpanic
+    Notify,
+}
+
+/// QEMU arguments and kernel parameters emulating a panic policy
+fn launch_options(arch: &str, policy: PanicPolicy, run_id: &str) -> LaunchOptions {
+    let mut options = LaunchOptions {
+        extra_cmdline: vec![format!("crash_test={}", run_id)],
+        ..LaunchOptions::default()
+    };
+
+    match policy {
+        PanicPolicy::Restart => {
+            options.reboot = true;
+            options.extra_cmdline.push("panic=1".to_string());
+        }
+        PanicPolicy::Halt => {
+            options.extra_cmdline.push("panic=1".to_string());
+        }
+        PanicPolicy::Notify => {
+            // The guest waits forever; only the pvpanic notification ends the run
+            options.extra_cmdline.push("panic=0".to_string());
+            let pvpanic = match arch {
+                "x86" => "pvpanic",
+                _ => "pvpanic-pci",
+            };
+            options.extra_args = vec![
+                "-device".to_string(),
+                pvpanic.to_string(),
+                "-action".to_string(),
+                "panic=exit-failure".to_string(),
+            ];
+        }
+    }
+
+    options
+}
+
+/// Print the outcome of a step
+fn report(ok: bool, step: &str) -> bool {
+    println!("  {:<4}  {}", if ok { "ok" } else { "FAIL" }, step);
+    ok
+}
+
+/// Check that the restarted guest replayed the panic from pstore, keeping the records
+fn pstore_recovered(config_name: &str, console: &str) -> bool {
+    let records = crate::pstore::records(console);
+    let dir = std::path::PathBuf::from("build")
+        .join(config_name)
+        .join("pstore");
+    let saved = crate::pstore::save_records(&dir, &records);
+    let panic_logged = records.iter().any(|(name, content)| {
+        name.starts_with("dmesg-") && String::from_utf8_lossy(content).contains("Kernel panic")
+    });
+    let ok = report(panic_logged, "panic log recovered from pstore");
+    for path in saved {
+        println!("        {}", path.display());
+    }
+    ok
+}
+
+/// Make the guest panic and check that the panic policy is carried out
+pub fn crash_recovery_test(config_name: &str, policy: PanicPolicy, timeout: Duration) -> bool {
+    let manifest = match load_manifest(config_name) {
+        Ok(manifest) if manifest.profiles.iter().any(|p| p == "crash") => manifest,
+        Ok(_) => {
+            eprintln!("{} does not enable the crash profile", config_name);
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    };
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+
+    let (arch, _) = parse_config_name(config_name);
+    let run_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs().to_string())
+        .unwrap_or_else(|_| "0".to_string());
+    let mut session = match launch(config_name, &launch_options(&arch, policy, &run_id)) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Failed to start QEMU: {}", e);
+            return false;
+        }
+    };
+
+    println!(
+        "Crash recovery test for {} with policy {:?}...",
+        config_name, policy
+    );
+    let mut passed = report(session.expect(INIT_MARKER, timeout), "guest reached init")
+        && report(
+            session.expect("crash-recovery: triggering panic", timeout),
+            "panic triggered",
+        )
+        && report(session.expect("Kernel panic", timeout), "guest panicked");
+
+    if passed {
+        passed = match policy {
+            PanicPolicy::Restart => {
+                report(
+                    session.expect(INIT_MARKER, timeout),
+                    "guest restarted to init",
+                ) && report(
+                    session.expect("crash-recovery: recovered after panic", timeout),
+                    "guest recovered",
+                ) && (!manifest.pstore || pstore_recovered(config_name, session.output()))
+            }
+            PanicPolicy::Halt => {
+                let status = session.wait_exit(timeout);
+                report(status.is_some(), "guest halted")
+                    && report(
+                        !session.output().contains("crash-recovery: recovered"),
+                        "guest did not restart",
+                    )
+            }
+            PanicPolicy::Notify => {
+                let status = session.wait_exit(timeout);
+                report(
+                    status.is_some_and(|s| !s.success()),
+                    "panic notification reached the host",
+                )
+            }
+        };
+    }
+    session.kill();
+
+    println!(
+        "Crash recovery test {} for {}",
+        if passed { "passed" } else { "failed" },
+        config_name
+    );
+    passed
+}
@@ -0,0 +1,140 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::arch::arch_of;
+use crate::config::ConfigManifest;
+use crate::memory::parse_size;
+
+/// Longest serial a virtio-blk device reports to the guest
+const MAX_NAME_LEN: usize = 20;
+
+/// Contents a disk is created with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiskFormat {
+    /// Zero-filled, for tests that write the block device directly
+    #[default]
+    Raw,
+    /// Empty ext4 filesystem labelled with the disk name
+    Ext4,
+}
+
+/// An additional disk, e.g. `{ name = "data", size = "256M", format = "ext4" }`
+///
+/// The guest finds it as `/dev/disk/by-name/<name>`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Disk {
+    pub name: String,
+    pub size: String,
+    #[serde(default)]
+    pub format: DiskFormat,
+    /// Keep the image and its contents across boots instead of recreating it
+    #[serde(default)]
+    pub persistent: bool,
+}
+
+/// Check the declared disks of a manifest
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    for (i, disk) in manifest.disks.iter().enumerate() {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if disk.name.is_empty()
+            || disk.name.len() > MAX_NAME_LEN
+            || !disk.name.chars().all(valid_char)
+        {
+            return Err(format!(
+                "invalid disk name {:?}: use up to {} letters, digits, '-' or '_'",
+                disk.name, MAX_NAME_LEN
+            ));
+        }
+        if manifest.disks[..i].iter().any(|d| d.name == disk.name) {
+            return Err(format!("duplicate disk {}", disk.name));
+        }
+        if parse_size(&disk.size)? == 0 {
+            return Err(format!("disk {} has no size", disk.name));
+        }
+    }
+    Ok(())
+}
+
+/// The declared disks plus the one a swap partition lives on
+pub fn declared(manifest: &ConfigManifest) -> Vec<Disk> {
+    let mut disks = manifest.disks.clone();
+    disks.extend(crate::swap::disk(manifest));
+    disks
+}
+
+/// Kernel options needed by the declared disks
+pub fn kernel_options(manifest: &ConfigManifest) -> Vec<&'static str> {
+    let mut options = Vec::new();
+    if !declared(manifest).is_empty() {
+        options.extend(["CONFIG_PCI", "CONFIG_VIRTIO_PCI", "CONFIG_VIRTIO_BLK"]);
+    }
+    if manifest.disks.iter().any(|d| d.format == DiskFormat::Ext4) {
+        options.push("CONFIG_EXT4_FS");
+    }
+    options
+}
+
+/// Image backing a disk
+pub fn disk_image(config_name: &str, disk: &Disk) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("disks")
+        .join(format!("{}.img", disk.name))
+}
+
+/// Create the disk images for a boot; ephemeral disks start out empty every time
+pub fn prepare_disks(config_name: &str, manifest: &ConfigManifest) -> Result<(), String> {
+    for disk in &declared(manifest) {
+        let image = disk_image(config_name, disk);
+        if disk.persistent && image.exists() {
+            continue;
+        }
+
+        if let Some(dir) = image.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let _ = fs::remove_file(&image);
+        let size = parse_size(&disk.size)?;
+        File::create(&image)
+            .and_then(|f| f.set_len(size))
+            .map_err(|e| format!("create {}: {}", image.display(), e))?;
+
+        if disk.format == DiskFormat::Ext4 {
+            let status = Command::new("mkfs.ext4")
+                .args(["-q", "-F", "-L", &disk.name])
+                .arg(&image)
+                .status()
+                .map_err(|e| format!("mkfs.ext4: {}", e))?;
+            if !status.success() {
+                let _ = fs::remove_file(&image);
+                return Err(format!("mkfs.ext4 failed for disk {}", disk.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// QEMU arguments attaching the declared disks as virtio-blk devices named by serial
+pub fn qemu_args(config_name: &str, manifest: &ConfigManifest) -> Vec<String> {
+    let suffix = arch_of(config_name).map_or("pci", |a| a.virtio_suffix);
+    let mut args = Vec::new();
+    for disk in &declared(manifest) {
+        args.push("-drive".to_string());
+        args.push(format!(
+            "if=none,id=disk-{},format=raw,file={}",
+            disk.name,
+            disk_image(config_name, disk).display()
+        ));
+        args.push("-device".to_string());
+        args.push(format!(
+            "virtio-blk-{},drive=disk-{},serial={}",
+            suffix, disk.name, disk.name
+        ));
+    }
+    args
+}
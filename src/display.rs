@@ -0,0 +1,226 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::config::parse_config_name;
+use crate::qmp::Qmp;
+
+/// Directory holding golden screenshots, as `<arch>/<name>/<checkpoint>.png`
+const GOLDEN_DIR: &str = "tests/screenshots";
+
+/// Channel difference up to which a pixel still counts as matching
+const CHANNEL_TOLERANCE: u8 = 16;
+
+/// Share of differing pixels allowed when a checkpoint does not set its own
+pub const DEFAULT_MAX_DIFF_PERCENT: f64 = 0.5;
+
+/// Emulated display device of a configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayDevice {
+    VirtioGpu,
+}
+
+impl DisplayDevice {
+    /// Kernel options for the driver and a framebuffer console on it
+    pub fn kernel_options(self) -> &'static [&'static str] {
+        match self {
+            DisplayDevice::VirtioGpu => &[
+                "CONFIG_PCI",
+                "CONFIG_VIRTIO_PCI",
+                "CONFIG_DRM",
+                "CONFIG_DRM_VIRTIO_GPU",
+                "CONFIG_DRM_FBDEV_EMULATION",
+                "CONFIG_FRAMEBUFFER_CONSOLE",
+            ],
+        }
+    }
+
+    /// QEMU device providing the display
+    pub fn qemu_device(self) -> &'static str {
+        match self {
+            DisplayDevice::VirtioGpu => "virtio-gpu-pci",
+        }
+    }
+}
+
+/// An 8-bit RGB image
+struct Image {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+}
+
+/// Read the binary PPM (P6) written by QEMU's screendump
+fn read_ppm(path: &Path) -> Result<Image, String> {
+    let data = fs::read(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+
+    // Header: magic, width, height and maxval separated by whitespace
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while data.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        let start = pos;
+        while data.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("truncated PPM header".to_string());
+        }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+    }
+    if fields[0] != "P6" || fields[3] != "255" {
+        return Err(format!(
+            "unsupported PPM {} maxval {}",
+            fields[0], fields[3]
+        ));
+    }
+    let width: u32 = fields[1].parse().map_err(|_| "bad PPM width")?;
+    let height: u32 = fields[2].parse().map_err(|_| "bad PPM height")?;
+
+    let rgb = data
+        .get(pos + 1..pos + 1 + (width * height * 3) as usize)
+        .ok_or("truncated PPM data")?
+        .to_vec();
+    Ok(Image { width, height, rgb })
+}
+
+/// Read a PNG, converting it to 8-bit RGB
+fn read_png(path: &Path) -> Result<Image, String> {
+    let file = File::open(path).map_err(|e| format!("open {}: {}", path.display(), e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    buf.truncate(info.buffer_size());
+
+    let rgb = match info.color_type {
+        png::ColorType::Rgb => buf,
+        png::ColorType::Rgba => buf.chunks(4).flat_map(|px| [px[0], px[1], px[2]]).collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v]).collect(),
+        png::ColorType::GrayscaleAlpha => {
+            buf.chunks(2).flat_map(|px| [px[0], px[0], px[0]]).collect()
+        }
+        png::ColorType::Indexed => return Err("indexed PNG not expanded".to_string()),
+    };
+    Ok(Image {
+        width: info.width,
+        height: info.height,
+        rgb,
+    })
+}
+
+/// Write an image as PNG
+fn write_png(path: &Path, image: &Image) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width, image.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(&image.rgb)
+        .map_err(|e| e.to_string())
+}
+
+/// Compare two images, returning the share of differing pixels in percent and a
+/// copy of `actual` with the differing pixels marked red
+fn compare(actual: &Image, golden: &Image) -> Result<(f64, Image), String> {
+    if (actual.width, actual.height) != (golden.width, golden.height) {
+        return Err(format!(
+            "size {}x{} differs from golden {}x{}",
+            actual.width, actual.height, golden.width, golden.height
+        ));
+    }
+
+    let mut diff = actual.rgb.clone();
+    let mut differing = 0usize;
+    for (i, (a, g)) in actual.rgb.chunks(3).zip(golden.rgb.chunks(3)).enumerate() {
+        if a.iter()
+            .zip(g)
+            .any(|(a, g)| a.abs_diff(*g) > CHANNEL_TOLERANCE)
+        {
+            differing += 1;
+            diff[i * 3..i * 3 + 3].copy_from_slice(&[255, 0, 0]);
+        }
+    }
+    let pixels = (actual.width * actual.height).max(1) as f64;
+    Ok((
+        differing as f64 * 100.0 / pixels,
+        Image {
+            width: actual.width,
+            height: actual.height,
+            rgb: diff,
+        },
+    ))
+}
+
+/// Golden screenshot of a checkpoint, shared by all variants of a configuration
+fn golden_path(config_name: &str, checkpoint: &str) -> PathBuf {
+    let (arch, name) = parse_config_name(config_name);
+    Path::new(GOLDEN_DIR)
+        .join(arch)
+        .join(name)
+        .join(format!("{}.png", checkpoint))
+}
+
+/// Capture the guest display and compare it against the checkpoint's golden image
+///
+/// The capture is kept as `build/<config>/screenshots/<checkpoint>.png`, with a
+/// `-diff.png` next to it on mismatch. With `update_golden` the capture replaces the
+/// golden image instead.
+pub fn check_screenshot(
+    qmp: &mut Qmp,
+    config_name: &str,
+    checkpoint: &str,
+    max_diff_percent: f64,
+    update_golden: bool,
+) -> Result<(), String> {
+    let dir = std::env::current_dir()
+        .map_err(|e| e.to_string())?
+        .join("build")
+        .join(config_name)
+        .join("screenshots");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let ppm = dir.join(format!("{}.ppm", checkpoint));
+    qmp.screendump(&ppm)?;
+    let actual = read_ppm(&ppm)?;
+    let _ = fs::remove_file(&ppm);
+    let capture = dir.join(format!("{}.png", checkpoint));
+    write_png(&capture, &actual)?;
+
+    let golden = golden_path(config_name, checkpoint);
+    if update_golden {
+        if let Some(parent) = golden.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::copy(&capture, &golden).map_err(|e| e.to_string())?;
+        println!("  updated golden {}", golden.display());
+        return Ok(());
+    }
+    if !golden.exists() {
+        return Err(format!(
+            "no golden image {}, run with --update-golden to create it",
+            golden.display()
+        ));
+    }
+
+    let (diff_percent, diff) = compare(&actual, &read_png(&golden)?)?;
+    if diff_percent > max_diff_percent {
+        let diff_path = dir.join(format!("{}-diff.png", checkpoint));
+        write_png(&diff_path, &diff)?;
+        return Err(format!(
+            "screenshot {} differs by {:.2}% (max {}%), see {}",
+            checkpoint,
+            diff_percent,
+            max_diff_percent,
+            diff_path.display()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::config::ConfigManifest;
+use crate::memory::{ARM64_RAM_BASE, DEFAULT_MEMORY, format_size, memory_size};
+
+/// Size of the restricted DMA pool carved out of guest RAM
+const RESTRICTED_POOL_SIZE: u64 = 16 * 1024 * 1024;
+
+/// How the guest routes device DMA
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DmaMode {
+    /// Bounce all streaming DMA through the global swiotlb buffer
+    Swiotlb,
+    /// Confine device DMA to a `restricted-dma-pool` reserved-memory region
+    RestrictedPool,
+}
+
+impl DmaMode {
+    /// Kernel options needed by this mode
+    pub fn kernel_options(self) -> &'static [&'static str] {
+        match self {
+            DmaMode::Swiotlb => &[],
+            DmaMode::RestrictedPool => &["CONFIG_DMA_RESTRICTED_POOL"],
+        }
+    }
+
+    /// Kernel command line parameters for this mode
+    pub fn cmdline(self) -> &'static [&'static str] {
+        match self {
+            DmaMode::Swiotlb => &["swiotlb=force"],
+            DmaMode::RestrictedPool => &[],
+        }
+    }
+}
+
+/// Write the restricted DMA pool overlay for a configuration, returning its path
+///
+/// The pool is allocated anywhere in guest RAM and attached to the PCIe host
+/// bridge, so every PCI device (including the root disk) bounces through it.
+pub fn write_restricted_pool_overlay(
+    config_name: &str,
+    manifest: &ConfigManifest,
+) -> Option<PathBuf> {
+    let memory = memory_size(manifest).unwrap_or(DEFAULT_MEMORY);
+    let dtso = format!(
+        "/* Restricted DMA pool of {config}, generated by guest-test-linux */\n\
+         /dts-v1/;\n\
+         /plugin/;\n\
+         \n\
+         &{{/}} {{\n\
+         \treserved-memory {{\n\
+         \t\t#address-cells = <2>;\n\
+         \t\t#size-cells = <2>;\n\
+         \t\tranges;\n\
+         \n\
+         \t\trestricted_dma: restricted-dma-pool {{\n\
+         \t\t\tcompatible = \"restricted-dma-pool\";\n\
+         \t\t\tsize = <0x0 {pool_size:#x}>;\n\
+         \t\t\talloc-ranges = <0x0 {base:#x} {mem_hi:#x} {mem_lo:#x}>;\n\
+         \t\t\tno-map;\n\
+         \t\t}};\n\
+         \t}};\n\
+         }};\n\
+         \n\
+         &{{/pcie@10000000}} {{\n\
+         \tmemory-region = <&restricted_dma>;\n\
+         }};\n",
+        config = config_name,
+        pool_size = RESTRICTED_POOL_SIZE,
+        base = ARM64_RAM_BASE,
+        mem_hi = memory >> 32,
+        mem_lo = memory & 0xffff_ffff,
+    );
+
+    let path = PathBuf::from("build")
+        .join(config_name)
+        .join("restricted-dma.dtso");
+    match fs::write(&path, dtso) {
+        Ok(_) => {
+            println!(
+                "Restricted DMA pool ({}) overlay written: {}",
+                format_size(RESTRICTED_POOL_SIZE),
+                path.display()
+            );
+            Some(path)
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            None
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::arch::{ARCHES, Arch, arch_of};
+use crate::config::{ConfigManifest, load_manifest};
+use crate::dtb::OverlayMode;
+
+/// How a requirement is found on the host
+enum Probe {
+    /// An executable on `PATH`
+    Tool(String),
+    /// A C header the host compiler finds, e.g. for the kernel's host programs
+    Header(&'static str),
+}
+
+/// A host requirement and the Debian/Ubuntu package providing it
+struct Requirement {
+    probe: Probe,
+    package: String,
+    /// Needed by `build`; the others are only needed to boot the guest
+    build: bool,
+}
+
+impl Requirement {
+    fn tool(name: &str, package: &str) -> Self {
+        Requirement {
+            probe: Probe::Tool(name.to_string()),
+            package: package.to_string(),
+            build: true,
+        }
+    }
+
+    fn header(header: &'static str, package: &str) -> Self {
+        Requirement {
+            probe: Probe::Header(header),
+            package: package.to_string(),
+            build: true,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match &self.probe {
+            Probe::Tool(name) => name,
+            Probe::Header(header) => header,
+        }
+    }
+
+    fn found(&self) -> bool {
+        match &self.probe {
+            Probe::Tool(name) => has_tool(name),
+            Probe::Header(header) => has_header(header),
+        }
+    }
+
+    fn hint(&self) -> String {
+        format!("apt install {}", self.package)
+    }
+}
+
+/// Check if a host tool is available
+pub fn has_tool(tool: &str) -> bool {
+    Command::new("which")
+        .arg(tool)
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+/// Check if the host compiler finds a header, by preprocessing an include of it
+fn has_header(header: &str) -> bool {
+    let child = Command::new("gcc")
+        .args(["-E", "-x", "c", "-", "-o", "/dev/null"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else {
+        return false;
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "#include <{}>", header);
+    }
+    child.wait().is_ok_and(|status| status.success())
+}
+
+/// Tools every kernel, BusyBox and rootfs build uses
+fn common_requirements() -> Vec<Requirement> {
+    vec![
+        Requirement::tool("make", "make"),
+        Requirement::tool("gcc", "gcc"),
+        Requirement::tool("flex", "flex"),
+        Requirement::tool("bison", "bison"),
+        Requirement::tool("bc", "bc"),
+        Requirement::tool("perl", "perl"),
+        Requirement::tool("git", "git"),
+        Requirement::tool("curl", "curl"),
+        Requirement::tool("tar", "tar"),
+        Requirement::tool("gzip", "gzip"),
+        Requirement::tool("cpio", "cpio"),
+        Requirement::tool("depmod", "kmod"),
+        Requirement::tool("mkfs.ext4", "e2fsprogs"),
+        Requirement::tool("openssl", "openssl"),
+        // Module signing and the certificate tools of the kernel link against libcrypto
+        Requirement::header("openssl/opensslv.h", "libssl-dev"),
+    ]
+}
+
+/// Cross compiler and QEMU of an arch
+fn arch_requirements(arch: &Arch, host_arch: &str) -> Vec<Requirement> {
+    let mut requirements = Vec::new();
+    if !arch.native_hosts.contains(&host_arch) {
+        let gcc = format!("{}gcc", arch.cross_prefix);
+        let package = format!(
+            "gcc-{}",
+            arch.cross_prefix.trim_end_matches('-').replace('_', "-")
+        );
+        requirements.push(Requirement::tool(&gcc, &package));
+    }
+    // objtool, which x86 kernels always build, needs libelf
+    if arch.name == "x86" {
+        requirements.push(Requirement::header("gelf.h", "libelf-dev"));
+    }
+    let qemu_package = match arch.name {
+        "arm64" => "qemu-system-arm",
+        "x86" => "qemu-system-x86",
+        _ => "qemu-system-misc",
+    };
+    requirements.push(Requirement {
+        build: false,
+        ..Requirement::tool(arch.qemu_binary, qemu_package)
+    });
+    requirements
+}
+
+/// Tools only some manifest settings use
+fn config_requirements(manifest: &ConfigManifest) -> Vec<Requirement> {
+    let mut requirements = Vec::new();
+    if !manifest.overlays.is_empty() {
+        requirements.push(Requirement::tool("dtc", "device-tree-compiler"));
+        if manifest.overlay_mode == OverlayMode::Merge {
+            requirements.push(Requirement::tool("fdtoverlay", "device-tree-compiler"));
+        }
+    }
+    if manifest.image_format.read_only() {
+        let (tool, package) = manifest.image_format.tool();
+        requirements.push(Requirement::tool(tool, package));
+    }
+    if manifest.verity {
+        requirements.push(Requirement::tool("veritysetup", "cryptsetup-bin"));
+    }
+    if manifest.luks {
+        requirements.push(Requirement::tool("cryptsetup", "cryptsetup-bin"));
+    }
+    if manifest.ssh {
+        requirements.push(Requirement::tool("ssh-keygen", "openssh-client"));
+    }
+    if manifest.board.is_some() {
+        requirements.push(Requirement::tool("sfdisk", "fdisk"));
+        requirements.push(Requirement::tool("mkfs.vfat", "dosfstools"));
+        requirements.push(Requirement::tool("mcopy", "mtools"));
+    }
+    if manifest.rust_workspace.is_some() {
+        requirements.push(Requirement::tool("cargo", "cargo"));
+    }
+    if manifest.tpm.is_some() {
+        requirements.push(Requirement {
+            build: false,
+            ..Requirement::tool("swtpm", "swtpm")
+        });
+    }
+    if manifest.nfsroot && manifest.nfs_server.is_none() {
+        requirements.push(Requirement {
+            build: false,
+            ..Requirement::tool("unfsd", "unfs3")
+        });
+    }
+    requirements
+}
+
+/// Requirements of a configuration, or of every arch without one
+fn requirements(config_name: Option<&str>) -> Result<Vec<Requirement>, String> {
+    let host_arch = crate::system::get_host_arch();
+    let mut requirements = common_requirements();
+    match config_name {
+        Some(config_name) => {
+            let arch =
+                arch_of(config_name).ok_or_else(|| format!("unknown arch of {}", config_name))?;
+            requirements.extend(arch_requirements(arch, &host_arch));
+            requirements.extend(config_requirements(&load_manifest(config_name)?));
+        }
+        None => {
+            for arch in ARCHES {
+                requirements.extend(arch_requirements(arch, &host_arch));
+            }
+        }
+    }
+    Ok(requirements)
+}
+
+/// Report every host requirement of a configuration, or of all arches, with install
+/// hints for the missing ones
+pub fn doctor(config_name: Option<&str>) -> bool {
+    let requirements = match requirements(config_name) {
+        Ok(requirements) => requirements,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    let mut missing = 0;
+    for requirement in &requirements {
+        let found = requirement.found();
+        let needed_for = if requirement.build { "" } else { " (run/test)" };
+        if found {
+            println!(
+                "  {} {}{}",
+                crate::output::status(true, 5),
+                requirement.name(),
+                needed_for
+            );
+        } else {
+            missing += 1;
+            println!(
+                "  {} {}{}: {}",
+                crate::output::status(false, 5),
+                requirement.name(),
+                needed_for,
+                requirement.hint()
+            );
+        }
+    }
+    if missing == 0 {
+        println!("All {} host requirements found", requirements.len());
+    } else {
+        println!(
+            "{} of {} host requirements missing",
+            missing,
+            requirements.len()
+        );
+    }
+    missing == 0
+}
+
+/// Warn about missing build requirements of a configuration before building it
+pub fn warn_missing(config_name: &str) {
+    let Ok(requirements) = requirements(Some(config_name)) else {
+        return;
+    };
+    let missing: Vec<&Requirement> = requirements
+        .iter()
+        .filter(|requirement| requirement.build && !requirement.found())
+        .collect();
+    for requirement in &missing {
+        eprintln!(
+            "Warning: {} not found, the build may fail ({})",
+            requirement.name(),
+            requirement.hint()
+        );
+    }
+    if !missing.is_empty() {
+        eprintln!("Run `doctor {}` for the full host check", config_name);
+    }
+}
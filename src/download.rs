@@ -0,0 +1,748 @@
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, RANGE};
+use reqwest::{Client, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+/// Directory downloaded tarballs are kept in
+pub const DOWNLOAD_DIR: &str = "downloads";
+
+/// Kernel repository and ref the default `linux/` tree is cloned from
+pub const LINUX_GIT_URL: &str = "https://github.com/torvalds/linux.git";
+const LINUX_REF: &str = "v6.12";
+
+/// Directory holding one checkout per non-default kernel repository and ref
+const KERNEL_REFS_DIR: &str = "linux-refs";
+
+/// Linux release fetched in tarball mode
+const LINUX_TARBALL: &str = "https://cdn.kernel.org/pub/linux/kernel/v6.x/linux-6.12.tar.xz";
+const LINUX_SHA256SUMS: &str = "https://cdn.kernel.org/pub/linux/kernel/v6.x/sha256sums.asc";
+
+/// BusyBox repository and ref the default `busybox/` tree is cloned from
+pub const BUSYBOX_GIT_URL: &str = "https://gitee.com/mirrors_addons/busybox.git";
+pub const BUSYBOX_REF: &str = "1_36_1";
+
+/// BusyBox release fetched in tarball mode, with its published checksum file
+const BUSYBOX_TARBALL: &str = "https://busybox.net/downloads/busybox-1.36.1.tar.bz2";
+const BUSYBOX_SHA256SUMS: &str = "https://busybox.net/downloads/busybox-1.36.1.tar.bz2.sha256";
+
+/// A source tree fetched into the working directory and pinned in `sources.lock`
+pub struct DefaultSource {
+    /// Its directory, which also names its entry in the lock
+    pub name: &'static str,
+    pub repo: &'static str,
+    pub git_ref: &'static str,
+    /// Release tarball fetched in tarball mode, and the listing of its published checksum
+    pub tarball: &'static str,
+    pub sums_url: &'static str,
+}
+
+pub const DEFAULT_SOURCES: [DefaultSource; 2] = [
+    DefaultSource {
+        name: "linux",
+        repo: LINUX_GIT_URL,
+        git_ref: LINUX_REF,
+        tarball: LINUX_TARBALL,
+        sums_url: LINUX_SHA256SUMS,
+    },
+    DefaultSource {
+        name: "busybox",
+        repo: BUSYBOX_GIT_URL,
+        git_ref: BUSYBOX_REF,
+        tarball: BUSYBOX_TARBALL,
+        sums_url: BUSYBOX_SHA256SUMS,
+    },
+];
+
+/// Files larger than this are fetched in parallel chunks when the server supports ranges
+const PARALLEL_THRESHOLD: u64 = 64 * 1024 * 1024;
+const CHUNKS: u64 = 4;
+/// Attempts per chunk; each retry resumes where the previous one stopped
+const RETRIES: u32 = 5;
+
+/// Marker in a tree unpacked from a local tarball, holding the tarball's SHA-256
+const SOURCE_MARKER: &str = ".guest-test-source";
+
+static OFFLINE: OnceLock<bool> = OnceLock::new();
+
+/// Forbid all network access: downloads and git fetches fail instead of reaching out
+pub fn set_offline(offline: bool) {
+    let _ = OFFLINE.set(offline);
+}
+
+/// Whether `--offline` is in effect
+pub fn offline() -> bool {
+    OFFLINE.get().copied().unwrap_or(false)
+}
+
+/// How to obtain the Linux and BusyBox sources
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SourceMode {
+    /// Shallow git clones
+    #[default]
+    Git,
+    /// Release tarballs, verified against their published SHA-256 sums
+    Tarball,
+}
+
+/// Shared download progress, printed on a single stderr line
+struct Progress {
+    name: String,
+    total: u64,
+    done: AtomicU64,
+    finished: AtomicBool,
+}
+
+impl Progress {
+    fn print(&self, started: Instant) {
+        let done = self.done.load(Ordering::Relaxed);
+        let rate = done as f64 / started.elapsed().as_secs_f64().max(0.001) / 1048576.0;
+        let percent = (done * 100).checked_div(self.total).unwrap_or(0);
+        eprint!(
+            "\r  {} {:>3}% {:.1}/{:.1} MiB {:.1} MiB/s   ",
+            self.name,
+            percent,
+            done as f64 / 1048576.0,
+            self.total as f64 / 1048576.0,
+            rate
+        );
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Size and range support of a remote file
+async fn probe(client: &Client, url: &str) -> Result<(Option<u64>, bool), String> {
+    let response = client
+        .head(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("{}: {}", url, e))?;
+    let headers = response.headers();
+    let length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let ranges = headers
+        .get(ACCEPT_RANGES)
+        .is_some_and(|v| v.as_bytes() == b"bytes");
+    Ok((length, ranges))
+}
+
+/// Download `[start, end)` of `url` into `part`, resuming from what `part` already holds
+///
+/// With `hasher` the bytes are hashed as they arrive; the part must then start empty
+/// or be hashed by the caller first. Such a whole-file transfer starts over from 0 when
+/// the server answers a resumed request with the full file.
+async fn fetch_range(
+    client: &Client,
+    url: &str,
+    part: &Path,
+    start: u64,
+    end: Option<u64>,
+    progress: &Progress,
+    mut hasher: Option<&mut Sha256>,
+) -> Result<(), String> {
+    let mut attempt = 0;
+    loop {
+        let have = tokio::fs::metadata(part)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        if end.is_some_and(|end| start + have >= end) {
+            return Ok(());
+        }
+
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start + have, end - 1),
+            None => format!("bytes={}-", start + have),
+        };
+        let mut request = client.get(url);
+        let ranged = start + have > 0 || end.is_some();
+        if ranged {
+            request = request.header(RANGE, range);
+        }
+
+        let result: Result<(), String> = async {
+            let mut response = request
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| e.to_string())?;
+            // Appending a full response to a partial file would corrupt it
+            let restart = ranged && response.status() != StatusCode::PARTIAL_CONTENT;
+            if restart && (start > 0 || hasher.is_none()) {
+                return Err("server ignored the range request".to_string());
+            }
+            let mut options = OpenOptions::new();
+            options.create(true);
+            if restart {
+                options.write(true).truncate(true);
+                if let Some(hasher) = hasher.as_deref_mut() {
+                    *hasher = Sha256::new();
+                }
+                progress.done.fetch_sub(have, Ordering::Relaxed);
+            } else {
+                options.append(true);
+            }
+            let mut file = options.open(part).await.map_err(|e| e.to_string())?;
+            let mut received = 0;
+            while let Some(bytes) = response.chunk().await.map_err(|e| e.to_string())? {
+                received += bytes.len();
+                file.write_all(&bytes).await.map_err(|e| e.to_string())?;
+                if let Some(hasher) = hasher.as_deref_mut() {
+                    hasher.update(&bytes);
+                }
+                progress
+                    .done
+                    .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            }
+            file.flush().await.map_err(|e| e.to_string())?;
+            // An empty response would otherwise be retried without ever using up attempts
+            if received == 0 && end.is_some() {
+                return Err("server sent no data".to_string());
+            }
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) if end.is_none() => return Ok(()),
+            Ok(()) => {}
+            Err(e) if attempt + 1 >= RETRIES => return Err(format!("{}: {}", url, e)),
+            Err(e) => {
+                attempt += 1;
+                eprintln!("\n  {} (retry {}/{})", e, attempt, RETRIES - 1);
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+            }
+        }
+    }
+}
+
+/// SHA-256 of a file as lowercase hex
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    hash_file(path, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Hash a file from the start
+fn hash_file(path: &Path, hasher: &mut Sha256) -> Result<(), String> {
+    let mut file = fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    std::io::copy(&mut file, hasher).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(())
+}
+
+/// Download `url` to `dest`, resuming interrupted transfers and checking `sha256`
+async fn download_async(url: &str, dest: &Path, sha256: Option<&str>) -> Result<(), String> {
+    let client = Client::builder()
+        .connect_timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let (length, ranges) = probe(&client, url).await?;
+
+    let name = dest.file_name().unwrap().to_string_lossy().to_string();
+    let progress = Arc::new(Progress {
+        name,
+        total: length.unwrap_or(0),
+        done: AtomicU64::new(0),
+        finished: AtomicBool::new(false),
+    });
+    let ticker = {
+        let progress = progress.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            while !progress.finished.load(Ordering::Relaxed) {
+                progress.print(started);
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+            progress.print(started);
+            eprintln!();
+        })
+    };
+
+    let part = PathBuf::from(format!("{}.part", dest.display()));
+    let mut hasher = Sha256::new();
+    let result = match length {
+        Some(length) if ranges && length > PARALLEL_THRESHOLD => {
+            // Each chunk resumes independently in its own part file
+            let chunk = length.div_ceil(CHUNKS);
+            let mut tasks = tokio::task::JoinSet::new();
+            for i in 0..CHUNKS {
+                let (client, url, progress) = (client.clone(), url.to_string(), progress.clone());
+                let chunk_part = PathBuf::from(format!("{}.{}", part.display(), i));
+                let start = i * chunk;
+                let end = (start + chunk).min(length);
+                progress.done.fetch_add(
+                    fs::metadata(&chunk_part).map(|m| m.len()).unwrap_or(0),
+                    Ordering::Relaxed,
+                );
+                tasks.spawn(async move {
+                    fetch_range(
+                        &client,
+                        &url,
+                        &chunk_part,
+                        start,
+                        Some(end),
+                        &progress,
+                        None,
+                    )
+                    .await
+                });
+            }
+            let mut result = Ok(());
+            while let Some(task) = tasks.join_next().await {
+                if let Err(e) = task.map_err(|e| e.to_string()).and_then(|r| r) {
+                    result = Err(e);
+                }
+            }
+
+            // Assemble the chunks, hashing on the way
+            result.and_then(|_| {
+                let mut out = fs::File::create(&part).map_err(|e| e.to_string())?;
+                for i in 0..CHUNKS {
+                    let chunk_part = PathBuf::from(format!("{}.{}", part.display(), i));
+                    let data = fs::read(&chunk_part).map_err(|e| e.to_string())?;
+                    hasher.update(&data);
+                    out.write_all(&data).map_err(|e| e.to_string())?;
+                    fs::remove_file(&chunk_part).map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            })
+        }
+        _ => {
+            // Resume a sequential transfer only when the server honours ranges
+            if !ranges {
+                let _ = fs::remove_file(&part);
+            }
+            if part.exists() {
+                hash_file(&part, &mut hasher)?;
+                progress.done.store(
+                    fs::metadata(&part).map(|m| m.len()).unwrap_or(0),
+                    Ordering::Relaxed,
+                );
+            }
+            let end = if ranges { length } else { None };
+            fetch_range(&client, url, &part, 0, end, &progress, Some(&mut hasher)).await
+        }
+    };
+
+    progress.finished.store(true, Ordering::Relaxed);
+    let _ = ticker.await;
+    result?;
+
+    let digest: String = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if let Some(expected) = sha256
+        && !digest.eq_ignore_ascii_case(expected)
+    {
+        let _ = fs::remove_file(&part);
+        return Err(format!(
+            "checksum mismatch for {}: expected {}, got {}",
+            url, expected, digest
+        ));
+    }
+
+    fs::rename(&part, dest).map_err(|e| format!("{}: {}", dest.display(), e))
+}
+
+/// Download `url` to `dest`, verifying it against `sha256` when given
+pub fn download(url: &str, dest: &Path, sha256: Option<&str>) -> Result<(), String> {
+    if offline() {
+        return Err(format!("offline, not downloading {}", url));
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?;
+    runtime.block_on(download_async(url, dest, sha256))
+}
+
+/// Look up the checksum of `file` in a `sha256sum`-style listing
+fn find_checksum(listing: &str, file: &str) -> Option<String> {
+    listing.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let sum = fields.next()?;
+        (fields.next()?.trim_start_matches('*') == file).then(|| sum.to_string())
+    })
+}
+
+/// Checksum of a release tarball from the listing published next to it
+pub fn published_checksum(url: &str, sums_url: &str) -> Result<String, String> {
+    let file = url.rsplit('/').next().unwrap();
+    let sums = PathBuf::from(DOWNLOAD_DIR).join(format!("{}.sha256", file));
+    download(sums_url, &sums, None)?;
+    let listing = fs::read_to_string(&sums).map_err(|e| e.to_string())?;
+    find_checksum(&listing, file).ok_or_else(|| format!("no checksum for {} in {}", file, sums_url))
+}
+
+/// Download a release tarball verified against a published checksum listing and
+/// `sources.lock`, and unpack it into `target`
+pub fn fetch_tarball(url: &str, sums_url: &str, target: &str) -> Result<(), String> {
+    let file = url.rsplit('/').next().unwrap();
+    let dest = PathBuf::from(DOWNLOAD_DIR).join(file);
+
+    let checksum = published_checksum(url, sums_url)?;
+    if !dest.exists() {
+        println!("Downloading {}...", url);
+        download(url, &dest, Some(&checksum))?;
+    }
+    crate::sources_lock::check_tarball(target, url, &sha256_file(&dest)?)?;
+
+    unpack(&dest, Path::new(target))
+}
+
+/// Unpack a source tarball into `target`, dropping its top-level directory
+fn unpack(archive: &Path, target: &Path) -> Result<(), String> {
+    fs::create_dir_all(target).map_err(|e| e.to_string())?;
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(archive)
+        .arg("-C")
+        .arg(target)
+        .arg("--strip-components=1")
+        .status()
+        .map_err(|e| e.to_string())?;
+    if !status.success() {
+        let _ = fs::remove_dir_all(target);
+        return Err(format!("failed to unpack {}", archive.display()));
+    }
+    Ok(())
+}
+
+/// Local replacements for the downloaded sources, for machines without network access
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct SourceOptions {
+    /// How to fetch the Linux and BusyBox sources
+    #[arg(long, value_enum, default_value = "git")]
+    pub source: SourceMode,
+    /// Linux source tree or release tarball to use as `linux/` instead of fetching it
+    #[arg(long, conflicts_with_all = ["kernel_ref", "kernel_repo"])]
+    pub kernel_src: Option<PathBuf>,
+    /// BusyBox source tree or release tarball to use as `busybox/` instead of fetching it
+    #[arg(long)]
+    pub busybox_src: Option<PathBuf>,
+}
+
+/// Make `target` the given source: a link to a tree, or a tarball unpacked in place
+fn use_local_source(source: &Path, target: &str) -> Result<(), String> {
+    let target_path = Path::new(target);
+    if source.is_dir() {
+        let source = source
+            .canonicalize()
+            .map_err(|e| format!("{}: {}", source.display(), e))?;
+        if !source.join("Makefile").exists() {
+            return Err(format!("{} is not a source tree", source.display()));
+        }
+        if target_path.exists() || target_path.is_symlink() {
+            if target_path.canonicalize().is_ok_and(|dir| dir == source) {
+                return Ok(());
+            }
+            return Err(format!(
+                "{}/ already exists, remove it to build from {}",
+                target,
+                source.display()
+            ));
+        }
+        println!("Using {} as {}/", source.display(), target);
+        return std::os::unix::fs::symlink(&source, target_path)
+            .map_err(|e| format!("{}: {}", target, e));
+    }
+
+    let sha256 = sha256_file(source)?;
+    if target_path.exists() {
+        let marker = fs::read_to_string(target_path.join(SOURCE_MARKER)).unwrap_or_default();
+        if marker.trim() == sha256 {
+            return Ok(());
+        }
+        return Err(format!(
+            "{}/ already exists, remove it to unpack {}",
+            target,
+            source.display()
+        ));
+    }
+    println!("Unpacking {} into {}/...", source.display(), target);
+    unpack(source, target_path)?;
+    fs::write(target_path.join(SOURCE_MARKER), format!("{}\n", sha256))
+        .map_err(|e| format!("{}: {}", target, e))
+}
+
+/// Fetch the Linux and BusyBox release tarballs into `linux/` and `busybox/`
+pub fn fetch_source_tarballs() -> bool {
+    for source in &DEFAULT_SOURCES {
+        if Path::new(source.name).exists() {
+            println!("{} source already exists, skipping download.", source.name);
+            continue;
+        }
+        if let Err(e) = fetch_tarball(source.tarball, source.sums_url, source.name) {
+            eprintln!("Failed to fetch {}: {}", source.name, e);
+            return false;
+        }
+        println!("Unpacked {} into {}/", source.tarball, source.name);
+    }
+    true
+}
+
+/// Fetch the Linux source into `linux/` unless it is already there or given locally
+///
+/// Git mode clones Linux only; BusyBox is cloned when the first rootfs is built.
+/// Offline, every source a build needs has to be present up front.
+pub fn fetch_sources(options: &SourceOptions, needs_busybox: bool) -> bool {
+    for (source, target) in [
+        (&options.kernel_src, "linux"),
+        (&options.busybox_src, "busybox"),
+    ] {
+        if let Some(source) = source
+            && let Err(e) = use_local_source(source, target)
+        {
+            eprintln!("Failed to use {}: {}", source.display(), e);
+            return false;
+        }
+    }
+    if offline() {
+        let mut missing = vec![("linux", "--kernel-src")];
+        if needs_busybox {
+            missing.push(("busybox", "--busybox-src"));
+        }
+        missing.retain(|(dir, _)| !Path::new(dir).join("Makefile").exists());
+        for (dir, flag) in &missing {
+            eprintln!(
+                "{}/ is missing and --offline forbids fetching it; pass {} with a source tree or tarball",
+                dir, flag
+            );
+        }
+        if !missing.is_empty() {
+            return false;
+        }
+        if let Err(e) = crate::sources_lock::verify_checkout("linux") {
+            eprintln!("{}", e);
+            return false;
+        }
+        return true;
+    }
+    if options.source == SourceMode::Tarball {
+        return fetch_source_tarballs();
+    }
+    if Path::new("linux").exists() {
+        println!("Linux source already exists, skipping clone.");
+        if let Err(e) = crate::sources_lock::verify_checkout("linux") {
+            eprintln!("{}", e);
+            return false;
+        }
+        return true;
+    }
+    println!("Cloning Linux {} source code...", LINUX_REF);
+    let status = Command::new("git")
+        .args([
+            "clone",
+            "--depth=1",
+            "-b",
+            LINUX_REF,
+            LINUX_GIT_URL,
+            "linux",
+        ])
+        .status();
+    match status {
+        Ok(status) if status.success() => match crate::sources_lock::verify_checkout("linux") {
+            Ok(()) => {
+                println!("Clone completed successfully.");
+                true
+            }
+            Err(e) => {
+                // Nothing may build from a tree that is not the pinned one
+                eprintln!("{}", e);
+                let _ = fs::remove_dir_all("linux");
+                false
+            }
+        },
+        Ok(_) => {
+            eprintln!("Clone failed.");
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to execute git clone: {}", e);
+            false
+        }
+    }
+}
+
+/// Kernel source overrides given on the command line
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct KernelSelection {
+    /// Kernel tag, branch or commit to build instead of the config's `kernel_ref`
+    #[arg(long)]
+    pub kernel_ref: Option<String>,
+    /// Kernel git repository to fetch the ref from instead of the config's `kernel_repo`
+    #[arg(long)]
+    pub kernel_repo: Option<String>,
+}
+
+/// Kernel repository and ref a configuration is built from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelSource {
+    pub repo: String,
+    pub git_ref: String,
+    /// Commit the ref must resolve to, when the config pins it
+    pub commit: Option<String>,
+    /// Patches applied on top of the checkout, in order
+    pub patches: Vec<PathBuf>,
+}
+
+impl KernelSource {
+    /// Command-line overrides first, then the config manifest, then the default tree
+    pub fn select(
+        selection: &KernelSelection,
+        manifest: &crate::config::ConfigManifest,
+        arch: &str,
+    ) -> Self {
+        let pick = |cli: &Option<String>, config: &Option<String>, default: &str| {
+            cli.clone()
+                .or_else(|| config.clone())
+                .unwrap_or_else(|| default.to_string())
+        };
+        KernelSource {
+            repo: pick(&selection.kernel_repo, &manifest.kernel_repo, LINUX_GIT_URL),
+            git_ref: pick(&selection.kernel_ref, &manifest.kernel_ref, LINUX_REF),
+            // The pin belongs to the config's own ref, not to one given for a single build
+            commit: manifest
+                .kernel_commit
+                .clone()
+                .filter(|_| selection.kernel_ref.is_none()),
+            patches: manifest
+                .kernel_patches
+                .iter()
+                .map(|patch| Path::new("config").join(arch).join(patch))
+                .collect(),
+        }
+    }
+
+    /// Whether this is the tree `fetch_sources` puts in `linux/`
+    pub fn is_default(&self) -> bool {
+        self.repo == LINUX_GIT_URL
+            && self.git_ref == LINUX_REF
+            && self.commit.is_none()
+            && self.patches.is_empty()
+    }
+
+    /// Source tree of this repository and ref
+    pub fn dir(&self) -> PathBuf {
+        if self.is_default() {
+            return PathBuf::from("linux");
+        }
+        let mut name = self.git_ref.replace('/', "_");
+        if self.repo != LINUX_GIT_URL {
+            let digest = Sha256::digest(self.repo.as_bytes());
+            let prefix: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+            name = format!("{}-{}", prefix, name);
+        }
+        if let Some(commit) = &self.commit {
+            name = format!("{}-{}", name, &commit[..commit.len().min(12)]);
+        }
+        // Patched trees are kept apart from the plain checkout and from other patch sets
+        if !self.patches.is_empty() {
+            let mut hasher = Sha256::new();
+            for patch in &self.patches {
+                hasher.update(fs::read(patch).unwrap_or_default());
+            }
+            let digest = hasher.finalize();
+            let suffix: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+            name = format!("{}-patched-{}", name, suffix);
+        }
+        Path::new(KERNEL_REFS_DIR).join(name)
+    }
+
+    /// Check that the checked-out tree is at the pinned commit, if any
+    fn check_pin(&self, dir: &Path) -> Result<(), String> {
+        let Some(commit) = &self.commit else {
+            return Ok(());
+        };
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .map_err(|e| format!("failed to run git: {}", e))?;
+        let head = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if head.starts_with(commit.as_str()) {
+            return Ok(());
+        }
+        Err(format!(
+            "{} is at {}, not at the pinned kernel_commit {}; update the pin or the ref",
+            self.git_ref, head, commit
+        ))
+    }
+
+    /// Shallow-fetch the ref into its own tree unless it is already there
+    ///
+    /// Fetching instead of cloning works for commits as well as tags and branches.
+    pub fn fetch(&self) -> Result<(), String> {
+        let dir = self.dir();
+        if dir.join("Makefile").exists() {
+            println!(
+                "Linux {} already checked out in {}",
+                self.git_ref,
+                dir.display()
+            );
+            return Ok(());
+        }
+        if offline() {
+            return Err(format!(
+                "{} is not checked out in {} and --offline forbids fetching it",
+                self.git_ref,
+                dir.display()
+            ));
+        }
+        println!("Fetching Linux {} from {}...", self.git_ref, self.repo);
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .status()
+                .map_err(|e| format!("failed to run git: {}", e))
+                .and_then(|status| {
+                    status
+                        .success()
+                        .then_some(())
+                        .ok_or_else(|| format!("git {} failed", args[0]))
+                })
+        };
+        let result = git(&["init", "-q"])
+            .and_then(|_| git(&["fetch", "--depth=1", &self.repo, &self.git_ref]))
+            .and_then(|_| git(&["checkout", "-q", "--detach", "FETCH_HEAD"]))
+            .and_then(|_| self.check_pin(&dir))
+            .and_then(|_| {
+                // Staged, so that new files count in the tree's revision for the cache
+                self.patches.iter().try_for_each(|patch| {
+                    println!("Applying {}", patch.display());
+                    let patch = patch
+                        .canonicalize()
+                        .map_err(|e| format!("{}: {}", patch.display(), e))?;
+                    git(&["apply", "--index", &patch.to_string_lossy()])
+                })
+            });
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&dir);
+        }
+        result
+    }
+}
@@ -0,0 +1,321 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::arch::Arch;
+use crate::config::{ConfigManifest, parse_config_name};
+use crate::dma::DmaMode;
+use crate::error::BuildError;
+
+/// How compiled overlays are delivered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlayMode {
+    /// Ship `.dtbo` files next to the image with instructions to apply them
+    #[default]
+    Separate,
+    /// Apply the overlays to the base DTB and ship the merged DTB
+    Merge,
+}
+
+/// Get the directory compiled overlays are written to
+pub fn overlays_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("overlays")
+}
+
+/// Get the path of the merged DTB
+pub fn merged_dtb_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("guest.dtb")
+}
+
+/// Get the path of QEMU's dumped machine DTB
+pub fn qemu_dtb_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("qemu.dtb")
+}
+
+/// Get the paths kernel DTBs are copied to, one per entry of `dtbs`
+pub fn kernel_dtb_paths(config_name: &str, manifest: &ConfigManifest) -> Vec<PathBuf> {
+    manifest
+        .dtbs
+        .iter()
+        .filter_map(|dtb| Path::new(dtb).file_name())
+        .map(|name| PathBuf::from("build").join(config_name).join(name))
+        .collect()
+}
+
+/// DTB a hypervisor hands the guest: the merged one, else QEMU's machine DTB,
+/// else the first kernel DTB
+pub fn guest_dtb_path(config_name: &str, manifest: &ConfigManifest) -> Option<PathBuf> {
+    std::iter::once(merged_dtb_path(config_name))
+        .chain(manifest.qemu_dtb.then(|| qemu_dtb_path(config_name)))
+        .chain(kernel_dtb_paths(config_name, manifest))
+        .find(|path| path.is_file())
+}
+
+/// Check the DTB settings of a manifest
+pub fn validate(manifest: &ConfigManifest, arch_info: &Arch) -> Result<(), String> {
+    if (!manifest.dtbs.is_empty() || manifest.qemu_dtb) && !arch_info.devicetree {
+        return Err(format!(
+            "dtbs and qemu_dtb are not supported on {}",
+            arch_info.name
+        ));
+    }
+    if let Some(dtb) = manifest.dtbs.iter().find(|dtb| {
+        !dtb.ends_with(".dtb") || dtb.starts_with('/') || dtb.split('/').any(|part| part == "..")
+    }) {
+        return Err(format!(
+            "dtbs entries are .dtb files relative to arch/{}/boot/dts/, got {:?}",
+            arch_info.kernel_arch, dtb
+        ));
+    }
+    let mut names: Vec<_> = manifest
+        .dtbs
+        .iter()
+        .filter_map(|dtb| Path::new(dtb).file_name())
+        .collect();
+    names.sort();
+    if names.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err("dtbs entries must have distinct file names".to_string());
+    }
+    Ok(())
+}
+
+/// Copy the kernel DTBs named in the manifest from the kernel build directory
+fn copy_kernel_dtbs(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    build_dir: &Path,
+    arch_info: &Arch,
+) -> Result<(), BuildError> {
+    let dts_dir = build_dir
+        .join("arch")
+        .join(arch_info.kernel_arch)
+        .join("boot")
+        .join("dts");
+    for (dtb, dest) in manifest
+        .dtbs
+        .iter()
+        .zip(kernel_dtb_paths(config_name, manifest))
+    {
+        let source = dts_dir.join(dtb);
+        if !source.is_file() {
+            return Err(BuildError::Step(format!(
+                "{} was not built; is its platform enabled in the kernel config?",
+                source.display()
+            )));
+        }
+        fs::copy(&source, &dest).map_err(BuildError::io(format!(
+            "failed to copy {}",
+            source.display()
+        )))?;
+        println!("  {} -> {}", source.display(), dest.display());
+    }
+    Ok(())
+}
+
+/// Have QEMU write the DTB it generates for the configuration's machine, CPUs and memory
+fn dump_qemu_dtb(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    arch_info: &Arch,
+) -> Result<(), BuildError> {
+    let path = qemu_dtb_path(config_name);
+    let memory = crate::memory::memory_size(manifest).map_err(BuildError::Config)?;
+    let (arch, _) = parse_config_name(config_name);
+    crate::error::run(
+        Command::new(arch_info.qemu_binary).args([
+            "-machine",
+            &format!(
+                "{},dumpdtb={}",
+                crate::qemu::machine_arg(arch_info, manifest),
+                path.display()
+            ),
+            "-cpu",
+            arch_info.qemu_cpu,
+            "-m",
+            &crate::memory::format_size(memory),
+            "-smp",
+            &crate::cpu_hotplug::smp_arg(&arch, manifest),
+            "-nographic",
+        ]),
+        "dumping the QEMU machine DTB",
+    )?;
+    println!("QEMU machine DTB written: {}", path.display());
+    Ok(())
+}
+
+/// Collect the DTBs of a configuration: kernel DTBs listed in `dtbs` and QEMU's own
+pub fn collect_dtbs(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    build_dir: &Path,
+    arch_info: &Arch,
+) -> Result<(), BuildError> {
+    copy_kernel_dtbs(config_name, manifest, build_dir, arch_info)?;
+    if manifest.qemu_dtb {
+        dump_qemu_dtb(config_name, manifest, arch_info)?;
+    }
+    Ok(())
+}
+
+/// Check if a host tool is available
+fn has_tool(tool: &str) -> bool {
+    Command::new("which")
+        .arg(tool)
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+/// Compile a single overlay source into a `.dtbo`
+fn compile_overlay(source: &Path, output: &Path) -> bool {
+    let status = Command::new("dtc")
+        .args([
+            "-@",
+            "-I",
+            "dts",
+            "-O",
+            "dtb",
+            "-o",
+            &output.to_string_lossy(),
+            &source.to_string_lossy(),
+        ])
+        .status();
+
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(_) => {
+            eprintln!("dtc failed to compile overlay: {}", source.display());
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run dtc: {}", e);
+            false
+        }
+    }
+}
+
+/// Apply compiled overlays on top of a base DTB
+fn merge_overlays(base_dtb: &Path, overlays: &[PathBuf], output: &Path) -> bool {
+    if !has_tool("fdtoverlay") {
+        eprintln!("fdtoverlay not found, install device-tree-compiler to merge overlays");
+        return false;
+    }
+
+    let status = Command::new("fdtoverlay")
+        .args(["-i", &base_dtb.to_string_lossy()])
+        .args(["-o", &output.to_string_lossy()])
+        .args(overlays)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(_) => {
+            eprintln!(
+                "fdtoverlay failed to apply overlays to {}",
+                base_dtb.display()
+            );
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run fdtoverlay: {}", e);
+            false
+        }
+    }
+}
+
+/// Write instructions for applying separately shipped overlays
+fn write_apply_instructions(config_name: &str, overlays: &[PathBuf]) {
+    let names: Vec<String> = overlays
+        .iter()
+        .map(|o| o.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+
+    let instructions = format!(
+        "Devicetree overlays for {config}\n\
+         \n\
+         Apply them to the DTB handed to the guest before booting, e.g.:\n\
+         \n\
+         \x20   fdtoverlay -i <base.dtb> -o guest.dtb {list}\n\
+         \n\
+         or load them at runtime through the hypervisor's DT overlay support.\n",
+        config = config_name,
+        list = names.join(" "),
+    );
+
+    let path = overlays_dir(config_name).join("APPLY.txt");
+    if let Err(e) = fs::write(&path, instructions) {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+/// Compile the devicetree overlays of a configuration, returning false on failure
+pub fn build_overlays(config_name: &str, manifest: &ConfigManifest) -> bool {
+    let (arch, _) = parse_config_name(config_name);
+    let mut sources: Vec<PathBuf> = manifest
+        .overlays
+        .iter()
+        .map(|overlay| PathBuf::from("config").join(&arch).join(overlay))
+        .collect();
+
+    // Generated overlays live in the build directory
+    if manifest.dma == Some(DmaMode::RestrictedPool) {
+        match crate::dma::write_restricted_pool_overlay(config_name, manifest) {
+            Some(source) => sources.push(source),
+            None => return false,
+        }
+    }
+
+    if sources.is_empty() {
+        return true;
+    }
+
+    println!("Compiling devicetree overlays for {}...", config_name);
+    if !has_tool("dtc") {
+        eprintln!("dtc not found, install device-tree-compiler to build overlays");
+        return false;
+    }
+
+    let out_dir = overlays_dir(config_name);
+    if out_dir.exists() {
+        fs::remove_dir_all(&out_dir).expect("Failed to remove old overlays");
+    }
+    fs::create_dir_all(&out_dir).expect("Failed to create overlays directory");
+
+    let mut compiled = Vec::new();
+    for source in &sources {
+        let output = out_dir.join(source.with_extension("dtbo").file_name().unwrap());
+        if !compile_overlay(source, &output) {
+            return false;
+        }
+        println!("  {} -> {}", source.display(), output.display());
+        compiled.push(output);
+    }
+
+    match manifest.overlay_mode {
+        OverlayMode::Separate => {
+            write_apply_instructions(config_name, &compiled);
+            true
+        }
+        OverlayMode::Merge => {
+            let base_dtb = match &manifest.base_dtb {
+                Some(base_dtb) => PathBuf::from(base_dtb),
+                None if manifest.qemu_dtb => qemu_dtb_path(config_name),
+                None => {
+                    eprintln!(
+                        "overlay_mode = \"merge\" requires base_dtb or qemu_dtb in the config manifest"
+                    );
+                    return false;
+                }
+            };
+            let merged = merged_dtb_path(config_name);
+            if !merge_overlays(&base_dtb, &compiled, &merged) {
+                return false;
+            }
+            println!("Merged devicetree written: {}", merged.display());
+            true
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::io;
+use std::process::Command;
+
+/// Why building a configuration's kernel, busybox or rootfs failed
+#[derive(Debug)]
+pub enum BuildError {
+    /// The configuration or its manifest cannot be built
+    Config(String),
+    /// A file operation failed
+    Io { context: String, source: io::Error },
+    /// An external command could not be started or exited with failure
+    Command { what: String, detail: String },
+    /// A build step failed after reporting the details itself
+    Step(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Config(message) | BuildError::Step(message) => f.write_str(message),
+            BuildError::Io { context, source } => write!(f, "{}: {}", context, source),
+            BuildError::Command { what, detail } => write!(f, "{} failed: {}", what, detail),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl BuildError {
+    /// Adapter for `map_err` on file operations, e.g. `.map_err(BuildError::io("failed to copy config"))`
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> BuildError {
+        let context = context.into();
+        move |source| BuildError::Io { context, source }
+    }
+}
+
+/// Run a command to completion, failing unless it exits successfully
+pub fn run(cmd: &mut Command, what: &str) -> Result<(), BuildError> {
+    match cmd.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(BuildError::Command {
+            what: what.to_string(),
+            detail: status.to_string(),
+        }),
+        Err(e) => Err(BuildError::Command {
+            what: what.to_string(),
+            detail: format!("could not run {:?}: {}", cmd.get_program(), e),
+        }),
+    }
+}
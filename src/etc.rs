@@ -0,0 +1,174 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::ConfigManifest;
+use crate::error::BuildError;
+
+/// Hostname of guests whose config sets none
+pub const DEFAULT_HOSTNAME: &str = "guest";
+
+/// Mounts every guest gets; init has most of them up before the fstab hook runs, which
+/// skips whatever is already mounted
+const DEFAULT_MOUNTS: &[(&str, &str, &str, &str)] = &[
+    ("proc", "/proc", "proc", "defaults"),
+    ("sysfs", "/sys", "sysfs", "defaults"),
+    ("devtmpfs", "/dev", "devtmpfs", "defaults"),
+    ("devpts", "/dev/pts", "devpts", "gid=5,mode=620"),
+];
+
+/// An `/etc/fstab` entry, e.g.
+/// `{ source = "/dev/disk/by-name/data", target = "/data", fstype = "ext4" }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mount {
+    pub source: String,
+    pub target: String,
+    pub fstype: String,
+    #[serde(default = "default_options")]
+    pub options: String,
+}
+
+fn default_options() -> String {
+    "defaults".to_string()
+}
+
+/// Check the `/etc` settings of a manifest
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    if manifest.bare
+        && (manifest.hostname.is_some() || manifest.getty.is_some() || !manifest.mounts.is_empty())
+    {
+        return Err("bare has no /etc; hostname, getty and mounts do not apply".to_string());
+    }
+    if let Some(hostname) = &manifest.hostname {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-';
+        if hostname.is_empty()
+            || hostname.len() > 63
+            || hostname.starts_with('-')
+            || !hostname.chars().all(valid_char)
+        {
+            return Err(format!(
+                "invalid hostname {:?}: use up to 63 letters, digits or '-'",
+                hostname
+            ));
+        }
+    }
+    if let Some(getty) = &manifest.getty
+        && (getty.is_empty() || getty.contains(['/', ' ', ':']))
+    {
+        return Err(format!(
+            "getty must be a device name under /dev such as ttyS1, got {:?}",
+            getty
+        ));
+    }
+    for (i, mount) in manifest.mounts.iter().enumerate() {
+        let fields = [&mount.source, &mount.target, &mount.fstype, &mount.options];
+        if fields
+            .iter()
+            .any(|field| field.is_empty() || field.contains(char::is_whitespace))
+        {
+            return Err(format!(
+                "mount {}: source, target, fstype and options must be non-empty and without spaces",
+                mount.target
+            ));
+        }
+        if !mount.target.starts_with('/') || mount.target == "/" {
+            return Err(format!(
+                "mount target {} must be an absolute path other than /",
+                mount.target
+            ));
+        }
+        if manifest.mounts[..i]
+            .iter()
+            .any(|m| m.target == mount.target)
+        {
+            return Err(format!("duplicate mount target {}", mount.target));
+        }
+    }
+    Ok(())
+}
+
+/// `/etc/fstab`: the default mounts, replaced or extended by the manifest's `mounts`
+fn fstab(manifest: &ConfigManifest) -> String {
+    let mut fstab = String::from(
+        "# <source> <target> <type> <options> <dump> <pass>\n\
+         # / is mounted by the kernel from root= on its command line\n",
+    );
+    let overridden = |target: &str| manifest.mounts.iter().any(|m| m.target == target);
+    for (source, target, fstype, options) in DEFAULT_MOUNTS {
+        if !overridden(target) {
+            fstab.push_str(&format!(
+                "{} {} {} {} 0 0\n",
+                source, target, fstype, options
+            ));
+        }
+    }
+    for mount in &manifest.mounts {
+        fstab.push_str(&format!(
+            "{} {} {} {} 0 0\n",
+            mount.source, mount.target, mount.fstype, mount.options
+        ));
+    }
+    fstab
+}
+
+/// `/etc/inittab` for BusyBox init, when a guest boots `/sbin/init` instead of `/init`:
+/// the boot hooks (hostname, disks, fstab, ...) and a root shell on the getty
+fn inittab(getty: &str) -> String {
+    format!(
+        "::sysinit:/bin/mount -t proc proc /proc\n\
+         ::sysinit:/bin/mount -t sysfs sysfs /sys\n\
+         ::sysinit:/bin/mount -t devtmpfs devtmpfs /dev\n\
+         ::sysinit:/bin/sh -c 'for hook in /etc/hooks/*; do [ -x \"$hook\" ] && \"$hook\"; done'\n\
+         {getty}::respawn:/sbin/getty -L 115200 {getty} vt100\n\
+         ::ctrlaltdel:/sbin/reboot\n\
+         ::shutdown:/sbin/swapoff -a\n\
+         ::shutdown:/bin/umount -a -r\n"
+    )
+}
+
+/// Write the rootfs's `/etc`: hostname, hosts, users, mount table and inittab
+///
+/// Files a payload or overlay puts in place later win; root logs in on the getty
+/// without a password.
+pub fn populate(
+    rootfs_dir: &Path,
+    arch: &str,
+    manifest: &ConfigManifest,
+) -> Result<(), BuildError> {
+    let etc = rootfs_dir.join("etc");
+    let hostname = manifest.hostname.as_deref().unwrap_or(DEFAULT_HOSTNAME);
+    let getty = manifest
+        .getty
+        .clone()
+        .unwrap_or_else(|| manifest.console(arch).device().to_string());
+    let files = [
+        ("hostname", format!("{}\n", hostname)),
+        (
+            "hosts",
+            format!("127.0.0.1 localhost\n127.0.1.1 {}\n", hostname),
+        ),
+        (
+            "passwd",
+            "root:x:0:0:root:/root:/bin/sh\n\
+             nobody:x:65534:65534:nobody:/nonexistent:/bin/false\n"
+                .to_string(),
+        ),
+        (
+            "group",
+            "root:x:0:\ntty:x:5:\nnogroup:x:65534:\n".to_string(),
+        ),
+        ("shadow", "root::::::::\nnobody:*:::::::\n".to_string()),
+        ("fstab", fstab(manifest)),
+        ("inittab", inittab(&getty)),
+    ];
+    for (name, content) in files {
+        let path = etc.join(name);
+        fs::write(&path, content)
+            .map_err(BuildError::io(format!("failed to write /etc/{}", name)))?;
+    }
+    fs::set_permissions(etc.join("shadow"), fs::Permissions::from_mode(0o600))
+        .map_err(BuildError::io("failed to restrict /etc/shadow"))
+}
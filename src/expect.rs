@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{load_manifest, parse_config_name, split_variant};
+use crate::harness::GuestSession;
+use crate::qemu::{LaunchOptions, is_built, launch};
+use crate::qmp::Qmp;
+
+/// Directory holding console scripts, as `<arch>/<name>/*.expect`
+pub const CONSOLE_SCRIPT_DIR: &str = "tests/console";
+
+/// Kernel parameter asking init for a console shell once the guest tests are done
+pub const SHELL_PARAM: &str = "guest_test.shell";
+
+/// Wait used until a script sets its own with `timeout`
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One statement of a console script
+#[derive(Debug)]
+enum Step {
+    /// `timeout <secs>`: timeout of the following waits
+    Timeout(Duration),
+    /// `expect <pattern>`: wait for the pattern or fail
+    Expect(String),
+    /// `send <text>`: type a line on the console
+    Send(String),
+    /// `sendraw <text>`: type text without a newline, e.g. `"\x03"`
+    SendRaw(String),
+    /// `sleep <secs>`
+    Sleep(Duration),
+    /// `on <pattern> goto <label>`: candidate for the next `wait`
+    On(String, String),
+    /// `wait`: wait for any `on` pattern and jump to its label
+    Wait,
+    /// `label <name>`
+    Label,
+    /// `goto <label>`
+    Goto(String),
+    /// `refute <pattern>`: fail if the pattern was printed at all
+    Refute(String),
+    /// `fail <message>`
+    Fail(String),
+    /// `pass`: end the script successfully
+    Pass,
+    /// `screenshot <checkpoint> [max-diff-percent]`: compare the display to a golden image
+    Screenshot(String, f64),
+}
+
+/// A parsed console script
+pub struct Script {
+    steps: Vec<Step>,
+    labels: HashMap<String, usize>,
+}
+
+/// Parse an argument: a double-quoted string with `\n`, `\r`, `\t`, `\\`, `\"` and
+/// `\xNN` escapes, or the rest of the line as is
+fn parse_text(arg: &str) -> Result<String, String> {
+    let Some(quoted) = arg.strip_prefix('"') else {
+        return Ok(arg.to_string());
+    };
+    let quoted = quoted.strip_suffix('"').ok_or("unterminated string")?;
+
+    let mut text = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            Some('t') => text.push('\t'),
+            Some('\\') => text.push('\\'),
+            Some('"') => text.push('"'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape \\x{}", hex))?;
+                text.push(byte as char);
+            }
+            other => return Err(format!("invalid escape \\{}", other.unwrap_or(' '))),
+        }
+    }
+    Ok(text)
+}
+
+/// Parse a number of seconds
+fn parse_secs(arg: &str) -> Result<Duration, String> {
+    arg.parse::<f64>()
+        .ok()
+        .filter(|secs| *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("invalid number of seconds {}", arg))
+}
+
+/// Parse a console script
+///
+/// One statement per line; blank lines and lines starting with `#` are ignored.
+pub fn parse(source: &str) -> Result<Script, String> {
+    let mut steps = Vec::new();
+    let mut labels = HashMap::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arg = arg.trim();
+        let error = |e: String| format!("line {}: {}", index + 1, e);
+        let require = |what: &str| {
+            if arg.is_empty() {
+                Err(error(format!("{} needs {}", command, what)))
+            } else {
+                Ok(())
+            }
+        };
+
+        let step = match command {
+            "timeout" => Step::Timeout(parse_secs(arg).map_err(error)?),
+            "sleep" => Step::Sleep(parse_secs(arg).map_err(error)?),
+            "expect" | "send" | "sendraw" | "refute" => {
+                require("an argument")?;
+                let text = parse_text(arg).map_err(error)?;
+                match command {
+                    "expect" => Step::Expect(text),
+                    "send" => Step::Send(text),
+                    "sendraw" => Step::SendRaw(text),
+                    _ => Step::Refute(text),
+                }
+            }
+            "on" => {
+                let (pattern, label) = arg
+                    .rsplit_once(" goto ")
+                    .ok_or_else(|| error("expected on <pattern> goto <label>".to_string()))?;
+                Step::On(
+                    parse_text(pattern.trim()).map_err(error)?,
+                    label.trim().to_string(),
+                )
+            }
+            "wait" => Step::Wait,
+            "label" => {
+                require("a name")?;
+                if labels.insert(arg.to_string(), steps.len()).is_some() {
+                    return Err(error(format!("duplicate label {}", arg)));
+                }
+                Step::Label
+            }
+            "goto" => {
+                require("a label")?;
+                Step::Goto(arg.to_string())
+            }
+            "fail" => Step::Fail(parse_text(arg).map_err(error)?),
+            "pass" => Step::Pass,
+            "screenshot" => {
+                require("a checkpoint name")?;
+                let mut args = arg.split_whitespace();
+                let checkpoint = args.next().unwrap().to_string();
+                let max_diff = match args.next() {
+                    Some(percent) => percent
+                        .parse::<f64>()
+                        .map_err(|_| error(format!("invalid percentage {}", percent)))?,
+                    None => crate::display::DEFAULT_MAX_DIFF_PERCENT,
+                };
+                Step::Screenshot(checkpoint, max_diff)
+            }
+            _ => return Err(error(format!("unknown command {}", command))),
+        };
+        steps.push(step);
+    }
+
+    // Catch typos in jump targets before booting anything
+    for step in &steps {
+        if let Step::Goto(label) | Step::On(_, label) = step
+            && !labels.contains_key(label)
+        {
+            return Err(format!("unknown label {}", label));
+        }
+    }
+    Ok(Script { steps, labels })
+}
+
+/// What a running script needs besides the console
+struct Context<'a> {
+    config_name: &'a str,
+    /// QMP socket of guests with a display
+    qmp_socket: Option<PathBuf>,
+    qmp: Option<Qmp>,
+    update_golden: bool,
+}
+
+/// Run a script against a guest session
+fn run(script: &Script, session: &mut GuestSession, context: &mut Context) -> Result<(), String> {
+    let mut timeout = DEFAULT_TIMEOUT;
+    let mut branches: Vec<(String, String)> = Vec::new();
+    let mut pc = 0;
+
+    while let Some(step) = script.steps.get(pc) {
+        pc += 1;
+        match step {
+            Step::Timeout(t) => timeout = *t,
+            Step::Expect(pattern) => {
+                if !session.expect(pattern, timeout) {
+                    return Err(format!(
+                        "'{}' not seen within {}s",
+                        pattern,
+                        timeout.as_secs_f64()
+                    ));
+                }
+            }
+            Step::Send(text) => {
+                if !session.send(&format!("{}\n", text)) {
+                    return Err("console input closed".to_string());
+                }
+            }
+            Step::SendRaw(text) => {
+                if !session.send(text) {
+                    return Err("console input closed".to_string());
+                }
+            }
+            Step::Sleep(duration) => thread::sleep(*duration),
+            Step::On(pattern, label) => branches.push((pattern.clone(), label.clone())),
+            Step::Wait => {
+                if branches.is_empty() {
+                    return Err("wait without a preceding on".to_string());
+                }
+                let patterns: Vec<&str> = branches.iter().map(|(p, _)| p.as_str()).collect();
+                let Some(index) = session.expect_any(&patterns, timeout) else {
+                    return Err(format!(
+                        "none of {:?} seen within {}s",
+                        patterns,
+                        timeout.as_secs_f64()
+                    ));
+                };
+                pc = script.labels[&branches[index].1];
+                branches.clear();
+            }
+            Step::Label => {}
+            Step::Goto(label) => pc = script.labels[label],
+            Step::Refute(pattern) => {
+                if session.output().contains(pattern.as_str()) {
+                    return Err(format!("'{}' was printed", pattern));
+                }
+            }
+            Step::Fail(message) => return Err(message.clone()),
+            Step::Pass => return Ok(()),
+            Step::Screenshot(checkpoint, max_diff) => {
+                let Some(socket) = &context.qmp_socket else {
+                    return Err("screenshot needs a display in the config manifest".to_string());
+                };
+                if context.qmp.is_none() {
+                    context.qmp = Some(Qmp::connect(socket, timeout)?);
+                }
+                crate::display::check_screenshot(
+                    context.qmp.as_mut().unwrap(),
+                    context.config_name,
+                    checkpoint,
+                    *max_diff,
+                    context.update_golden,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Console scripts of a configuration: those shared by all its variants in
+/// `<arch>/<name>/`, then the variant's own in `<arch>/<name>@<variant>/`
+pub fn console_scripts(config_name: &str) -> Vec<PathBuf> {
+    let (arch, name) = parse_config_name(config_name);
+    let mut dirs = vec![name.clone()];
+    if let (_, Some(variant)) = split_variant(config_name) {
+        dirs.push(format!("{}@{}", name, variant));
+    }
+
+    let mut scripts = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(Path::new(CONSOLE_SCRIPT_DIR).join(&arch).join(dir)) else {
+            continue;
+        };
+        let mut dir_scripts: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "expect"))
+            .collect();
+        dir_scripts.sort();
+        scripts.extend(dir_scripts);
+    }
+    scripts
+}
+
+/// Boot the guest once per console script and run it, returning true if all passed
+pub fn run_console_scripts(config_name: &str, update_golden: bool) -> bool {
+    let scripts = console_scripts(config_name);
+    if scripts.is_empty() {
+        return true;
+    }
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+
+    let has_display = match load_manifest(config_name) {
+        Ok(manifest) => manifest.display.is_some(),
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    };
+    let qmp_socket = has_display.then(|| PathBuf::from("build").join(config_name).join("qmp.sock"));
+
+    println!("Running console scripts for {}...", config_name);
+    let mut passed = true;
+    for path in scripts {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let result = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| parse(&source))
+            .and_then(|script| {
+                if let Some(socket) = &qmp_socket {
+                    let _ = fs::remove_file(socket);
+                }
+                let options = LaunchOptions {
+                    extra_cmdline: vec![SHELL_PARAM.to_string()],
+                    qmp: qmp_socket.clone(),
+                    ..Default::default()
+                };
+                let mut session = launch(config_name, &options)
+                    .map_err(|e| format!("failed to start QEMU: {}", e))?;
+                let mut context = Context {
+                    config_name,
+                    qmp_socket: qmp_socket.clone(),
+                    qmp: None,
+                    update_golden,
+                };
+                let result = run(&script, &mut session, &mut context);
+                session.kill();
+
+                let log_path = PathBuf::from("build")
+                    .join(config_name)
+                    .join(format!("console-{}.log", name));
+                if let Err(e) = fs::write(&log_path, session.output()) {
+                    eprintln!("Failed to write console log {}: {}", log_path.display(), e);
+                }
+                result
+            });
+
+        match result {
+            Ok(()) => println!("  PASS: {}", name),
+            Err(e) => {
+                println!("  FAIL: {} ({})", name, e);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
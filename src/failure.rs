@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{manifest_file, parse_config_name};
+use crate::testing::TestRun;
+
+/// Directory of failure bundles under the results directory
+const FAILURES_DIR: &str = "failures";
+
+/// Pack the evidence of a failed run into `failure-<timestamp>.tar.zst` under `results_dir`
+///
+/// The bundle holds the console log, the host-side evidence of the run (QMP event trace,
+/// memory dump), the build and config manifests, the replayed pstore records and the
+/// recorded run itself. Returns the bundle path, or None if it could not be written.
+pub fn pack_bundle(
+    results_dir: &Path,
+    config_name: &str,
+    timestamp: u64,
+    run: &TestRun,
+    pstore_logs: &[PathBuf],
+    record_json: &str,
+) -> Option<PathBuf> {
+    let dir = results_dir.join(FAILURES_DIR).join(config_name);
+    let bundle = dir.join(format!("failure-{}.tar.zst", timestamp));
+    let staging = dir.join(format!("failure-{}", timestamp));
+    if let Err(e) = stage(&staging, config_name, run, pstore_logs, record_json) {
+        eprintln!("Failed to gather failure evidence: {}", e);
+        return None;
+    }
+
+    let packed = Command::new("tar")
+        .arg("--zstd")
+        .arg("-cf")
+        .arg(&bundle)
+        .arg("-C")
+        .arg(&staging)
+        .arg(".")
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if !packed {
+        // Without zstd the evidence is still worth keeping, only unpacked
+        let _ = fs::remove_file(&bundle);
+        eprintln!(
+            "Warning: could not pack {} (tar with zstd support needed), evidence kept in {}",
+            bundle.display(),
+            staging.display()
+        );
+        return Some(staging);
+    }
+    let _ = fs::remove_dir_all(&staging);
+    println!("Failure evidence packed into {}", bundle.display());
+    Some(bundle)
+}
+
+/// Copy the evidence of a run into `staging`
+fn stage(
+    staging: &Path,
+    config_name: &str,
+    run: &TestRun,
+    pstore_logs: &[PathBuf],
+    record_json: &str,
+) -> Result<(), String> {
+    let write = |name: &str, content: &[u8]| {
+        let path = staging.join(name);
+        fs::write(&path, content).map_err(|e| format!("{}: {}", path.display(), e))
+    };
+    // Memory dumps are as large as the guest RAM; link rather than copy where possible
+    let copy = |src: &Path, dest: &Path| {
+        fs::hard_link(src, dest)
+            .or_else(|_| fs::copy(src, dest).map(|_| ()))
+            .map_err(|e| format!("{}: {}", src.display(), e))
+    };
+
+    let _ = fs::remove_dir_all(staging);
+    fs::create_dir_all(staging).map_err(|e| format!("{}: {}", staging.display(), e))?;
+    write("console.log", run.console.as_bytes())?;
+    write("run.json", record_json.as_bytes())?;
+
+    let (arch, name) = parse_config_name(config_name);
+    let sources = [
+        crate::manifest::manifest_path(config_name),
+        manifest_file(&arch, &name),
+    ];
+    // Evidence that is missing, e.g. a config without a manifest, is skipped
+    for src in sources.iter().chain(&run.evidence) {
+        if let Some(file_name) = src.file_name()
+            && src.is_file()
+        {
+            copy(src, &staging.join(file_name))?;
+        }
+    }
+
+    if !pstore_logs.is_empty() {
+        let pstore_dir = staging.join("pstore");
+        fs::create_dir_all(&pstore_dir).map_err(|e| format!("{}: {}", pstore_dir.display(), e))?;
+        for log in pstore_logs {
+            if let Some(file_name) = log.file_name() {
+                copy(log, &pstore_dir.join(file_name))?;
+            }
+        }
+    }
+    Ok(())
+}
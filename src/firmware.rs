@@ -0,0 +1,277 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::config::ConfigManifest;
+use crate::download::{DOWNLOAD_DIR, download, sha256_file};
+
+/// Directory firmware images are kept in, shared by all configurations
+const FIRMWARE_DIR: &str = "firmware";
+
+/// Pinned checksums of the images in `firmware/`, `sha256sum` style; kept under version control
+const CHECKSUMS: &str = "firmware/SHA256SUMS";
+
+/// Firmware images are taken from the `pc-bios/` directory of a QEMU release
+const PC_BIOS_URL: &str = "https://gitlab.com/qemu-project/qemu/-/raw/v9.1.0/pc-bios";
+
+/// Firmware the guest boots through; QEMU's built-in default when unset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirmwareKind {
+    /// UEFI: OVMF on x86, AAVMF on arm64, the RISC-V virt build on riscv64
+    Edk2,
+    /// Legacy BIOS (x86 only)
+    Seabios,
+}
+
+/// A firmware image of one architecture
+struct Image {
+    kind: FirmwareKind,
+    arch: &'static str,
+    /// File name in `firmware/`
+    name: &'static str,
+    /// File name in QEMU's `pc-bios/`, bzip2-compressed if it ends in `.bz2`
+    upstream: &'static str,
+    /// Template of the UEFI variable store, copied per configuration
+    vars: bool,
+}
+
+const IMAGES: &[Image] = &[
+    Image {
+        kind: FirmwareKind::Edk2,
+        arch: "arm64",
+        name: "edk2-aarch64-code.fd",
+        upstream: "edk2-aarch64-code.fd.bz2",
+        vars: false,
+    },
+    Image {
+        kind: FirmwareKind::Edk2,
+        arch: "arm64",
+        name: "edk2-arm-vars.fd",
+        upstream: "edk2-arm-vars.fd.bz2",
+        vars: true,
+    },
+    Image {
+        kind: FirmwareKind::Edk2,
+        arch: "x86",
+        name: "edk2-x86_64-code.fd",
+        upstream: "edk2-x86_64-code.fd.bz2",
+        vars: false,
+    },
+    Image {
+        kind: FirmwareKind::Edk2,
+        arch: "x86",
+        name: "edk2-i386-vars.fd",
+        upstream: "edk2-i386-vars.fd.bz2",
+        vars: true,
+    },
+    Image {
+        kind: FirmwareKind::Edk2,
+        arch: "riscv64",
+        name: "edk2-riscv-code.fd",
+        upstream: "edk2-riscv-code.fd.bz2",
+        vars: false,
+    },
+    Image {
+        kind: FirmwareKind::Edk2,
+        arch: "riscv64",
+        name: "edk2-riscv-vars.fd",
+        upstream: "edk2-riscv-vars.fd.bz2",
+        vars: true,
+    },
+    Image {
+        kind: FirmwareKind::Seabios,
+        arch: "x86",
+        name: "bios-256k.bin",
+        upstream: "bios-256k.bin",
+        vars: false,
+    },
+];
+
+impl FirmwareKind {
+    /// Kernel options for being booted by the firmware
+    pub fn kernel_options(self) -> &'static [&'static str] {
+        match self {
+            // QEMU hands -kernel to EDK2, which starts it as an EFI application
+            FirmwareKind::Edk2 => &["CONFIG_EFI", "CONFIG_EFI_STUB"],
+            FirmwareKind::Seabios => &[],
+        }
+    }
+
+    /// Name used in manifests
+    pub fn name(self) -> &'static str {
+        match self {
+            FirmwareKind::Edk2 => "edk2",
+            FirmwareKind::Seabios => "seabios",
+        }
+    }
+
+    /// Whether there are images of this firmware for an architecture
+    pub fn available(self, arch: &str) -> bool {
+        self.images(arch).next().is_some()
+    }
+
+    fn images(self, arch: &str) -> impl Iterator<Item = &'static Image> {
+        IMAGES
+            .iter()
+            .filter(move |image| image.kind == self && image.arch == arch)
+    }
+}
+
+/// Check the firmware setting of a manifest
+pub fn validate(manifest: &ConfigManifest, arch: &str) -> Result<(), String> {
+    if let Some(kind) = manifest.firmware
+        && !kind.available(arch)
+    {
+        return Err(format!("firmware {:?} is not available on {}", kind, arch));
+    }
+    Ok(())
+}
+
+/// Firmware files a configuration boots with
+pub struct FirmwareFiles {
+    /// Firmware code: the EDK2 code flash or the BIOS image
+    pub code: PathBuf,
+    /// The configuration's own UEFI variable store, persisting across boots
+    pub vars: Option<PathBuf>,
+}
+
+/// UEFI variable store of a configuration
+fn vars_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("efivars.fd")
+}
+
+/// Firmware files of a configuration, if it sets `firmware`
+pub fn files(config_name: &str, manifest: &ConfigManifest) -> Option<FirmwareFiles> {
+    let kind = manifest.firmware?;
+    let (arch, _) = crate::config::parse_config_name(config_name);
+    let code = kind.images(&arch).find(|image| !image.vars)?;
+    let vars = kind.images(&arch).any(|image| image.vars);
+    Some(FirmwareFiles {
+        code: Path::new(FIRMWARE_DIR).join(code.name),
+        vars: vars.then(|| vars_path(config_name)),
+    })
+}
+
+/// QEMU arguments booting the configuration through its firmware
+pub fn qemu_args(config_name: &str, manifest: &ConfigManifest) -> Vec<String> {
+    let Some(files) = files(config_name, manifest) else {
+        return Vec::new();
+    };
+    match files.vars {
+        Some(vars) => vec![
+            "-drive".to_string(),
+            format!(
+                "if=pflash,format=raw,unit=0,readonly=on,file={}",
+                files.code.display()
+            ),
+            "-drive".to_string(),
+            format!("if=pflash,format=raw,unit=1,file={}", vars.display()),
+        ],
+        None => vec!["-bios".to_string(), files.code.display().to_string()],
+    }
+}
+
+/// Pinned checksum of an image, if it has one
+fn pinned(name: &str) -> Option<String> {
+    let listing = fs::read_to_string(CHECKSUMS).ok()?;
+    listing.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let sum = fields.next()?;
+        (fields.next()? == name).then(|| sum.to_string())
+    })
+}
+
+/// Download an image into `firmware/` unless it is there, then check it against its pin.
+/// An image without a pin fails, unless `pin` records what was downloaded.
+fn fetch_image(image: &Image, pin: bool) -> Result<PathBuf, String> {
+    let path = Path::new(FIRMWARE_DIR).join(image.name);
+    if !path.exists() {
+        let url = format!("{}/{}", PC_BIOS_URL, image.upstream);
+        println!("Downloading {}...", url);
+        if image.upstream.ends_with(".bz2") {
+            let archive = Path::new(DOWNLOAD_DIR).join(image.upstream);
+            download(&url, &archive, None)?;
+            fs::create_dir_all(FIRMWARE_DIR).map_err(|e| format!("{}: {}", FIRMWARE_DIR, e))?;
+            let output = Command::new("bunzip2")
+                .arg("-c")
+                .arg(&archive)
+                .output()
+                .map_err(|e| format!("failed to run bunzip2: {}", e))?;
+            if !output.status.success() {
+                return Err(format!("failed to decompress {}", archive.display()));
+            }
+            fs::write(&path, output.stdout).map_err(|e| format!("{}: {}", path.display(), e))?;
+        } else {
+            download(&url, &path, None)?;
+        }
+    }
+
+    let sha256 = sha256_file(&path)?;
+    match pinned(image.name) {
+        Some(expected) if expected != sha256 => Err(format!(
+            "checksum mismatch for {}: expected {}, got {}; delete it to download it again",
+            path.display(),
+            expected,
+            sha256
+        )),
+        Some(_) => Ok(path),
+        None if !pin => Err(format!(
+            "{} has no pinned checksum in {}; run `firmware --pin` to pin it and commit {}",
+            image.name, CHECKSUMS, CHECKSUMS
+        )),
+        None => {
+            let mut listing = fs::read_to_string(CHECKSUMS).unwrap_or_default();
+            listing.push_str(&format!("{}  {}\n", sha256, image.name));
+            fs::write(CHECKSUMS, listing).map_err(|e| format!("{}: {}", CHECKSUMS, e))?;
+            println!(
+                "Pinned {} to {}, commit {} to keep it",
+                image.name, sha256, CHECKSUMS
+            );
+            Ok(path)
+        }
+    }
+}
+
+/// Fetch and verify the firmware of a configuration and give it a UEFI variable store
+pub fn prepare(config_name: &str, manifest: &ConfigManifest) -> Result<(), String> {
+    let Some(kind) = manifest.firmware else {
+        return Ok(());
+    };
+    let (arch, _) = crate::config::parse_config_name(config_name);
+    for image in kind.images(&arch) {
+        let path = fetch_image(image, false)?;
+        let vars = vars_path(config_name);
+        // The store is writable by the guest, so each configuration keeps its own copy
+        if image.vars && !vars.exists() {
+            fs::copy(&path, &vars).map_err(|e| format!("{}: {}", vars.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Fetch and verify every firmware image of the given architectures, with `pin` recording
+/// the checksums of images that have none yet
+pub fn fetch_all(arches: &[String], pin: bool) -> bool {
+    let mut ok = true;
+    for image in IMAGES {
+        if !arches.is_empty() && !arches.iter().any(|arch| arch == image.arch) {
+            continue;
+        }
+        match fetch_image(image, pin) {
+            Ok(path) => println!("  {} {}", crate::output::status(true, 4), path.display()),
+            Err(e) => {
+                println!(
+                    "  {} {}: {}",
+                    crate::output::status(false, 4),
+                    image.name,
+                    e
+                );
+                ok = false;
+            }
+        }
+    }
+    ok
+}
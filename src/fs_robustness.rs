@@ -0,0 +1,224 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde_json::json;
+
+use crate::arch::arch_of;
+use crate::config::load_manifest;
+use crate::qemu::{LaunchOptions, is_built, launch};
+use crate::qmp::Qmp;
+use crate::smoke::INIT_MARKER;
+use crate::testing::{ALL_PASSED_MARKER, SOME_FAILED_MARKER};
+
+/// Size of the scratch disk created for each run
+const SCRATCH_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Files the guest must have synced before the fault is injected
+const SYNCED_BEFORE_FAULT: u32 = 16;
+
+/// virtio-blk serial the guest payload finds the scratch disk by
+const SCRATCH_SERIAL: &str = "fs-scratch";
+
+/// How the guest loses its scratch disk in the middle of a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FsFault {
+    /// Kill QEMU without letting it flush anything
+    Kill,
+    /// Remove the disk backend under the running guest, then kill QEMU
+    Yank,
+}
+
+/// Scratch disk image of one run
+fn scratch_image(config_name: &str, run_id: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join(format!("scratch-{}.img", run_id))
+}
+
+/// Create an empty ext4 scratch disk
+fn create_scratch(path: &Path) -> Result<(), String> {
+    File::create(path)
+        .and_then(|f| f.set_len(SCRATCH_SIZE))
+        .map_err(|e| format!("create {}: {}", path.display(), e))?;
+    let status = Command::new("mkfs.ext4")
+        .args(["-q", "-F"])
+        .arg(path)
+        .status()
+        .map_err(|e| format!("mkfs.ext4: {}", e))?;
+    if !status.success() {
+        return Err(format!("mkfs.ext4 failed for {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Launch options attaching the scratch disk and selecting the payload's mode
+fn launch_options(config_name: &str, image: &Path, mode: &str) -> LaunchOptions {
+    let suffix = arch_of(config_name).map_or("pci", |a| a.virtio_suffix);
+    LaunchOptions {
+        extra_args: vec![
+            "-drive".to_string(),
+            format!(
+                "if=none,id=scratch,format=raw,cache=writeback,file={}",
+                image.display()
+            ),
+            "-device".to_string(),
+            format!(
+                "virtio-blk-{},drive=scratch,serial={}",
+                suffix, SCRATCH_SERIAL
+            ),
+        ],
+        extra_cmdline: vec![format!("fs_robustness={}", mode)],
+        ..LaunchOptions::default()
+    }
+}
+
+/// Highest file number the guest reported as synced
+fn last_synced(output: &str) -> Option<u32> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("fs-robustness: synced "))
+        .filter_map(|n| n.parse().ok())
+        .max()
+}
+
+/// Print the outcome of a step
+fn report(ok: bool, step: &str) -> bool {
+    println!("  {:<4}  {}", if ok { "ok" } else { "FAIL" }, step);
+    ok
+}
+
+/// Write to a scratch disk, fault it mid-write, then reboot and verify journal recovery
+fn run(config_name: &str, fault: FsFault, image: &Path, timeout: Duration) -> bool {
+    let qmp_socket = PathBuf::from("build").join(config_name).join("qmp.sock");
+    let mut options = launch_options(config_name, image, "write");
+    if fault == FsFault::Yank {
+        let _ = fs::remove_file(&qmp_socket);
+        options.qmp = Some(qmp_socket.clone());
+    }
+    let mut session = match launch(config_name, &options) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Failed to start QEMU: {}", e);
+            return false;
+        }
+    };
+
+    let synced_marker = format!("fs-robustness: synced {}", SYNCED_BEFORE_FAULT - 1);
+    let mut passed = report(session.expect(INIT_MARKER, timeout), "guest reached init")
+        && report(
+            session.expect(&synced_marker, timeout),
+            "guest is writing to the scratch disk",
+        );
+    if passed && fault == FsFault::Yank {
+        let yanked = Qmp::connect(&qmp_socket, timeout).and_then(|mut qmp| {
+            qmp.execute(
+                "human-monitor-command",
+                json!({ "command-line": "drive_del scratch" }),
+            )
+        });
+        if let Err(e) = &yanked {
+            eprintln!("Failed to remove the scratch disk: {}", e);
+        }
+        passed = report(yanked.is_ok(), "scratch disk yanked")
+            && report(
+                session.expect("fs-robustness: write f", timeout),
+                "guest saw the disk fail",
+            );
+    }
+    session.kill();
+    if !passed {
+        return false;
+    }
+    let Some(last) = last_synced(session.output()) else {
+        return false;
+    };
+    println!("  {:<4}  guest killed after syncing {} files", "ok", last + 1);
+
+    // The journal is replayed when the rebooted guest mounts the disk
+    let options = launch_options(config_name, image, &format!("verify:{}", last));
+    let mut session = match launch(config_name, &options) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Failed to start QEMU: {}", e);
+            return false;
+        }
+    };
+    let verified = session.expect("fs-robustness: verified", timeout)
+        && session.expect_any(&[ALL_PASSED_MARKER, SOME_FAILED_MARKER], timeout) == Some(0);
+    session.kill();
+    for line in session.output().lines() {
+        let line = line.trim();
+        if line.starts_with("fs-robustness: f") || line.starts_with("fs-robustness: verified") {
+            println!("        {}", line);
+        }
+    }
+    if !report(verified, "synced files intact after recovery") {
+        return false;
+    }
+
+    // The guest unmounted cleanly, so a forced check must find nothing to fix
+    let fsck = Command::new("e2fsck").arg("-fn").arg(image).output();
+    let clean = match fsck {
+        Ok(output) => {
+            if !output.status.success() {
+                print!("{}", String::from_utf8_lossy(&output.stdout));
+            }
+            output.status.success()
+        }
+        Err(e) => {
+            eprintln!("Failed to run e2fsck: {}", e);
+            false
+        }
+    };
+    report(clean, "filesystem consistent (e2fsck -fn)")
+}
+
+/// Check that a guest's synced writes survive a crash or a lost disk
+pub fn fs_robustness_test(config_name: &str, fault: FsFault, timeout: Duration) -> bool {
+    match load_manifest(config_name) {
+        Ok(manifest) if manifest.profiles.iter().any(|p| p == "fs-robustness") => {}
+        Ok(_) => {
+            eprintln!("{} does not enable the fs-robustness profile", config_name);
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    }
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+
+    // A fresh disk per run, so a previous run's damage cannot hide this one's
+    let run_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs().to_string())
+        .unwrap_or_else(|_| "0".to_string());
+    let image = scratch_image(config_name, &run_id);
+    if let Err(e) = create_scratch(&image) {
+        eprintln!("Failed to create scratch disk: {}", e);
+        return false;
+    }
+
+    println!(
+        "Filesystem robustness test for {} with fault {:?}...",
+        config_name, fault
+    );
+    let passed = run(config_name, fault, &image, timeout);
+    if passed {
+        let _ = fs::remove_file(&image);
+    } else {
+        println!("Scratch disk kept for inspection: {}", image.display());
+    }
+    println!(
+        "Filesystem robustness test {} for {}",
+        if passed { "passed" } else { "failed" },
+        config_name
+    );
+    passed
+}
@@ -0,0 +1,330 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::arch::find_arch;
+use crate::config::{load_manifest, parse_config_name};
+use crate::download::{DEFAULT_SOURCES, DefaultSource, KernelSelection, KernelSource};
+
+/// Output format of the exported build graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    Make,
+    Ninja,
+}
+
+/// One build stage: the files it produces, what it needs and how to run it
+struct Stage {
+    name: &'static str,
+    outputs: Vec<String>,
+    /// Source files the stage reads
+    inputs: Vec<String>,
+    /// Stages whose outputs the stage reads
+    deps: Vec<&'static str>,
+    commands: Vec<String>,
+}
+
+/// Shallow clone of a default source at the ref the build fetches
+fn clone_command(source: &DefaultSource) -> String {
+    format!(
+        "git clone --depth=1 -b {} {} {}",
+        source.git_ref, source.repo, source.name
+    )
+}
+
+/// Fetch of a kernel source the way the build fetches it: the default tree is cloned,
+/// others are fetched at their ref, checked against their pin and patched
+fn kernel_fetch_commands(source: &KernelSource, root: &Path) -> Vec<String> {
+    if source.is_default() {
+        return vec![clone_command(
+            DEFAULT_SOURCES
+                .iter()
+                .find(|source| source.name == "linux")
+                .expect("default source"),
+        )];
+    }
+    let dir = source.dir().display().to_string();
+    let mut commands = vec![
+        format!("mkdir -p {}", dir),
+        format!("git -C {} init -q", dir),
+        format!(
+            "git -C {} fetch --depth=1 {} {}",
+            dir, source.repo, source.git_ref
+        ),
+        format!("git -C {} checkout -q --detach FETCH_HEAD", dir),
+    ];
+    if let Some(commit) = &source.commit {
+        commands.push(format!(
+            "git -C {} rev-parse HEAD | grep -q '^{}'",
+            dir, commit
+        ));
+    }
+    for patch in &source.patches {
+        commands.push(format!(
+            "git -C {} apply --index {}/{}",
+            dir,
+            root.display(),
+            patch.display()
+        ));
+    }
+    commands
+}
+
+/// Describe the build of a configuration as a list of stages in dependency order
+fn stages(
+    config_name: &str,
+    root: &Path,
+    selection: &KernelSelection,
+) -> Result<Vec<Stage>, String> {
+    let (arch, name) = parse_config_name(config_name);
+    let arch_info = find_arch(&arch).ok_or_else(|| format!("unsupported architecture {}", arch))?;
+    let manifest = load_manifest(config_name)?;
+    let kernel_source = KernelSource::select(selection, &manifest, &arch);
+    let linux_dir = kernel_source.dir().display().to_string();
+
+    let host_arch = crate::system::get_host_arch();
+    let cross = arch_info
+        .cross_compile_prefix(&host_arch)
+        .map(|prefix| format!(" CROSS_COMPILE={}", prefix))
+        .unwrap_or_default();
+
+    let out = format!("build/{}", config_name);
+    let linux_out = format!("{}/{}/linux", root.display(), out);
+    let busybox_out = format!("{}/{}/busybox", root.display(), out);
+    let kernel_config = format!("{}/linux/.config", out);
+
+    let mut config_inputs = vec![format!("config/{}/{}", arch, name)];
+    let toml = format!("config/{}/{}.toml", arch, name);
+    if Path::new(&toml).exists() {
+        config_inputs.push(toml);
+    }
+    let mut fragments: Vec<String> = manifest
+        .fragments
+        .iter()
+        .map(|fragment| format!("config/{}/{}", arch, fragment))
+        .collect();
+    config_inputs.extend(fragments.iter().cloned());
+
+    let mut kconfig_commands = vec![format!("mkdir -p {}/linux", out)];
+    match &manifest.base_config {
+        Some(target) => {
+            kconfig_commands.push(format!(
+                "make -C {} O={} ARCH={}{} {}",
+                linux_dir, linux_out, arch_info.kernel_arch, cross, target
+            ));
+            fragments.insert(0, config_inputs[0].clone());
+        }
+        None => kconfig_commands.push(format!("cp {} {}", config_inputs[0], kernel_config)),
+    }
+    if !fragments.is_empty() {
+        let fragments: Vec<String> = fragments
+            .iter()
+            .map(|fragment| format!("{}/{}", root.display(), fragment))
+            .collect();
+        kconfig_commands.push(format!(
+            "cd {} && {}/{}/scripts/kconfig/merge_config.sh -m -O . .config {}",
+            linux_out,
+            root.display(),
+            linux_dir,
+            fragments.join(" ")
+        ));
+    }
+    for (option, value) in manifest.kernel_options(&arch) {
+        let action = match value.as_str() {
+            "y" => format!("--enable {}", option),
+            "n" => format!("--disable {}", option),
+            "m" => format!("--module {}", option),
+            _ => format!("--set-val {} {}", option, value),
+        };
+        kconfig_commands.push(format!(
+            "{}/scripts/config --file {} {}",
+            linux_dir, kernel_config, action
+        ));
+    }
+    kconfig_commands.push(format!(
+        "make -C {} O={} ARCH={}{} olddefconfig",
+        linux_dir, linux_out, arch_info.kernel_arch, cross
+    ));
+
+    let busybox_source = DEFAULT_SOURCES
+        .iter()
+        .find(|source| source.name == "busybox")
+        .expect("default source");
+
+    let image = format!("{}/{}", out, arch_info.image_name());
+    let rootfs = format!("{}/rootfs.img", out);
+
+    Ok(vec![
+        Stage {
+            name: "linux-source",
+            outputs: vec![format!("{}/Makefile", linux_dir)],
+            inputs: kernel_source
+                .patches
+                .iter()
+                .map(|patch| patch.display().to_string())
+                .collect(),
+            deps: Vec::new(),
+            commands: kernel_fetch_commands(&kernel_source, root),
+        },
+        Stage {
+            name: "kernel-config",
+            outputs: vec![kernel_config.clone()],
+            inputs: config_inputs,
+            deps: vec!["linux-source"],
+            commands: kconfig_commands,
+        },
+        Stage {
+            name: "kernel",
+            outputs: vec![image.clone()],
+            inputs: Vec::new(),
+            deps: vec!["kernel-config"],
+            commands: vec![
+                format!(
+                    "make -C {} O={} ARCH={}{} {} -j$(nproc)",
+                    linux_dir, linux_out, arch_info.kernel_arch, cross, arch_info.kernel_target
+                ),
+                format!("cp {}/linux/{} {}", out, arch_info.kernel_image, image),
+            ],
+        },
+        Stage {
+            name: "busybox-source",
+            outputs: vec!["busybox/Makefile".to_string()],
+            inputs: Vec::new(),
+            deps: Vec::new(),
+            commands: vec![clone_command(busybox_source)],
+        },
+        Stage {
+            name: "busybox",
+            outputs: vec![format!("{}/busybox/busybox", out)],
+            inputs: Vec::new(),
+            deps: vec!["busybox-source"],
+            commands: vec![
+                format!("mkdir -p {}/busybox", out),
+                format!("make -C busybox O={}{} defconfig", busybox_out, cross),
+                format!(
+                    "sed -i -e 's/^# CONFIG_STATIC is not set/CONFIG_STATIC=y/' \
+                     -e 's/^CONFIG_FEATURE_SHARED_BUSYBOX=y/# CONFIG_FEATURE_SHARED_BUSYBOX is not set/' \
+                     -e 's/^CONFIG_TC=y/# CONFIG_TC is not set/' {}/busybox/.config",
+                    out
+                ),
+                format!("make -C busybox O={}{} -j$(nproc)", busybox_out, cross),
+            ],
+        },
+        // Rootfs assembly, payloads, overlays and the manifest are internal to
+        // the tool; the full build reuses the kernel and busybox trees above
+        Stage {
+            name: "rootfs",
+            outputs: vec![rootfs, format!("{}/manifest.json", out)],
+            inputs: vec!["init/init".to_string()],
+            deps: vec!["kernel", "busybox"],
+            commands: vec![format!("cargo xtask build {} --only rootfs", config_name)],
+        },
+    ])
+}
+
+/// Render stages as a Makefile
+fn render_make(config_name: &str, stages: &[Stage]) -> String {
+    let mut out = format!(
+        "# Build graph of {}, generated by guest-test-linux\n\n.PHONY: all\nall: rootfs\n",
+        config_name
+    );
+    for stage in stages {
+        let _ = writeln!(
+            out,
+            "\n.PHONY: {}\n{}: {}",
+            stage.name, stage.name, stage.outputs[0]
+        );
+        let prerequisites: Vec<String> = stage
+            .deps
+            .iter()
+            .map(|dep| stages.iter().find(|s| s.name == *dep).unwrap().outputs[0].clone())
+            .chain(stage.inputs.iter().cloned())
+            .collect();
+        let _ = writeln!(
+            out,
+            "{} &: {}",
+            stage.outputs.join(" "),
+            prerequisites.join(" ")
+        );
+        for command in &stage.commands {
+            let _ = writeln!(out, "\t{}", command.replace('$', "$$"));
+        }
+    }
+    out
+}
+
+/// Render stages as a ninja file
+fn render_ninja(config_name: &str, stages: &[Stage]) -> String {
+    let mut out = format!(
+        "# Build graph of {}, generated by guest-test-linux\n",
+        config_name
+    );
+    for stage in stages {
+        let inputs: Vec<String> = stage
+            .deps
+            .iter()
+            .map(|dep| stages.iter().find(|s| s.name == *dep).unwrap().outputs[0].clone())
+            .chain(stage.inputs.iter().cloned())
+            .collect();
+        let command = stage.commands.join(" && ").replace('$', "$$");
+        let _ = write!(
+            out,
+            "\nrule {name}\n  command = {command}\n  description = {name}\n  pool = console\n\
+             build {outputs}: {name} {inputs}\nbuild {name}: phony {first}\n",
+            name = stage.name,
+            command = command,
+            outputs = stage.outputs.join(" "),
+            inputs = inputs.join(" "),
+            first = stage.outputs[0],
+        );
+    }
+    out.push_str("\ndefault rootfs\n");
+    out
+}
+
+/// Export the stage graph of a configuration for external build orchestrators
+///
+/// Paths in the graph are relative to the repository root, which is where it must run.
+pub fn export_build_graph(
+    config_name: &str,
+    selection: &KernelSelection,
+    format: GraphFormat,
+    output: Option<&Path>,
+) -> bool {
+    let root = std::env::current_dir().expect("Failed to get current directory");
+    let stages = match stages(config_name, &root, selection) {
+        Ok(stages) => stages,
+        Err(e) => {
+            eprintln!("Invalid configuration {}: {}", config_name, e);
+            return false;
+        }
+    };
+
+    let content = match format {
+        GraphFormat::Make => render_make(config_name, &stages),
+        GraphFormat::Ninja => render_ninja(config_name, &stages),
+    };
+
+    let path = match output {
+        Some(path) => path.to_path_buf(),
+        None => PathBuf::from("build").join(config_name).join(match format {
+            GraphFormat::Make => "Makefile",
+            GraphFormat::Ninja => "build.ninja",
+        }),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("Failed to create output directory");
+    }
+    match fs::write(&path, content) {
+        Ok(_) => {
+            println!("Build graph written: {}", path.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            false
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::fs;
+use std::path::Path;
+
+use crate::config::ConfigManifest;
+use crate::error::BuildError;
+
+/// Script init sources before the boot hooks, relative to the rootfs
+pub const ENV_SCRIPT: &str = "etc/profile.d/guest-test-env.sh";
+
+/// `/etc/profile` for login shells, sourcing the scripts init sources
+const PROFILE: &str =
+    "for script in /etc/profile.d/*.sh; do\n    [ -r \"$script\" ] && . \"$script\"\ndone\n";
+
+/// Check the `[env]` table of a manifest
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    if manifest.env.is_empty() {
+        return Ok(());
+    }
+    if manifest.bare {
+        return Err("env needs the busybox init and cannot be combined with bare".to_string());
+    }
+    for (name, value) in &manifest.env {
+        let mut chars = name.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("env name {:?} is not a shell variable name", name));
+        }
+        if value.contains(|c: char| c.is_control()) {
+            return Err(format!("env {} has control characters in its value", name));
+        }
+    }
+    Ok(())
+}
+
+/// Quote a value for sh
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Write the manifest's `[env]` as a script exporting it; variables the kernel command
+/// line already set (`--append NAME=value`) keep their value
+pub fn install(rootfs_dir: &Path, manifest: &ConfigManifest) -> Result<(), BuildError> {
+    if manifest.env.is_empty() {
+        return Ok(());
+    }
+    let mut script = String::from("# Generated from the [env] table of the config manifest\n");
+    for (name, value) in &manifest.env {
+        script.push_str(&format!(
+            "[ -n \"${{{name}+set}}\" ] || {name}={}\nexport {name}\n",
+            quote(value)
+        ));
+    }
+    let path = rootfs_dir.join(ENV_SCRIPT);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(BuildError::io("failed to create /etc/profile.d"))?;
+    }
+    fs::write(&path, script).map_err(BuildError::io("failed to write the guest environment"))?;
+
+    // Login shells, e.g. over SSH, do not inherit init's environment
+    let profile = rootfs_dir.join("etc").join("profile");
+    if !profile.exists() {
+        fs::write(&profile, PROFILE).map_err(BuildError::io("failed to write /etc/profile"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Console;
+
+/// A running guest whose serial console is attached to our stdin/stdout pipes
+pub struct GuestSession {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    output_rx: Receiver<(Instant, String)>,
+    output: String,
+    /// Offset into `output` at which each chunk starts, and when it was read
+    arrivals: Vec<(usize, Instant)>,
+    cursor: usize,
+    /// Host services the guest depends on, stopped along with it
+    helpers: Vec<Child>,
+}
+
+impl GuestSession {
+    /// Spawn the guest, capturing its console output in the background
+    pub fn spawn(mut cmd: Command) -> std::io::Result<Self> {
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (tx, output_rx) = mpsc::channel();
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        for mut stream in [
+            Box::new(stdout) as Box<dyn Read + Send>,
+            Box::new(stderr) as Box<dyn Read + Send>,
+        ] {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = stream.read(&mut buf) {
+                    let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+                    if n == 0 || tx.send((Instant::now(), chunk)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let stdin = child.stdin.take();
+        Ok(Self {
+            child,
+            stdin,
+            output_rx,
+            output: String::new(),
+            arrivals: Vec::new(),
+            cursor: 0,
+            helpers: Vec::new(),
+        })
+    }
+
+    /// Wait until `pattern` shows up in the console output after the last match
+    pub fn expect(&mut self, pattern: &str, timeout: Duration) -> bool {
+        self.expect_any(&[pattern], timeout).is_some()
+    }
+
+    /// Wait until one of `patterns` shows up, returning the index of the earliest match
+    pub fn expect_any(&mut self, patterns: &[&str], timeout: Duration) -> Option<usize> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(index) = self.find_any(patterns) {
+                return Some(index);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            match self.output_rx.recv_timeout(remaining) {
+                Ok((read_at, chunk)) => self.append(read_at, &chunk),
+                // Guest exited and all output has been consumed
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                    return self.find_any(patterns);
+                }
+            }
+        }
+    }
+
+    /// Find the earliest of `patterns` after the last match and move past it
+    fn find_any(&mut self, patterns: &[&str]) -> Option<usize> {
+        let rest = &self.output[self.cursor..];
+        let (index, pos, len) = patterns
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| rest.find(p).map(|pos| (i, pos, p.len())))
+            .min_by_key(|(_, pos, _)| *pos)?;
+        self.cursor += pos + len;
+        Some(index)
+    }
+
+    /// Send raw input to the guest console
+    pub fn send(&mut self, data: &str) -> bool {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin
+                .write_all(data.as_bytes())
+                .and_then(|_| stdin.flush())
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    /// Send a magic sysrq command (e.g. `t` to show tasks) over the console
+    pub fn send_sysrq(&mut self, console: Console, key: char) -> bool {
+        self.send(console.sysrq_prefix()) && self.send(&key.to_string())
+    }
+
+    /// Wait for the guest to exit on its own
+    pub fn wait_exit(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            match self.child.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) => thread::sleep(Duration::from_millis(100)),
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+
+    /// Get all console output captured so far
+    pub fn output(&mut self) -> &str {
+        while let Ok((read_at, chunk)) = self.output_rx.try_recv() {
+            self.append(read_at, &chunk);
+        }
+        &self.output
+    }
+
+    fn append(&mut self, read_at: Instant, chunk: &str) {
+        self.arrivals.push((self.output.len(), read_at));
+        self.output.push_str(chunk);
+    }
+
+    /// When the console output first containing `pattern` was read
+    pub fn seen_at(&mut self, pattern: &str) -> Option<Instant> {
+        let end = self.output().find(pattern)? + pattern.len();
+        // The chunk holding the last byte of the match completed it
+        self.arrivals
+            .iter()
+            .rev()
+            .find(|(offset, _)| *offset < end)
+            .map(|(_, read_at)| *read_at)
+    }
+
+    /// Stop a host service together with the guest
+    pub fn attach_helper(&mut self, helper: Child) {
+        self.helpers.push(helper);
+    }
+
+    /// Stop the guest if it is still running
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        for helper in &mut self.helpers {
+            let _ = helper.kill();
+            let _ = helper.wait();
+        }
+    }
+}
+
+impl Drop for GuestSession {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
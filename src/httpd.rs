@@ -0,0 +1,107 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Host port forwarded to the guest's httpd when the manifest does not pick one
+pub const DEFAULT_HTTPD_PORT: u16 = 8080;
+
+/// Size of the known-content file served from `/www/known.bin`
+const KNOWN_SIZE: usize = 64 * 1024;
+
+/// Content of `/www/known.bin`, for the host to verify
+fn known_content() -> Vec<u8> {
+    (0..KNOWN_SIZE).map(|i| (i * 31 + 7) as u8).collect()
+}
+
+/// Write a file into the rootfs and make it executable
+fn install_script(dest: &Path, content: &str) {
+    fs::write(dest, content).expect("Failed to write httpd script");
+    let status = Command::new("chmod")
+        .args(["+x", &dest.to_string_lossy()])
+        .status()
+        .expect("Failed to make httpd script executable");
+    if !status.success() {
+        eprintln!("Failed to make {} executable", dest.display());
+    }
+}
+
+/// Install the httpd boot hook, its `/www` tree and the guest-side test
+pub fn install_httpd(rootfs_dir: &Path) {
+    let www = rootfs_dir.join("www");
+    fs::create_dir_all(www.join("cgi-bin")).expect("Failed to create /www in rootfs");
+    fs::write(www.join("health"), "OK\n").expect("Failed to write health endpoint");
+    fs::write(www.join("known.bin"), known_content()).expect("Failed to write known content");
+    install_script(
+        &www.join("cgi-bin").join("done"),
+        include_str!("../init/httpd/done"),
+    );
+
+    // Late hook and test names so they run after everything else
+    fs::create_dir_all(rootfs_dir.join("etc/hooks")).expect("Failed to create hooks directory");
+    fs::create_dir_all(rootfs_dir.join("etc/tests.d")).expect("Failed to create tests.d directory");
+    install_script(
+        &rootfs_dir.join("etc/hooks/90-httpd"),
+        include_str!("../init/httpd/hook"),
+    );
+    install_script(
+        &rootfs_dir.join("etc/tests.d/zz-httpd"),
+        include_str!("../init/httpd/test"),
+    );
+    println!("Installed httpd endpoint into /www");
+}
+
+/// Issue a GET over the forwarded port, returning the body of a 200 response
+fn get(port: u16, path: &str) -> Result<Vec<u8>, String> {
+    let mut stream =
+        TcpStream::connect(("127.0.0.1", port)).map_err(|e| format!("connect: {}", e))?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .map_err(|e| e.to_string())?;
+    write!(stream, "GET {} HTTP/1.0\r\nHost: guest\r\n\r\n", path)
+        .map_err(|e| format!("send: {}", e))?;
+
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| format!("receive: {}", e))?;
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("malformed response")?;
+    let status = String::from_utf8_lossy(&response[..split]);
+    let status = status.lines().next().unwrap_or_default();
+    if !status.contains(" 200 ") {
+        return Err(format!("GET {}: {}", path, status));
+    }
+    Ok(response[split + 4..].to_vec())
+}
+
+/// Check the guest's endpoint from the host, retrying until httpd is up
+///
+/// Requests `/cgi-bin/done` at the end so the guest test stops waiting.
+pub fn probe(port: u16, timeout: Duration) -> Result<(), String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match get(port, "/health") {
+            Ok(body) if body.starts_with(b"OK") => break,
+            Ok(_) => return Err("unexpected health response".to_string()),
+            Err(e) if Instant::now() >= deadline => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(500)),
+        }
+    }
+
+    let known = get(port, "/known.bin")?;
+    if known != known_content() {
+        return Err(format!(
+            "known.bin mismatch ({} bytes, expected {})",
+            known.len(),
+            KNOWN_SIZE
+        ));
+    }
+    get(port, "/cgi-bin/done")?;
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::ConfigManifest;
+
+/// Where the IMA certificate and policy are installed in the guest
+const GUEST_CERT: &str = "etc/keys/x509_ima.der";
+const GUEST_POLICY: &str = "etc/ima/ima-policy";
+
+/// Appraisal policy: executables, mmapped code and modules need a valid
+/// signature; pseudo and volatile filesystems (proc, sysfs, debugfs, tmpfs,
+/// devpts, binfmt_misc, securityfs, cgroup, nsfs) are skipped
+const POLICY: &str = "\
+dont_measure fsmagic=0x9fa0
+dont_appraise fsmagic=0x9fa0
+dont_measure fsmagic=0x62656572
+dont_appraise fsmagic=0x62656572
+dont_measure fsmagic=0x64626720
+dont_appraise fsmagic=0x64626720
+dont_measure fsmagic=0x1021994
+dont_appraise fsmagic=0x1021994
+dont_measure fsmagic=0x1cd1
+dont_appraise fsmagic=0x1cd1
+dont_measure fsmagic=0x42494e4d
+dont_appraise fsmagic=0x42494e4d
+dont_measure fsmagic=0x73636673
+dont_appraise fsmagic=0x73636673
+dont_measure fsmagic=0x27e0eb
+dont_appraise fsmagic=0x27e0eb
+dont_measure fsmagic=0x6e736673
+dont_appraise fsmagic=0x6e736673
+measure func=BPRM_CHECK
+measure func=FILE_MMAP mask=MAY_EXEC
+measure func=MODULE_CHECK
+appraise func=BPRM_CHECK appraise_type=imasig
+appraise func=FILE_MMAP mask=MAY_EXEC appraise_type=imasig
+appraise func=MODULE_CHECK appraise_type=imasig
+";
+
+/// What the files in `build/<config>/ima/` are for
+const KEYS_README: &str = "\
+IMA/EVM keys for this configuration
+===================================
+
+ca.crt       CA certificate, built into the guest
+             kernel (CONFIG_SYSTEM_TRUSTED_KEYS), so only keys it signed can
+             join the .ima and .evm keyrings.
+ca.pem       CA private key.
+ima.key      Private key every rootfs file was signed with (evmctl sign
+             --imasig --portable, computed for uid/gid 0).
+ima.der      Certificate of ima.key, signed by the CA; installed in the guest
+             as /etc/keys/x509_ima.der.
+ima-policy   Appraisal policy loaded by the guest's ima test; also installed
+             as /etc/ima/ima-policy.
+
+In the guest, the ima test loads ima.der into both keyrings, enables EVM
+signature checking, loads the policy, then checks that signed binaries still
+run, an unsigned copy is refused, and the measurement list is populated:
+/sys/kernel/security/ima/ascii_runtime_measurements.
+
+With a vTPM, the measurements are also extended into PCR 10; compare the
+list's aggregate against the TPM to check the hypervisor's plumbing.
+
+Sign additional files with:
+  sudo evmctl sign --imasig --portable --uid 0 --gid 0 --key ima.key <file>
+";
+
+/// Kernel options for IMA appraisal and EVM with signatures checked against built-in keys
+pub const IMA_KERNEL_OPTIONS: &[&str] = &[
+    "CONFIG_KEYS",
+    "CONFIG_SECURITY",
+    "CONFIG_SECURITYFS",
+    "CONFIG_ASYMMETRIC_KEY_TYPE",
+    "CONFIG_ASYMMETRIC_PUBLIC_KEY_SUBTYPE",
+    "CONFIG_X509_CERTIFICATE_PARSER",
+    "CONFIG_SYSTEM_TRUSTED_KEYRING",
+    "CONFIG_INTEGRITY",
+    "CONFIG_INTEGRITY_SIGNATURE",
+    "CONFIG_INTEGRITY_ASYMMETRIC_KEYS",
+    "CONFIG_INTEGRITY_TRUSTED_KEYRING",
+    "CONFIG_IMA",
+    "CONFIG_IMA_APPRAISE",
+    "CONFIG_IMA_WRITE_POLICY",
+    "CONFIG_IMA_READ_POLICY",
+    "CONFIG_EVM",
+    "CONFIG_EXT4_FS_SECURITY",
+];
+
+/// Key and policy directory of a configuration
+pub fn keys_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("ima")
+}
+
+/// Run a command, reporting failures
+fn run(cmd: &mut Command, what: &str) -> bool {
+    match cmd.status() {
+        Ok(status) if status.success() => true,
+        Ok(_) => {
+            eprintln!("{} failed", what);
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run {}: {}", what, e);
+            false
+        }
+    }
+}
+
+/// Extensions of the IMA signing certificate, as the kernel's keyring checks expect
+const IMA_CERT_EXTENSIONS: &str = "\
+basicConstraints=critical,CA:FALSE
+keyUsage=digitalSignature
+subjectKeyIdentifier=hash
+authorityKeyIdentifier=keyid
+";
+
+/// Create the CA and the IMA signing key, unless they already exist
+fn generate_keys(dir: &Path) -> bool {
+    if dir.join("ca.crt").exists() && dir.join("ima.der").exists() {
+        return true;
+    }
+    println!("Generating IMA/EVM keys in {}...", dir.display());
+    fs::create_dir_all(dir).expect("Failed to create IMA key directory");
+    fs::write(dir.join("ima.ext"), IMA_CERT_EXTENSIONS)
+        .expect("Failed to write IMA certificate extensions");
+    let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+
+    let generated = run(
+        Command::new("openssl")
+            .args([
+                "req", "-new", "-x509", "-nodes", "-utf8", "-sha256", "-batch",
+            ])
+            .args(["-days", "36500", "-newkey", "rsa:2048"])
+            .args(["-subj", "/CN=guest-test IMA CA"])
+            .args(["-addext", "basicConstraints=critical,CA:TRUE"])
+            .args(["-addext", "keyUsage=critical,keyCertSign"])
+            .args(["-addext", "subjectKeyIdentifier=hash"])
+            .args(["-keyout", &path("ca.pem"), "-out", &path("ca.crt")]),
+        "openssl req (IMA CA)",
+    ) && run(
+        Command::new("openssl")
+            .args(["req", "-new", "-nodes", "-utf8", "-sha256", "-batch"])
+            .args(["-newkey", "rsa:2048"])
+            .args(["-subj", "/CN=guest-test IMA signing key"])
+            .args(["-keyout", &path("ima.key"), "-out", &path("ima.csr")]),
+        "openssl req (IMA key)",
+    ) && run(
+        Command::new("openssl")
+            .args(["x509", "-req", "-sha256", "-days", "36500"])
+            .args(["-in", &path("ima.csr"), "-CA", &path("ca.crt")])
+            .args(["-CAkey", &path("ca.pem"), "-CAcreateserial"])
+            .args(["-extfile", &path("ima.ext")])
+            .args(["-outform", "DER", "-out", &path("ima.der")]),
+        "openssl x509 (IMA certificate)",
+    );
+    if !generated {
+        let _ = fs::remove_file(dir.join("ima.der"));
+    }
+    generated
+}
+
+/// Whether the manifest enables the `ima` profile
+pub fn enabled(manifest: &ConfigManifest) -> bool {
+    manifest.profiles.iter().any(|profile| profile == "ima")
+}
+
+/// Kernel options building the IMA CA into the trusted keys, if the profile is enabled
+pub fn kernel_options(
+    config_name: &str,
+    manifest: &ConfigManifest,
+) -> Option<Vec<(String, String)>> {
+    if !enabled(manifest) {
+        return Some(Vec::new());
+    }
+    let dir = keys_dir(config_name);
+    if !generate_keys(&dir) {
+        return None;
+    }
+    let ca = dir.join("ca.crt").canonicalize().ok()?;
+    Some(vec![(
+        "CONFIG_SYSTEM_TRUSTED_KEYS".to_string(),
+        format!("\"{}\"", ca.display()),
+    )])
+}
+
+/// Install the certificate and policy, then sign every file in the rootfs
+///
+/// Signatures live in security.ima/security.evm xattrs, which need root to set
+/// and survive the `cp -a` into the image.
+pub fn sign_rootfs(config_name: &str, rootfs_dir: &Path) -> bool {
+    let dir = keys_dir(config_name);
+    if !generate_keys(&dir) {
+        return false;
+    }
+    let cert_dest = rootfs_dir.join(GUEST_CERT);
+    let policy_dest = rootfs_dir.join(GUEST_POLICY);
+    let installed = [&cert_dest, &policy_dest]
+        .iter()
+        .all(|dest| dest.parent().is_some_and(|p| fs::create_dir_all(p).is_ok()))
+        && fs::copy(dir.join("ima.der"), &cert_dest).is_ok()
+        && fs::write(&policy_dest, POLICY).is_ok()
+        && fs::write(dir.join("ima-policy"), POLICY).is_ok()
+        && fs::write(dir.join("README"), KEYS_README).is_ok();
+    if !installed {
+        eprintln!("Failed to install the IMA certificate and policy");
+        return false;
+    }
+
+    println!("Signing rootfs files for IMA/EVM appraisal...");
+    // EVM covers ownership, which becomes root:root in the image
+    let script = format!(
+        "find {} -type f -print0 | xargs -0 -r sudo evmctl sign --imasig --portable \
+         --uid 0 --gid 0 --hashalgo sha256 --key {}",
+        rootfs_dir.display(),
+        dir.join("ima.key").display()
+    );
+    run(
+        Command::new("bash").args(["-c", &format!("set -o pipefail; {}", script)]),
+        "evmctl sign",
+    )
+}
@@ -0,0 +1,47 @@
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{Value, json};
+
+use crate::qmp::Qmp;
+
+/// Printed by payloads/virtio-input once it listens for events
+pub const READY_MARKER: &str = "virtio-input: ready";
+
+/// QEMU devices the virtio-input profile adds
+pub const QEMU_DEVICES: &[&str] = &["virtio-keyboard-pci", "virtio-mouse-pci"];
+
+/// Key press and release
+fn key(qcode: &str, down: bool) -> Value {
+    json!({ "type": "key", "data": { "down": down, "key": { "type": "qcode", "data": qcode } } })
+}
+
+/// Mouse button press and release
+fn button(name: &str, down: bool) -> Value {
+    json!({ "type": "btn", "data": { "down": down, "button": name } })
+}
+
+/// Relative pointer motion
+fn motion(axis: &str, value: i64) -> Value {
+    json!({ "type": "rel", "data": { "axis": axis, "value": value } })
+}
+
+/// Inject the key, pointer and button events the guest payload waits for
+///
+/// The virtio devices take over input routing once their guest driver is up, so
+/// no device needs to be named.
+pub fn inject_test_events(qmp: &mut Qmp) -> Result<(), String> {
+    let steps = [
+        vec![key("a", true)],
+        vec![key("a", false)],
+        vec![motion("x", 10), motion("y", 10)],
+        vec![button("left", true)],
+        vec![button("left", false)],
+    ];
+    for events in steps {
+        qmp.execute("input-send-event", json!({ "events": events }))?;
+        // Separate reports so the guest sees distinct press and release events
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use std::net::TcpListener;
+use std::time::Duration;
+
+use crate::arch::arch_of;
+use crate::config::load_manifest;
+use crate::harness::GuestSession;
+use crate::qemu::{LaunchOptions, is_built, launch};
+
+/// Pick a free TCP port on the host for the guests' socket network
+fn free_port() -> Option<u16> {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .ok()
+}
+
+/// Launch options connecting a guest to the shared socket network
+///
+/// Both guests may boot the same configuration: -snapshot keeps their writes out of the
+/// shared rootfs image, and ssh/httpd get host ports of their own.
+fn launch_options(config_name: &str, role: &str, netdev: String) -> LaunchOptions {
+    let mac = match role {
+        "server" => "52:54:00:77:00:01",
+        _ => "52:54:00:77:00:02",
+    };
+    let device = format!(
+        "virtio-net-{}",
+        arch_of(config_name).map_or("pci", |a| a.virtio_suffix)
+    );
+
+    LaunchOptions {
+        extra_args: vec![
+            "-snapshot".to_string(),
+            "-netdev".to_string(),
+            format!("socket,id=intervm,{}", netdev),
+            "-device".to_string(),
+            format!("{},netdev=intervm,mac={}", device, mac),
+        ],
+        extra_cmdline: vec![format!("intervm_role={}", role)],
+        ssh_port: free_port(),
+        httpd_port: free_port(),
+        ..LaunchOptions::default()
+    }
+}
+
+/// Check that a configuration is built with the intervm profile
+fn check_config(config_name: &str) -> bool {
+    match load_manifest(config_name) {
+        Ok(manifest) if manifest.profiles.iter().any(|p| p == "intervm") => {}
+        Ok(_) => {
+            eprintln!("{} does not enable the intervm profile", config_name);
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    }
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+    true
+}
+
+/// Spawn a guest for the scenario
+fn spawn_guest(config_name: &str, options: &LaunchOptions) -> Option<GuestSession> {
+    match launch(config_name, options) {
+        Ok(session) => Some(session),
+        Err(e) => {
+            eprintln!("Failed to start QEMU for {}: {}", config_name, e);
+            None
+        }
+    }
+}
+
+/// Wait for a marker and print the outcome of the step
+fn step(session: &mut GuestSession, who: &str, marker: &str, timeout: Duration) -> bool {
+    let ok = session.expect(marker, timeout);
+    println!(
+        "  {:<4}  {:<6} {}",
+        if ok { "ok" } else { "FAIL" },
+        who,
+        marker
+    );
+    ok
+}
+
+/// Boot two guests on a shared virtual network and run ping/ordering/throughput tests
+pub fn intervm_test(server_config: &str, client_config: &str, timeout: Duration) -> bool {
+    if !check_config(server_config) || !check_config(client_config) {
+        return false;
+    }
+    let Some(port) = free_port() else {
+        eprintln!("No free TCP port for the inter-VM network");
+        return false;
+    };
+
+    println!(
+        "Inter-VM test: server {} <-> client {} (port {})",
+        server_config, client_config, port
+    );
+
+    let server_options = launch_options(
+        server_config,
+        "server",
+        format!("listen=127.0.0.1:{}", port),
+    );
+    let Some(mut server) = spawn_guest(server_config, &server_options) else {
+        return false;
+    };
+    if !step(&mut server, "server", "intervm: server ready", timeout) {
+        return false;
+    }
+
+    let client_options = launch_options(
+        client_config,
+        "client",
+        format!("connect=127.0.0.1:{}", port),
+    );
+    let Some(mut client) = spawn_guest(client_config, &client_options) else {
+        return false;
+    };
+
+    let passed = step(&mut client, "client", "intervm: ping OK", timeout)
+        && step(&mut client, "client", "intervm: ordering sent", timeout)
+        && step(&mut server, "server", "intervm: ordering OK", timeout)
+        && step(&mut client, "client", "intervm: client done", timeout)
+        && step(&mut server, "server", "intervm: server done", timeout);
+
+    if let Some(line) = client
+        .output()
+        .lines()
+        .find(|l| l.starts_with("intervm: throughput"))
+    {
+        println!("  {}", line.trim());
+    }
+
+    client.kill();
+    server.kill();
+
+    println!("Inter-VM test {}", if passed { "passed" } else { "failed" });
+    passed
+}
@@ -0,0 +1,198 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::load_manifest;
+use crate::qemu::{LaunchOptions, is_built, launch};
+
+/// Size of the shared-memory region given to the guest
+pub const IVSHMEM_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Offset and size of the patterns, must match payloads/ivshmem-test
+const PATTERN_OFFSET: u64 = 4096;
+const PATTERN_SIZE: usize = 4096;
+
+/// Name of the POSIX shared memory object backing a configuration's device
+pub fn shm_name(config_name: &str) -> String {
+    format!("guest-test-linux-{}-ivshmem", config_name)
+}
+
+/// Path of the shared memory object on the host
+pub fn shm_path(config_name: &str) -> PathBuf {
+    Path::new("/dev/shm").join(shm_name(config_name))
+}
+
+/// Pattern the host writes for the guest to verify
+fn host_pattern() -> Vec<u8> {
+    (0..PATTERN_SIZE).map(|i| (i * 7 + 3) as u8).collect()
+}
+
+/// Pattern the guest writes for the host to verify
+fn guest_pattern() -> Vec<u8> {
+    (0..PATTERN_SIZE).map(|i| (i * 13 + 5) as u8).collect()
+}
+
+/// Fill the shared memory with the host magic and pattern
+fn write_host_pattern(path: &Path) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    file.set_len(IVSHMEM_SIZE)?;
+    file.write_all(b"IVSHMEM-HOST\0")?;
+    file.seek(SeekFrom::Start(PATTERN_OFFSET))?;
+    file.write_all(&host_pattern())?;
+    // Clear any guest data left over from a previous run
+    file.seek(SeekFrom::Start(IVSHMEM_SIZE / 2))?;
+    file.write_all(&[0u8; 16])?;
+    Ok(())
+}
+
+/// Check the guest magic and pattern in the shared memory
+fn check_guest_pattern(path: &Path) -> Result<(), String> {
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut magic = [0u8; 13];
+    file.seek(SeekFrom::Start(IVSHMEM_SIZE / 2))
+        .and_then(|_| file.read_exact(&mut magic))
+        .map_err(|e| e.to_string())?;
+    if &magic != b"IVSHMEM-GUEST" {
+        return Err("guest magic not found in shared memory".to_string());
+    }
+
+    let mut pattern = vec![0u8; PATTERN_SIZE];
+    file.seek(SeekFrom::Start(IVSHMEM_SIZE / 2 + PATTERN_OFFSET))
+        .and_then(|_| file.read_exact(&mut pattern))
+        .map_err(|e| e.to_string())?;
+    match pattern
+        .iter()
+        .zip(guest_pattern())
+        .position(|(a, b)| *a != b)
+    {
+        Some(i) => Err(format!("guest pattern mismatch at byte {}", i)),
+        None => Ok(()),
+    }
+}
+
+/// Start an ivshmem-server for the doorbell test
+fn start_server(config_name: &str, socket: &Path) -> Option<Child> {
+    let _ = fs::remove_file(socket);
+    let child = Command::new("ivshmem-server")
+        .args(["-F", "-S", &socket.to_string_lossy()])
+        .args(["-M", &shm_name(config_name)])
+        .args(["-l", &IVSHMEM_SIZE.to_string()])
+        .args(["-n", "1"])
+        .spawn();
+
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Failed to start ivshmem-server: {}", e);
+            return None;
+        }
+    };
+
+    // Wait for the server to create its socket and shared memory
+    for _ in 0..50 {
+        if socket.exists() {
+            return Some(child);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    eprintln!("ivshmem-server did not create {}", socket.display());
+    None
+}
+
+/// Boot a configuration with the ivshmem profile and check the device end to end
+pub fn ivshmem_test(config_name: &str, doorbell: bool, timeout: Duration) -> bool {
+    match load_manifest(config_name) {
+        Ok(manifest) if manifest.profiles.iter().any(|p| p == "ivshmem") => {}
+        Ok(_) => {
+            eprintln!("{} does not enable the ivshmem profile", config_name);
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    }
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+
+    let mut options = LaunchOptions::default();
+    let mut server = None;
+    if doorbell {
+        let socket = std::env::temp_dir().join(format!("{}.sock", shm_name(config_name)));
+        match start_server(config_name, &socket) {
+            Some(child) => server = Some(child),
+            None => return false,
+        }
+        options.ivshmem_server = Some(socket);
+    }
+
+    let shm = shm_path(config_name);
+    if let Err(e) = write_host_pattern(&shm) {
+        eprintln!("Failed to prepare {}: {}", shm.display(), e);
+        return false;
+    }
+
+    let mut session = match launch(config_name, &options) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Failed to start QEMU: {}", e);
+            return false;
+        }
+    };
+
+    let mut passed = true;
+    let checks = [
+        "ivshmem: host pattern OK",
+        "ivshmem: guest pattern written",
+        "PASS: ivshmem-test",
+    ];
+    for check in checks {
+        if session.expect(check, timeout) {
+            println!("  ok    {}", check);
+        } else {
+            println!("  FAIL  {}", check);
+            passed = false;
+            break;
+        }
+    }
+    session.kill();
+    if let Some(mut server) = server {
+        let _ = server.kill();
+        let _ = server.wait();
+    }
+
+    // The latency line is informational
+    if let Some(line) = session
+        .output()
+        .lines()
+        .find(|l| l.starts_with("ivshmem: latency"))
+    {
+        println!("  {}", line.trim());
+    }
+
+    match check_guest_pattern(&shm) {
+        Ok(()) => println!("  ok    guest pattern visible on host"),
+        Err(e) => {
+            println!("  FAIL  {}", e);
+            passed = false;
+        }
+    }
+    let _ = fs::remove_file(&shm);
+
+    println!(
+        "ivshmem test {} for {}",
+        if passed { "passed" } else { "failed" },
+        config_name
+    );
+    passed
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::testing::{TESTS_START_MARKER, TestRun, TestStatus, parse_result};
+
+/// Console lines kept as a test's `<system-out>`, counted back from its result line
+const EXCERPT_LINES: usize = 50;
+
+/// Escape text for XML, dropping control characters XML 1.0 cannot carry
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' => escaped.push(c),
+            c if c.is_control() => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Console output of each guest test: the lines between its result and the previous one
+fn test_output(console: &str) -> HashMap<String, String> {
+    let mut outputs = HashMap::new();
+    let mut pending: Vec<&str> = Vec::new();
+    for line in console.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.trim() == TESTS_START_MARKER {
+            pending.clear();
+        } else if let Some((name, status, _)) = parse_result(line.trim()) {
+            if status != TestStatus::Skip {
+                let start = pending.len().saturating_sub(EXCERPT_LINES);
+                outputs.insert(name.to_string(), pending[start..].join("\n"));
+            }
+            pending.clear();
+        } else {
+            pending.push(line);
+        }
+    }
+    outputs
+}
+
+/// Render a test run as a JUnit XML document with one test suite for the configuration
+pub fn render(config_name: &str, run: &TestRun) -> String {
+    let outputs = test_output(&run.console);
+    let count = |status| run.cases.iter().filter(|c| c.status == status).count();
+    // A boot that never reached the tests still has to show up as a failure
+    let boot_failed = run.cases.is_empty() && !run.passed;
+    let (tests, failures, skipped) = (
+        run.cases.len() + boot_failed as usize,
+        count(TestStatus::Fail) + boot_failed as usize,
+        count(TestStatus::Skip),
+    );
+    let time = run.duration.as_secs_f64();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"guest-test-linux\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        tests, failures, skipped, time
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\">",
+        escape(config_name),
+        tests,
+        failures,
+        skipped,
+        time
+    );
+    for case in &run.cases {
+        let _ = write!(
+            xml,
+            "    <testcase classname=\"{}\" name=\"{}\"",
+            escape(config_name),
+            escape(&case.name)
+        );
+        if let Some(secs) = case.duration_secs {
+            let _ = write!(xml, " time=\"{:.3}\"", secs);
+        }
+        xml.push_str(">\n");
+        match case.status {
+            TestStatus::Pass => {}
+            TestStatus::Skip => {
+                xml.push_str("      <skipped message=\"excluded by --filter/--exclude\"/>\n")
+            }
+            TestStatus::Fail => {
+                // The last line a failing test printed usually says why
+                let last_line = outputs
+                    .get(&case.name)
+                    .and_then(|o| o.lines().rev().find(|l| !l.trim().is_empty()));
+                let message = match (&case.detail, last_line) {
+                    (Some(detail), _) => detail.clone(),
+                    (None, Some(line)) => format!("{} failed: {}", case.name, line.trim()),
+                    (None, None) => format!("{} exited with failure", case.name),
+                };
+                let _ = writeln!(xml, "      <failure message=\"{}\"/>", escape(&message));
+            }
+        }
+        if let Some(output) = outputs.get(&case.name).filter(|o| !o.is_empty()) {
+            let _ = writeln!(xml, "      <system-out>{}</system-out>", escape(output));
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    if boot_failed {
+        let _ = writeln!(
+            xml,
+            "    <testcase classname=\"{0}\" name=\"boot\">\n      \
+             <failure message=\"guest did not report test results\"/>\n    </testcase>",
+            escape(config_name)
+        );
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Write a test run as JUnit XML for CI dashboards
+pub fn write_report(config_name: &str, run: &TestRun, output: &Path) -> bool {
+    if let Some(dir) = output.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    match fs::write(output, render(config_name, run)) {
+        Ok(()) => {
+            println!("JUnit report written to {}", output.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", output.display(), e);
+            false
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Read the enabled options of a kernel `.config` file (e.g. `CONFIG_VIRTIO_BLK` -> `y`)
+pub fn read_config(config_path: &Path) -> BTreeMap<String, String> {
+    let mut options = BTreeMap::new();
+    let Ok(content) = fs::read_to_string(config_path) else {
+        return options;
+    };
+
+    for line in content.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some((name, value)) = line.split_once('=') {
+            options.insert(name.to_string(), value.trim_matches('"').to_string());
+        }
+    }
+
+    options
+}
+
+/// Check if an option is built in or built as a module
+pub fn is_enabled(options: &BTreeMap<String, String>, name: &str) -> bool {
+    matches!(options.get(name).map(String::as_str), Some("y") | Some("m"))
+}
+
+/// Set options in a kernel `.config` file; a value of `n` disables the option
+pub fn set_options(config_path: &Path, options: &[(String, String)]) -> std::io::Result<()> {
+    let content = fs::read_to_string(config_path)?;
+
+    let format_option = |name: &str, value: &str| {
+        if value == "n" {
+            format!("# {} is not set", name)
+        } else {
+            format!("{}={}", name, value)
+        }
+    };
+
+    let mut remaining: Vec<&(String, String)> = options.iter().collect();
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let name = line
+                .strip_prefix("# ")
+                .and_then(|l| l.strip_suffix(" is not set"))
+                .or_else(|| line.split_once('=').map(|(name, _)| name));
+            match name.and_then(|name| remaining.iter().position(|(n, _)| n == name)) {
+                Some(index) => {
+                    let (name, value) = remaining.remove(index);
+                    format_option(name, value)
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect();
+
+    // Options not present yet are appended; olddefconfig sorts them out
+    for (name, value) in remaining {
+        lines.push(format_option(name, value));
+    }
+
+    fs::write(config_path, lines.join("\n") + "\n")
+}
@@ -1,44 +1,295 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use crate::config::parse_config_name;
-use crate::system::{get_host_arch, get_arch_config, num_cpus};
+use crate::config::{ConfigManifest, load_manifest, parse_config_name};
+use crate::arch::{Arch, find_arch};
+use crate::cache::ArtifactCache;
+use crate::download::{KernelSelection, KernelSource};
+use crate::error::{BuildError, run};
+use crate::stages::{Stage, StageSelection};
+use crate::system::{get_host_arch, make_jobs};
 
 /// Build Linux for a specific configuration
-pub fn build_linux_for_config(config_name: &str) {
+pub fn build_linux_for_config(config_name: &str, selection: &KernelSelection, stages: &StageSelection, cache: Option<&ArtifactCache>, strict: bool) -> Result<(), BuildError> {
     let (arch, name) = parse_config_name(config_name);
     
     println!("Starting build for configuration: {}", config_name);
+    crate::output::log_to(config_name);
     let config_path = PathBuf::from("config").join(&arch).join(&name);
-    let linux_dir = "linux";
     let build_dir = PathBuf::from("build").join(config_name).join("linux");
 
-    // Copy config
+    let Some(arch_info) = find_arch(&arch) else {
+        return Err(BuildError::Config(format!("unsupported architecture: {}", arch)));
+    };
+
+    let manifest = load_manifest(config_name).map_err(|e| {
+        BuildError::Config(format!("invalid manifest for configuration {}: {}", config_name, e))
+    })?;
+
+    // Stages left out with --only reuse the outputs of an earlier build
+    stages.check_inputs(config_name, manifest.bare);
+    let build_kernel = stages.runs(Stage::Kernel);
+
+    // The default tree is fetched up front; other refs get their own checkout
+    let source = KernelSource::select(selection, &manifest, &arch);
+    if build_kernel && !source.is_default() {
+        crate::output::stage("kernel source", || source.fetch())
+            .map_err(|e| BuildError::Step(format!("failed to fetch Linux {}: {}", source.git_ref, e)))?;
+    }
+    // Without the kernel stage, keep to the tree the last kernel build used
+    let linux_dir = if build_kernel { source.dir() } else { source_dir(config_name) };
+
     if !Path::new(&config_path).exists() {
-        eprintln!("Config file not found for configuration: {}", config_name);
-        return;
+        return Err(BuildError::Config(format!("config file not found for configuration: {}", config_name)));
     }
-    fs::create_dir_all(&build_dir).expect("Failed to create build directory");
-    fs::copy(&config_path, build_dir.join(".config")).expect("Failed to copy config");
+    fs::create_dir_all(&build_dir).map_err(BuildError::io("failed to create build directory"))?;
 
     // Detect host architecture and set cross-compile prefix if needed
     let host_arch = get_host_arch();
-    let (kernel_arch, cross_compile_prefix) = get_arch_config(&arch, &host_arch);
+    let kernel_arch = arch_info.kernel_arch.to_string();
+    let cross_compile_prefix = arch_info.cross_compile_prefix(&host_arch);
     let cross_compile_prefix_clone = cross_compile_prefix.clone();
 
+    if build_kernel {
+        crate::output::stage("kernel config", || {
+            write_base_config(config_name, &manifest, &linux_dir, &build_dir, &kernel_arch, &cross_compile_prefix)?;
+
+            apply_kernel_options(config_name, &manifest, &arch, &build_dir)?;
+            olddefconfig(&linux_dir, &build_dir, &kernel_arch, &cross_compile_prefix)
+        })?;
+
+        crate::output::stage("kernel build", || {
+            // Reuse a cached kernel when config, source and toolchain match; modules, ORC
+            // export and DTBs need the full build tree, so those always build
+            let kconfig = crate::kconfig::read_config(&build_dir.join(".config"));
+            let needs_tree = manifest.orc
+                || crate::kconfig::is_enabled(&kconfig, "CONFIG_MODULES")
+                || manifest.initramfs_embed
+                || !manifest.dtbs.is_empty()
+                || !manifest.modules.is_empty()
+                || crate::profile::enabled_profiles(&manifest)
+                    .iter()
+                    .any(|p| !p.modules.is_empty());
+            let kernel_entry = cache
+                .filter(|_| !needs_tree)
+                .and_then(|_| kernel_cache_entry(&linux_dir, &build_dir, &kernel_arch, &cross_compile_prefix));
+            let cached = match (cache, &kernel_entry) {
+                (Some(cache), Some(entry)) => cache.restore(entry, &build_dir),
+                _ => false,
+            };
+
+            if !cached {
+                make_kernel(config_name, &linux_dir, &build_dir, arch_info, &cross_compile_prefix, !manifest.dtbs.is_empty())?;
+
+                println!(
+                    "Linux kernel build completed for {}: {}",
+                    config_name,
+                    build_dir.display()
+                );
+
+                if let (Some(cache), Some(entry)) = (cache, &kernel_entry) {
+                    let image = arch_info.built_kernel_image(&build_dir);
+                    let image = image.strip_prefix(&build_dir).unwrap_or(&image).to_string_lossy();
+                    cache.populate(entry, &build_dir, &[&image, "System.map", "vmlinux", "include/config/kernel.release"]);
+                }
+            }
+            Ok(())
+        })?;
+
+        if !manifest.dtbs.is_empty() || manifest.qemu_dtb {
+            crate::output::stage("devicetree blobs", || {
+                crate::dtb::collect_dtbs(config_name, &manifest, &build_dir, arch_info)
+            })?;
+        }
+
+        crate::output::stage("kernel artifacts", || {
+            // Copy kernel image to build/config_name directory
+            copy_kernel_image(config_name, arch_info, &build_dir)?;
+
+            // Export symbols so hypervisor-side tracers can resolve guest PCs
+            crate::symbols::export_symbols(config_name, &manifest, &build_dir, &cross_compile_prefix_clone)?;
+
+            // Generate the devicetree memory layout for hypervisors that build their own DT
+            crate::memory::write_dt_fragment(config_name, &arch, &manifest);
+
+            // Compile devicetree overlays for hypervisor-emulated devices
+            if !crate::dtb::build_overlays(config_name, &manifest) {
+                return Err(BuildError::Step("devicetree overlay build failed".to_string()));
+            }
+
+            // Record what the guest expects from the hypervisor
+            crate::manifest::write_manifest(config_name, &manifest, &source, &build_dir);
+            Ok(())
+        })?;
+    }
+
+    // Build busybox and create rootfs, or just the bare init
+    if manifest.bare {
+        if stages.runs(Stage::Rootfs) {
+            crate::output::stage("bare init", || {
+                crate::bare::create_initramfs_for_config(config_name, &manifest, &cross_compile_prefix_clone)
+            })?;
+        }
+    } else {
+        crate::busybox::build_busybox_and_rootfs_for_config(config_name, &arch, &kernel_arch, &cross_compile_prefix_clone, stages, cache, strict)?;
+    }
+
+    // A rebuilt kernel or rootfs both need relinking
+    if manifest.initramfs_embed && (build_kernel || stages.runs(Stage::Rootfs)) {
+        crate::output::stage("embed initramfs", || {
+            embed_initramfs(config_name, &linux_dir, &build_dir, arch_info, &cross_compile_prefix_clone)
+        })?;
+    }
+
+    if manifest.vmm_artifacts {
+        crate::output::stage("vmm artifacts", || crate::vmm::write_artifacts(config_name, &manifest, &build_dir))?;
+    }
+
+    crate::output::stage("firmware", || crate::firmware::prepare(config_name, &manifest)).map_err(BuildError::Step)?;
+
+    crate::output::stage("vm config", || crate::vmconfig::write_vm_config(config_name, &manifest))?;
+
+    if manifest.board.is_some() {
+        crate::output::stage("sdcard image", || crate::board::write_image(config_name, &manifest)).map_err(BuildError::Step)?;
+    }
+
+    crate::output::stage("size report", || crate::sizes::report(config_name, &cross_compile_prefix_clone)).map_err(BuildError::Step)?;
+
+    crate::manifest::record_artifacts(config_name).map_err(BuildError::Step)?;
+
+    // License texts of everything in the image, for redistributing it
+    crate::output::stage("licenses", || crate::licenses::collect(config_name, &manifest)).map_err(BuildError::Step)?;
+
+    // Record artifact sizes and hold them against the config's budget
+    crate::budget::check_build(config_name, &manifest)
+}
+
+/// Relink the kernel with the freshly packed rootfs as its built-in initramfs
+fn embed_initramfs(config_name: &str, linux_dir: &Path, build_dir: &Path, arch_info: &Arch, cross_compile_prefix: &Option<String>) -> Result<(), BuildError> {
+    let archive = crate::rootfs::embedded_initramfs_path(config_name);
+    let archive = archive.canonicalize().map_err(BuildError::io(format!("failed to resolve {}", archive.display())))?;
+    println!("Embedding {} into the kernel image...", archive.display());
+    crate::kconfig::set_options(
+        &build_dir.join(".config"),
+        &[
+            ("CONFIG_INITRAMFS_SOURCE".to_string(), format!("\"{}\"", archive.display())),
+            ("CONFIG_INITRAMFS_COMPRESSION_GZIP".to_string(), "y".to_string()),
+        ],
+    )
+    .map_err(BuildError::io("failed to apply kernel options"))?;
+    olddefconfig(linux_dir, build_dir, arch_info.kernel_arch, cross_compile_prefix)?;
+    make_kernel(config_name, linux_dir, build_dir, arch_info, cross_compile_prefix, false)?;
+    copy_kernel_image(config_name, arch_info, build_dir)
+}
+
+/// Kernel source tree a configuration was configured against
+///
+/// Out-of-tree builds leave a `source` link to it in the build directory.
+pub fn source_dir(config_name: &str) -> PathBuf {
+    let build_dir = PathBuf::from("build").join(config_name).join("linux");
+    match fs::read_link(build_dir.join("source")) {
+        // A relative link is relative to the build directory, which `join` keeps
+        Ok(target) => build_dir.join(target),
+        Err(_) => PathBuf::from("linux"),
+    }
+}
+
+/// Write the `.config` of a configuration before the manifest's kernel options go on top
+///
+/// Without `base_config` the config file is a full `.config`; with it, the file is a
+/// fragment merged onto the generated base. `fragments` are merged last either way.
+fn write_base_config(config_name: &str, manifest: &ConfigManifest, linux_dir: &Path, build_dir: &Path, kernel_arch: &str, cross_compile_prefix: &Option<String>) -> Result<(), BuildError> {
+    let (arch, name) = parse_config_name(config_name);
+    let config_dir = PathBuf::from("config").join(&arch);
+    let dot_config = build_dir.join(".config");
+    let mut fragments: Vec<PathBuf> = manifest.fragments.iter().map(|f| config_dir.join(f)).collect();
+    match &manifest.base_config {
+        Some(target) => {
+            let build_dir = build_dir.canonicalize().map_err(BuildError::io(format!("failed to resolve {}", build_dir.display())))?;
+            let mut cmd = Command::new("make");
+            cmd.current_dir(linux_dir).args([
+                format!("O={}", build_dir.display()),
+                format!("ARCH={}", kernel_arch),
+                target.clone(),
+            ]);
+            if let Some(prefix) = cross_compile_prefix {
+                cmd.arg(format!("CROSS_COMPILE={}", prefix));
+            }
+            run(&mut cmd, &format!("make {}", target))?;
+            fragments.insert(0, config_dir.join(&name));
+        }
+        None => {
+            fs::copy(config_dir.join(&name), &dot_config).map_err(BuildError::io("failed to copy config"))?;
+        }
+    }
+    if fragments.is_empty() {
+        return Ok(());
+    }
+
+    // merge_config.sh leaves temporary files in its working directory, so run it in the build directory
+    let resolve = |path: &Path| path.canonicalize().map_err(BuildError::io(format!("failed to resolve {}", path.display())));
+    let script = resolve(&linux_dir.join("scripts/kconfig/merge_config.sh"))?;
+    let mut cmd = Command::new(script);
+    cmd.current_dir(build_dir).args(["-m", "-O", "."]).arg(".config");
+    for fragment in &fragments {
+        println!("Merging config fragment {}", fragment.display());
+        cmd.arg(resolve(fragment)?);
+    }
+    run(&mut cmd, "merging config fragments")
+}
+
+/// Complete a `.config` in `build_dir` against the kernel source in `linux_dir`
+fn olddefconfig(linux_dir: &Path, build_dir: &Path, kernel_arch: &str, cross_compile_prefix: &Option<String>) -> Result<(), BuildError> {
+    let build_dir = build_dir.canonicalize().map_err(BuildError::io(format!("failed to resolve {}", build_dir.display())))?;
+    let mut cmd = Command::new("make");
+    cmd.current_dir(linux_dir).args([
+        format!("O={}", build_dir.display()),
+        format!("ARCH={}", kernel_arch),
+        "olddefconfig".to_string(),
+    ]);
+    if let Some(prefix) = cross_compile_prefix {
+        cmd.arg(format!("CROSS_COMPILE={}", prefix));
+    }
+    run(&mut cmd, "make olddefconfig")
+}
+
+/// Apply the kernel options required by the config manifest: its own, module signing,
+/// IMA and the built-in command line
+fn apply_kernel_options(config_name: &str, manifest: &ConfigManifest, arch: &str, build_dir: &Path) -> Result<(), BuildError> {
+    let mut kernel_options = manifest.kernel_options(arch);
+    let signing_options = crate::modsign::kernel_options(config_name, manifest)
+        .ok_or_else(|| BuildError::Step("module signing setup failed".to_string()))?;
+    kernel_options.extend(signing_options);
+    let ima_options = crate::ima::kernel_options(config_name, manifest)
+        .ok_or_else(|| BuildError::Step("IMA key setup failed".to_string()))?;
+    kernel_options.extend(ima_options);
+    kernel_options.extend(crate::cmdline::kernel_options(config_name, manifest));
+    println!("Applying kernel options: {:?}", kernel_options);
+    crate::kconfig::set_options(&build_dir.join(".config"), &kernel_options)
+        .map_err(BuildError::io("failed to apply kernel options"))
+}
+
+/// Build the kernel image of a configured `build_dir` from the source in `linux_dir`
+fn make_kernel(config_name: &str, linux_dir: &Path, build_dir: &Path, arch_info: &Arch, cross_compile_prefix: &Option<String>, dtbs: bool) -> Result<(), BuildError> {
     // Build make arguments - select appropriate target based on architecture
-    let kernel_target = match arch.as_str() {
-        "arm64" => "Image",
-        "x86" => "bzImage",
-        _ => "bzImage", // default for other architectures
-    };
+    let kernel_target = arch_info.kernel_target;
+    let build_dir = build_dir.canonicalize().map_err(BuildError::io(format!("failed to resolve {}", build_dir.display())))?;
 
     let mut make_args = vec![
-        format!("O={}", build_dir.canonicalize().unwrap().display()),
-        format!("ARCH={}", kernel_arch),
+        format!("O={}", build_dir.display()),
+        format!("ARCH={}", arch_info.kernel_arch),
         kernel_target.to_string(),
-        format!("-j{}", num_cpus()),
+        format!("-j{}", make_jobs()),
     ];
+    // Devicetree blobs of the platforms enabled in the config
+    if dtbs {
+        make_args.push("dtbs".to_string());
+    }
+    // In-tree modules (=m), for modules_install in the rootfs stage
+    let config = crate::kconfig::read_config(&build_dir.join(".config"));
+    if crate::kconfig::is_enabled(&config, "CONFIG_MODULES") {
+        make_args.push("modules".to_string());
+    }
 
     // Add CROSS_COMPILE if cross-compiling
     if let Some(prefix) = cross_compile_prefix {
@@ -54,81 +305,88 @@ pub fn build_linux_for_config(config_name: &str) {
         config_name, kernel_target, make_args
     );
     let mut cmd = Command::new("make");
-    cmd.current_dir(linux_dir).args(make_args);
+    cmd.current_dir(linux_dir).args(make_args).envs(crate::repro::make_env());
     println!("{:?}", cmd);
 
-    let status = cmd.status().expect("Failed to run make");
+    run(&mut cmd, "make")
+}
 
-    if !status.success() {
-        eprintln!("Make failed for configuration: {}", config_name);
-        return;
-    }
+/// Build only the kernel image of a configuration from another source tree
+///
+/// Used for version matrices, which boot each kernel with the configuration's rootfs.
+pub fn build_kernel_only(config_name: &str, linux_dir: &Path, build_dir: &Path) -> Option<PathBuf> {
+    let (arch, _) = parse_config_name(config_name);
+    let arch_info = find_arch(&arch)?;
+    let manifest = match load_manifest(config_name) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return None;
+        }
+    };
+    let cross_compile_prefix = arch_info.cross_compile_prefix(&get_host_arch());
 
-    println!(
-        "Linux kernel build completed for {}: {}",
-        config_name,
-        build_dir.display()
-    );
+    if let Err(e) = fs::create_dir_all(build_dir)
+        .map_err(BuildError::io("failed to create build directory"))
+        .and_then(|_| write_base_config(config_name, &manifest, linux_dir, build_dir, arch_info.kernel_arch, &cross_compile_prefix))
+        .and_then(|_| apply_kernel_options(config_name, &manifest, &arch, build_dir))
+    {
+        eprintln!("Kernel config failed for {}: {}", config_name, e);
+        return None;
+    }
 
-    // Copy kernel image to build/config_name directory
-    copy_kernel_image(config_name, &arch, kernel_target, &build_dir);
+    if let Err(e) = olddefconfig(linux_dir, build_dir, arch_info.kernel_arch, &cross_compile_prefix)
+        .and_then(|_| make_kernel(config_name, linux_dir, build_dir, arch_info, &cross_compile_prefix, false))
+    {
+        eprintln!("Kernel build failed for {}: {}", config_name, e);
+        return None;
+    }
+    Some(arch_info.built_kernel_image(build_dir))
+}
 
-    // Build busybox and create rootfs
-    crate::busybox::build_busybox_and_rootfs_for_config(config_name, &arch, &kernel_arch, &cross_compile_prefix_clone);
+/// Cache entry of the kernel built from the current `.config`, source tree and toolchain
+fn kernel_cache_entry(
+    linux_dir: &Path,
+    build_dir: &Path,
+    kernel_arch: &str,
+    cross_compile_prefix: &Option<String>,
+) -> Option<String> {
+    let config = fs::read_to_string(build_dir.join(".config")).ok()?;
+    let material = format!(
+        "ARCH={}\n{}\n{}\n{}",
+        kernel_arch,
+        crate::cache::toolchain_id(cross_compile_prefix),
+        crate::cache::source_revision(linux_dir),
+        config
+    );
+    crate::cache::cache_entry("kernel", &material, &build_dir.join("cache-key"))
 }
 
 /// Copy kernel image to the output directory
-fn copy_kernel_image(config_name: &str, arch: &str, kernel_target: &str, build_dir: &Path) {
+fn copy_kernel_image(config_name: &str, arch: &Arch, build_dir: &Path) -> Result<(), BuildError> {
     println!("Copying kernel image for configuration: {}", config_name);
 
     let output_dir = PathBuf::from("build").join(config_name);
-    fs::create_dir_all(&output_dir).expect("Failed to create output directory");
-
-    // Determine source kernel image path based on architecture and target
-    let source_kernel_path = match arch {
-        "arm64" => {
-            // For arm64, the Image is in arch/arm64/boot/Image
-            build_dir
-                .join("arch")
-                .join("arm64")
-                .join("boot")
-                .join("Image")
-        }
-        "x86" => {
-            // For x86, the bzImage is in arch/x86/boot/bzImage
-            build_dir
-                .join("arch")
-                .join("x86")
-                .join("boot")
-                .join("bzImage")
-        }
-        _ => {
-            eprintln!("Unsupported architecture for kernel copy: {}", arch);
-            return;
-        }
-    };
+    fs::create_dir_all(&output_dir).map_err(BuildError::io("failed to create output directory"))?;
+
+    // e.g. arch/arm64/boot/Image
+    let source_kernel_path = arch.built_kernel_image(build_dir);
 
     if !source_kernel_path.exists() {
-        eprintln!(
-            "Kernel image not found at: {}",
+        return Err(BuildError::Step(format!(
+            "kernel image not found at: {}",
             source_kernel_path.display()
-        );
-        return;
+        )));
     }
 
     // Copy kernel image to build/config_name directory
-    let dest_kernel_path = output_dir.join(kernel_target);
-
-    match fs::copy(&source_kernel_path, &dest_kernel_path) {
-        Ok(_) => {
-            println!(
-                "Kernel image copied successfully: {} -> {}",
-                source_kernel_path.display(),
-                dest_kernel_path.display()
-            );
-        }
-        Err(e) => {
-            eprintln!("Failed to copy kernel image: {}", e);
-        }
-    }
+    let dest_kernel_path = output_dir.join(arch.image_name());
+
+    fs::copy(&source_kernel_path, &dest_kernel_path).map_err(BuildError::io("failed to copy kernel image"))?;
+    println!(
+        "Kernel image copied successfully: {} -> {}",
+        source_kernel_path.display(),
+        dest_kernel_path.display()
+    );
+    Ok(())
 }
@@ -0,0 +1,141 @@
+//! Build Linux guests (kernel, BusyBox rootfs, DTBs and boot artifacts) for hypervisor
+//! testing, and boot and test them under QEMU
+//!
+//! [`Builder`] builds one configuration and returns its [`Artifacts`];
+//! [`list_configs`] enumerates the configurations under `config/`. The `guest-test-linux`
+//! binary is a command-line front-end to the same code; the modules it reaches into are
+//! hidden from the documentation and are not part of the API.
+
+#[doc(hidden)]
+pub mod analyze;
+#[doc(hidden)]
+pub mod arch;
+#[doc(hidden)]
+pub mod attest;
+mod audit;
+#[doc(hidden)]
+pub mod backend;
+mod bare;
+#[doc(hidden)]
+pub mod board;
+mod budget;
+#[doc(hidden)]
+pub mod build_all;
+mod builder;
+mod busybox;
+#[doc(hidden)]
+pub mod cache;
+#[doc(hidden)]
+pub mod clean;
+#[doc(hidden)]
+pub mod cli_env;
+mod clock;
+#[doc(hidden)]
+pub mod cmdline;
+#[doc(hidden)]
+pub mod compat;
+#[doc(hidden)]
+pub mod config;
+mod coredump;
+mod cpu_hotplug;
+#[doc(hidden)]
+pub mod crash;
+mod disks;
+mod display;
+mod dma;
+#[doc(hidden)]
+pub mod doctor;
+#[doc(hidden)]
+pub mod download;
+mod dtb;
+mod error;
+mod etc;
+#[doc(hidden)]
+pub mod expect;
+mod failure;
+#[doc(hidden)]
+pub mod firmware;
+#[doc(hidden)]
+pub mod fs_robustness;
+#[doc(hidden)]
+pub mod graph;
+mod guest_env;
+mod harness;
+mod httpd;
+mod ima;
+mod input;
+#[doc(hidden)]
+pub mod intervm;
+#[doc(hidden)]
+pub mod ivshmem;
+#[doc(hidden)]
+pub mod junit;
+mod kconfig;
+#[doc(hidden)]
+pub mod kernel;
+mod licenses;
+#[doc(hidden)]
+pub mod lint;
+mod luks;
+#[doc(hidden)]
+pub mod manifest;
+#[doc(hidden)]
+pub mod matrix;
+mod memory;
+mod minimize;
+mod modsign;
+mod modules;
+mod nfs;
+mod oom;
+#[doc(hidden)]
+pub mod output;
+mod payload;
+#[doc(hidden)]
+pub mod pool;
+#[doc(hidden)]
+pub mod profile;
+mod pstore;
+#[doc(hidden)]
+pub mod pxe;
+#[doc(hidden)]
+pub mod qemu;
+mod qmp;
+#[doc(hidden)]
+pub mod repro;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod results;
+mod rootfs;
+#[doc(hidden)]
+pub mod self_update;
+mod sizes;
+#[doc(hidden)]
+pub mod smoke;
+mod sound;
+#[doc(hidden)]
+pub mod sources_lock;
+mod ssh;
+#[doc(hidden)]
+pub mod stages;
+mod swap;
+mod symbols;
+#[doc(hidden)]
+pub mod sysrq;
+#[doc(hidden)]
+pub mod system;
+#[doc(hidden)]
+pub mod testing;
+mod tpm;
+mod verity;
+mod virtio_mem;
+mod vmconfig;
+mod vmm;
+#[doc(hidden)]
+pub mod wizard;
+
+pub use builder::{Artifacts, Builder};
+pub use config::{is_valid_config, list_configs};
+pub use error::BuildError;
+pub use manifest::BuildManifest;
+pub use stages::Stage;
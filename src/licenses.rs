@@ -0,0 +1,226 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigManifest;
+use crate::profile::enabled_profiles;
+
+/// SPDX expression of a component that declares no license
+const NOASSERTION: &str = "NOASSERTION";
+
+/// License of a third-party or test component shipped in a guest image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentLicense {
+    /// e.g. "linux", "busybox", "payload ima"
+    pub component: String,
+    pub version: Option<String>,
+    /// SPDX license expression, "NOASSERTION" when the component declares none
+    pub spdx: String,
+    /// License texts relative to `build/<config>/`
+    pub files: Vec<String>,
+}
+
+/// A component before its license texts are collected
+struct Component {
+    name: String,
+    /// Directory under `licenses/` its texts go to
+    dir: String,
+    version: Option<String>,
+    spdx: String,
+    texts: Vec<PathBuf>,
+}
+
+/// Directory the license texts of a build are collected in
+fn licenses_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("licenses")
+}
+
+/// License texts at the top of a source tree: LICENSE, COPYING and their variants
+fn license_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path.file_name().is_some_and(|name| {
+                    let name = name.to_string_lossy().to_uppercase();
+                    name.starts_with("LICENSE")
+                        || name.starts_with("LICENCE")
+                        || name.starts_with("COPYING")
+                })
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// SPDX identifiers the sources of a directory declare, joined with AND
+fn spdx_of_sources(dir: &Path) -> Option<String> {
+    let mut ids: Vec<String> = fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+        .filter_map(|content| {
+            content.lines().take(5).find_map(|line| {
+                line.split_once("SPDX-License-Identifier:")
+                    .map(|(_, id)| id.trim().trim_end_matches("*/").trim().to_string())
+            })
+        })
+        .collect();
+    ids.sort();
+    ids.dedup();
+    (!ids.is_empty()).then(|| ids.join(" AND "))
+}
+
+/// `license` of a Cargo workspace, from its package or `[workspace.package]`
+fn cargo_license(workspace: &Path) -> Option<String> {
+    let content = fs::read_to_string(workspace.join("Cargo.toml")).ok()?;
+    let table = content.parse::<toml::Table>().ok()?;
+    [
+        table.get("package"),
+        table.get("workspace").and_then(|w| w.get("package")),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|package| package.get("license")?.as_str().map(str::to_string))
+}
+
+/// Copy license texts into `licenses/<name>/`, returning them relative to `build/<config>/`
+fn copy_files(config_name: &str, name: &str, files: &[PathBuf]) -> Result<Vec<String>, String> {
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+    let dest_dir = licenses_dir(config_name).join(name);
+    fs::create_dir_all(&dest_dir).map_err(|e| format!("{}: {}", dest_dir.display(), e))?;
+    let mut copied = Vec::new();
+    for file in files {
+        let Some(file_name) = file.file_name() else {
+            continue;
+        };
+        let dest = dest_dir.join(file_name);
+        fs::copy(file, &dest).map_err(|e| format!("{}: {}", file.display(), e))?;
+        copied.push(format!("licenses/{}/{}", name, file_name.to_string_lossy()));
+    }
+    Ok(copied)
+}
+
+/// Collect the licenses of everything a configuration's guest image ships into
+/// `build/<config>/licenses/` and summarize them in the build manifest
+///
+/// Components without a declared license are recorded as NOASSERTION with a warning,
+/// so they can be cleared before an image is handed on.
+pub fn collect(config_name: &str, manifest: &ConfigManifest) -> Result<(), String> {
+    let dir = licenses_dir(config_name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    let build = crate::manifest::read_manifest(config_name);
+
+    let mut components = Vec::new();
+    let kernel_source = crate::kernel::source_dir(config_name);
+    let kernel_texts = vec![
+        kernel_source.join("COPYING"),
+        kernel_source.join("LICENSES/preferred/GPL-2.0"),
+        kernel_source.join("LICENSES/exceptions/Linux-syscall-note"),
+    ];
+    components.push(Component {
+        name: "linux".to_string(),
+        dir: "linux".to_string(),
+        version: build.as_ref().and_then(|b| b.kernel_version.clone()),
+        spdx: "GPL-2.0-only WITH Linux-syscall-note".to_string(),
+        texts: kernel_texts.clone(),
+    });
+    if !manifest.bare {
+        components.push(Component {
+            name: "busybox".to_string(),
+            dir: "busybox".to_string(),
+            version: build.as_ref().and_then(|b| b.busybox_version.clone()),
+            spdx: "GPL-2.0-only".to_string(),
+            texts: license_files(Path::new("busybox")),
+        });
+    }
+    if manifest.ssh {
+        components.push(Component {
+            name: "dropbear".to_string(),
+            dir: "dropbear".to_string(),
+            version: None,
+            spdx: "MIT".to_string(),
+            texts: license_files(Path::new(crate::ssh::DROPBEAR_SOURCE)),
+        });
+    }
+
+    let mut modules: Vec<&str> = enabled_profiles(manifest)
+        .iter()
+        .flat_map(|p| p.modules.iter().copied())
+        .collect();
+    modules.sort();
+    modules.dedup();
+    let sources = crate::payload::payload_names(manifest)
+        .into_iter()
+        .map(|name| ("payload", name, Path::new("payloads").join(name)))
+        .chain(
+            modules
+                .into_iter()
+                .map(|name| ("module", name, Path::new("modules").join(name))),
+        );
+    for (kind, name, source) in sources {
+        let mut texts = license_files(&source);
+        // Modules are part of the kernel and fall under its license texts
+        if texts.is_empty() && kind == "module" {
+            texts = kernel_texts.clone();
+        }
+        components.push(Component {
+            name: format!("{} {}", kind, name),
+            dir: format!("{}-{}", kind, name),
+            version: None,
+            spdx: spdx_of_sources(&source).unwrap_or_else(|| NOASSERTION.to_string()),
+            texts,
+        });
+    }
+    if let Some(workspace) = &manifest.rust_workspace {
+        let workspace = Path::new(workspace);
+        components.push(Component {
+            name: format!("rust workspace {}", workspace.display()),
+            dir: "rust-workspace".to_string(),
+            version: None,
+            spdx: cargo_license(workspace).unwrap_or_else(|| NOASSERTION.to_string()),
+            texts: license_files(workspace),
+        });
+    }
+
+    let mut licenses = Vec::new();
+    for component in components {
+        let texts: Vec<PathBuf> = component
+            .texts
+            .into_iter()
+            .filter(|file| file.is_file())
+            .collect();
+        if component.spdx == NOASSERTION || texts.is_empty() {
+            eprintln!(
+                "Warning: {} has no {}, clear it before redistributing the image",
+                component.name,
+                if component.spdx == NOASSERTION {
+                    "declared license"
+                } else {
+                    "license text"
+                }
+            );
+        }
+        licenses.push(ComponentLicense {
+            files: copy_files(config_name, &component.dir, &texts)?,
+            component: component.name,
+            version: component.version,
+            spdx: component.spdx,
+        });
+    }
+
+    for license in &licenses {
+        println!("  {:<24} {}", license.component, license.spdx);
+    }
+    crate::manifest::update_manifest(config_name, |build| build.licenses = licenses);
+    Ok(())
+}
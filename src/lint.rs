@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directories whose scripts are baked into the rootfs alongside the init template
+const SCRIPT_DIRS: [&str; 2] = ["init/hooks", "init/tests.d"];
+
+/// Collect every script that ends up in the rootfs
+pub fn collect_scripts() -> Vec<PathBuf> {
+    let mut scripts = vec![PathBuf::from("init").join("init")];
+
+    for dir in SCRIPT_DIRS {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut dir_scripts: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        dir_scripts.sort();
+        scripts.extend(dir_scripts);
+    }
+
+    // Payload test scripts are installed into tests.d as well
+    if let Ok(entries) = fs::read_dir("payloads") {
+        let mut payload_scripts: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path().join("test"))
+            .filter(|path| path.is_file())
+            .collect();
+        payload_scripts.sort();
+        scripts.extend(payload_scripts);
+    }
+
+    scripts
+}
+
+/// Check if shellcheck is available on the host
+fn has_shellcheck() -> bool {
+    Command::new("which")
+        .arg("shellcheck")
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+/// Built-in lint pass: shebang, line endings and `sh -n` syntax check
+fn builtin_lint(script: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let content = match fs::read_to_string(script) {
+        Ok(content) => content,
+        Err(e) => {
+            problems.push(format!("cannot read script: {}", e));
+            return problems;
+        }
+    };
+
+    // The kernel refuses to exec a script without an interpreter line
+    if !content.starts_with("#!") {
+        problems.push("missing shebang line (e.g. #!/bin/sh)".to_string());
+    }
+
+    // CRLF endings turn the interpreter into "/bin/sh\r", which does not exist
+    if content.contains("\r\n") {
+        problems.push("script has CRLF line endings".to_string());
+    }
+
+    let output = Command::new("sh")
+        .args(["-n", &script.to_string_lossy()])
+        .output();
+    match output {
+        Ok(output) if !output.status.success() => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            problems.push(format!("syntax error: {}", stderr.trim()));
+        }
+        Ok(_) => {}
+        Err(e) => problems.push(format!("failed to run sh -n: {}", e)),
+    }
+
+    problems
+}
+
+/// Run shellcheck on a script, reporting only error-level findings
+fn shellcheck_lint(script: &Path) -> Vec<String> {
+    let output = Command::new("shellcheck")
+        .args(["-s", "sh", "-S", "error", &script.to_string_lossy()])
+        .output();
+
+    match output {
+        Ok(output) if !output.status.success() => {
+            vec![format!(
+                "shellcheck:\n{}",
+                String::from_utf8_lossy(&output.stdout).trim_end()
+            )]
+        }
+        Ok(_) => Vec::new(),
+        Err(e) => vec![format!("failed to run shellcheck: {}", e)],
+    }
+}
+
+/// Lint all scripts baked into the rootfs, returning true if all of them pass
+pub fn lint_scripts() -> bool {
+    let use_shellcheck = has_shellcheck();
+    if use_shellcheck {
+        println!("Linting init scripts with shellcheck...");
+    } else {
+        println!("shellcheck not found, using built-in lint pass for init scripts...");
+    }
+
+    let mut ok = true;
+    for script in collect_scripts() {
+        if !script.exists() {
+            eprintln!("Script not found: {}", script.display());
+            ok = false;
+            continue;
+        }
+
+        let mut problems = builtin_lint(&script);
+        if use_shellcheck {
+            problems.extend(shellcheck_lint(&script));
+        }
+
+        if problems.is_empty() {
+            println!("  ok    {}", script.display());
+        } else {
+            ok = false;
+            eprintln!("  FAIL  {}", script.display());
+            for problem in problems {
+                eprintln!("        {}", problem);
+            }
+        }
+    }
+
+    ok && lint_console_scripts()
+}
+
+/// Parse every console script so syntax errors and bad labels show up before a boot
+fn lint_console_scripts() -> bool {
+    let mut scripts = Vec::new();
+    for arch_dir in read_dirs(Path::new(crate::expect::CONSOLE_SCRIPT_DIR)) {
+        for config_dir in read_dirs(&arch_dir) {
+            let Ok(entries) = fs::read_dir(&config_dir) else {
+                continue;
+            };
+            scripts.extend(
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "expect")),
+            );
+        }
+    }
+    scripts.sort();
+
+    let mut ok = true;
+    for script in scripts {
+        let parsed = fs::read_to_string(&script)
+            .map_err(|e| e.to_string())
+            .and_then(|source| crate::expect::parse(&source).map(|_| ()));
+        match parsed {
+            Ok(()) => println!("  ok    {}", script.display()),
+            Err(e) => {
+                ok = false;
+                eprintln!("  FAIL  {}", script.display());
+                eprintln!("        {}", e);
+            }
+        }
+    }
+    ok
+}
+
+/// Subdirectories of a directory, sorted
+fn read_dirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::qemu::rootfs_image_path;
+
+/// Start of the encrypted data in 512-byte sectors (LUKS2's default 16M header area)
+const DATA_OFFSET_SECTORS: u64 = 32768;
+
+/// Cipher of the rootfs; the 512-bit key splits into two AES-256 keys for XTS
+const CIPHER: &str = "aes-xts-plain64";
+const KEY_BITS: usize = 512;
+
+/// Passphrase of the key slot, used when a config does not set `luks_passphrase`
+pub const DEFAULT_PASSPHRASE: &str = "guest-test";
+
+/// Kernel options for a dm-crypt root set up from the command line
+pub const LUKS_KERNEL_OPTIONS: &[&str] = &[
+    "CONFIG_MD",
+    "CONFIG_BLK_DEV_DM",
+    "CONFIG_DM_INIT",
+    "CONFIG_DM_CRYPT",
+    "CONFIG_CRYPTO_AES",
+    "CONFIG_CRYPTO_XTS",
+];
+
+/// Volume key the image was formatted with, raw bytes
+fn volume_key_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("rootfs.volume-key")
+}
+
+/// Run a command, reporting failures
+fn run(cmd: &mut Command, what: &str) -> bool {
+    match cmd.status() {
+        Ok(status) if status.success() => true,
+        Ok(_) => {
+            eprintln!("{} failed", what);
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run {}: {}", what, e);
+            false
+        }
+    }
+}
+
+/// Replace the rootfs image with a LUKS2 container holding it
+///
+/// The volume key is generated here and kept next to the image, so the guest
+/// can map the root with dm-crypt from its command line without an initramfs;
+/// the passphrase slot lets the image be opened with stock cryptsetup too.
+pub fn encrypt_rootfs(config_name: &str, passphrase: &str) -> bool {
+    let image = rootfs_image_path(config_name);
+    let size = match fs::metadata(&image) {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            eprintln!("Cannot encrypt {}: {}", image.display(), e);
+            return false;
+        }
+    };
+    let dir = PathBuf::from("build").join(config_name);
+    let encrypted = dir.join("rootfs.luks.img");
+    let key_file = dir.join("rootfs.luks-passphrase");
+    let volume_key = volume_key_path(config_name);
+
+    println!("Encrypting {} with LUKS2...", image.display());
+    let mut key = vec![0u8; KEY_BITS / 8];
+    if let Err(e) = fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut key))
+        .and_then(|_| fs::write(&volume_key, &key))
+        .and_then(|_| fs::write(&key_file, passphrase))
+        .and_then(|_| fs::File::create(&encrypted)?.set_len(size + DATA_OFFSET_SECTORS * 512))
+    {
+        eprintln!("Failed to prepare the LUKS image: {}", e);
+        return false;
+    }
+
+    let mapping = format!("guest-test-{}", config_name.replace('@', "-"));
+    let mapped = format!("/dev/mapper/{}", mapping);
+    let formatted = run(
+        Command::new("cryptsetup")
+            .args(["luksFormat", "--batch-mode", "--type", "luks2"])
+            .args(["--cipher", CIPHER, "--key-size", &KEY_BITS.to_string()])
+            .args(["--sector-size", "512"])
+            .args(["--offset", &DATA_OFFSET_SECTORS.to_string()])
+            .arg("--volume-key-file")
+            .arg(&volume_key)
+            .arg("--key-file")
+            .arg(&key_file)
+            .arg(&encrypted),
+        "cryptsetup luksFormat",
+    );
+    // Writing through dm-crypt needs the same privileges as mounting the image
+    let copied = formatted
+        && run(
+            Command::new("sudo")
+                .args(["cryptsetup", "open", "--type", "luks2", "--key-file"])
+                .arg(&key_file)
+                .arg(&encrypted)
+                .arg(&mapping),
+            "cryptsetup open",
+        )
+        && {
+            let copied = run(
+                Command::new("sudo").args([
+                    "dd",
+                    &format!("if={}", image.display()),
+                    &format!("of={}", mapped),
+                    "bs=1M",
+                    "conv=fsync",
+                    "status=none",
+                ]),
+                "copying the rootfs into the LUKS container",
+            );
+            run(
+                Command::new("sudo").args(["cryptsetup", "close", &mapping]),
+                "cryptsetup close",
+            ) && copied
+        };
+    if !copied {
+        let _ = fs::remove_file(&encrypted);
+        return false;
+    }
+
+    if let Err(e) = fs::rename(&encrypted, &image) {
+        eprintln!("Failed to replace {}: {}", image.display(), e);
+        return false;
+    }
+    println!("LUKS2 rootfs written; passphrase in {}", key_file.display());
+    true
+}
+
+/// Kernel parameters mapping `device` through dm-crypt and booting from the result
+///
+/// The volume key ends up on the command line, which is fine for test images
+/// and keeps the boot free of an initramfs.
+pub fn cmdline_params(config_name: &str, device: &str) -> Vec<String> {
+    let image = rootfs_image_path(config_name);
+    let (Ok(key), Ok(metadata)) = (fs::read(volume_key_path(config_name)), fs::metadata(&image))
+    else {
+        eprintln!(
+            "No LUKS volume key for {}, build the config first",
+            config_name
+        );
+        return vec![format!("root={}", device)];
+    };
+    let key: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+    let sectors = (metadata.len() / 512).saturating_sub(DATA_OFFSET_SECTORS);
+    let table = format!(
+        "0 {} crypt {} {} 0 {} {}",
+        sectors, CIPHER, key, device, DATA_OFFSET_SECTORS
+    );
+    vec![
+        format!("dm-mod.create=\"croot,,,rw,{}\"", table),
+        format!("dm-mod.waitfor={}", device),
+        "root=/dev/dm-0".to_string(),
+    ]
+}
@@ -1,22 +1,39 @@
-use clap::{Parser, Subcommand};
-use std::fs;
-use std::path::Path;
-use std::process::Command;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
 
-mod busybox;
-mod config;
-mod kernel;
-mod rootfs;
-mod system;
+use guest_test_linux::{
+    analyze, arch, attest, backend, board, build_all, cache, clean, cli_env, cmdline,
+    compat, config, crash, doctor, download, expect, firmware, fs_robustness, graph, intervm,
+    ivshmem, junit, kernel, lint, manifest, matrix, output, pool, profile, pxe, qemu,
+    repro, report, results, self_update, smoke, sources_lock, stages, sysrq, system, testing,
+    wizard,
+};
 
 use config::{is_valid_config, list_configs};
 use kernel::build_linux_for_config;
 
 #[derive(Parser)]
-#[command(author, version, about = "Manage Linux 6.12 source code and builds")]
+#[command(author, version, about = "Manage Linux kernel source code and builds")]
+#[command(arg_required_else_help = true)]
 struct Args {
+    /// List the supported architectures and their capabilities
+    #[arg(long)]
+    list_arches: bool,
+    /// Plain output for CI logs: no colors (also with NO_COLOR set or when not a terminal)
+    #[arg(long, global = true, alias = "no-color")]
+    plain: bool,
+    /// Show the output of build stages as well as writing it to build/<config>/logs/
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+    /// Hide stage headers and results; failures are still shown
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Never touch the network; sources, firmware and caches must be available locally
+    #[arg(long, global = true, env = "GUEST_TEST_OFFLINE")]
+    offline: bool,
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
@@ -25,65 +42,932 @@ enum Commands {
     Build {
         /// Configuration name in format arch-name (e.g., arm64-qemu, x86-qemu)
         config: String,
+        /// Artifact cache directory or http(s) URL
+        #[arg(long, env = cache::CACHE_ENV)]
+        cache: Option<String>,
+        #[command(flatten)]
+        sources: download::SourceOptions,
+        #[command(flatten)]
+        kernel: download::KernelSelection,
+        /// Only run these stages (kernel, busybox, rootfs), reusing the others' earlier outputs
+        #[arg(long, value_enum, value_delimiter = ',')]
+        only: Vec<stages::Stage>,
+        /// Fail when the rootfs audit finds setuid, world-writable or leaked host files
+        #[arg(long)]
+        strict: bool,
+        /// Parallel make jobs of the build (default: all CPUs)
+        #[arg(long, env = system::JOBS_ENV)]
+        jobs_per_config: Option<usize>,
+    },
+    /// Rebuild only the rootfs of a built configuration, e.g. after editing the init script
+    RebuildRootfs {
+        /// Configuration name in format arch-name (e.g., arm64-qemu, x86-qemu)
+        config: String,
+        /// Fail when the rootfs audit finds setuid, world-writable or leaked host files
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Build every configuration listed by `list`, continuing past failures
+    BuildAll {
+        /// Artifact cache directory or http(s) URL
+        #[arg(long, env = cache::CACHE_ENV)]
+        cache: Option<String>,
+        #[command(flatten)]
+        sources: download::SourceOptions,
+        #[command(flatten)]
+        kernel: download::KernelSelection,
+        /// Fail when the rootfs audit finds setuid, world-writable or leaked host files
+        #[arg(long)]
+        strict: bool,
+        /// Parallel make jobs of each configuration; as many configurations as the CPUs
+        /// fit at that many jobs build at once (default: all CPUs, one at a time)
+        #[arg(long, env = system::JOBS_ENV)]
+        jobs_per_config: Option<usize>,
+    },
+    /// Clean the build directory, or parts of it
+    Clean {
+        /// Only this configuration (e.g., arm64-qemu); with --stage or --prune, every built one
+        /// when omitted
+        config: Option<String>,
+        /// Only the build trees and outputs of these stages (kernel, busybox, rootfs)
+        #[arg(long, value_enum, value_delimiter = ',')]
+        stage: Vec<stages::Stage>,
+        /// Remove the intermediate build trees but keep the final images, manifest and vmlinux
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Resolve the Linux and BusyBox refs and tarball checksums anew and write sources.lock
+    UpdateSources,
+    /// Download the firmware images configs can boot through and check their pinned checksums
+    Firmware {
+        /// Only these architectures (e.g. arm64), all when omitted
+        #[arg(long)]
+        arch: Vec<String>,
+        /// Record the checksums of images not pinned in firmware/SHA256SUMS yet
+        #[arg(long)]
+        pin: bool,
     },
-    /// Clean the build directory
-    Clean,
     /// List all available configurations
     List,
+    /// Create a new configuration by answering questions instead of writing kconfig and TOML
+    Wizard,
+    /// List the opt-in test profiles configs can enable
+    Profiles,
+    /// Lint the init template, hook scripts and tests.d scripts
+    Lint,
+    /// Check the host for the tools builds and guests need, with install hints
+    Doctor {
+        /// Only check what this configuration needs (e.g., arm64-qemu), all arches when omitted
+        config: Option<String>,
+    },
+    /// Boot built images under QEMU and check that init is reached
+    Smoke {
+        /// Configuration to boot (e.g., arm64-qemu)
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        config: Option<String>,
+        /// Boot every configuration that has been built
+        #[arg(long)]
+        all: bool,
+        /// Seconds to wait for the init marker before failing
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// Boot a built configuration and check the results of the guest tests
+    Test {
+        /// Configuration name (e.g., arm64-qemu)
+        config: String,
+        /// Make the guest panic and verify the panic policy instead
+        #[arg(long)]
+        crash_recovery: bool,
+        /// Panic policy to verify with --crash-recovery
+        #[arg(long, value_enum, default_value = "restart", requires = "crash_recovery")]
+        policy: crash::PanicPolicy,
+        /// Kill the guest or yank its scratch disk mid-write and verify journal recovery instead
+        #[arg(long, value_enum, conflicts_with = "crash_recovery")]
+        fs_robustness: Option<fs_robustness::FsFault>,
+        /// Store console script screenshots as the new golden images instead of comparing
+        #[arg(long)]
+        update_golden: bool,
+        /// Build and test these kernel versions (e.g. v6.6,v6.12,v6.13-rc) with the config's rootfs
+        #[arg(
+            long,
+            value_delimiter = ',',
+            conflicts_with_all = ["crash_recovery", "fs_robustness"]
+        )]
+        kernel_versions: Vec<String>,
+        /// Only run guest tests whose name matches this extended regex (e.g. 'net.*')
+        #[arg(long, conflicts_with_all = ["crash_recovery", "fs_robustness"])]
+        filter: Vec<String>,
+        /// Skip guest tests whose name matches this extended regex
+        #[arg(long, conflicts_with_all = ["crash_recovery", "fs_robustness"])]
+        exclude: Vec<String>,
+        /// Kernel parameters to append for this run; {config}, {tag} and {seed} are substituted
+        #[arg(long, conflicts_with_all = ["crash_recovery", "fs_robustness"])]
+        append: Vec<String>,
+        /// Label of this run, substituted for {tag}; defaults to the start time
+        #[arg(long)]
+        tag: Option<String>,
+        /// Seed substituted for {seed}; defaults to one derived from the start time
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Hypervisor version recorded with the results, defaults to the backend's version
+        #[arg(long, env = "GUEST_TEST_HYPERVISOR")]
+        hypervisor: Option<String>,
+        /// Hypervisor frontend to boot the guest with
+        #[arg(
+            long,
+            value_enum,
+            default_value = "qemu",
+            env = "GUEST_TEST_BACKEND",
+            conflicts_with_all = ["crash_recovery", "fs_robustness"]
+        )]
+        backend: backend::BackendKind,
+        /// Binary of the backend instead of its default, e.g. a QEMU wrapper, or the script to run
+        #[arg(
+            long,
+            value_name = "PATH",
+            env = "GUEST_TEST_HYPERVISOR_BIN",
+            conflicts_with_all = ["crash_recovery", "fs_robustness"]
+        )]
+        hypervisor_bin: Option<PathBuf>,
+        /// Do not add the run to the results archive
+        #[arg(long)]
+        no_record: bool,
+        /// On failure, timeout or kernel panic, dump the guest memory and vCPU registers next to
+        /// vmlinux (`build/<config>/linux/`) and into the failure bundle
+        #[arg(
+            long,
+            conflicts_with_all = ["crash_recovery", "fs_robustness", "kernel_versions", "no_record"]
+        )]
+        memory_dump: bool,
+        /// Write the guest test results as JUnit XML to this file
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["crash_recovery", "fs_robustness", "kernel_versions"]
+        )]
+        junit: Option<PathBuf>,
+        /// Seconds to wait for each step
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
+    },
+    /// Boot a built configuration under QEMU with its console on this terminal
+    Run {
+        /// Configuration name (e.g., arm64-qemu)
+        config: String,
+        /// Kernel parameters to append for this run; {config}, {tag} and {seed} are substituted
+        #[arg(long)]
+        append: Vec<String>,
+        /// Label of this run, substituted for {tag}; defaults to the start time
+        #[arg(long)]
+        tag: Option<String>,
+        /// Seed substituted for {seed}; defaults to one derived from the start time
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Power off after the guest tests instead of starting a shell
+        #[arg(long)]
+        no_shell: bool,
+        /// Hypervisor frontend to boot the guest with
+        #[arg(long, value_enum, default_value = "qemu", env = "GUEST_TEST_BACKEND")]
+        backend: backend::BackendKind,
+        /// Binary of the backend instead of its default, e.g. a QEMU wrapper, or the script to run
+        #[arg(long, value_name = "PATH", env = "GUEST_TEST_HYPERVISOR_BIN")]
+        hypervisor_bin: Option<PathBuf>,
+        /// Extra hypervisor arguments, after `--`
+        #[arg(last = true)]
+        qemu_args: Vec<String>,
+    },
+    /// Boot a configuration and send magic sysrq commands over its console
+    Sysrq {
+        /// Configuration name (e.g., arm64-qemu@debug)
+        config: String,
+        /// Sysrq keys to send, e.g. t (tasks), m (memory), w (blocked), c (crash)
+        #[arg(long, value_delimiter = ',', default_value = "t,m")]
+        keys: Vec<char>,
+        /// Console output to wait for before sending the commands
+        #[arg(long, default_value = smoke::INIT_MARKER)]
+        wait_for: String,
+        /// Seconds to wait for the console output
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// Open a guest memory dump in crash or drgn with the configuration's vmlinux
+    Analyze {
+        /// Memory dump, e.g. build/<config>/linux/vmcore from `test --memory-dump`
+        dump: PathBuf,
+        /// Configuration the dumped guest was built from (e.g., arm64-qemu)
+        #[arg(long)]
+        config: String,
+        /// Debugger to open the dump with
+        #[arg(long, value_enum, default_value = "crash")]
+        tool: analyze::AnalyzeTool,
+        /// Print the panic message and the backtraces of all CPUs instead of a prompt
+        #[arg(long)]
+        summary: bool,
+    },
+    /// Check a built configuration against a hypervisor capability description
+    CheckCompat {
+        /// Configuration name (e.g., arm64-qemu)
+        config: String,
+        /// Hypervisor version (looked up in hypervisor/<version>.json) or path to a JSON file
+        #[arg(long)]
+        hypervisor: String,
+    },
+    /// Boot a configuration with the ivshmem profile and test the shared-memory device
+    Ivshmem {
+        /// Configuration name (e.g., arm64-qemu@ivshmem)
+        config: String,
+        /// Use ivshmem-doorbell with an ivshmem-server and test interrupts too
+        #[arg(long)]
+        doorbell: bool,
+        /// Seconds to wait for each check
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// Boot two guests on a shared virtual network and test communication between them
+    Intervm {
+        /// Configuration of the server guest (e.g., arm64-qemu@intervm)
+        config: String,
+        /// Configuration of the client guest, defaults to the server's
+        #[arg(long)]
+        peer: Option<String>,
+        /// Seconds to wait for each step
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
+    },
+    /// Update the tool's checkout to the latest upstream version
+    SelfUpdate {
+        /// Only report whether an update is available
+        #[arg(long)]
+        check: bool,
+    },
+    /// Export the build stages of a configuration as a Makefile or ninja file
+    ExportBuildGraph {
+        /// Configuration name (e.g., arm64-qemu)
+        config: String,
+        /// Output format
+        #[arg(long, value_enum, default_value = "make")]
+        format: graph::GraphFormat,
+        /// Output file, defaults to build/<config>/Makefile or build/<config>/build.ninja
+        #[arg(long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        kernel: download::KernelSelection,
+    },
+    /// Lay out a built configuration for network boot (TFTP root, iPXE script, dnsmasq snippet)
+    ExportPxe {
+        /// Configuration name (e.g., x86-qemu)
+        config: String,
+        /// TFTP root to write to, defaults to build/<config>/tftp
+        #[arg(long)]
+        tftp_root: Option<PathBuf>,
+    },
+    /// Write the SD card image of a board configuration to a block device
+    Flash {
+        /// Board configuration name (e.g., arm64-rk3588)
+        config: String,
+        /// Block device of the card, e.g. /dev/sdX
+        device: PathBuf,
+        /// Do not ask to confirm by typing the device name
+        #[arg(long)]
+        yes: bool,
+        /// Also write to a disk that is not removable, e.g. a loop device or an SSD
+        #[arg(long)]
+        allow_fixed: bool,
+        /// Grow the root partition and its filesystem to fill the card
+        #[arg(long)]
+        expand: bool,
+    },
+    /// Check the artifacts of a build against the checksums in its manifest
+    Verify {
+        /// Configuration name (e.g., arm64-qemu)
+        config: String,
+    },
+    /// Build a configuration twice from scratch and report every artifact that differs
+    CheckRepro {
+        /// Configuration name (e.g., arm64-qemu)
+        config: String,
+        #[command(flatten)]
+        sources: download::SourceOptions,
+        #[command(flatten)]
+        kernel: download::KernelSelection,
+        /// Keep the first build in build/.repro/<config>/ to compare by hand
+        #[arg(long)]
+        keep: bool,
+    },
+    /// Sign or verify the build provenance of a configuration
+    Attest {
+        #[command(subcommand)]
+        action: AttestAction,
+    },
+    /// List or compare the test runs recorded in results/history.jsonl
+    Results {
+        #[command(subcommand)]
+        action: ResultsAction,
+    },
+    /// Keep booted guests warm and run commands on them
+    Pool {
+        #[command(subcommand)]
+        action: PoolAction,
+    },
+    /// Render recorded test runs into a shareable report
+    Report {
+        #[command(subcommand)]
+        format: ReportFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum ReportFormat {
+    /// Standalone HTML page with tests, benchmarks, artifacts and console excerpts
+    Html {
+        /// Run tags to include, defaults to the latest run of each configuration
+        tags: Vec<String>,
+        /// Only include runs of this configuration
+        #[arg(long)]
+        config: Option<String>,
+        /// Output file
+        #[arg(long, default_value_os_t = report::default_html_path())]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ResultsAction {
+    /// List recorded runs
+    List {
+        /// Only list runs of this configuration
+        #[arg(long)]
+        config: Option<String>,
+    },
+    /// Diff two runs and fail on test or benchmark regressions
+    Compare {
+        /// Tag of the baseline run
+        base: String,
+        /// Tag of the run to check
+        head: String,
+        /// Configuration, when the tags were recorded for several
+        #[arg(long)]
+        config: Option<String>,
+        /// Percentage a metric may get worse before it counts as a regression
+        #[arg(long, default_value_t = 5.0)]
+        threshold: f64,
+    },
+}
+
+#[derive(Subcommand)]
+enum PoolAction {
+    /// Boot guests of a built configuration to a shell and pause them until needed
+    Start {
+        /// Configuration name in format arch-name (e.g., arm64-qemu)
+        config: String,
+        /// Number of guests
+        #[arg(short = 'n', long, default_value_t = 3)]
+        count: usize,
+        /// Seconds each guest gets to reach its shell
+        #[arg(long, default_value_t = 300)]
+        timeout: u64,
+    },
+    /// Run a shell command on an idle guest and print its output; exits with its status
+    Exec {
+        /// Seconds the command may run
+        #[arg(long, default_value_t = 600)]
+        timeout: u64,
+        /// Command line, run with `sh -c` in the guest
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Copy a file out of an idle guest (over SSH when the config runs dropbear)
+    Fetch {
+        /// Seconds the copy may take
+        #[arg(long, default_value_t = 600)]
+        timeout: u64,
+        /// Path of the file in the guest
+        remote: String,
+        /// Where to write it on the host
+        local: PathBuf,
+    },
+    /// Show the pool's guests and whether they are busy
+    Status,
+    /// Shut down all pool guests
+    Stop,
+}
+
+#[derive(Subcommand)]
+enum AttestAction {
+    /// Sign the build manifest (and optionally the artifacts) of a built configuration
+    Sign {
+        /// Configuration name (e.g., arm64-qemu)
+        config: String,
+        /// PEM private key to sign with
+        #[arg(long, required_unless_present = "keyless", conflicts_with = "keyless")]
+        key: Option<PathBuf>,
+        /// Use the sigstore keyless flow through cosign
+        #[arg(long)]
+        keyless: bool,
+        /// Also sign the kernel image, rootfs image or initramfs, DTB and VMM artifacts
+        #[arg(long)]
+        artifacts: bool,
+    },
+    /// Verify the signed provenance of a configuration before consuming it
+    Verify {
+        /// Configuration name (e.g., arm64-qemu)
+        config: String,
+        /// PEM public key to verify with
+        #[arg(long, required_unless_present = "keyless", conflicts_with = "keyless")]
+        pubkey: Option<PathBuf>,
+        /// Verify sigstore bundles through cosign
+        #[arg(long)]
+        keyless: bool,
+        /// Signer identity (e.g. CI workflow or email) expected with --keyless
+        #[arg(long, requires = "keyless", required_if_eq("keyless", "true"))]
+        identity: Option<String>,
+        /// OIDC issuer expected with --keyless
+        #[arg(long, requires = "keyless", required_if_eq("keyless", "true"))]
+        issuer: Option<String>,
+    },
 }
 
 fn main() {
-    env_logger::init();
-    let args = Args::parse();
+    cli_env::load_env_file();
+    let matches = cli_env::with_env(Args::command()).get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let verbosity = match (args.verbose, args.quiet) {
+        (true, _) => output::Verbosity::Verbose,
+        (_, true) => output::Verbosity::Quiet,
+        _ => output::Verbosity::Normal,
+    };
+    // RUST_LOG still wins; -v adds the debug records, e.g. rootfs contents
+    let default_level = if args.verbose { "debug" } else { "error" };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level))
+        .init();
+    output::init(args.plain, verbosity);
+    download::set_offline(args.offline);
+
+    let Some(command) = args.command else {
+        if args.list_arches {
+            arch::list_arches();
+        } else {
+            let _ = Args::command().print_help();
+        }
+        return;
+    };
 
-    match args.command {
-        Commands::Build { config } => {
+    match command {
+        Commands::Build {
+            config,
+            cache,
+            sources,
+            kernel,
+            only,
+            strict,
+            jobs_per_config,
+        } => {
             // Validate the config format and existence
             if !is_valid_config(&config) {
                 eprintln!("Invalid configuration: {}", config);
                 eprintln!("Use 'list' command to see available configurations.");
-                return;
+                std::process::exit(1);
             }
 
-            // Clone Linux source if not exists
-            if Path::new("linux").exists() {
-                println!("Linux source already exists, skipping clone.");
-            } else {
-                println!("Cloning Linux 6.12 source code...");
-                let status = Command::new("git")
-                    .args([
-                        "clone",
-                        "--depth=1",
-                        "-b",
-                        "v6.12",
-                        "https://github.com/torvalds/linux.git",
-                        "linux",
-                    ])
-                    .status()
-                    .expect("Failed to execute git clone");
-                if status.success() {
-                    println!("Clone completed successfully.");
-                } else {
-                    eprintln!("Clone failed.");
-                    return;
-                }
+            // Catch script errors before spending minutes on a kernel build
+            if !lint::lint_scripts() {
+                eprintln!("Script lint failed, aborting build.");
+                std::process::exit(1);
+            }
+            // Missing host tools would otherwise fail the build halfway through
+            doctor::warn_missing(&config);
+
+            // Clone Linux source if not exists; bare configs need no BusyBox
+            let stages = stages::StageSelection::only(only);
+            let bare = config::load_manifest(&config).is_ok_and(|manifest| manifest.bare);
+            if !download::fetch_sources(&sources, !bare && stages.runs(stages::Stage::Busybox)) {
+                std::process::exit(1);
             }
 
             // Build for the specific configuration
             println!("Building for configuration: {}", config);
-            build_linux_for_config(&config);
+            system::set_make_jobs(jobs_per_config);
+            let cache = cache.map(|location| cache::ArtifactCache::from_location(&location));
+            if let Err(e) = build_linux_for_config(&config, &kernel, &stages, cache.as_ref(), strict) {
+                eprintln!("Build failed for configuration {}: {}", config, e);
+                std::process::exit(1);
+            }
         }
-        Commands::Clean => {
-            let build_dir = Path::new("build");
-            if build_dir.exists() {
-                fs::remove_dir_all(build_dir).expect("Failed to remove build directory");
-                println!("Build directory cleaned.");
-            } else {
-                println!("Build directory does not exist.");
+        Commands::RebuildRootfs { config, strict } => {
+            if !is_valid_config(&config) {
+                eprintln!("Invalid configuration: {}", config);
+                eprintln!("Use 'list' command to see available configurations.");
+                std::process::exit(1);
+            }
+            if !lint::lint_scripts() {
+                eprintln!("Script lint failed, aborting build.");
+                std::process::exit(1);
+            }
+
+            // Same as `build <config> --only rootfs`
+            let stages = stages::StageSelection::only(vec![stages::Stage::Rootfs]);
+            let kernel = download::KernelSelection::default();
+            if let Err(e) = build_linux_for_config(&config, &kernel, &stages, None, strict) {
+                eprintln!("Rootfs rebuild failed for configuration {}: {}", config, e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Clean {
+            config,
+            stage,
+            prune,
+        } => {
+            if !clean::clean(config.as_deref(), &stage, prune) {
+                std::process::exit(1);
+            }
+        }
+        Commands::BuildAll {
+            cache,
+            sources,
+            kernel,
+            strict,
+            jobs_per_config,
+        } => {
+            if !lint::lint_scripts() {
+                eprintln!("Script lint failed, aborting build.");
+                std::process::exit(1);
+            }
+            if !download::fetch_sources(&sources, true) {
+                std::process::exit(1);
+            }
+            system::set_make_jobs(jobs_per_config);
+            if !build_all::build_all(&list_configs(), &kernel, cache.as_deref(), strict, jobs_per_config) {
+                std::process::exit(1);
             }
         }
         Commands::List => {
-            list_configs();
+            let configs = list_configs();
+            if configs.is_empty() {
+                println!("No configurations found.");
+            } else {
+                println!("Available configurations:");
+                for config in configs {
+                    println!("  {}", config);
+                }
+            }
+        }
+        Commands::Wizard => {
+            if !wizard::run() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Profiles => {
+            profile::list_profiles();
+        }
+        Commands::Lint => {
+            if !lint::lint_scripts() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Doctor { config } => {
+            if !doctor::doctor(config.as_deref()) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Smoke {
+            config,
+            all,
+            timeout,
+        } => {
+            let configs = if all {
+                smoke::built_configs()
+            } else {
+                config.into_iter().collect()
+            };
+            if !smoke::smoke(&configs, Duration::from_secs(timeout)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Test {
+            config,
+            crash_recovery,
+            policy,
+            fs_robustness,
+            update_golden,
+            kernel_versions,
+            filter,
+            exclude,
+            append,
+            tag,
+            seed,
+            hypervisor,
+            backend,
+            hypervisor_bin,
+            no_record,
+            memory_dump,
+            junit,
+            timeout,
+        } => {
+            let timeout = Duration::from_secs(timeout);
+            let vars = cmdline::RunVars::new(&config, tag, seed);
+            let mut options = match vars.expand_params(&append) {
+                Ok(extra_cmdline) => qemu::LaunchOptions {
+                    extra_cmdline,
+                    backend,
+                    binary: hypervisor_bin,
+                    ..Default::default()
+                },
+                Err(e) => {
+                    eprintln!("Invalid --append: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            match testing::selection_params(&filter, &exclude) {
+                Ok(params) => options.extra_cmdline.extend(params),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            if !append.is_empty() {
+                println!(
+                    "Run {} (seed {}): appending {}",
+                    vars.tag,
+                    vars.seed,
+                    options.extra_cmdline.join(" ")
+                );
+            }
+            let passed = if crash_recovery {
+                crash::crash_recovery_test(&config, policy, timeout)
+            } else if let Some(fault) = fs_robustness {
+                fs_robustness::fs_robustness_test(&config, fault, timeout)
+            } else if !kernel_versions.is_empty() {
+                matrix::run_matrix(&config, &kernel_versions, &options, timeout)
+            } else {
+                // Console scripts run even when a guest test failed, for the full picture
+                let run = testing::run_tests(&config, &options, timeout, memory_dump);
+                if !no_record && !run.console.is_empty() {
+                    // A binary without a version is named after itself unless --hypervisor says more
+                    let hypervisor = hypervisor
+                        .or_else(|| backend::select(&options).ok()?.version(&config))
+                        .or_else(|| options.binary.as_ref().map(|bin| bin.display().to_string()));
+                    results::record(&config, &vars.tag, hypervisor.as_deref(), &run);
+                }
+                let report_written = junit
+                    .as_deref()
+                    .is_none_or(|path| junit::write_report(&config, &run, path));
+                expect::run_console_scripts(&config, update_golden) && run.passed && report_written
+            };
+            if !passed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Run {
+            config,
+            append,
+            tag,
+            seed,
+            no_shell,
+            backend,
+            hypervisor_bin,
+            qemu_args,
+        } => {
+            if !is_valid_config(&config) {
+                eprintln!("Invalid configuration: {}", config);
+                std::process::exit(1);
+            }
+            let vars = cmdline::RunVars::new(&config, tag, seed);
+            let mut extra_cmdline = match vars.expand_params(&append) {
+                Ok(params) => params,
+                Err(e) => {
+                    eprintln!("Invalid --append: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if !no_shell {
+                extra_cmdline.push(expect::SHELL_PARAM.to_string());
+            }
+            let options = qemu::LaunchOptions {
+                extra_args: qemu_args,
+                extra_cmdline,
+                backend,
+                binary: hypervisor_bin,
+                ..Default::default()
+            };
+            if !qemu::run(&config, &options) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Sysrq {
+            config,
+            keys,
+            wait_for,
+            timeout,
+        } => {
+            if !sysrq::sysrq_debug(&config, &keys, &wait_for, Duration::from_secs(timeout)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Analyze {
+            dump,
+            config,
+            tool,
+            summary,
+        } => {
+            if !analyze::analyze(&config, &dump, tool, summary) {
+                std::process::exit(1);
+            }
+        }
+        Commands::CheckCompat { config, hypervisor } => {
+            if !compat::check_compat(&config, &hypervisor) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Ivshmem {
+            config,
+            doorbell,
+            timeout,
+        } => {
+            if !ivshmem::ivshmem_test(&config, doorbell, Duration::from_secs(timeout)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Intervm {
+            config,
+            peer,
+            timeout,
+        } => {
+            let peer = peer.unwrap_or_else(|| config.clone());
+            if !intervm::intervm_test(&config, &peer, Duration::from_secs(timeout)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::SelfUpdate { check } => {
+            if !self_update::self_update(check) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportBuildGraph {
+            config,
+            format,
+            output,
+            kernel,
+        } => {
+            if !is_valid_config(&config) {
+                eprintln!("Invalid configuration: {}", config);
+                std::process::exit(1);
+            }
+            if !graph::export_build_graph(&config, &kernel, format, output.as_deref()) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExportPxe { config, tftp_root } => {
+            if !is_valid_config(&config) {
+                eprintln!("Invalid configuration: {}", config);
+                std::process::exit(1);
+            }
+            if !pxe::export_pxe(&config, tftp_root.as_deref()) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Flash {
+            config,
+            device,
+            yes,
+            allow_fixed,
+            expand,
+        } => {
+            if !is_valid_config(&config) {
+                eprintln!("Invalid configuration: {}", config);
+                std::process::exit(1);
+            }
+            if !board::flash(
+                &config,
+                &device,
+                board::FlashOptions {
+                    yes,
+                    allow_fixed,
+                    expand,
+                },
+            ) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Results { action } => {
+            let ok = match action {
+                ResultsAction::List { config } => results::list(config.as_deref()),
+                ResultsAction::Compare {
+                    base,
+                    head,
+                    config,
+                    threshold,
+                } => results::compare(&base, &head, config.as_deref(), threshold),
+            };
+            if !ok {
+                std::process::exit(1);
+            }
+        }
+        Commands::Pool { action } => match action {
+            PoolAction::Start {
+                config,
+                count,
+                timeout,
+            } => {
+                if !pool::start(&config, count, Duration::from_secs(timeout)) {
+                    std::process::exit(1);
+                }
+            }
+            PoolAction::Exec { timeout, command } => {
+                std::process::exit(pool::exec(&command.join(" "), Duration::from_secs(timeout)));
+            }
+            PoolAction::Fetch {
+                timeout,
+                remote,
+                local,
+            } => {
+                if !pool::fetch(&remote, &local, Duration::from_secs(timeout)) {
+                    std::process::exit(1);
+                }
+            }
+            PoolAction::Status => {
+                pool::status();
+            }
+            PoolAction::Stop => {
+                pool::stop();
+            }
+        },
+        Commands::Report { format } => {
+            let ok = match format {
+                ReportFormat::Html {
+                    tags,
+                    config,
+                    output,
+                } => report::write_html(&tags, config.as_deref(), &output),
+            };
+            if !ok {
+                std::process::exit(1);
+            }
+        }
+        Commands::UpdateSources => {
+            if !sources_lock::update_sources() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Firmware { arch, pin } => {
+            if !firmware::fetch_all(&arch, pin) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Verify { config } => {
+            if !manifest::verify(&config) {
+                std::process::exit(1);
+            }
+        }
+        Commands::CheckRepro {
+            config,
+            sources,
+            kernel,
+            keep,
+        } => {
+            if !is_valid_config(&config) {
+                eprintln!("Invalid configuration: {}", config);
+                std::process::exit(1);
+            }
+            let bare = config::load_manifest(&config).is_ok_and(|manifest| manifest.bare);
+            if !download::fetch_sources(&sources, !bare) || !repro::check_repro(&config, &kernel, keep) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Attest { action } => {
+            let ok = match action {
+                AttestAction::Sign {
+                    config,
+                    key,
+                    keyless: _,
+                    artifacts,
+                } => {
+                    let signer = match key {
+                        Some(key) => attest::Signer::Key(key),
+                        None => attest::Signer::Keyless {
+                            identity: None,
+                            issuer: None,
+                        },
+                    };
+                    attest::sign(&config, &signer, artifacts)
+                }
+                AttestAction::Verify {
+                    config,
+                    pubkey,
+                    keyless: _,
+                    identity,
+                    issuer,
+                } => {
+                    let signer = match pubkey {
+                        Some(pubkey) => attest::Signer::Key(pubkey),
+                        None => attest::Signer::Keyless { identity, issuer },
+                    };
+                    attest::verify(&config, &signer)
+                }
+            };
+            if !ok {
+                std::process::exit(1);
+            }
         }
     }
 }
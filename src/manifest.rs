@@ -0,0 +1,397 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::budget::Measurements;
+use crate::config::{ConfigManifest, parse_config_name};
+use crate::download::KernelSource;
+use crate::kconfig::{is_enabled, read_config};
+use crate::sizes::SizeReport;
+
+/// Virtio drivers the hypervisor may need to provide, keyed by kernel option
+const VIRTIO_DRIVERS: [(&str, &str); 12] = [
+    ("CONFIG_VIRTIO_MMIO", "mmio"),
+    ("CONFIG_VIRTIO_PCI", "pci"),
+    ("CONFIG_VIRTIO_BLK", "blk"),
+    ("CONFIG_VIRTIO_NET", "net"),
+    ("CONFIG_VIRTIO_CONSOLE", "console"),
+    ("CONFIG_HW_RANDOM_VIRTIO", "rng"),
+    ("CONFIG_VIRTIO_BALLOON", "balloon"),
+    ("CONFIG_VIRTIO_MEM", "mem"),
+    ("CONFIG_VIRTIO_INPUT", "input"),
+    ("CONFIG_DRM_VIRTIO_GPU", "gpu"),
+    ("CONFIG_SND_VIRTIO", "snd"),
+    ("CONFIG_VIRTIO_FS", "fs"),
+];
+
+/// Manifest format written by this tool; bump on changes old readers would misinterpret
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Description of what was built for a configuration, stored as `build/<config>/manifest.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildManifest {
+    /// Manifest format version, 0 for manifests written before versioning
+    #[serde(default)]
+    pub format_version: u32,
+    /// Version of guest-test-linux that wrote the manifest
+    #[serde(default)]
+    pub tool_version: String,
+    pub config: String,
+    pub arch: String,
+    /// Build time in seconds since the UNIX epoch
+    pub built_at: u64,
+    /// Hypervisor features the guest expects
+    pub hypervisor: HypervisorRequirements,
+    /// Whether the kernel layout is randomized; symbol addresses are only stable without it
+    #[serde(default)]
+    pub kaslr: bool,
+    /// Kernel command line `run` and the generated VM configs boot the guest with
+    #[serde(default)]
+    pub cmdline: Option<String>,
+    /// Filesystem of `rootfs.img`, none when the guest boots without one
+    #[serde(default)]
+    pub image_format: Option<crate::rootfs::ImageFormat>,
+    /// Repository the kernel source was fetched from
+    #[serde(default)]
+    pub kernel_repo: Option<String>,
+    /// Tag, branch or commit the kernel source was checked out at
+    #[serde(default)]
+    pub kernel_ref: Option<String>,
+    /// Commit of the kernel source, if it is a git checkout
+    #[serde(default)]
+    pub kernel_commit: Option<String>,
+    /// Patches applied on top of the kernel commit
+    #[serde(default)]
+    pub kernel_patches: Vec<String>,
+    /// Artifact sizes and boot time, checked against the config's budget
+    #[serde(default)]
+    pub measurements: Measurements,
+    /// `uname -r` of the built kernel
+    #[serde(default)]
+    pub kernel_version: Option<String>,
+    /// Version of the BusyBox in the rootfs
+    #[serde(default)]
+    pub busybox_version: Option<String>,
+    /// Build outputs with their checksums, checked by `verify`
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+    /// Kernel section sizes, growth since the previous build and largest rootfs files
+    #[serde(default)]
+    pub size_report: Option<SizeReport>,
+    /// Licenses of the kernel, BusyBox and the other components in the guest image
+    #[serde(default)]
+    pub licenses: Vec<crate::licenses::ComponentLicense>,
+}
+
+/// One build output, relative to `build/<config>/`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Hypervisor ABI the guest kernel was built against
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HypervisorRequirements {
+    /// GIC versions the guest has drivers for (arm64 only)
+    pub gic_versions: Vec<u32>,
+    /// Virtio transports and devices the guest has drivers for
+    pub virtio: Vec<String>,
+    /// Console transport the guest writes its output to
+    pub console: Option<String>,
+}
+
+/// Get the manifest path for a configuration
+pub fn manifest_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("manifest.json")
+}
+
+/// Derive the hypervisor requirements from the config manifest and kernel `.config`
+fn hypervisor_requirements(
+    arch: &str,
+    config_manifest: &ConfigManifest,
+    kernel_config: &Path,
+) -> HypervisorRequirements {
+    let options = read_config(kernel_config);
+
+    // A config targeting one GIC model needs exactly that one from the hypervisor
+    let mut gic_versions = Vec::new();
+    if let Some(gic) = config_manifest.gic {
+        gic_versions.push(gic);
+    } else {
+        if is_enabled(&options, "CONFIG_ARM_GIC") {
+            gic_versions.push(2);
+        }
+        if is_enabled(&options, "CONFIG_ARM_GIC_V3") {
+            gic_versions.push(3);
+        }
+    }
+
+    let virtio = VIRTIO_DRIVERS
+        .iter()
+        .filter(|(option, _)| is_enabled(&options, option))
+        .map(|(_, name)| name.to_string())
+        .collect();
+
+    HypervisorRequirements {
+        gic_versions,
+        virtio,
+        console: Some(config_manifest.console(arch).name().to_string()),
+    }
+}
+
+/// Commit checked out in a kernel source tree
+fn source_commit(linux_dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(linux_dir)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !commit.is_empty()).then_some(commit)
+}
+
+/// Write the build manifest for a configuration
+pub fn write_manifest(
+    config_name: &str,
+    config_manifest: &ConfigManifest,
+    source: &KernelSource,
+    kernel_build_dir: &Path,
+) {
+    let (arch, _) = parse_config_name(config_name);
+    let hypervisor =
+        hypervisor_requirements(&arch, config_manifest, &kernel_build_dir.join(".config"));
+    let manifest = BuildManifest {
+        format_version: FORMAT_VERSION,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        config: config_name.to_string(),
+        arch,
+        built_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        hypervisor,
+        kaslr: config_manifest.kaslr != Some(false)
+            && is_enabled(
+                &read_config(&kernel_build_dir.join(".config")),
+                "CONFIG_RANDOMIZE_BASE",
+            ),
+        cmdline: Some(crate::cmdline::kernel_cmdline(config_name, config_manifest)),
+        // Set by the rootfs stage, which may run without rebuilding the kernel
+        image_format: read_manifest(config_name).and_then(|build| build.image_format),
+        kernel_repo: Some(source.repo.clone()),
+        kernel_ref: Some(source.git_ref.clone()),
+        kernel_commit: source_commit(&source.dir()),
+        kernel_patches: source
+            .patches
+            .iter()
+            .map(|patch| patch.display().to_string())
+            .collect(),
+        measurements: Measurements::default(),
+        kernel_version: None,
+        busybox_version: None,
+        artifacts: Vec::new(),
+        size_report: None,
+        licenses: Vec::new(),
+    };
+
+    let path = manifest_path(config_name);
+    let json = serde_json::to_string_pretty(&manifest).expect("Failed to serialize manifest");
+    match fs::write(&path, json) {
+        Ok(_) => println!("Build manifest written: {}", path.display()),
+        Err(e) => eprintln!("Failed to write build manifest: {}", e),
+    }
+}
+
+/// Read the build manifest for a configuration
+pub fn read_manifest(config_name: &str) -> Option<BuildManifest> {
+    let path = manifest_path(config_name);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Failed to read manifest {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let value: serde_json::Value = match serde_json::from_str(&content) {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("Invalid manifest {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    // Refuse newer formats instead of misreading them
+    let format_version = value
+        .get("format_version")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0);
+    if format_version > FORMAT_VERSION as u64 {
+        let tool_version = value
+            .get("tool_version")
+            .and_then(|v| v.as_str())
+            .unwrap_or("a newer version");
+        eprintln!(
+            "{} uses manifest format {} but this tool only reads up to format {}",
+            path.display(),
+            format_version,
+            FORMAT_VERSION
+        );
+        eprintln!(
+            "It was written by guest-test-linux {}; run `cargo xtask self-update`",
+            tool_version
+        );
+        return None;
+    }
+
+    match serde_json::from_value(value) {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            eprintln!("Invalid manifest {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Update the build manifest of a configuration, if it has one
+pub fn update_manifest(config_name: &str, update: impl FnOnce(&mut BuildManifest)) {
+    let path = manifest_path(config_name);
+    if !path.exists() {
+        return;
+    }
+    let Some(mut manifest) = read_manifest(config_name) else {
+        return;
+    };
+    update(&mut manifest);
+    let json = serde_json::to_string_pretty(&manifest).expect("Failed to serialize manifest");
+    if let Err(e) = fs::write(&path, json) {
+        eprintln!("Failed to update build manifest: {}", e);
+    }
+}
+
+/// Build outputs a consumer boots: kernel, rootfs image or initramfs, DTBs, VM config and VMM artifacts
+pub fn artifact_paths(config_name: &str) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(kernel) = crate::qemu::kernel_image_path(config_name) {
+        paths.push(kernel);
+    }
+    paths.push(crate::qemu::rootfs_image_path(config_name));
+    paths.push(crate::qemu::initramfs_path(config_name));
+    paths.push(crate::dtb::merged_dtb_path(config_name));
+    if let Ok(manifest) = crate::config::load_manifest(config_name) {
+        paths.extend(crate::dtb::kernel_dtb_paths(config_name, &manifest));
+    }
+    paths.push(crate::dtb::qemu_dtb_path(config_name));
+    paths.push(crate::vmconfig::vm_config_path(config_name));
+    paths.push(crate::board::sdcard_image_path(config_name));
+    if let Ok(entries) = fs::read_dir(crate::vmm::vmm_dir(config_name)) {
+        let mut vmm: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+        vmm.sort();
+        paths.extend(vmm);
+    }
+    paths.retain(|p| p.is_file());
+    paths
+}
+
+/// Version line BusyBox writes at the top of its `.config`
+fn busybox_version(config_name: &str) -> Option<String> {
+    let config = PathBuf::from("build")
+        .join(config_name)
+        .join("busybox")
+        .join(".config");
+    fs::read_to_string(config)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("# Busybox version: "))
+        .map(|version| version.trim().to_string())
+}
+
+/// Record checksums, sizes and versions of a finished build in its manifest
+pub fn record_artifacts(config_name: &str) -> Result<(), String> {
+    let output_dir = PathBuf::from("build").join(config_name);
+    let mut artifacts = Vec::new();
+    for path in artifact_paths(config_name) {
+        let size = fs::metadata(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .len();
+        artifacts.push(Artifact {
+            path: path
+                .strip_prefix(&output_dir)
+                .unwrap_or(&path)
+                .display()
+                .to_string(),
+            sha256: crate::download::sha256_file(&path)?,
+            size,
+        });
+    }
+    let kernel_version = fs::read_to_string(output_dir.join("linux/include/config/kernel.release"))
+        .ok()
+        .map(|release| release.trim().to_string());
+    let busybox_version = busybox_version(config_name);
+    println!(
+        "Recorded {} artifacts in {}",
+        artifacts.len(),
+        manifest_path(config_name).display()
+    );
+    update_manifest(config_name, |manifest| {
+        manifest.artifacts = artifacts;
+        manifest.kernel_version = kernel_version;
+        manifest.busybox_version = busybox_version;
+    });
+    Ok(())
+}
+
+/// Check the artifacts of a configuration against the checksums in its manifest
+pub fn verify(config_name: &str) -> bool {
+    let Some(manifest) = read_manifest(config_name) else {
+        return false;
+    };
+    if manifest.artifacts.is_empty() {
+        eprintln!(
+            "{} records no artifacts, rebuild it",
+            manifest_path(config_name).display()
+        );
+        return false;
+    }
+    let output_dir = PathBuf::from("build").join(config_name);
+    let mut passed = true;
+    for artifact in &manifest.artifacts {
+        let path = output_dir.join(&artifact.path);
+        let problem = match crate::download::sha256_file(&path) {
+            Err(e) => Some(e),
+            Ok(sha256) if sha256 != artifact.sha256 => {
+                Some(format!("sha256 {} instead of {}", sha256, artifact.sha256))
+            }
+            Ok(_) => None,
+        };
+        println!(
+            "  {} {}{}",
+            crate::output::status(problem.is_none(), 4),
+            artifact.path,
+            problem
+                .as_deref()
+                .map(|p| format!(": {}", p))
+                .unwrap_or_default()
+        );
+        passed &= problem.is_none();
+    }
+    println!(
+        "{} (kernel {}, busybox {})",
+        if passed {
+            "All artifacts match"
+        } else {
+            "Artifacts do not match the manifest"
+        },
+        manifest.kernel_version.as_deref().unwrap_or("unknown"),
+        manifest.busybox_version.as_deref().unwrap_or("unknown")
+    );
+    passed
+}
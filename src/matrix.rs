@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::download::LINUX_GIT_URL;
+use crate::kernel::build_kernel_only;
+use crate::qemu::{LaunchOptions, is_built};
+use crate::testing::run_tests;
+
+/// Directory holding one shallow clone per kernel version
+const SOURCES_DIR: &str = "linux-versions";
+
+/// Outcome of one kernel version
+struct VersionResult {
+    version: String,
+    tag: String,
+    built: bool,
+    passed: bool,
+}
+
+/// Resolve a version to a tag; `v6.13-rc` picks the newest release candidate
+fn resolve_tag(version: &str) -> Result<String, String> {
+    if !version.ends_with("-rc") {
+        return Ok(version.to_string());
+    }
+
+    let output = Command::new("git")
+        .args(["ls-remote", "--tags", LINUX_GIT_URL])
+        .arg(format!("refs/tags/{}*", version))
+        .output()
+        .map_err(|e| format!("failed to run git ls-remote: {}", e))?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter_map(|r| r.strip_prefix("refs/tags/"))
+        .filter(|tag| !tag.ends_with("^{}"))
+        .filter_map(|tag| {
+            let rc = tag.strip_prefix(version)?.parse::<u32>().ok()?;
+            Some((rc, tag.to_string()))
+        })
+        .max()
+        .map(|(_, tag)| tag)
+        .ok_or_else(|| format!("no release candidates found for {}", version))
+}
+
+/// Get the source tree of a kernel tag, cloning it if needed
+fn source_dir(tag: &str) -> Option<PathBuf> {
+    let dir = Path::new(SOURCES_DIR).join(tag);
+    if dir.join("Makefile").exists() {
+        return Some(dir);
+    }
+
+    if crate::download::offline() {
+        eprintln!("Linux {} is not in {} and --offline forbids cloning it", tag, SOURCES_DIR);
+        return None;
+    }
+
+    println!("Cloning Linux {}...", tag);
+    let status = Command::new("git")
+        .args(["clone", "--depth=1", "-b", tag, LINUX_GIT_URL])
+        .arg(&dir)
+        .status()
+        .expect("Failed to execute git clone");
+    if !status.success() {
+        eprintln!("Clone of {} failed", tag);
+        return None;
+    }
+    Some(dir)
+}
+
+/// Build and test one kernel version against the configuration's rootfs
+fn test_version(
+    config_name: &str,
+    version: &str,
+    options: &LaunchOptions,
+    timeout: Duration,
+) -> VersionResult {
+    let mut result = VersionResult {
+        version: version.to_string(),
+        tag: version.to_string(),
+        built: false,
+        passed: false,
+    };
+    match resolve_tag(version) {
+        Ok(tag) => result.tag = tag,
+        Err(e) => {
+            eprintln!("Failed to resolve {}: {}", version, e);
+            return result;
+        }
+    }
+
+    println!("\n=== {} ({}) ===", config_name, result.tag);
+    let Some(linux_dir) = source_dir(&result.tag) else {
+        return result;
+    };
+    let build_dir = PathBuf::from("build")
+        .join(config_name)
+        .join("kernels")
+        .join(&result.tag);
+    let Some(kernel) = build_kernel_only(config_name, &linux_dir, &build_dir) else {
+        eprintln!("Kernel {} failed to build for {}", result.tag, config_name);
+        return result;
+    };
+    result.built = true;
+
+    let options = LaunchOptions {
+        kernel: Some(kernel),
+        ..options.clone()
+    };
+    result.passed = run_tests(config_name, &options, timeout, false).passed;
+    result
+}
+
+/// Test a configuration against several kernel versions and print a result matrix
+///
+/// Only the kernel is rebuilt per version; the rootfs (and thus its modules) comes
+/// from the configuration's regular build.
+pub fn run_matrix(
+    config_name: &str,
+    versions: &[String],
+    options: &LaunchOptions,
+    timeout: Duration,
+) -> bool {
+    if !is_built(config_name) {
+        eprintln!(
+            "{} has not been built, build it first to provide the rootfs",
+            config_name
+        );
+        return false;
+    }
+
+    let results: Vec<VersionResult> = versions
+        .iter()
+        .map(|version| test_version(config_name, version, options, timeout))
+        .collect();
+
+    let width = results
+        .iter()
+        .map(|r| r.version.len())
+        .max()
+        .unwrap_or(0)
+        .max("VERSION".len());
+    println!();
+    println!("Kernel version matrix for {}:", config_name);
+    println!("{:<width$}  {:<14}  BUILD  TESTS", "VERSION", "TAG");
+    for r in &results {
+        println!(
+            "{:<width$}  {:<14}  {:<5}  {}",
+            r.version,
+            r.tag,
+            if r.built { "OK" } else { "FAIL" },
+            match (r.built, r.passed) {
+                (false, _) => "-",
+                (true, true) => "PASS",
+                (true, false) => "FAIL",
+            }
+        );
+    }
+
+    let failed = results.iter().filter(|r| !r.passed).count();
+    println!();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    failed == 0
+}
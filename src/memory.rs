@@ -0,0 +1,164 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::ConfigManifest;
+
+/// Guest RAM size used when a config does not declare one
+pub const DEFAULT_MEMORY: u64 = 1024 * 1024 * 1024;
+
+/// Start of RAM on QEMU's arm64 virt machine
+pub const ARM64_RAM_BASE: u64 = 0x4000_0000;
+
+/// Start of RAM on QEMU's riscv64 virt machine
+pub const RISCV64_RAM_BASE: u64 = 0x8000_0000;
+
+/// Parse a size such as `512M`, `1G`, `0x1000` or `4096` into bytes
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (digits, shift) = match size.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&size[..i], 10),
+        Some((i, 'M' | 'm')) => (&size[..i], 20),
+        Some((i, 'G' | 'g')) => (&size[..i], 30),
+        _ => (size, 0),
+    };
+
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse::<u64>(),
+    }
+    .map_err(|_| format!("invalid size: {}", size))?;
+
+    value
+        .checked_shl(shift)
+        .filter(|v| v >> shift == value)
+        .ok_or_else(|| format!("size out of range: {}", size))
+}
+
+/// Format a byte count with the largest exact unit (e.g. `512M`)
+pub fn format_size(bytes: u64) -> String {
+    for (suffix, shift) in [("G", 30), ("M", 20), ("K", 10)] {
+        if bytes != 0 && bytes.is_multiple_of(1 << shift) {
+            return format!("{}{}", bytes >> shift, suffix);
+        }
+    }
+    bytes.to_string()
+}
+
+/// Guest RAM size in bytes
+pub fn memory_size(manifest: &ConfigManifest) -> Result<u64, String> {
+    match &manifest.memory {
+        Some(memory) => parse_size(memory),
+        None => Ok(DEFAULT_MEMORY),
+    }
+}
+
+/// Declared physical memory regions as `(base, size)` pairs, sorted by base
+pub fn memory_regions(manifest: &ConfigManifest) -> Result<Vec<(u64, u64)>, String> {
+    let mut regions = Vec::new();
+    for region in &manifest.memory_map {
+        let (base, size) = (parse_size(&region.base)?, parse_size(&region.size)?);
+        if base.checked_add(size).is_none() {
+            return Err(format!(
+                "memory region {:#x}+{} ends past the 64-bit address space",
+                base,
+                format_size(size)
+            ));
+        }
+        regions.push((base, size));
+    }
+    regions.sort();
+
+    for pair in regions.windows(2) {
+        let (base, size) = pair[0];
+        if base + size > pair[1].0 {
+            return Err(format!(
+                "memory regions {:#x}+{} and {:#x} overlap",
+                base,
+                format_size(size),
+                pair[1].0
+            ));
+        }
+    }
+
+    let total = regions
+        .iter()
+        .try_fold(0u64, |total, (_, size)| total.checked_add(*size))
+        .ok_or("memory map size overflows")?;
+    if total > memory_size(manifest)? {
+        return Err(format!(
+            "memory map covers {} but the guest only has {}",
+            format_size(total),
+            format_size(memory_size(manifest)?)
+        ));
+    }
+
+    Ok(regions)
+}
+
+/// Kernel command line parameters restricting the guest to its memory map
+pub fn cmdline_params(arch: &str, manifest: &ConfigManifest) -> Vec<String> {
+    let Ok(regions) = memory_regions(manifest) else {
+        return Vec::new();
+    };
+    if regions.is_empty() {
+        return Vec::new();
+    }
+
+    match arch {
+        // Replace the firmware-provided e820 map with exactly our regions
+        "x86" => std::iter::once("memmap=exactmap".to_string())
+            .chain(
+                regions
+                    .iter()
+                    .map(|(base, size)| format!("memmap={}@{:#x}", format_size(*size), base)),
+            )
+            .collect(),
+        // DT-based arches take the layout from the memory node; cap the total as well
+        _ => {
+            let total: u64 = regions.iter().map(|(_, size)| size).sum();
+            vec![format!("mem={}", format_size(total))]
+        }
+    }
+}
+
+/// Write a devicetree fragment with the guest's memory nodes to `build/<config>/memory.dtsi`
+pub fn write_dt_fragment(config_name: &str, arch: &str, manifest: &ConfigManifest) {
+    // Only devicetree-based arches need the fragment
+    if !crate::arch::find_arch(arch).is_some_and(|a| a.devicetree) {
+        return;
+    }
+
+    let regions = match memory_regions(manifest) {
+        Ok(regions) if !regions.is_empty() => regions,
+        Ok(_) => vec![(
+            ARM64_RAM_BASE,
+            memory_size(manifest).unwrap_or(DEFAULT_MEMORY),
+        )],
+        Err(e) => {
+            eprintln!("Invalid memory map for {}: {}", config_name, e);
+            return;
+        }
+    };
+
+    let mut dtsi = format!(
+        "/* Memory layout of {}, generated by guest-test-linux */\n/ {{\n",
+        config_name
+    );
+    for (base, size) in regions {
+        dtsi.push_str(&format!(
+            "\tmemory@{:x} {{\n\t\tdevice_type = \"memory\";\n\t\treg = <{:#x} {:#x} {:#x} {:#x}>;\n\t}};\n",
+            base,
+            base >> 32,
+            base & 0xffff_ffff,
+            size >> 32,
+            size & 0xffff_ffff
+        ));
+    }
+    dtsi.push_str("};\n");
+
+    let path = PathBuf::from("build").join(config_name).join("memory.dtsi");
+    match fs::write(&path, dtsi) {
+        Ok(_) => println!("Memory devicetree fragment written: {}", path.display()),
+        Err(e) => eprintln!("Failed to write {}: {}", path.display(), e),
+    }
+}
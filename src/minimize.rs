@@ -0,0 +1,185 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path};
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+
+use crate::config::ConfigManifest;
+
+/// Documentation, locales and headers nothing in a test guest reads
+const PRUNED_DIRS: &[&str] = &[
+    "usr/share/man",
+    "usr/share/doc",
+    "usr/share/info",
+    "usr/share/locale",
+    "usr/share/i18n",
+    "usr/lib/locale",
+    "usr/include",
+    "usr/local/share/man",
+    "usr/local/share/doc",
+    "usr/local/include",
+];
+
+/// Static libraries and their libtool descriptions
+const PRUNED_EXTENSIONS: &[&str] = &["a", "la"];
+
+/// Rootfs minimization, e.g. `minimize = { keep = ["/usr/share/doc/licenses"] }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Minimize {
+    /// Strip symbols from ELF binaries and libraries; kernel modules (`*.ko`, wherever they
+    /// are) keep theirs and their signatures
+    #[serde(default = "default_strip")]
+    pub strip: bool,
+    /// Guest paths left alone, with everything below them
+    #[serde(default)]
+    pub keep: Vec<String>,
+}
+
+fn default_strip() -> bool {
+    true
+}
+
+/// Check the minimization settings of a manifest
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    let Some(minimize) = &manifest.minimize else {
+        return Ok(());
+    };
+    if manifest.bare {
+        return Err("bare has no rootfs to minimize".to_string());
+    }
+    for path in &minimize.keep {
+        let path = Path::new(path);
+        if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+            return Err(format!(
+                "minimize keep entries are absolute guest paths, got {}",
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a file starts with the ELF magic
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+/// Removed and stripped files of a minimization pass
+#[derive(Default)]
+struct Pass {
+    removed: usize,
+    stripped: usize,
+    failed: Vec<String>,
+}
+
+impl Pass {
+    fn remove(&mut self, path: &Path, guest_path: &Path) {
+        let removed = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+        match removed {
+            Ok(()) => self.removed += 1,
+            Err(e) => self.failed.push(format!("{}: {}", guest_path.display(), e)),
+        }
+    }
+}
+
+/// Prune documentation, locales, headers and static libraries from a staged rootfs and
+/// strip its binaries, printing the size before and after
+///
+/// Runs before IMA signing, since stripping rewrites the files. Binaries the cross
+/// `strip` cannot handle are left as they are with a warning.
+pub fn minimize_rootfs(
+    rootfs_dir: &Path,
+    manifest: &ConfigManifest,
+    cross_compile_prefix: &Option<String>,
+) {
+    let Some(minimize) = &manifest.minimize else {
+        return;
+    };
+    println!("Minimizing rootfs...");
+    let before = crate::budget::tree_size(rootfs_dir);
+    let kept = |guest_path: &Path| minimize.keep.iter().any(|k| guest_path.starts_with(k));
+    let holds_kept = |guest_path: &Path| {
+        minimize
+            .keep
+            .iter()
+            .any(|k| Path::new(k).starts_with(guest_path))
+    };
+    let strip = format!("{}strip", cross_compile_prefix.as_deref().unwrap_or(""));
+
+    let mut pass = Pass::default();
+    // Directories to walk, and whether they lie in a pruned one whose kept paths survive
+    let mut pending = vec![(rootfs_dir.to_path_buf(), false)];
+    while let Some((dir, pruning)) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            let relative = path.strip_prefix(rootfs_dir).unwrap_or(&path);
+            let guest_path = Path::new("/").join(relative);
+            if kept(&guest_path) {
+                continue;
+            }
+            let pruned = pruning || PRUNED_DIRS.iter().any(|d| relative == Path::new(d));
+            if metadata.is_dir() {
+                if pruned && !holds_kept(&guest_path) {
+                    pass.remove(&path, &guest_path);
+                } else if !relative.starts_with("lib/modules") {
+                    pending.push((path, pruned));
+                }
+                continue;
+            }
+            if pruned {
+                pass.remove(&path, &guest_path);
+                continue;
+            }
+            if !metadata.is_file() {
+                continue;
+            }
+            let extension = path.extension().and_then(|e| e.to_str());
+            if extension.is_some_and(|e| PRUNED_EXTENSIONS.contains(&e)) {
+                pass.remove(&path, &guest_path);
+            } else if minimize.strip && extension != Some("ko") && is_elf(&path) {
+                let stripped = Command::new(&strip)
+                    .arg("--strip-unneeded")
+                    .arg(&path)
+                    .stderr(Stdio::null())
+                    .status();
+                match stripped {
+                    Ok(status) if status.success() => pass.stripped += 1,
+                    _ => pass
+                        .failed
+                        .push(format!("{}: {} failed", guest_path.display(), strip)),
+                }
+            }
+        }
+    }
+
+    for failure in &pass.failed {
+        eprintln!("Warning: minimize: {}", failure);
+    }
+    let mib = |bytes: Option<u64>| match bytes {
+        Some(bytes) => format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+        None => "?".to_string(),
+    };
+    println!(
+        "Minimized rootfs: {} -> {} ({} removed, {} stripped)",
+        mib(before),
+        mib(crate::budget::tree_size(rootfs_dir)),
+        pass.removed,
+        pass.stripped
+    );
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::ConfigManifest;
+
+/// Hash modules are signed with
+const SIG_HASH: &str = "sha256";
+
+/// Kernel options for signed modules; modules_install signs in-tree modules
+pub const MODULE_SIG_KERNEL_OPTIONS: &[&str] = &[
+    "CONFIG_MODULES",
+    "CONFIG_MODULE_SIG",
+    "CONFIG_MODULE_SIG_ALL",
+    "CONFIG_MODULE_SIG_SHA256",
+];
+
+/// Key generated for a config that does not bring its own
+fn generated_key_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("module-signing.pem")
+}
+
+/// Generate a self-signed module signing key, with the extensions the kernel checks
+fn generate_key(path: &Path) -> bool {
+    println!("Generating module signing key {}...", path.display());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).expect("Failed to create build directory");
+    }
+    let status = Command::new("openssl")
+        .args([
+            "req", "-new", "-nodes", "-utf8", "-sha256", "-days", "36500", "-batch", "-x509",
+        ])
+        .args([
+            "-newkey",
+            "rsa:4096",
+            "-subj",
+            "/CN=guest-test module signing key",
+        ])
+        .args(["-addext", "basicConstraints=critical,CA:FALSE"])
+        .args(["-addext", "keyUsage=digitalSignature"])
+        .args(["-addext", "subjectKeyIdentifier=hash"])
+        .args(["-outform", "PEM", "-out"])
+        .arg(path)
+        .arg("-keyout")
+        .arg(path)
+        .status();
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(_) => {
+            eprintln!("openssl failed to generate the module signing key");
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run openssl: {}", e);
+            false
+        }
+    }
+}
+
+/// PEM file holding the private key and certificate modules are signed with,
+/// generated on first use unless the manifest names one
+pub fn signing_key(config_name: &str, manifest: &ConfigManifest) -> Option<PathBuf> {
+    let path = match &manifest.module_signing_key {
+        Some(key) => PathBuf::from(key),
+        None => {
+            let path = generated_key_path(config_name);
+            // Kept across rebuilds so earlier signed modules stay loadable
+            if !path.exists() && !generate_key(&path) {
+                return None;
+            }
+            path
+        }
+    };
+    match path.canonicalize() {
+        Ok(path) => Some(path),
+        Err(e) => {
+            eprintln!("Module signing key {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Kernel options enabling module signing with the config's key
+pub fn kernel_options(
+    config_name: &str,
+    manifest: &ConfigManifest,
+) -> Option<Vec<(String, String)>> {
+    if !manifest.module_signing {
+        return Some(Vec::new());
+    }
+    let key = signing_key(config_name, manifest)?;
+    let mut options: Vec<(String, String)> = MODULE_SIG_KERNEL_OPTIONS
+        .iter()
+        .map(|option| (option.to_string(), "y".to_string()))
+        .collect();
+    options.push((
+        "CONFIG_MODULE_SIG_KEY".to_string(),
+        format!("\"{}\"", key.display()),
+    ));
+    Some(options)
+}
+
+/// Sign an out-of-tree module with the kernel build's sign-file and the config's key
+pub fn sign_module(config_name: &str, manifest: &ConfigManifest, module: &Path) -> bool {
+    let Some(key) = signing_key(config_name, manifest) else {
+        return false;
+    };
+    let sign_file = PathBuf::from("build")
+        .join(config_name)
+        .join("linux")
+        .join("scripts")
+        .join("sign-file");
+    // The combined PEM serves as both private key and certificate
+    let status = Command::new(&sign_file)
+        .arg(SIG_HASH)
+        .arg(&key)
+        .arg(&key)
+        .arg(module)
+        .status();
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(_) => {
+            eprintln!("Failed to sign {}", module.display());
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run {}: {}", sign_file.display(), e);
+            false
+        }
+    }
+}
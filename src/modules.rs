@@ -0,0 +1,163 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::ConfigManifest;
+use crate::error::{BuildError, run};
+
+/// Release string of a configuration's built kernel, e.g. `6.12.0`, which names its
+/// directory under `/lib/modules`
+pub fn kernel_release(config_name: &str) -> Option<String> {
+    let path = PathBuf::from("build")
+        .join(config_name)
+        .join("linux")
+        .join("include/config/kernel.release");
+    let release = fs::read_to_string(path).ok()?.trim().to_string();
+    (!release.is_empty()).then_some(release)
+}
+
+/// Check the expected modules of a manifest
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    if manifest.modules.is_empty() {
+        return Ok(());
+    }
+    if manifest.bare {
+        return Err("bare has no rootfs for kernel modules".to_string());
+    }
+    if let Some(name) = manifest.modules.iter().find(|name| {
+        name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }) {
+        return Err(format!(
+            "modules are module names such as virtio_blk, got {:?}",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Module name of a modules.dep or modules.builtin path, e.g. `zram` for
+/// `kernel/drivers/block/zram/zram.ko.xz`; `-` and `_` are the same to modprobe
+fn module_name(path: &str) -> Option<String> {
+    let file = path.rsplit('/').next()?;
+    let (name, _) = file.split_once(".ko")?;
+    Some(name.replace('-', "_"))
+}
+
+/// Names of the modules depmod indexed and of those built into the kernel
+fn indexed_modules(release_dir: &Path) -> (Vec<String>, Vec<String>) {
+    let names = |file: &str, separator: char| -> Vec<String> {
+        fs::read_to_string(release_dir.join(file))
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(|line| module_name(line.split(separator).next()?))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    (names("modules.dep", ':'), names("modules.builtin", ' '))
+}
+
+/// Install the kernel's modules into the rootfs, index them with depmod for the guest's
+/// layout and check that the manifest's `modules` are there
+///
+/// Without expected modules, failures only warn: kernels built without `CONFIG_MODULES`
+/// have nothing to install.
+pub fn install_modules(
+    config_name: &str,
+    rootfs_dir: &Path,
+    kernel_arch: &str,
+    cross_compile_prefix: &Option<String>,
+    manifest: &ConfigManifest,
+) -> Result<(), BuildError> {
+    println!("Installing kernel modules...");
+    let linux_build_dir = PathBuf::from("build").join(config_name).join("linux");
+    let modules_dir = rootfs_dir.join("lib").join("modules");
+    fs::create_dir_all(&modules_dir)
+        .map_err(BuildError::io("failed to create modules directory"))?;
+    let required = !manifest.modules.is_empty();
+    let problem = |message: String| -> Result<(), BuildError> {
+        if required {
+            return Err(BuildError::Step(message));
+        }
+        eprintln!("Warning: {} for configuration: {}", message, config_name);
+        Ok(())
+    };
+
+    let resolve = |dir: &Path| {
+        dir.canonicalize().map_err(BuildError::io(format!(
+            "failed to resolve {}",
+            dir.display()
+        )))
+    };
+    let mut make_args = vec![
+        format!("O={}", resolve(&linux_build_dir)?.display()),
+        format!("ARCH={}", kernel_arch),
+        format!("INSTALL_MOD_PATH={}", resolve(rootfs_dir)?.display()),
+        "modules_install".to_string(),
+    ];
+    if let Some(prefix) = cross_compile_prefix {
+        make_args.push(format!("CROSS_COMPILE={}", prefix));
+    }
+    if let Err(e) = run(
+        Command::new("make")
+            .current_dir(crate::kernel::source_dir(config_name))
+            .args(&make_args),
+        "modules_install",
+    ) {
+        problem(e.to_string())?;
+    }
+    // The build/source links point into the host's kernel tree
+    if let Ok(versions) = fs::read_dir(&modules_dir) {
+        for version in versions.flatten() {
+            for link in ["build", "source"] {
+                let _ = fs::remove_file(version.path().join(link));
+            }
+        }
+    }
+
+    let Some(release) = kernel_release(config_name) else {
+        return problem("no kernel release in the kernel build tree".to_string());
+    };
+    let release_dir = modules_dir.join(&release);
+    if !release_dir.is_dir() {
+        return problem(format!("no modules were installed for kernel {}", release));
+    }
+    if let Err(e) = run(
+        Command::new("depmod")
+            .arg("-b")
+            .arg(rootfs_dir)
+            .arg(&release),
+        "depmod",
+    ) {
+        problem(e.to_string())?;
+    }
+
+    let (installed, builtin) = indexed_modules(&release_dir);
+    println!(
+        "Kernel {}: {} modules installed, {} built in",
+        release,
+        installed.len(),
+        builtin.len()
+    );
+    let missing: Vec<&str> = manifest
+        .modules
+        .iter()
+        .filter(|name| {
+            let name = name.replace('-', "_");
+            !installed.contains(&name) && !builtin.contains(&name)
+        })
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(BuildError::Step(format!(
+            "expected kernel modules missing from /lib/modules/{}: {}; enable them in a fragment",
+            release,
+            missing.join(", ")
+        )));
+    }
+    Ok(())
+}
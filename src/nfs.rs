@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+use crate::config::ConfigManifest;
+
+/// Host ports of the NFS and MOUNT services started for NFS-root guests
+///
+/// QEMU's user-mode network maps the guest's 10.0.2.2 to the host's loopback.
+pub const NFSD_PORT: u16 = 3049;
+pub const MOUNTD_PORT: u16 = 3048;
+
+/// Kernel options for mounting the root filesystem over NFS
+pub const NFSROOT_KERNEL_OPTIONS: &[&str] = &[
+    "CONFIG_NETWORK_FILESYSTEMS",
+    "CONFIG_NFS_FS",
+    "CONFIG_NFS_V3",
+    "CONFIG_ROOT_NFS",
+    "CONFIG_IP_PNP",
+    "CONFIG_IP_PNP_DHCP",
+];
+
+/// Rootfs directory exported to the guest, as an absolute path
+pub fn export_dir(config_name: &str) -> PathBuf {
+    std::env::current_dir()
+        .expect("Failed to get current directory")
+        .join("build")
+        .join(config_name)
+        .join("rootfs")
+}
+
+/// Kernel parameters mounting the root filesystem over NFS
+pub fn cmdline_params(config_name: &str, manifest: &ConfigManifest) -> Vec<String> {
+    let nfsroot = match &manifest.nfs_server {
+        Some(server) => server.clone(),
+        None => format!(
+            "10.0.2.2:{},vers=3,tcp,port={},mountport={},nolock",
+            export_dir(config_name).display(),
+            NFSD_PORT,
+            MOUNTD_PORT
+        ),
+    };
+    vec![
+        "root=/dev/nfs".to_string(),
+        format!("nfsroot={}", nfsroot),
+        "ip=dhcp".to_string(),
+    ]
+}
+
+/// Start unfsd exporting the configuration's rootfs directory
+///
+/// Returns `Ok(None)` when the manifest points at an external server, e.g. nfs-ganesha.
+pub fn start_server(config_name: &str, manifest: &ConfigManifest) -> Result<Option<Child>, String> {
+    if !manifest.nfsroot || manifest.nfs_server.is_some() {
+        return Ok(None);
+    }
+
+    let rootfs_dir = export_dir(config_name);
+    if !rootfs_dir.is_dir() {
+        return Err(format!("{} does not exist", rootfs_dir.display()));
+    }
+    let exports = PathBuf::from("build").join(config_name).join("nfs-exports");
+    fs::write(
+        &exports,
+        format!("{} (rw,no_root_squash,insecure)\n", rootfs_dir.display()),
+    )
+    .map_err(|e| format!("failed to write {}: {}", exports.display(), e))?;
+
+    // Foreground, TCP only, without portmapper so no root is needed
+    println!(
+        "Exporting {} over NFS on port {}...",
+        rootfs_dir.display(),
+        NFSD_PORT
+    );
+    Command::new("unfsd")
+        .args(["-d", "-p", "-t", "-l", "127.0.0.1"])
+        .args(["-n", &NFSD_PORT.to_string(), "-m", &MOUNTD_PORT.to_string()])
+        .arg("-e")
+        .arg(&exports)
+        .stdout(Stdio::null())
+        .spawn()
+        .map(Some)
+        .map_err(|e| {
+            format!(
+                "failed to run unfsd ({}); install unfs3 or set nfs_server to an external server",
+                e
+            )
+        })
+}
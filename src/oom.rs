@@ -0,0 +1,71 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::arch::Arch;
+use crate::qmp::Qmp;
+
+/// Memory the balloon takes from the guest during the global OOM;
+/// payloads/oom expects the same amount
+pub const BALLOON_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Printed by payloads/oom when the balloon should inflate, and deflate again
+pub const INFLATE_MARKER: &str = "oom: inflate";
+pub const DEFLATE_MARKER: &str = "oom: deflate";
+
+/// How long the balloon gets to reach its target
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// QEMU arguments adding the virtio-balloon device; it never deflates on guest OOM
+pub fn qemu_args(arch: &Arch) -> Vec<String> {
+    vec![
+        "-device".to_string(),
+        format!("virtio-balloon-{},id=balloon0", arch.virtio_suffix),
+    ]
+}
+
+/// Guest memory the balloon leaves, as QEMU reports it
+fn actual(qmp: &mut Qmp) -> Result<u64, String> {
+    qmp.execute("query-balloon", json!({}))?["actual"]
+        .as_u64()
+        .ok_or_else(|| "query-balloon returned no size".to_string())
+}
+
+/// Resize the balloon so the guest keeps `target` bytes and wait until the guest let go
+/// of (or took back) the pages
+pub fn resize(qmp: &mut Qmp, target: u64) -> Result<(), String> {
+    println!(
+        "Resizing the balloon to leave the guest {} bytes...",
+        target
+    );
+    qmp.execute("balloon", json!({ "value": target }))?;
+    let started = Instant::now();
+    loop {
+        let actual = actual(qmp)?;
+        if actual == target {
+            return Ok(());
+        }
+        if started.elapsed() > SETTLE_TIMEOUT {
+            return Err(format!(
+                "the guest has {} bytes after {}s, the balloon asked for {}",
+                actual,
+                SETTLE_TIMEOUT.as_secs(),
+                target
+            ));
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Check that the balloon still holds the guest at `target` bytes, e.g. after an OOM
+pub fn check_held(qmp: &mut Qmp, target: u64) -> Result<(), String> {
+    let actual = actual(qmp)?;
+    if actual != target {
+        return Err(format!(
+            "the balloon gave the guest {} bytes under pressure instead of {}",
+            actual, target
+        ));
+    }
+    Ok(())
+}
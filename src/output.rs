@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+const BOLD: &str = "1";
+const BLUE: &str = "1;34";
+const GREEN: &str = "32";
+const RED: &str = "1;31";
+
+/// Log lines shown when a stage fails
+const FAILURE_TAIL: usize = 30;
+
+/// How much of a build reaches the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only failures
+    Quiet,
+    /// Stage headers, results and failures; stage output goes to the logs only
+    Normal,
+    /// Stage output as well
+    Verbose,
+}
+
+static COLOR: OnceLock<bool> = OnceLock::new();
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static VERBOSITY: OnceLock<Verbosity> = OnceLock::new();
+/// Directory stage logs are written to, set per configuration
+static LOG_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+/// Logs written during this run, appended to instead of truncated
+static OPENED_LOGS: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+/// Pick the output style; colors only go to a terminal, and never with `--plain` or `NO_COLOR`
+pub fn init(plain: bool, verbosity: Verbosity) {
+    STARTED.get_or_init(Instant::now);
+    let color = !plain && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+    let _ = COLOR.set(color);
+    let _ = VERBOSITY.set(verbosity);
+}
+
+fn verbosity() -> Verbosity {
+    VERBOSITY.get().copied().unwrap_or(Verbosity::Normal)
+}
+
+/// Send the output of the following stages to `build/<config>/logs/`
+pub fn log_to(config_name: &str) {
+    let dir = PathBuf::from("build").join(config_name).join("logs");
+    *LOG_DIR.lock().unwrap() = Some(dir);
+}
+
+fn paint(text: &str, code: &str) -> String {
+    if COLOR.get().copied().unwrap_or(false) {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Whether output goes to a terminal that takes escape codes, e.g. to redraw status lines
+pub fn interactive() -> bool {
+    COLOR.get().copied().unwrap_or(false)
+}
+
+/// Time since the tool started, e.g. `[03:07]`
+pub fn timestamp() -> String {
+    let secs = STARTED.get_or_init(Instant::now).elapsed().as_secs();
+    format!("[{:02}:{:02}]", secs / 60, secs % 60)
+}
+
+/// Print a heading that opens a group of stages, e.g. one configuration of `build-all`
+pub fn heading(text: &str) {
+    println!("{} {}", timestamp(), paint(&format!("### {}", text), BOLD));
+}
+
+/// `ok`/`FAIL` padded to `width`, colored when enabled
+pub fn status(passed: bool, width: usize) -> String {
+    if passed {
+        paint(&format!("{:<width$}", "ok"), GREEN)
+    } else {
+        paint(&format!("{:<width$}", "FAIL"), RED)
+    }
+}
+
+/// Stdout and stderr of the process, including child processes, redirected into a log
+/// until dropped; with `-v` they still reach the terminal as well
+struct Capture {
+    saved_stdout: OwnedFd,
+    saved_stderr: OwnedFd,
+    tee: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+    fn start(log: &Path) -> io::Result<Capture> {
+        let mut opened = OPENED_LOGS.lock().unwrap();
+        let first = opened
+            .get_or_insert_with(HashSet::new)
+            .insert(log.to_path_buf());
+        if let Some(dir) = log.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(!first)
+            .truncate(first)
+            .open(log)?;
+
+        io::stdout().flush()?;
+        let saved_stdout = io::stdout().as_fd().try_clone_to_owned()?;
+        let saved_stderr = io::stderr().as_fd().try_clone_to_owned()?;
+        let mut terminal = (verbosity() == Verbosity::Verbose)
+            .then(|| saved_stdout.try_clone().map(File::from))
+            .transpose()?;
+        let (mut reader, writer) = io::pipe()?;
+        for fd in [1, 2] {
+            // SAFETY: dup2 onto the standard descriptors, which stay open throughout
+            if unsafe { libc::dup2(writer.as_raw_fd(), fd) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let tee = thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                let _ = file.write_all(&buf[..n]);
+                if let Some(terminal) = &mut terminal {
+                    let _ = terminal.write_all(&buf[..n]);
+                }
+            }
+        });
+        Ok(Capture {
+            saved_stdout,
+            saved_stderr,
+            tee: Some(tee),
+        })
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        // SAFETY: restores the descriptors saved in `start`; the pipe closes with them
+        unsafe {
+            libc::dup2(self.saved_stdout.as_raw_fd(), 1);
+            libc::dup2(self.saved_stderr.as_raw_fd(), 2);
+        }
+        if let Some(tee) = self.tee.take() {
+            let _ = tee.join();
+        }
+    }
+}
+
+/// Log file of a stage: stages share the log named after their first word,
+/// e.g. `kernel.log` for "kernel config" and "kernel build"
+fn stage_log(name: &str) -> Option<PathBuf> {
+    let dir = LOG_DIR.lock().unwrap().clone()?;
+    let first = name.split_whitespace().next().unwrap_or(name);
+    Some(dir.join(format!("{}.log", first)))
+}
+
+/// Last lines of a log, for showing why a stage failed
+fn tail(log: &Path, lines: usize) -> Vec<String> {
+    let content = fs::read_to_string(log).unwrap_or_default();
+    let all: Vec<&str> = content.lines().collect();
+    all[all.len().saturating_sub(lines)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Run one stage of a build between a header line and a result line; during a
+/// configuration's build its output goes to the stage log
+pub fn stage<T, E: Display>(name: &str, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let quiet = verbosity() == Verbosity::Quiet;
+    if !quiet {
+        println!(
+            "{} {} {}",
+            timestamp(),
+            paint("==>", BLUE),
+            paint(name, BOLD)
+        );
+    }
+    let log = stage_log(name);
+    let capture = log.as_deref().and_then(|log| match Capture::start(log) {
+        Ok(capture) => Some(capture),
+        Err(e) => {
+            eprintln!("Warning: not logging to {}: {}", log.display(), e);
+            None
+        }
+    });
+    let started = Instant::now();
+    let result = f();
+    let secs = started.elapsed().as_secs_f64();
+    let captured = capture.is_some();
+    drop(capture);
+    match &result {
+        Ok(_) if !quiet => println!(
+            "{} {} {} done ({:.1}s)",
+            timestamp(),
+            paint("<==", GREEN),
+            name,
+            secs
+        ),
+        Ok(_) => {}
+        Err(e) => {
+            println!(
+                "{} {} {} failed ({:.1}s): {}",
+                timestamp(),
+                paint("<==", RED),
+                name,
+                secs,
+                e
+            );
+            // The terminal did not see the output that explains the failure
+            if let Some(log) = log.filter(|_| captured && verbosity() != Verbosity::Verbose) {
+                for line in tail(&log, FAILURE_TAIL) {
+                    eprintln!("    {}", line);
+                }
+                eprintln!("Full log: {}", log.display());
+            }
+        }
+    }
+    result
+}
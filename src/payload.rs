@@ -0,0 +1,339 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::ConfigManifest;
+use crate::profile::enabled_profiles;
+
+/// Directory holding the payload sources
+const PAYLOAD_DIR: &str = "payloads";
+
+/// Directory holding the out-of-tree kernel module sources
+const MODULE_DIR: &str = "modules";
+
+/// Directory in the rootfs payload binaries are installed to
+pub const GUEST_PAYLOAD_DIR: &str = "opt/tests";
+
+/// Get the names of all payloads needed by a configuration
+pub fn payload_names(manifest: &ConfigManifest) -> Vec<&str> {
+    let mut names: Vec<&str> = enabled_profiles(manifest)
+        .iter()
+        .flat_map(|p| p.payloads.iter().copied())
+        .chain(manifest.payloads.iter().map(String::as_str))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Check that the extra payloads of a manifest exist
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    match manifest
+        .payloads
+        .iter()
+        .find(|name| !Path::new(PAYLOAD_DIR).join(name).is_dir())
+    {
+        Some(name) => Err(format!(
+            "payload {} has no directory in {}/",
+            name, PAYLOAD_DIR
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Cross-compile a payload into static binaries: its C sources into one named after it,
+/// or, with a `Cargo.toml`, its Rust crate's binaries
+fn build_payload(
+    config_name: &str,
+    name: &str,
+    build_dir: &Path,
+    rust_target: Option<&str>,
+    cross_compile_prefix: &Option<String>,
+) -> Option<Vec<PathBuf>> {
+    let source_dir = Path::new(PAYLOAD_DIR).join(name);
+    if source_dir.join("Cargo.toml").is_file() {
+        let Some(target) = rust_target else {
+            eprintln!("No Rust target known for {}", config_name);
+            return None;
+        };
+        return build_cargo_bins(config_name, &source_dir, target, cross_compile_prefix);
+    }
+    let mut sources: Vec<PathBuf> = match fs::read_dir(&source_dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "c"))
+            .collect(),
+        Err(e) => {
+            eprintln!(
+                "Payload {} not found in {}: {}",
+                name,
+                source_dir.display(),
+                e
+            );
+            return None;
+        }
+    };
+    sources.sort();
+
+    // Script-only payloads have nothing to compile
+    if sources.is_empty() {
+        return Some(Vec::new());
+    }
+
+    fs::create_dir_all(build_dir).expect("Failed to create payload build directory");
+    let output = build_dir.join(name);
+    let cc = format!("{}gcc", cross_compile_prefix.as_deref().unwrap_or(""));
+
+    println!("Building payload {} with {}...", name, cc);
+    let status = Command::new(&cc)
+        .args(["-static", "-O2", "-Wall", "-o", &output.to_string_lossy()])
+        .args(&sources)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => Some(vec![output]),
+        Ok(_) => {
+            eprintln!("Failed to build payload {}", name);
+            None
+        }
+        Err(e) => {
+            eprintln!("Failed to run {}: {}", cc, e);
+            None
+        }
+    }
+}
+
+/// Build an out-of-tree kernel module against the configuration's kernel build
+fn build_module(
+    config_name: &str,
+    name: &str,
+    kernel_arch: &str,
+    cross_compile_prefix: &Option<String>,
+) -> Option<PathBuf> {
+    let source_dir = Path::new(MODULE_DIR).join(name);
+    let linux_build_dir = PathBuf::from("build").join(config_name).join("linux");
+    let build_dir = PathBuf::from("build")
+        .join(config_name)
+        .join("modules")
+        .join(name);
+
+    // Kbuild writes objects next to the sources, so build from a copy
+    if build_dir.exists() {
+        fs::remove_dir_all(&build_dir).expect("Failed to remove old module build");
+    }
+    fs::create_dir_all(&build_dir).expect("Failed to create module build directory");
+    let entries = match fs::read_dir(&source_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!(
+                "Module {} not found in {}: {}",
+                name,
+                source_dir.display(),
+                e
+            );
+            return None;
+        }
+    };
+    for entry in entries.flatten() {
+        if entry.path().is_file() {
+            fs::copy(entry.path(), build_dir.join(entry.file_name()))
+                .expect("Failed to copy module source");
+        }
+    }
+
+    let mut make_args = vec![
+        format!("O={}", linux_build_dir.canonicalize().unwrap().display()),
+        format!("M={}", build_dir.canonicalize().unwrap().display()),
+        format!("ARCH={}", kernel_arch),
+        "modules".to_string(),
+    ];
+    if let Some(prefix) = cross_compile_prefix {
+        make_args.push(format!("CROSS_COMPILE={}", prefix));
+    }
+
+    println!("Building kernel module {}...", name);
+    let status = Command::new("make")
+        .current_dir(crate::kernel::source_dir(config_name))
+        .args(&make_args)
+        .status()
+        .expect("Failed to run make for kernel module");
+    if !status.success() {
+        eprintln!("Failed to build kernel module {}", name);
+        return None;
+    }
+
+    Some(build_dir.join(format!("{}.ko", name)))
+}
+
+/// Rust target of the guest: the manifest's `rust_target` or the arch's musl target
+fn rust_target(config_name: &str, manifest: &ConfigManifest) -> Option<String> {
+    match (&manifest.rust_target, crate::arch::arch_of(config_name)) {
+        (Some(target), _) => Some(target.clone()),
+        (None, Some(arch)) => Some(arch.rust_target.to_string()),
+        (None, None) => None,
+    }
+}
+
+/// Cross-build the binaries of a Cargo workspace or crate for the guest
+fn build_cargo_bins(
+    config_name: &str,
+    workspace: &Path,
+    target: &str,
+    cross_compile_prefix: &Option<String>,
+) -> Option<Vec<PathBuf>> {
+    let target_dir = PathBuf::from("build").join(config_name).join("rust");
+    let mut cmd = Command::new("cargo");
+    cmd.args(["build", "--release", "--workspace", "--bins"])
+        .arg("--manifest-path")
+        .arg(workspace.join("Cargo.toml"))
+        .args(["--target", target])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .arg("--message-format=json-render-diagnostics");
+
+    // Link with the cross gcc; musl targets bring their own static libc
+    if let Some(prefix) = cross_compile_prefix {
+        cmd.env(
+            format!(
+                "CARGO_TARGET_{}_LINKER",
+                target.to_uppercase().replace('-', "_")
+            ),
+            format!("{}gcc", prefix),
+        );
+    }
+
+    println!(
+        "Building Rust workspace {} for {}...",
+        workspace.display(),
+        target
+    );
+    let output = match cmd.output() {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Failed to run cargo: {}", e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        eprintln!(
+            "Failed to build Rust workspace {} (is the target installed? rustup target add {})",
+            workspace.display(),
+            target
+        );
+        return None;
+    }
+
+    // Cargo reports every built executable as a compiler artifact
+    let binaries = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .filter_map(|message| message["executable"].as_str().map(PathBuf::from))
+        .collect();
+    Some(binaries)
+}
+
+/// Build the configuration's payloads and install them into the rootfs
+pub fn install_payloads(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    rootfs_dir: &Path,
+    kernel_arch: &str,
+    cross_compile_prefix: &Option<String>,
+) -> bool {
+    let mut modules: Vec<&'static str> = enabled_profiles(manifest)
+        .iter()
+        .flat_map(|p| p.modules.iter().copied())
+        .collect();
+    modules.sort();
+    modules.dedup();
+
+    if !modules.is_empty() {
+        let module_dir = rootfs_dir.join(GUEST_PAYLOAD_DIR).join("modules");
+        fs::create_dir_all(&module_dir).expect("Failed to create module directory in rootfs");
+        for name in modules {
+            let Some(ko) = build_module(config_name, name, kernel_arch, cross_compile_prefix)
+            else {
+                return false;
+            };
+            if manifest.module_signing && !crate::modsign::sign_module(config_name, manifest, &ko) {
+                return false;
+            }
+            fs::copy(&ko, module_dir.join(ko.file_name().unwrap()))
+                .expect("Failed to install kernel module");
+            println!(
+                "Installed kernel module {} into /{}/modules",
+                name, GUEST_PAYLOAD_DIR
+            );
+        }
+    }
+
+    let rust_target = rust_target(config_name, manifest);
+    if let Some(workspace) = &manifest.rust_workspace {
+        let Some(target) = &rust_target else {
+            eprintln!("No Rust target known for {}", config_name);
+            return false;
+        };
+        let Some(binaries) = build_cargo_bins(
+            config_name,
+            Path::new(workspace),
+            target,
+            cross_compile_prefix,
+        ) else {
+            return false;
+        };
+
+        let install_dir = rootfs_dir.join(GUEST_PAYLOAD_DIR);
+        fs::create_dir_all(&install_dir).expect("Failed to create payload directory in rootfs");
+        for binary in binaries {
+            let name = binary.file_name().unwrap();
+            fs::copy(&binary, install_dir.join(name)).expect("Failed to install Rust binary");
+            println!(
+                "Installed Rust binary {} into /{}",
+                name.to_string_lossy(),
+                GUEST_PAYLOAD_DIR
+            );
+        }
+    }
+
+    let names = payload_names(manifest);
+    if names.is_empty() {
+        return true;
+    }
+
+    let build_dir = PathBuf::from("build").join(config_name).join("payloads");
+    let install_dir = rootfs_dir.join(GUEST_PAYLOAD_DIR);
+    let tests_dir = rootfs_dir.join("etc").join("tests.d");
+    fs::create_dir_all(&install_dir).expect("Failed to create payload directory in rootfs");
+    fs::create_dir_all(&tests_dir).expect("Failed to create tests.d directory in rootfs");
+
+    for name in names {
+        let Some(binaries) = build_payload(
+            config_name,
+            name,
+            &build_dir,
+            rust_target.as_deref(),
+            cross_compile_prefix,
+        ) else {
+            return false;
+        };
+        for binary in binaries {
+            fs::copy(&binary, install_dir.join(binary.file_name().unwrap()))
+                .expect("Failed to install payload");
+        }
+
+        // A payload's `test` script runs it from init like any other guest test
+        let test_script = Path::new(PAYLOAD_DIR).join(name).join("test");
+        if test_script.exists() {
+            let dest = tests_dir.join(name);
+            fs::copy(&test_script, &dest).expect("Failed to install payload test");
+            let _ = Command::new("chmod")
+                .args(["+x", &dest.to_string_lossy()])
+                .status();
+        }
+        println!("Installed payload {} into /{}", name, GUEST_PAYLOAD_DIR);
+    }
+
+    true
+}
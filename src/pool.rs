@@ -0,0 +1,476 @@
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::{Console, load_manifest, parse_config_name};
+use crate::qemu::{LaunchOptions, is_built, qemu_command};
+use crate::qmp::Qmp;
+use crate::ssh::{DEFAULT_SSH_PORT, SshChannel, shell_quote};
+
+/// Kernel parameter making init skip the guest tests and start a shell right away
+pub const POOL_PARAM: &str = "guest_test.pool";
+
+/// Directory holding the pool state and each guest's sockets
+const POOL_DIR: &str = "build/pool";
+
+/// How often a booting guest is asked whether its shell is up
+const READY_PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a resumed guest gets to answer on SSH before the console is used instead
+const SSH_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A warm guest, booted to a shell and paused while idle
+#[derive(Debug, Serialize, Deserialize)]
+struct PoolGuest {
+    index: usize,
+    pid: u32,
+    /// Host port forwarded to the guest's dropbear, for configs with `ssh = true`
+    #[serde(default)]
+    ssh_port: Option<u16>,
+}
+
+/// The running pool, stored as `build/pool/pool.json`
+#[derive(Debug, Serialize, Deserialize)]
+struct PoolState {
+    config: String,
+    guests: Vec<PoolGuest>,
+}
+
+fn state_path() -> PathBuf {
+    Path::new(POOL_DIR).join("pool.json")
+}
+
+fn guest_dir(index: usize) -> PathBuf {
+    Path::new(POOL_DIR).join(index.to_string())
+}
+
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+fn read_state() -> Option<PoolState> {
+    let content = fs::read_to_string(state_path()).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Marker text the guest prints, written so the echoed input line never contains it
+fn marker_command(name: &str, token: &str) -> (String, String) {
+    (
+        format!("printf '%s_%s\\n' {} {}", name, token),
+        format!("{}_{}", name, token),
+    )
+}
+
+/// Console of a pool guest, i.e. its serial port on a UNIX socket
+struct PoolConsole {
+    stream: UnixStream,
+    output: String,
+}
+
+impl PoolConsole {
+    fn connect(index: usize) -> Result<PoolConsole, String> {
+        let socket = guest_dir(index).join("console.sock");
+        let stream = UnixStream::connect(&socket)
+            .map_err(|e| format!("connect to {}: {}", socket.display(), e))?;
+        stream
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .map_err(|e| e.to_string())?;
+        Ok(PoolConsole {
+            stream,
+            output: String::new(),
+        })
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        self.stream
+            .write_all(format!("{}\n", line).as_bytes())
+            .map_err(|e| format!("console write: {}", e))
+    }
+
+    /// Read until a complete line containing `pattern` arrived or `deadline` passes
+    fn wait_for(&mut self, pattern: &str, deadline: Instant) -> Result<bool, String> {
+        let mut buf = [0u8; 4096];
+        loop {
+            if let Some(pos) = self.output.find(pattern)
+                && self.output[pos..].contains('\n')
+            {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err("console closed".to_string()),
+                Ok(n) => self.output.push_str(&String::from_utf8_lossy(&buf[..n])),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(format!("console read: {}", e)),
+            }
+        }
+    }
+}
+
+fn qmp(index: usize, timeout: Duration) -> Result<Qmp, String> {
+    Qmp::connect(&guest_dir(index).join("qmp.sock"), timeout)
+}
+
+/// Boot a guest to its shell, then pause it
+fn boot_guest(
+    config_name: &str,
+    index: usize,
+    ssh_port: Option<u16>,
+    timeout: Duration,
+) -> Result<PoolGuest, String> {
+    let dir = guest_dir(index);
+    fs::create_dir_all(&dir).map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    let console = dir.join("console.sock");
+    let qmp_socket = dir.join("qmp.sock");
+    let _ = fs::remove_file(&console);
+    let _ = fs::remove_file(&qmp_socket);
+
+    // -snapshot keeps the guests' writes out of the shared rootfs image
+    let options = LaunchOptions {
+        extra_args: vec![
+            "-snapshot".to_string(),
+            "-serial".to_string(),
+            format!("unix:{},server=on,wait=off", console.display()),
+            "-monitor".to_string(),
+            "none".to_string(),
+        ],
+        extra_cmdline: vec![POOL_PARAM.to_string()],
+        qmp: Some(qmp_socket),
+        ssh_port,
+        ..Default::default()
+    };
+    let mut cmd = qemu_command(config_name, &options)
+        .ok_or_else(|| format!("no QEMU command for {}", config_name))?;
+    // A process group of its own keeps the guest alive after this command returns
+    let child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("failed to start QEMU: {}", e))?;
+    let guest = PoolGuest {
+        index,
+        pid: child.id(),
+        ssh_port,
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut console = loop {
+        match PoolConsole::connect(index) {
+            Ok(console) => break console,
+            Err(e) if Instant::now() >= deadline || !process_alive(guest.pid) => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    };
+    // Input typed before the shell starts waits on the tty, so keep asking until it answers
+    let (probe, ready) = marker_command("POOL_READY", &index.to_string());
+    loop {
+        console.send(&probe)?;
+        let probe_deadline = (Instant::now() + READY_PROBE_INTERVAL).min(deadline);
+        if console.wait_for(&ready, probe_deadline)? {
+            break;
+        }
+        if Instant::now() >= deadline {
+            stop_guest(&guest);
+            return Err(format!("guest {} did not reach its shell", index));
+        }
+    }
+    qmp(index, timeout)?.execute("stop", json!({}))?;
+    Ok(guest)
+}
+
+fn stop_guest(guest: &PoolGuest) {
+    if !process_alive(guest.pid) {
+        return;
+    }
+    let quit = qmp(guest.index, Duration::from_secs(2))
+        .and_then(|mut qmp| qmp.execute("quit", json!({})).map(|_| ()));
+    if quit.is_err() {
+        let _ = std::process::Command::new("kill")
+            .arg(guest.pid.to_string())
+            .status();
+    }
+}
+
+/// Boot `count` guests of a configuration and leave them paused at a shell
+pub fn start(config_name: &str, count: usize, timeout: Duration) -> bool {
+    if let Some(state) = read_state()
+        && state.guests.iter().any(|g| process_alive(g.pid))
+    {
+        eprintln!(
+            "A pool of {} is already running; stop it with `pool stop`",
+            state.config
+        );
+        return false;
+    }
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+    let manifest = match load_manifest(config_name) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    };
+    let (arch, _) = parse_config_name(config_name);
+    if manifest.console(&arch) == Console::Virtio {
+        eprintln!(
+            "pool needs a UART console, {} uses virtio-console",
+            config_name
+        );
+        return false;
+    }
+    // Host services are per configuration, not per guest
+    if manifest.nfsroot || manifest.tpm.is_some() {
+        eprintln!("pool does not support configs with nfsroot or tpm");
+        return false;
+    }
+    if let Err(e) = crate::disks::prepare_disks(config_name, &manifest) {
+        eprintln!("Failed to prepare disks: {}", e);
+        return false;
+    }
+
+    let _ = fs::remove_dir_all(POOL_DIR);
+    let mut state = PoolState {
+        config: config_name.to_string(),
+        guests: Vec::new(),
+    };
+    // Each guest forwards its own port, counting up from the manifest's
+    let ssh_port = manifest
+        .ssh
+        .then(|| manifest.ssh_port.unwrap_or(DEFAULT_SSH_PORT));
+    for index in 0..count {
+        println!("Booting pool guest {} of {}...", index + 1, count);
+        let port = ssh_port.map(|port| port + index as u16);
+        match boot_guest(config_name, index, port, timeout) {
+            Ok(guest) => state.guests.push(guest),
+            Err(e) => {
+                eprintln!("Failed to boot pool guest {}: {}", index, e);
+                state.guests.iter().for_each(stop_guest);
+                return false;
+            }
+        }
+    }
+    let json = serde_json::to_string_pretty(&state).expect("Failed to serialize pool state");
+    if let Err(e) = fs::write(state_path(), json) {
+        eprintln!("Failed to write {}: {}", state_path().display(), e);
+        state.guests.iter().for_each(stop_guest);
+        return false;
+    }
+    println!(
+        "Pool of {} {} guests ready; run commands with `pool exec`",
+        count, config_name
+    );
+    true
+}
+
+/// Claim an idle guest; the lock file holds the claiming process's pid
+fn claim_guest(state: &PoolState) -> Option<(&PoolGuest, PathBuf)> {
+    for guest in state.guests.iter().filter(|g| process_alive(g.pid)) {
+        let lock = guest_dir(guest.index).join("lock");
+        for _ in 0..2 {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock)
+            {
+                Ok(mut file) => {
+                    let _ = write!(file, "{}", std::process::id());
+                    return Some((guest, lock));
+                }
+                // Drop locks of exec processes that died mid-command
+                Err(_) => {
+                    let owner = fs::read_to_string(&lock).ok();
+                    match owner.and_then(|pid| pid.trim().parse().ok()) {
+                        Some(pid) if !process_alive(pid) => {
+                            let _ = fs::remove_file(&lock);
+                        }
+                        _ => break,
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Run a shell command on a running guest's console, returning its output and exit status
+fn console_exec(index: usize, command: &str, timeout: Duration) -> Result<(String, i32), String> {
+    let mut console = PoolConsole::connect(index)?;
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let token = format!("{}x{}", std::process::id(), nanos);
+    let (begin_cmd, begin) = marker_command("BEGIN", &token);
+    let (_, end) = marker_command("END", &token);
+    console.send(&format!(
+        "{}; sh -c {}; printf '%s_%s:%d\\n' END {} $?",
+        begin_cmd,
+        shell_quote(command),
+        token
+    ))?;
+
+    // The end marker line carries the exit status: END_<token>:<status>
+    let end = format!("{}:", end);
+    if !console.wait_for(&end, Instant::now() + timeout)? {
+        return Err(format!("command did not finish within {:?}", timeout));
+    }
+
+    let output = console.output.replace('\r', "");
+    let after_begin = output
+        .split_once(&format!("{}\n", begin))
+        .map_or("", |(_, rest)| rest);
+    let (body, status) = after_begin.split_once(&end).unwrap_or((after_begin, "1"));
+    let status = status
+        .lines()
+        .next()
+        .unwrap_or("1")
+        .trim()
+        .parse()
+        .unwrap_or(1);
+    Ok((body.to_string(), status))
+}
+
+/// SSH channel to a resumed guest, if its config runs dropbear and it answers
+fn ssh_channel(config_name: &str, guest: &PoolGuest) -> Option<SshChannel> {
+    let port = guest.ssh_port?;
+    match SshChannel::connect(config_name, port, SSH_CONNECT_TIMEOUT) {
+        Ok(channel) => Some(channel),
+        Err(e) => {
+            eprintln!(
+                "pool guest {}: {}; falling back to the console",
+                guest.index, e
+            );
+            None
+        }
+    }
+}
+
+/// Resume a claimed guest for `action` and pause it again afterwards
+fn with_guest<T>(
+    guest: &PoolGuest,
+    action: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    let mut qmp = qmp(guest.index, Duration::from_secs(5))?;
+    qmp.execute("cont", json!({}))?;
+    let result = action();
+    let _ = qmp.execute("stop", json!({}));
+    result
+}
+
+/// Run `action` on an idle pool guest, holding its lock meanwhile
+fn on_idle_guest<T>(
+    action: impl FnOnce(&str, &PoolGuest) -> Result<T, String>,
+) -> Result<T, String> {
+    let state = read_state().ok_or("no pool is running; start one with `pool start <config>`")?;
+    let (guest, lock) =
+        claim_guest(&state).ok_or_else(|| format!("no idle guest in the {} pool", state.config))?;
+    let result = with_guest(guest, || action(&state.config, guest))
+        .map_err(|e| format!("pool guest {}: {}", guest.index, e));
+    let _ = fs::remove_file(lock);
+    result
+}
+
+/// Run a command on an idle pool guest and print its output; returns its exit status
+///
+/// Guests with dropbear run it over SSH, which keeps binary output intact; the
+/// console is the fallback.
+pub fn exec(command: &str, timeout: Duration) -> i32 {
+    let result = on_idle_guest(|config_name, guest| match ssh_channel(config_name, guest) {
+        Some(channel) => channel.exec(command, timeout),
+        None => console_exec(guest.index, command, timeout)
+            .map(|(output, status)| (output.into_bytes(), status)),
+    });
+    match result {
+        Ok((output, status)) => {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(&output).and_then(|_| stdout.flush());
+            status
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Decode `od -An -v -tx1` output back into bytes
+fn decode_od(dump: &str) -> Result<Vec<u8>, String> {
+    dump.split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("bad od output {:?}", byte)))
+        .collect()
+}
+
+/// Copy a file out of an idle pool guest, over SSH or else as a hex dump on the console
+pub fn fetch(remote: &str, local: &Path, timeout: Duration) -> bool {
+    let result = on_idle_guest(|config_name, guest| {
+        if let Some(channel) = ssh_channel(config_name, guest) {
+            return channel.fetch(remote, local, timeout);
+        }
+        let command = format!("od -An -v -tx1 {}", shell_quote(remote));
+        let (dump, status) = console_exec(guest.index, &command, timeout)?;
+        if status != 0 {
+            return Err(format!("cannot read {} in the guest", remote));
+        }
+        let data = decode_od(&dump)?;
+        fs::write(local, &data)
+            .map_err(|e| format!("failed to write {}: {}", local.display(), e))?;
+        Ok(data.len() as u64)
+    });
+    match result {
+        Ok(size) => {
+            println!("Copied {} ({} bytes) to {}", remote, size, local.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    }
+}
+
+/// Print the guests of the pool and whether they are idle
+pub fn status() {
+    let Some(state) = read_state() else {
+        println!("No pool is running.");
+        return;
+    };
+    println!("Pool of {}:", state.config);
+    for guest in &state.guests {
+        let lock = guest_dir(guest.index).join("lock");
+        let status = if !process_alive(guest.pid) {
+            "exited"
+        } else if lock.exists() {
+            "busy"
+        } else {
+            "idle"
+        };
+        println!("  guest {}  pid {:<8}  {}", guest.index, guest.pid, status);
+    }
+}
+
+/// Shut down every pool guest
+pub fn stop() {
+    let Some(state) = read_state() else {
+        println!("No pool is running.");
+        return;
+    };
+    state.guests.iter().for_each(stop_guest);
+    let _ = fs::remove_dir_all(POOL_DIR);
+    println!("Stopped the {} pool", state.config);
+}
@@ -0,0 +1,210 @@
+use crate::config::ConfigManifest;
+
+/// An opt-in bundle of kernel options and guest payloads for a test area
+pub struct Profile {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Kernel options enabled by the profile
+    pub kernel_options: &'static [&'static str],
+    /// Kernel command line parameters added by the profile
+    pub cmdline: &'static [&'static str],
+    /// Payloads under `payloads/` built and installed into the rootfs
+    pub payloads: &'static [&'static str],
+    /// Out-of-tree kernel modules under `modules/` built against the guest kernel
+    pub modules: &'static [&'static str],
+}
+
+/// All available profiles
+pub const PROFILES: &[Profile] = &[
+    Profile {
+        name: "ivshmem",
+        description: "inter-VM shared memory device with read/write/latency/doorbell checks",
+        kernel_options: &["CONFIG_PCI", "CONFIG_UIO", "CONFIG_UIO_PCI_GENERIC"],
+        cmdline: &[],
+        payloads: &["ivshmem-test"],
+        modules: &[],
+    },
+    Profile {
+        name: "intervm",
+        description: "two guests on a virtual network running ping/ordering/throughput tests",
+        kernel_options: &["CONFIG_NET", "CONFIG_INET", "CONFIG_VIRTIO_NET"],
+        cmdline: &[],
+        payloads: &["intervm"],
+        modules: &[],
+    },
+    Profile {
+        name: "crash",
+        description: "guest panic injection through sysrq with pvpanic notification",
+        kernel_options: &[
+            "CONFIG_MAGIC_SYSRQ",
+            "CONFIG_PVPANIC",
+            "CONFIG_PVPANIC_MMIO",
+            "CONFIG_PVPANIC_PCI",
+        ],
+        cmdline: &[],
+        payloads: &["crash-recovery"],
+        modules: &[],
+    },
+    Profile {
+        name: "debug",
+        description: "magic sysrq over the console for debugging stuck guests",
+        kernel_options: &["CONFIG_MAGIC_SYSRQ", "CONFIG_MAGIC_SYSRQ_SERIAL"],
+        cmdline: &["sysrq_always_enabled"],
+        payloads: &[],
+        modules: &[],
+    },
+    Profile {
+        name: "module-stress",
+        description: "load/unload a test module in a loop to stress stage-2 and I-cache paths",
+        kernel_options: &["CONFIG_MODULES", "CONFIG_MODULE_UNLOAD"],
+        cmdline: &[],
+        payloads: &["module-stress"],
+        modules: &["stress_mod"],
+    },
+    Profile {
+        name: "mmio-stress",
+        description: "trap-and-emulate benchmark of PCI config space and RTC accesses",
+        // Options for another arch's RTC are dropped by olddefconfig
+        kernel_options: &[
+            "CONFIG_PCI",
+            "CONFIG_RTC_CLASS",
+            "CONFIG_RTC_INTF_DEV",
+            "CONFIG_RTC_DRV_PL031",
+            "CONFIG_RTC_DRV_CMOS",
+        ],
+        cmdline: &[],
+        payloads: &["mmio-stress"],
+        modules: &[],
+    },
+    Profile {
+        name: "dma-io",
+        description: "block and network I/O checks for swiotlb and restricted DMA variants",
+        kernel_options: &[
+            "CONFIG_PCI",
+            "CONFIG_VIRTIO_PCI",
+            "CONFIG_NET",
+            "CONFIG_INET",
+            "CONFIG_VIRTIO_NET",
+            "CONFIG_DEBUG_FS",
+        ],
+        cmdline: &[],
+        payloads: &["dma-io"],
+        modules: &[],
+    },
+    Profile {
+        name: "virtio-snd",
+        description: "tone playback and capture through virtio-snd, checked in the host backend",
+        kernel_options: &[
+            "CONFIG_PCI",
+            "CONFIG_VIRTIO_PCI",
+            "CONFIG_SOUND",
+            "CONFIG_SND",
+            "CONFIG_SND_VIRTIO",
+        ],
+        cmdline: &[],
+        payloads: &["virtio-snd"],
+        modules: &[],
+    },
+    Profile {
+        name: "virtio-input",
+        description: "keyboard and mouse events injected over QMP, checked through evdev",
+        kernel_options: &[
+            "CONFIG_PCI",
+            "CONFIG_VIRTIO_PCI",
+            "CONFIG_INPUT",
+            "CONFIG_INPUT_EVDEV",
+            "CONFIG_VIRTIO_INPUT",
+        ],
+        cmdline: &[],
+        payloads: &["virtio-input"],
+        modules: &[],
+    },
+    Profile {
+        name: "fs-robustness",
+        description: "journal recovery on a scratch disk after a kill or disk yank mid-write",
+        kernel_options: &[
+            "CONFIG_PCI",
+            "CONFIG_VIRTIO_PCI",
+            "CONFIG_VIRTIO_BLK",
+            "CONFIG_EXT4_FS",
+        ],
+        cmdline: &[],
+        payloads: &["fs-robustness"],
+        modules: &[],
+    },
+    Profile {
+        name: "virtio-mem",
+        description: "memory plugged and unplugged at runtime through a virtio-mem device",
+        kernel_options: &[
+            "CONFIG_PCI",
+            "CONFIG_PCI_HOST_GENERIC",
+            "CONFIG_VIRTIO_PCI",
+            "CONFIG_MEMORY_HOTPLUG",
+            "CONFIG_MEMORY_HOTREMOVE",
+            "CONFIG_MIGRATION",
+            "CONFIG_CONTIG_ALLOC",
+            "CONFIG_VIRTIO_MEM",
+        ],
+        // Movable blocks can always be offlined again for unplug
+        cmdline: &["memhp_default_state=online_movable"],
+        payloads: &["virtio-mem"],
+        modules: &[],
+    },
+    Profile {
+        name: "oom",
+        description: "cgroup and global OOM kills with a ballooned guest that must stay responsive",
+        kernel_options: &[
+            "CONFIG_CGROUPS",
+            "CONFIG_MEMCG",
+            "CONFIG_PCI",
+            "CONFIG_VIRTIO_PCI",
+            "CONFIG_VIRTIO_MMIO",
+            "CONFIG_VIRTIO_BALLOON",
+        ],
+        cmdline: &[],
+        payloads: &["oom"],
+        modules: &[],
+    },
+    Profile {
+        name: "cpu-hotplug",
+        description: "vCPUs added and removed at runtime while busy workers keep running",
+        kernel_options: &[
+            "CONFIG_SMP",
+            "CONFIG_HOTPLUG_CPU",
+            "CONFIG_ACPI_HOTPLUG_CPU",
+        ],
+        cmdline: &[],
+        payloads: &["cpu-hotplug"],
+        modules: &[],
+    },
+    Profile {
+        name: "ima",
+        description: "IMA appraisal and EVM with a signed rootfs and a generated policy",
+        kernel_options: crate::ima::IMA_KERNEL_OPTIONS,
+        cmdline: &[],
+        payloads: &["ima"],
+        modules: &[],
+    },
+];
+
+/// Look up a profile by name
+pub fn find_profile(name: &str) -> Option<&'static Profile> {
+    PROFILES.iter().find(|p| p.name == name)
+}
+
+/// Get the profiles enabled by a config manifest
+pub fn enabled_profiles(manifest: &ConfigManifest) -> Vec<&'static Profile> {
+    manifest
+        .profiles
+        .iter()
+        .filter_map(|name| find_profile(name))
+        .collect()
+}
+
+/// Print all available profiles
+pub fn list_profiles() {
+    println!("Available profiles:");
+    for profile in PROFILES {
+        println!("  {:<14} {}", profile.name, profile.description);
+    }
+}
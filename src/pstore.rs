@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::config::ConfigManifest;
+use crate::memory::{ARM64_RAM_BASE, RISCV64_RAM_BASE, format_size, memory_regions, memory_size};
+
+/// Kernel options for ramoops-backed pstore with the console log kept as well
+pub const KERNEL_OPTIONS: &[&str] = &[
+    "CONFIG_PSTORE",
+    "CONFIG_PSTORE_RAM",
+    "CONFIG_PSTORE_CONSOLE",
+];
+
+/// Size of the ramoops region at the top of guest RAM
+const REGION_SIZE: u64 = 1024 * 1024;
+/// Size of one oops/panic record
+const RECORD_SIZE: u64 = 128 * 1024;
+/// Size of the console log of the previous boot
+const CONSOLE_SIZE: u64 = 256 * 1024;
+
+/// q35 keeps at most 2G below the PCI hole once the guest has 2.75G or more
+const X86_LOWMEM_SPLIT: u64 = 0xb000_0000;
+const X86_LOWMEM_TOP: u64 = 0x8000_0000;
+
+/// Lines the pstore hook prints around each record it replays, base64-encoded so a
+/// previous boot's markers and panic messages do not count for this one
+pub const BEGIN_MARKER: &str = "pstore: begin ";
+pub const END_MARKER: &str = "pstore: end ";
+
+/// Guest physical address of the ramoops region: the last `REGION_SIZE` bytes of RAM,
+/// of the last declared region with a memory map
+fn region_base(arch: &str, manifest: &ConfigManifest) -> Result<u64, String> {
+    let top = match memory_regions(manifest)?.last() {
+        Some((base, size)) => base + size,
+        None => {
+            let memory = memory_size(manifest)?;
+            match arch {
+                "arm64" => ARM64_RAM_BASE + memory,
+                "riscv64" => RISCV64_RAM_BASE + memory,
+                _ if memory >= X86_LOWMEM_SPLIT => X86_LOWMEM_TOP,
+                _ => memory,
+            }
+        }
+    };
+    Ok(top - REGION_SIZE)
+}
+
+/// Check that the ramoops region fits in the guest's RAM
+pub fn validate(manifest: &ConfigManifest, arch: &str) -> Result<(), String> {
+    if !manifest.pstore {
+        return Ok(());
+    }
+    // The region is placed from the RAM layout of the arch's QEMU machine
+    if !matches!(arch, "arm64" | "x86" | "riscv64") {
+        return Err(format!("pstore is not supported on {}", arch));
+    }
+    let last_region = match memory_regions(manifest)?.last() {
+        Some((_, size)) => *size,
+        None => memory_size(manifest)?,
+    };
+    if last_region < 16 * REGION_SIZE {
+        return Err(format!(
+            "pstore needs at least {} in the last memory region",
+            format_size(16 * REGION_SIZE)
+        ));
+    }
+    Ok(())
+}
+
+/// Kernel parameters placing ramoops at the top of guest RAM and keeping the kernel off it
+///
+/// x86 reserves the region in the e820 map; devicetree arches cap RAM below it with `mem=`,
+/// which comes after the memory map's own `mem=` and so wins.
+pub fn cmdline_params(arch: &str, manifest: &ConfigManifest) -> Vec<String> {
+    if !manifest.pstore {
+        return Vec::new();
+    }
+    let Ok(base) = region_base(arch, manifest) else {
+        return Vec::new();
+    };
+    let mut params = vec![
+        format!("ramoops.mem_address={:#x}", base),
+        format!("ramoops.mem_size={:#x}", REGION_SIZE),
+        format!("ramoops.record_size={:#x}", RECORD_SIZE),
+        format!("ramoops.console_size={:#x}", CONSOLE_SIZE),
+        "ramoops.ecc=1".to_string(),
+    ];
+    if arch == "x86" {
+        params.push(format!("memmap={}${:#x}", format_size(REGION_SIZE), base));
+    } else {
+        let total = match memory_regions(manifest) {
+            Ok(regions) if !regions.is_empty() => regions.iter().map(|(_, size)| size).sum(),
+            _ => memory_size(manifest).unwrap_or(crate::memory::DEFAULT_MEMORY),
+        };
+        params.push(format!("mem={}", format_size(total - REGION_SIZE)));
+    }
+    params
+}
+
+/// Records of an earlier boot that the guest replayed on the console, as (name, content)
+pub fn records(console: &str) -> Vec<(String, Vec<u8>)> {
+    let mut records = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    for line in console.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix(BEGIN_MARKER) {
+            current = Some((name.to_string(), String::new()));
+        } else if line.starts_with(END_MARKER) {
+            let Some((name, encoded)) = current.take() else {
+                continue;
+            };
+            match STANDARD.decode(&encoded) {
+                Ok(content) => records.push((name, content)),
+                Err(e) => eprintln!("Ignoring garbled pstore record {}: {}", name, e),
+            }
+        } else if let Some((_, encoded)) = &mut current {
+            encoded.push_str(line);
+        }
+    }
+    records
+}
+
+/// Write replayed records to `dir`, returning the written paths
+pub fn save_records(dir: &Path, records: &[(String, Vec<u8>)]) -> Vec<PathBuf> {
+    if records.is_empty() {
+        return Vec::new();
+    }
+    if let Err(e) = fs::create_dir_all(dir) {
+        eprintln!("Failed to create {}: {}", dir.display(), e);
+        return Vec::new();
+    }
+    let mut saved = Vec::new();
+    for (name, content) in records {
+        // Record names come from the guest; keep them inside the directory
+        let name = name.replace(['/', '\\'], "_");
+        if name.is_empty() || name.starts_with('.') {
+            continue;
+        }
+        let path = dir.join(name);
+        match fs::write(&path, content) {
+            Ok(()) => saved.push(path),
+            Err(e) => eprintln!("Failed to write {}: {}", path.display(), e),
+        }
+    }
+    saved
+}
+
+/// Names of replayed records, for one-line summaries
+pub fn record_names(records: &[(String, Vec<u8>)]) -> String {
+    records
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
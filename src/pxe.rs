@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::arch::arch_of;
+use crate::cmdline::kernel_cmdline;
+use crate::config::load_manifest;
+use crate::qemu::{is_built, kernel_image_path};
+
+/// Name of the initramfs in the TFTP layout
+const INITRAMFS_NAME: &str = "initramfs.cpio.gz";
+
+/// Kernel command line for a network-booted guest
+///
+/// Without NFS root the rootfs comes along as the initramfs instead of a disk.
+fn pxe_cmdline(nfsroot: bool, cmdline: &str) -> String {
+    if nfsroot {
+        return cmdline.to_string();
+    }
+    let mut params: Vec<&str> = cmdline
+        .split_whitespace()
+        .filter(|p| !p.starts_with("root=") && !p.starts_with("init="))
+        .collect();
+    params.push("rdinit=/init");
+    params.join(" ")
+}
+
+/// Lay out the kernel, initramfs and an iPXE script under a TFTP root, plus a dnsmasq snippet
+///
+/// Files go to `<tftp_root>/<config>/` so several configs can share one TFTP root.
+pub fn export_pxe(config_name: &str, tftp_root: Option<&Path>) -> bool {
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+    let (Some(arch_info), Some(kernel)) = (arch_of(config_name), kernel_image_path(config_name))
+    else {
+        eprintln!("Unsupported architecture for {}", config_name);
+        return false;
+    };
+    let manifest = match load_manifest(config_name) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    };
+
+    let tftp_root = match tftp_root {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("build").join(config_name).join("tftp"),
+    };
+    let dir = tftp_root.join(config_name);
+    fs::create_dir_all(&dir).expect("Failed to create TFTP directory");
+
+    let image = arch_info.image_name();
+    if let Err(e) = fs::copy(&kernel, dir.join(image)) {
+        eprintln!("Failed to copy {}: {}", kernel.display(), e);
+        return false;
+    }
+
+    // iPXE resolves relative names against the script's own URL
+    let cmdline = pxe_cmdline(manifest.nfsroot, &kernel_cmdline(config_name, &manifest));
+    let mut script = format!(
+        "#!ipxe\n# Network boot of {}, generated by guest-test-linux\n\nkernel {} {}\n",
+        config_name, image, cmdline
+    );
+    if !manifest.nfsroot {
+        let rootfs_dir = PathBuf::from("build").join(config_name).join("rootfs");
+        if !crate::rootfs::create_initramfs(&rootfs_dir, &dir.join(INITRAMFS_NAME)) {
+            eprintln!("Failed to create initramfs for {}", config_name);
+            return false;
+        }
+        script.push_str(&format!("initrd {}\n", INITRAMFS_NAME));
+    }
+    script.push_str("boot\n");
+    let script_path = dir.join("boot.ipxe");
+    fs::write(&script_path, script).expect("Failed to write iPXE script");
+
+    // Firmware without iPXE chainloads it over TFTP first, then gets the script
+    let absolute_root = tftp_root
+        .canonicalize()
+        .expect("Failed to resolve TFTP root");
+    let snippet = format!(
+        "# dnsmasq snippet for network booting {config}, generated by guest-test-linux\n\
+         enable-tftp\n\
+         tftp-root={root}\n\
+         dhcp-match=set:ipxe,175\n\
+         dhcp-boot=tag:!ipxe,{ipxe}\n\
+         dhcp-boot=tag:ipxe,{config}/boot.ipxe\n",
+        config = config_name,
+        root = absolute_root.display(),
+        ipxe = arch_info.ipxe_binary,
+    );
+    let snippet_path = PathBuf::from("build")
+        .join(config_name)
+        .join("dnsmasq-pxe.conf");
+    fs::write(&snippet_path, snippet).expect("Failed to write dnsmasq snippet");
+
+    println!("Network boot files written to {}", dir.display());
+    println!("  iPXE script:     {}", script_path.display());
+    println!("  dnsmasq snippet: {}", snippet_path.display());
+    println!(
+        "  (place {} from the iPXE project in {})",
+        arch_info.ipxe_binary,
+        tftp_root.display()
+    );
+    true
+}
@@ -0,0 +1,350 @@
+use std::path::PathBuf;
+use std::process::{Child, Command};
+
+use crate::arch::{Arch, arch_of};
+use crate::backend::BackendKind;
+use crate::cmdline::kernel_cmdline;
+use crate::config::{ConfigManifest, Console, load_manifest, parse_config_name};
+use crate::harness::GuestSession;
+use crate::httpd::DEFAULT_HTTPD_PORT;
+use crate::memory::{DEFAULT_MEMORY, format_size, memory_size};
+use crate::ssh::DEFAULT_SSH_PORT;
+
+/// Per-launch additions to the QEMU command of a configuration
+#[derive(Debug, Default, Clone)]
+pub struct LaunchOptions {
+    /// Connect the ivshmem device to this ivshmem-server socket instead of a shared file
+    pub ivshmem_server: Option<PathBuf>,
+    /// Extra QEMU arguments, e.g. additional network devices
+    pub extra_args: Vec<String>,
+    /// Extra kernel command line parameters
+    pub extra_cmdline: Vec<String>,
+    /// Let the guest reboot instead of exiting QEMU on reset
+    pub reboot: bool,
+    /// Boot this kernel image instead of the configuration's own
+    pub kernel: Option<PathBuf>,
+    /// Serve QMP on this UNIX socket, e.g. for screendumps
+    pub qmp: Option<PathBuf>,
+    /// Serve a second QMP monitor on this UNIX socket, for recording events
+    pub qmp_events: Option<PathBuf>,
+    /// Hypervisor frontend that boots the guest
+    pub backend: BackendKind,
+    /// Binary (or script) of the backend instead of its default, e.g. a QEMU wrapper
+    pub binary: Option<PathBuf>,
+    /// Forward this host port to the guest's dropbear instead of the manifest's
+    pub ssh_port: Option<u16>,
+    /// Forward this host port to the guest's httpd instead of the manifest's
+    pub httpd_port: Option<u16>,
+}
+
+/// Get the kernel image path produced by the build for a configuration
+pub fn kernel_image_path(config_name: &str) -> Option<PathBuf> {
+    let arch = arch_of(config_name)?;
+    Some(
+        PathBuf::from("build")
+            .join(config_name)
+            .join(arch.image_name()),
+    )
+}
+
+/// Get the rootfs image path produced by the build for a configuration
+pub fn rootfs_image_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("rootfs.img")
+}
+
+/// Get the initramfs produced by the build for configurations with `initramfs = true`
+pub fn initramfs_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("initramfs.cpio.gz")
+}
+
+/// Check if both the kernel image and the rootfs image (or initramfs) exist for a configuration
+pub fn is_built(config_name: &str) -> bool {
+    kernel_image_path(config_name).is_some_and(|p| p.exists())
+        && (rootfs_image_path(config_name).exists() || initramfs_path(config_name).exists())
+}
+
+/// `-machine` value of a configuration: the arch's machine and its GIC version
+pub fn machine_arg(arch_info: &Arch, manifest: &ConfigManifest) -> String {
+    let mut machine = arch_info.qemu_machine.to_string();
+    if arch_info.gic {
+        machine.push_str(&format!(",gic-version={}", manifest.gic.unwrap_or(3)));
+    }
+    machine
+}
+
+/// Build the QEMU command that boots a built configuration with the serial console on stdio
+pub fn qemu_command(config_name: &str, options: &LaunchOptions) -> Option<Command> {
+    let (arch, _) = parse_config_name(config_name);
+    let kernel = match &options.kernel {
+        Some(kernel) => kernel.clone(),
+        None => kernel_image_path(config_name)?,
+    };
+    let rootfs = rootfs_image_path(config_name);
+    let manifest = match load_manifest(config_name) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return None;
+        }
+    };
+    if manifest.board.is_some() {
+        eprintln!(
+            "{} targets a board, not QEMU; write it to an SD card with `flash`",
+            config_name
+        );
+        return None;
+    }
+
+    let mut cmdline = kernel_cmdline(config_name, &manifest);
+    for param in &options.extra_cmdline {
+        cmdline.push(' ');
+        cmdline.push_str(param);
+    }
+    let memory = memory_size(&manifest).unwrap_or(DEFAULT_MEMORY);
+    let virtio_mem = manifest.profiles.iter().any(|p| p == "virtio-mem");
+    let mut memory_arg = format!("size={}", format_size(memory));
+    if virtio_mem {
+        memory_arg.push_str(&format!(
+            ",maxmem={}",
+            format_size(memory + crate::virtio_mem::REGION_SIZE)
+        ));
+    }
+
+    let Some(arch_info) = arch_of(config_name) else {
+        eprintln!("Unsupported architecture for QEMU: {}", arch);
+        return None;
+    };
+    let machine = machine_arg(arch_info, &manifest);
+    let virtio_serial = format!("virtio-serial-{}", arch_info.virtio_suffix);
+
+    let mut cmd = match &options.binary {
+        Some(binary) => Command::new(binary),
+        None => Command::new(arch_info.qemu_binary),
+    };
+    cmd.args([
+        "-machine",
+        &machine,
+        "-nographic",
+        "-m",
+        &memory_arg,
+        "-cpu",
+        arch_info.qemu_cpu,
+        "-smp",
+        &crate::cpu_hotplug::smp_arg(&arch, &manifest),
+        "-kernel",
+        &kernel.to_string_lossy(),
+        "-append",
+        &cmdline,
+    ]);
+    if manifest.initramfs {
+        // An embedded initramfs comes along with the kernel image
+        if !manifest.initramfs_embed {
+            cmd.args(["-initrd", &initramfs_path(config_name).to_string_lossy()]);
+        }
+    } else if !manifest.nfsroot {
+        // The image may predate a change of image_format in the manifest
+        let built = crate::manifest::read_manifest(config_name)
+            .and_then(|build| build.image_format)
+            .unwrap_or(manifest.image_format);
+        if built != manifest.image_format {
+            eprintln!(
+                "Warning: rootfs.img is {} but the manifest asks for {}, rebuild with --only rootfs",
+                built.name(),
+                manifest.image_format.name()
+            );
+        }
+        let mut drive = format!("format=raw,file={}", rootfs.display());
+        if manifest.readonly_root || built.read_only() {
+            drive.push_str(",readonly=on");
+        }
+        cmd.args(["-drive", &drive]);
+    }
+    cmd.args(crate::disks::qemu_args(config_name, &manifest));
+    cmd.args(crate::clock::qemu_args(&manifest));
+    cmd.args(crate::tpm::qemu_args(config_name, &manifest));
+    cmd.args(crate::firmware::qemu_args(config_name, &manifest));
+    if !options.reboot {
+        cmd.arg("-no-reboot");
+    }
+    cmd.args(&manifest.qemu_args);
+    cmd.args(&options.extra_args);
+
+    // UART consoles use the default serial port on stdio; virtio-console
+    // takes over stdio so the serial port and monitor are disabled
+    if manifest.console(&arch) == Console::Virtio {
+        cmd.args([
+            "-serial",
+            "none",
+            "-monitor",
+            "none",
+            "-chardev",
+            "stdio,id=con0",
+            "-device",
+            &virtio_serial,
+            "-device",
+            "virtconsole,chardev=con0",
+        ]);
+    }
+
+    if let Some(display) = manifest.display {
+        cmd.args(["-device", display.qemu_device()]);
+    }
+    for socket in [&options.qmp, &options.qmp_events].into_iter().flatten() {
+        cmd.args([
+            "-qmp",
+            &format!("unix:{},server=on,wait=off", socket.display()),
+        ]);
+    }
+
+    // A merged DTB carries overlays such as the restricted DMA pool
+    let dtb = crate::dtb::merged_dtb_path(config_name);
+    if dtb.exists() {
+        cmd.args(["-dtb", &dtb.to_string_lossy()]);
+    }
+
+    // User-mode networking gives the DMA test a peer to exchange traffic with,
+    // reaches the host's NFS server and forwards host ports to httpd and dropbear
+    if manifest.httpd
+        || manifest.ssh
+        || manifest.nfsroot
+        || manifest.profiles.iter().any(|p| p == "dma-io")
+    {
+        let mut netdev = "user,id=net0".to_string();
+        if manifest.httpd {
+            netdev.push_str(&format!(
+                ",hostfwd=tcp:127.0.0.1:{}-:80",
+                options
+                    .httpd_port
+                    .or(manifest.httpd_port)
+                    .unwrap_or(DEFAULT_HTTPD_PORT)
+            ));
+        }
+        if manifest.ssh {
+            netdev.push_str(&format!(
+                ",hostfwd=tcp:127.0.0.1:{}-:22",
+                options
+                    .ssh_port
+                    .or(manifest.ssh_port)
+                    .unwrap_or(DEFAULT_SSH_PORT)
+            ));
+        }
+        cmd.args(["-netdev", &netdev, "-device", "virtio-net-pci,netdev=net0"]);
+    }
+
+    // The wav backend records playback for the host-side tone check
+    if manifest.profiles.iter().any(|p| p == "virtio-snd") {
+        cmd.args([
+            "-audiodev",
+            &format!(
+                "wav,id=snd0,path={}",
+                crate::sound::wav_path(config_name).display()
+            ),
+            "-device",
+            "virtio-sound-pci,audiodev=snd0",
+        ]);
+    }
+
+    if manifest.profiles.iter().any(|p| p == "virtio-input") {
+        for device in crate::input::QEMU_DEVICES {
+            cmd.args(["-device", device]);
+        }
+    }
+
+    if virtio_mem {
+        cmd.args(crate::virtio_mem::qemu_args());
+    }
+
+    if manifest.profiles.iter().any(|p| p == "oom") {
+        cmd.args(crate::oom::qemu_args(arch_info));
+    }
+
+    if manifest.profiles.iter().any(|p| p == "ivshmem") {
+        match &options.ivshmem_server {
+            Some(socket) => cmd.args([
+                "-chardev",
+                &format!("socket,path={},id=ivshmem", socket.display()),
+                "-device",
+                "ivshmem-doorbell,chardev=ivshmem,vectors=1",
+            ]),
+            None => cmd.args([
+                "-object",
+                &format!(
+                    "memory-backend-file,id=ivshmem,share=on,mem-path={},size={}",
+                    crate::ivshmem::shm_path(config_name).display(),
+                    crate::ivshmem::IVSHMEM_SIZE
+                ),
+                "-device",
+                "ivshmem-plain,memdev=ivshmem",
+            ]),
+        };
+    }
+
+    Some(cmd)
+}
+
+/// Prepare disks and start the host services a configuration's guest needs
+fn start_helpers(config_name: &str) -> Result<Vec<Child>, String> {
+    let manifest = load_manifest(config_name)?;
+    crate::disks::prepare_disks(config_name, &manifest)?;
+    let nfs_server = crate::nfs::start_server(config_name, &manifest)?;
+    let swtpm = crate::tpm::start_swtpm(config_name, &manifest)?;
+    Ok(nfs_server.into_iter().chain(swtpm).collect())
+}
+
+/// Boot a built configuration with the launch's backend, along with the host services it needs
+pub fn launch(config_name: &str, options: &LaunchOptions) -> Result<GuestSession, String> {
+    let cmd = crate::backend::command(config_name, options)?;
+    let helpers = start_helpers(config_name)?;
+
+    let mut session = GuestSession::spawn(cmd).map_err(|e| e.to_string())?;
+    for helper in helpers {
+        session.attach_helper(helper);
+    }
+    Ok(session)
+}
+
+/// Boot a built configuration with its console on this terminal until the hypervisor exits
+pub fn run(config_name: &str, options: &LaunchOptions) -> bool {
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+    let mut cmd = match crate::backend::command(config_name, options) {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    let mut helpers = match start_helpers(config_name) {
+        Ok(helpers) => helpers,
+        Err(e) => {
+            eprintln!("Failed to start host services: {}", e);
+            return false;
+        }
+    };
+
+    if options.backend == BackendKind::Qemu {
+        println!("Booting {} (Ctrl-A x quits QEMU)...", config_name);
+    } else {
+        println!("Booting {}...", config_name);
+    }
+    let status = cmd.status();
+    for helper in &mut helpers {
+        let _ = helper.kill();
+        let _ = helper.wait();
+    }
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            eprintln!("Hypervisor exited with {}", status);
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run the hypervisor: {}", e);
+            false
+        }
+    }
+}
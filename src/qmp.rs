@@ -0,0 +1,121 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+/// A QMP connection to a running QEMU
+pub struct Qmp {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Qmp {
+    /// Connect to a QMP socket, retrying while QEMU starts up, and leave negotiation mode
+    pub fn connect(socket: &Path, timeout: Duration) -> Result<Qmp, String> {
+        let deadline = Instant::now() + timeout;
+        let stream = loop {
+            match UnixStream::connect(socket) {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() >= deadline => {
+                    return Err(format!("connect to {}: {}", socket.display(), e));
+                }
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        };
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .map_err(|e| e.to_string())?;
+
+        let writer = stream.try_clone().map_err(|e| e.to_string())?;
+        let mut qmp = Qmp {
+            reader: BufReader::new(stream),
+            writer,
+        };
+        // The greeting announces the QEMU version and capabilities
+        qmp.read_message()?;
+        qmp.execute("qmp_capabilities", json!({}))?;
+        Ok(qmp)
+    }
+
+    /// Read one JSON message
+    fn read_message(&mut self) -> Result<Value, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Err("QMP connection closed".to_string()),
+            Ok(_) => serde_json::from_str(&line).map_err(|e| format!("bad QMP message: {}", e)),
+            Err(e) => Err(format!("QMP read: {}", e)),
+        }
+    }
+
+    /// Run a command and return its result, skipping asynchronous events
+    pub fn execute(&mut self, command: &str, arguments: Value) -> Result<Value, String> {
+        let request = json!({ "execute": command, "arguments": arguments });
+        writeln!(self.writer, "{}", request).map_err(|e| format!("QMP write: {}", e))?;
+
+        loop {
+            let message = self.read_message()?;
+            if let Some(result) = message.get("return") {
+                return Ok(result.clone());
+            }
+            if let Some(error) = message.get("error") {
+                return Err(format!(
+                    "{} failed: {}",
+                    command,
+                    error["desc"].as_str().unwrap_or("unknown error")
+                ));
+            }
+        }
+    }
+
+    /// Write every event as one JSON line until QEMU closes the connection
+    ///
+    /// Meant for a monitor of its own: commands on the same connection would miss their events.
+    pub fn record_events(mut self, out: &mut impl Write) -> Result<(), String> {
+        self.reader
+            .get_ref()
+            .set_read_timeout(None)
+            .map_err(|e| e.to_string())?;
+        loop {
+            let message = match self.read_message() {
+                Ok(message) => message,
+                Err(_) => return Ok(()),
+            };
+            if message.get("event").is_some() {
+                writeln!(out, "{}", message).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    /// Run a human monitor command, e.g. `info registers`, and return its output
+    pub fn human_monitor_command(&mut self, command_line: &str) -> Result<String, String> {
+        let output = self.execute(
+            "human-monitor-command",
+            json!({ "command-line": command_line }),
+        )?;
+        Ok(output.as_str().unwrap_or_default().to_string())
+    }
+
+    /// Write the guest memory to an ELF core file, e.g. for crash(8)
+    pub fn dump_guest_memory(&mut self, path: &Path) -> Result<(), String> {
+        let path = std::path::absolute(path).map_err(|e| e.to_string())?;
+        // The reply only comes once the whole guest RAM is written
+        self.reader
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_secs(600)))
+            .map_err(|e| e.to_string())?;
+        self.execute(
+            "dump-guest-memory",
+            json!({ "paging": false, "protocol": format!("file:{}", path.display()) }),
+        )
+        .map(|_| ())
+    }
+
+    /// Dump the primary display to a PPM file
+    pub fn screendump(&mut self, path: &Path) -> Result<(), String> {
+        self.execute("screendump", json!({ "filename": path.to_string_lossy() }))
+            .map(|_| ())
+    }
+}
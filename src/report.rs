@@ -0,0 +1,304 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::results::{RunRecord, load_records};
+use crate::testing::TestStatus;
+
+/// Console lines shown around the end of a run and around each failure
+const EXCERPT_LINES: usize = 40;
+
+/// Default output of `report html`
+pub fn default_html_path() -> PathBuf {
+    PathBuf::from("results").join("report.html")
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin: 0.5em 0 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.6em; text-align: left; }
+th { background: #f0f0f0; }
+.pass { color: #176f2c; font-weight: bold; }
+.fail { color: #b42318; font-weight: bold; }
+.skip { color: #777; }
+pre { background: #f6f6f6; padding: 0.6em; overflow-x: auto; font-size: 85%; }
+section { border-top: 2px solid #ddd; margin-top: 2em; }
+";
+
+/// Escape text for HTML element content and attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn status_cell(status: TestStatus) -> &'static str {
+    match status {
+        TestStatus::Pass => "<td class=\"pass\">pass</td>",
+        TestStatus::Fail => "<td class=\"fail\">FAIL</td>",
+        TestStatus::Skip => "<td class=\"skip\">skip</td>",
+    }
+}
+
+/// Console lines worth showing: the lead-up to each FAIL and the end of the run
+fn console_excerpt(console: &str) -> String {
+    let lines: Vec<&str> = console.lines().collect();
+    let mut shown = vec![false; lines.len()];
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim_start().starts_with("FAIL: ") {
+            let start = i.saturating_sub(EXCERPT_LINES / 2);
+            shown[start..=i].iter_mut().for_each(|s| *s = true);
+        }
+    }
+    let tail = lines.len().saturating_sub(EXCERPT_LINES);
+    shown[tail..].iter_mut().for_each(|s| *s = true);
+
+    let mut excerpt = String::new();
+    let mut skipping = false;
+    for (line, shown) in lines.iter().zip(shown) {
+        if shown {
+            excerpt.push_str(line);
+            excerpt.push('\n');
+            skipping = false;
+        } else if !skipping {
+            excerpt.push_str("[...]\n");
+            skipping = true;
+        }
+    }
+    excerpt
+}
+
+/// Build artifacts of a configuration: manifest details and image sizes
+fn artifacts_table(config_name: &str, html: &mut String) {
+    let dir = Path::new("build").join(config_name);
+    let mut rows = Vec::new();
+    if crate::manifest::manifest_path(config_name).exists()
+        && let Some(manifest) = crate::manifest::read_manifest(config_name)
+    {
+        rows.push(("arch".to_string(), manifest.arch));
+        rows.push(("tool version".to_string(), manifest.tool_version));
+        rows.push(("built at".to_string(), manifest.built_at.to_string()));
+        rows.push(("kaslr".to_string(), manifest.kaslr.to_string()));
+        if let Some(commit) = manifest.kernel_commit {
+            rows.push(("kernel commit".to_string(), commit));
+        }
+        rows.push((
+            "virtio drivers".to_string(),
+            manifest.hypervisor.virtio.join(", "),
+        ));
+        if let Some(console) = manifest.hypervisor.console {
+            rows.push(("console".to_string(), console));
+        }
+        if !manifest.hypervisor.gic_versions.is_empty() {
+            let versions: Vec<String> = manifest
+                .hypervisor
+                .gic_versions
+                .iter()
+                .map(u32::to_string)
+                .collect();
+            rows.push(("GIC versions".to_string(), versions.join(", ")));
+        }
+    }
+    for artifact in [
+        crate::qemu::kernel_image_path(config_name),
+        Some(crate::qemu::rootfs_image_path(config_name)),
+        Some(crate::dtb::merged_dtb_path(config_name)),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if let Ok(metadata) = fs::metadata(&artifact) {
+            rows.push((
+                artifact.display().to_string(),
+                format!("{} bytes", metadata.len()),
+            ));
+        }
+    }
+    if rows.is_empty() {
+        let _ = writeln!(
+            html,
+            "<p>No build artifacts in {}.</p>",
+            escape(&dir.display().to_string())
+        );
+        return;
+    }
+    html.push_str("<table>\n");
+    for (key, value) in rows {
+        let _ = writeln!(
+            html,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape(&key),
+            escape(&value)
+        );
+    }
+    html.push_str("</table>\n");
+}
+
+/// One run: tests, metrics, artifacts and console excerpt
+fn run_section(run: &RunRecord, html: &mut String) {
+    let _ = writeln!(
+        html,
+        "<section id=\"{0}-{1}\">\n<h2>{0} &mdash; run {1}</h2>",
+        escape(&run.config),
+        escape(&run.tag)
+    );
+    let _ = writeln!(
+        html,
+        "<p>Kernel {} on {}, {:.1}s.</p>",
+        escape(&run.kernel_commit),
+        escape(&run.hypervisor),
+        run.duration_secs
+    );
+
+    html.push_str(
+        "<h3>Tests</h3>\n<table>\n<tr><th>Test</th><th>Result</th><th>Detail</th></tr>\n",
+    );
+    for test in &run.tests {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td>{}<td>{}</td></tr>",
+            escape(&test.name),
+            status_cell(test.status),
+            escape(test.detail.as_deref().unwrap_or(""))
+        );
+    }
+    html.push_str("</table>\n");
+
+    if !run.metrics.is_empty() {
+        html.push_str(
+            "<h3>Benchmarks</h3>\n<table>\n<tr><th>Metric</th><th>Value</th><th>Unit</th></tr>\n",
+        );
+        for (name, metric) in &run.metrics {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(name),
+                metric.value,
+                escape(&metric.unit)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h3>Artifacts</h3>\n");
+    artifacts_table(&run.config, html);
+
+    html.push_str("<h3>Console</h3>\n");
+    match fs::read_to_string(&run.console_log) {
+        Ok(console) => {
+            let _ = writeln!(html, "<pre>{}</pre>", escape(&console_excerpt(&console)));
+        }
+        Err(_) => {
+            let _ = writeln!(
+                html,
+                "<p>Console log {} is gone.</p>",
+                escape(&run.console_log)
+            );
+        }
+    }
+    html.push_str("</section>\n");
+}
+
+/// Render recorded runs into a standalone HTML page
+///
+/// Without tags, the latest run of each configuration (or of `config`) is shown.
+pub fn render_html(tags: &[String], config: Option<&str>) -> Result<String, String> {
+    let records = load_records()?;
+    let matches_config = |r: &&RunRecord| config.is_none_or(|c| r.config == c);
+    let mut runs: Vec<&RunRecord> = Vec::new();
+    if tags.is_empty() {
+        for record in records.iter().rev().filter(matches_config) {
+            if !runs.iter().any(|r| r.config == record.config) {
+                runs.push(record);
+            }
+        }
+        runs.reverse();
+    } else {
+        for tag in tags {
+            let found: Vec<&RunRecord> = records
+                .iter()
+                .filter(|r| &r.tag == tag)
+                .filter(matches_config)
+                .collect();
+            if found.is_empty() {
+                return Err(format!("no recorded run {}", tag));
+            }
+            runs.extend(found);
+        }
+    }
+    if runs.is_empty() {
+        return Err("no recorded runs, run `test` first".to_string());
+    }
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Guest test report</title>\n<style>\n{}</style>\n</head>\n<body>\n\
+         <h1>Guest test report</h1>\n",
+        STYLE
+    );
+    html.push_str(
+        "<table>\n<tr><th>Config</th><th>Run</th><th>Result</th><th>Passed</th>\
+         <th>Failed</th><th>Skipped</th><th>Kernel</th><th>Hypervisor</th></tr>\n",
+    );
+    for run in &runs {
+        let count = |status| run.tests.iter().filter(|t| t.status == status).count();
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"#{0}-{1}\">{0}</a></td><td>{1}</td>{2}<td>{3}</td><td>{4}</td>\
+             <td>{5}</td><td>{6}</td><td>{7}</td></tr>",
+            escape(&run.config),
+            escape(&run.tag),
+            status_cell(if run.passed {
+                TestStatus::Pass
+            } else {
+                TestStatus::Fail
+            }),
+            count(TestStatus::Pass),
+            count(TestStatus::Fail),
+            count(TestStatus::Skip),
+            escape(&run.kernel_commit),
+            escape(&run.hypervisor)
+        );
+    }
+    html.push_str("</table>\n");
+    for run in &runs {
+        run_section(run, &mut html);
+    }
+    html.push_str("</body>\n</html>\n");
+    Ok(html)
+}
+
+/// Write an HTML report of recorded runs
+pub fn write_html(tags: &[String], config: Option<&str>, output: &Path) -> bool {
+    let html = match render_html(tags, config) {
+        Ok(html) => html,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    if let Some(dir) = output.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    match fs::write(output, html) {
+        Ok(()) => {
+            println!("Report written to {}", output.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", output.display(), e);
+            false
+        }
+    }
+}
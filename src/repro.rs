@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::{ConfigManifest, load_manifest};
+use crate::download::KernelSelection;
+use crate::manifest::BuildManifest;
+
+/// Time builds stamp into their outputs instead of the current one, see
+/// https://reproducible-builds.org/specs/source-date-epoch/
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// User and host the kernel records as its builder in reproducible builds
+const BUILDER: &str = "guest-test";
+
+/// `SOURCE_DATE_EPOCH`, when reproducible outputs are asked for
+pub fn source_date_epoch() -> Option<u64> {
+    std::env::var(SOURCE_DATE_EPOCH).ok()?.trim().parse().ok()
+}
+
+/// Environment of the kernel and BusyBox makes, fixing what they stamp into their outputs
+/// with `SOURCE_DATE_EPOCH`
+pub fn make_env() -> Vec<(&'static str, String)> {
+    let Some(epoch) = source_date_epoch() else {
+        return Vec::new();
+    };
+    vec![
+        ("KBUILD_BUILD_TIMESTAMP", format!("@{}", epoch)),
+        ("KBUILD_BUILD_USER", BUILDER.to_string()),
+        ("KBUILD_BUILD_HOST", BUILDER.to_string()),
+        ("KBUILD_BUILD_VERSION", "1".to_string()),
+        ("KCONFIG_NOTIMESTAMP", "1".to_string()),
+    ]
+}
+
+/// Environment making e2fsprogs stamp `SOURCE_DATE_EPOCH` instead of the current time
+pub fn e2fsprogs_env() -> Vec<(&'static str, String)> {
+    source_date_epoch()
+        .map(|epoch| ("E2FSPROGS_FAKE_TIME", epoch.to_string()))
+        .into_iter()
+        .collect()
+}
+
+/// Set the modification time of everything in a staged rootfs to `SOURCE_DATE_EPOCH`,
+/// as images and archives packed from it keep them
+pub fn clamp_mtimes(dir: &Path) -> Result<(), String> {
+    let Some(epoch) = source_date_epoch() else {
+        return Ok(());
+    };
+    let status = Command::new("find")
+        .arg(dir)
+        .args([
+            "-exec",
+            "touch",
+            "-h",
+            "-d",
+            &format!("@{}", epoch),
+            "{}",
+            "+",
+        ])
+        .status()
+        .map_err(|e| format!("failed to run find: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("setting the rootfs timestamps failed: {}", status))
+    }
+}
+
+/// Filesystem UUID of a configuration's reproducible images, derived from its name
+pub fn fs_uuid(config_name: &str) -> String {
+    let hex: String = Sha256::digest(config_name.as_bytes())
+        .iter()
+        .take(16)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Settings that make a build differ by design, e.g. through keys generated per build
+fn nondeterministic_inputs(manifest: &ConfigManifest) -> Vec<&'static str> {
+    let mut inputs = Vec::new();
+    if manifest.module_signing && manifest.module_signing_key.is_none() {
+        inputs.push("module_signing without module_signing_key generates a key per build");
+    }
+    if crate::ima::enabled(manifest) {
+        inputs.push("the ima profile generates its keys per build");
+    }
+    if manifest.ssh {
+        inputs.push("ssh generates a key per build");
+    }
+    if manifest.verity {
+        inputs.push("verity salts its hash tree randomly");
+    }
+    if manifest.luks {
+        inputs.push("luks encrypts with a random volume key");
+    }
+    inputs
+}
+
+/// Where the first of the two builds is kept while the second runs
+pub fn first_build_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(".repro").join(config_name)
+}
+
+/// Build a configuration from scratch in a child `build`, without an artifact cache
+fn clean_build(config_name: &str, selection: &KernelSelection, epoch: u64) -> Result<(), String> {
+    let dir = PathBuf::from("build").join(config_name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    let mut cmd = Command::new(std::env::current_exe().map_err(|e| e.to_string())?);
+    cmd.args(["build", config_name]);
+    if let Some(kernel_ref) = &selection.kernel_ref {
+        cmd.args(["--kernel-ref", kernel_ref]);
+    }
+    if let Some(kernel_repo) = &selection.kernel_repo {
+        cmd.args(["--kernel-repo", kernel_repo]);
+    }
+    // An empty value also keeps `.env` from supplying a cache
+    let status = cmd
+        .env(crate::cache::CACHE_ENV, "")
+        .env(SOURCE_DATE_EPOCH, epoch.to_string())
+        .status()
+        .map_err(|e| format!("failed to start build: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("build failed: {}", status))
+    }
+}
+
+/// Checksums of a build's artifacts, relative to its directory
+fn artifact_checksums(manifest: Option<BuildManifest>) -> BTreeMap<String, String> {
+    manifest
+        .map(|manifest| manifest.artifacts)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|artifact| (artifact.path, artifact.sha256))
+        .collect()
+}
+
+/// SHA-256 of each file in a tree (of the target for symlinks), by path relative to it
+fn tree_checksums(root: &Path) -> BTreeMap<String, Vec<u8>> {
+    let mut checksums = BTreeMap::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            let Ok(kind) = entry.file_type() else {
+                continue;
+            };
+            let contents = if kind.is_dir() {
+                pending.push(path);
+                continue;
+            } else if kind.is_symlink() {
+                fs::read_link(&path).map(|target| target.into_os_string().into_encoded_bytes())
+            } else {
+                fs::read(&path)
+            };
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            checksums.insert(
+                relative.display().to_string(),
+                contents
+                    .map(|c| Sha256::digest(c).to_vec())
+                    .unwrap_or_default(),
+            );
+        }
+    }
+    checksums
+}
+
+/// Paths whose checksums differ between two builds, or that only one of them has
+fn differences<T: PartialEq>(a: &BTreeMap<String, T>, b: &BTreeMap<String, T>) -> Vec<String> {
+    let mut paths: Vec<&String> = a.keys().chain(b.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    paths
+        .into_iter()
+        .filter(|path| a.get(*path) != b.get(*path))
+        .cloned()
+        .collect()
+}
+
+/// SHA-256 of the contents of each section of a 64-bit little-endian ELF file, by name
+fn elf_sections(path: &Path) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let bad = || format!("{} is not a 64-bit little-endian ELF file", path.display());
+    if data.len() < 64 || &data[..4] != b"\x7fELF" || data[4] != 2 || data[5] != 1 {
+        return Err(bad());
+    }
+    let u16_at = |offset: usize| -> Option<usize> {
+        Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?) as usize)
+    };
+    let u32_at = |offset: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as usize)
+    };
+    let u64_at = |offset: usize| -> Option<usize> {
+        Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?) as usize)
+    };
+    let header = |index: usize| -> Option<(usize, usize, usize, usize)> {
+        let base = u64_at(0x28)? + index * u16_at(0x3a)?;
+        // Name offset, type, file offset and size
+        Some((
+            u32_at(base)?,
+            u32_at(base + 4)?,
+            u64_at(base + 0x18)?,
+            u64_at(base + 0x20)?,
+        ))
+    };
+    let count = u16_at(0x3c).ok_or_else(bad)?;
+    let (_, _, names, _) = header(u16_at(0x3e).ok_or_else(bad)?).ok_or_else(bad)?;
+
+    let mut sections = BTreeMap::new();
+    for index in 1..count {
+        let (name, kind, offset, size) = header(index).ok_or_else(bad)?;
+        let name = data
+            .get(names + name..)
+            .and_then(|rest| rest.split(|&b| b == 0).next())
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .ok_or_else(bad)?;
+        // SHT_NOBITS sections, e.g. .bss, occupy no file space
+        let contents = if kind == 8 {
+            &[][..]
+        } else {
+            data.get(offset..offset + size).ok_or_else(bad)?
+        };
+        sections.insert(name, Sha256::digest(contents).to_vec());
+    }
+    Ok(sections)
+}
+
+/// Build a configuration twice from scratch with the same `SOURCE_DATE_EPOCH` and report
+/// every artifact that differs, and for the kernel the ELF sections that do
+///
+/// The first build is kept under `build/.repro/<config>/` with `keep`.
+pub fn check_repro(config_name: &str, selection: &KernelSelection, keep: bool) -> bool {
+    let manifest = match load_manifest(config_name) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    };
+    for input in nondeterministic_inputs(&manifest) {
+        eprintln!("Warning: {}, expect differences", input);
+    }
+    let epoch = source_date_epoch().unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    });
+
+    let first = first_build_dir(config_name);
+    let second = PathBuf::from("build").join(config_name);
+    for round in 1..=2 {
+        crate::output::heading(&format!(
+            "Clean build {} of 2 of {} (SOURCE_DATE_EPOCH={})",
+            round, config_name, epoch
+        ));
+        if let Err(e) = clean_build(config_name, selection, epoch) {
+            eprintln!("Build {} of {} failed: {}", round, config_name, e);
+            return false;
+        }
+        // Both builds run in the same directory, so paths recorded in outputs match
+        if round == 1 {
+            let _ = fs::remove_dir_all(&first);
+            let moved = fs::create_dir_all(first.parent().unwrap())
+                .and_then(|_| fs::rename(&second, &first));
+            if let Err(e) = moved {
+                eprintln!(
+                    "Failed to move the first build to {}: {}",
+                    first.display(),
+                    e
+                );
+                return false;
+            }
+        }
+    }
+
+    let read = |dir: &Path| {
+        fs::read_to_string(dir.join("manifest.json"))
+            .ok()
+            .and_then(|json| serde_json::from_str::<BuildManifest>(&json).ok())
+    };
+    let before = artifact_checksums(read(&first));
+    let after = artifact_checksums(read(&second));
+    if before.is_empty() || after.is_empty() {
+        eprintln!("No artifacts recorded in the build manifests to compare");
+        return false;
+    }
+    let mut paths: Vec<&String> = before.keys().chain(after.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let width = paths
+        .iter()
+        .map(|path| path.len())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+    let mut differing = 0;
+    println!();
+    for path in &paths {
+        let result = match (before.get(*path), after.get(*path)) {
+            (Some(a), Some(b)) if a == b => "same",
+            (Some(_), Some(_)) => "differs",
+            (Some(_), None) => "only in build 1",
+            _ => "only in build 2",
+        };
+        if result != "same" {
+            differing += 1;
+        }
+        println!(
+            "  {} {:<width$}  {}",
+            crate::output::status(result == "same", 5),
+            path,
+            result
+        );
+    }
+
+    // Which parts of the kernel and files of the rootfs differ narrows down the cause
+    let vmlinux = Path::new("linux").join("vmlinux");
+    match (
+        elf_sections(&first.join(&vmlinux)),
+        elf_sections(&second.join(&vmlinux)),
+    ) {
+        (Ok(a), Ok(b)) => {
+            let sections = differences(&a, &b);
+            if sections.is_empty() {
+                println!("  vmlinux: all sections identical");
+            } else {
+                differing += 1;
+                println!("  vmlinux: sections differ: {}", sections.join(" "));
+            }
+        }
+        (Err(e), _) | (_, Err(e)) => eprintln!("Cannot compare vmlinux sections: {}", e),
+    }
+    let files = differences(
+        &tree_checksums(&first.join("rootfs")),
+        &tree_checksums(&second.join("rootfs")),
+    );
+    if !files.is_empty() {
+        differing += 1;
+        println!("  rootfs: files differ:");
+        for file in files {
+            println!("    /{}", file);
+        }
+    }
+
+    if keep {
+        println!("First build kept in {}", first.display());
+    } else {
+        let _ = fs::remove_dir_all(&first);
+    }
+    if differing == 0 {
+        println!(
+            "{} builds reproducibly: {} artifacts identical",
+            config_name,
+            paths.len()
+        );
+        true
+    } else {
+        println!("{} does not build reproducibly", config_name);
+        false
+    }
+}
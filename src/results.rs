@@ -0,0 +1,323 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::testing::{Metric, TestCase, TestRun, TestStatus};
+
+/// Directory holding the results archive and console logs; kept by `clean`
+const RESULTS_DIR: &str = "results";
+
+/// One JSON record per line, appended after every `test` run
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// Units where a smaller value is better; everything else counts as throughput
+const LOWER_IS_BETTER: &[&str] = &["ns", "us", "ms", "s", "cycles"];
+
+/// A recorded test run, keyed by config, kernel commit and hypervisor version
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Run tag (`test --tag`), defaults to the start time
+    pub tag: String,
+    pub config: String,
+    /// Kernel source commit from the build manifest, "unknown" for tarball builds
+    pub kernel_commit: String,
+    /// Hypervisor under test (`test --hypervisor`), defaults to the QEMU version
+    pub hypervisor: String,
+    /// Seconds since the UNIX epoch
+    pub recorded_at: u64,
+    pub passed: bool,
+    pub duration_secs: f64,
+    pub tests: Vec<TestCase>,
+    pub metrics: BTreeMap<String, Metric>,
+    /// Console output of the run, relative to the working directory
+    pub console_log: String,
+    /// Crash logs of an earlier boot the guest replayed from pstore, relative to the working directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pstore_logs: Vec<String>,
+    /// Evidence of a failed run packed into one archive, relative to the working directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_bundle: Option<String>,
+}
+
+fn history_path() -> PathBuf {
+    Path::new(RESULTS_DIR).join(HISTORY_FILE)
+}
+
+/// Append a test run to the results archive, with its console log next to it
+pub fn record(config_name: &str, tag: &str, hypervisor: Option<&str>, run: &TestRun) -> bool {
+    let logs_dir = Path::new(RESULTS_DIR).join("logs");
+    let console_log = logs_dir.join(format!("{}-{}.log", config_name, tag));
+    let kernel_commit = crate::manifest::read_manifest(config_name)
+        .and_then(|manifest| manifest.kernel_commit)
+        .unwrap_or_else(|| "unknown".to_string());
+    let hypervisor = hypervisor.unwrap_or("unknown").to_string();
+    let pstore_logs = crate::pstore::save_records(
+        &logs_dir.join(format!("{}-{}-pstore", config_name, tag)),
+        &crate::pstore::records(&run.console),
+    );
+    let mut record = RunRecord {
+        tag: tag.to_string(),
+        config: config_name.to_string(),
+        kernel_commit,
+        hypervisor,
+        recorded_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        passed: run.passed,
+        duration_secs: run.duration.as_secs_f64(),
+        tests: run.cases.clone(),
+        metrics: run.metrics.clone(),
+        console_log: console_log.display().to_string(),
+        pstore_logs: pstore_logs
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+        failure_bundle: None,
+    };
+
+    let line = serde_json::to_string(&record).expect("Failed to serialize run record");
+    let line = if run.passed {
+        line
+    } else {
+        record.failure_bundle = crate::failure::pack_bundle(
+            Path::new(RESULTS_DIR),
+            config_name,
+            record.recorded_at,
+            run,
+            &pstore_logs,
+            &line,
+        )
+        .map(|path| path.display().to_string());
+        serde_json::to_string(&record).expect("Failed to serialize run record")
+    };
+    let written = fs::create_dir_all(&logs_dir)
+        .and_then(|_| fs::write(&console_log, &run.console))
+        .and_then(|_| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(history_path())
+        })
+        .and_then(|mut file| writeln!(file, "{}", line));
+    match written {
+        Ok(()) => {
+            println!(
+                "Results recorded as run {} in {}",
+                tag,
+                history_path().display()
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to record results: {}", e);
+            false
+        }
+    }
+}
+
+/// Read every recorded run, oldest first
+pub fn load_records() -> Result<Vec<RunRecord>, String> {
+    let path = history_path();
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+    };
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("{}:{}: invalid record: {}", path.display(), i + 1, e))
+        })
+        .collect()
+}
+
+/// Find the run with a tag, optionally restricted to one config
+fn find_run<'a>(
+    records: &'a [RunRecord],
+    tag: &str,
+    config: Option<&str>,
+) -> Result<&'a RunRecord, String> {
+    let matches: Vec<&RunRecord> = records
+        .iter()
+        .filter(|r| r.tag == tag && config.is_none_or(|c| r.config == c))
+        .collect();
+    match matches.as_slice() {
+        [] => Err(format!("no recorded run {}", tag)),
+        [record] => Ok(record),
+        // Re-running with the same tag replaces the earlier run
+        [.., latest] if matches.iter().all(|r| r.config == latest.config) => Ok(latest),
+        _ => Err(format!(
+            "run {} exists for several configs, pick one with --config",
+            tag
+        )),
+    }
+}
+
+/// Print the recorded runs, optionally of one config
+pub fn list(config: Option<&str>) -> bool {
+    let records = match load_records() {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    println!(
+        "{:<14}  {:<24}  {:<6}  {:<12}  HYPERVISOR",
+        "TAG", "CONFIG", "RESULT", "KERNEL"
+    );
+    for r in records
+        .iter()
+        .filter(|r| config.is_none_or(|c| r.config == c))
+    {
+        println!(
+            "{:<14}  {:<24}  {:<6}  {:<12.12}  {}",
+            r.tag,
+            r.config,
+            if r.passed { "pass" } else { "FAIL" },
+            r.kernel_commit,
+            r.hypervisor
+        );
+    }
+    true
+}
+
+/// Status of a test in a run, "-" if it did not run
+fn status_label(status: Option<TestStatus>) -> &'static str {
+    match status {
+        Some(TestStatus::Pass) => "pass",
+        Some(TestStatus::Fail) => "fail",
+        Some(TestStatus::Skip) => "skip",
+        None => "-",
+    }
+}
+
+/// Relative change of a metric from `base` to `head` in percent, positive when worse
+fn regression_pct(unit: &str, base: f64, head: f64) -> Option<f64> {
+    if base == 0.0 {
+        return None;
+    }
+    let change = (head - base) / base.abs() * 100.0;
+    Some(if LOWER_IS_BETTER.contains(&unit) {
+        change
+    } else {
+        -change
+    })
+}
+
+/// Diff two recorded runs; returns false if `head` regressed against `base`
+///
+/// Regressions are tests that passed in `base` but not in `head`, and metrics
+/// that got worse by more than `threshold` percent.
+pub fn compare(base_tag: &str, head_tag: &str, config: Option<&str>, threshold: f64) -> bool {
+    let records = match load_records() {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    let (base, head) = match (
+        find_run(&records, base_tag, config),
+        find_run(&records, head_tag, config),
+    ) {
+        (Ok(base), Ok(head)) => (base, head),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+
+    println!("Comparing run {} against {}:", head.tag, base.tag);
+    for (what, b, h) in [
+        ("config", &base.config, &head.config),
+        ("kernel", &base.kernel_commit, &head.kernel_commit),
+        ("hypervisor", &base.hypervisor, &head.hypervisor),
+    ] {
+        if b == h {
+            println!("  {:<10}  {}", what, h);
+        } else {
+            println!("  {:<10}  {} -> {}", what, b, h);
+        }
+    }
+
+    let mut regressions = 0;
+    let status = |record: &RunRecord, name: &str| {
+        record
+            .tests
+            .iter()
+            .find(|t| t.name == name)
+            .map(|t| t.status)
+    };
+    let names: BTreeSet<&str> = base
+        .tests
+        .iter()
+        .chain(&head.tests)
+        .map(|t| t.name.as_str())
+        .collect();
+    for name in names {
+        let (b, h) = (status(base, name), status(head, name));
+        let change = match (b, h) {
+            (Some(TestStatus::Pass), Some(TestStatus::Fail) | None) => {
+                regressions += 1;
+                "REGRESSED"
+            }
+            (Some(TestStatus::Fail), Some(TestStatus::Pass)) => "fixed",
+            (None, Some(TestStatus::Fail)) => {
+                regressions += 1;
+                "new, failing"
+            }
+            (None, Some(_)) => "new",
+            _ => continue,
+        };
+        println!(
+            "  test {:<30}  {} -> {}  {}",
+            name,
+            status_label(b),
+            status_label(h),
+            change
+        );
+    }
+
+    for (name, h) in &head.metrics {
+        let Some(b) = base.metrics.get(name) else {
+            continue;
+        };
+        let Some(worse) = regression_pct(&h.unit, b.value, h.value) else {
+            continue;
+        };
+        let flag = if worse > threshold {
+            regressions += 1;
+            "REGRESSED"
+        } else if worse < -threshold {
+            "improved"
+        } else {
+            ""
+        };
+        println!(
+            "  metric {:<28}  {} -> {} {} ({:+.1}%)  {}",
+            name,
+            b.value,
+            h.value,
+            h.unit,
+            (h.value - b.value) / b.value.abs() * 100.0,
+            flag
+        );
+    }
+
+    if regressions > 0 {
+        println!("{} regression(s) beyond {}%", regressions, threshold);
+        false
+    } else {
+        println!("No regressions beyond {}%", threshold);
+        true
+    }
+}
@@ -1,23 +1,143 @@
 use std::fs;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigManifest;
+use crate::error::{BuildError, run};
+
+/// Kernel options for booting from an initramfs
+pub const INITRAMFS_KERNEL_OPTIONS: &[&str] =
+    &["CONFIG_BLK_DEV_INITRD", "CONFIG_RD_GZIP", "CONFIG_DEVTMPFS"];
+
+/// Filesystem of the rootfs image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[default]
+    Ext4,
+    /// Compressed and read-only, made with mksquashfs
+    Squashfs,
+    /// Compressed and read-only, made with mkfs.erofs
+    Erofs,
+}
+
+impl ImageFormat {
+    /// Name as in the manifest and for `rootfstype=`
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageFormat::Ext4 => "ext4",
+            ImageFormat::Squashfs => "squashfs",
+            ImageFormat::Erofs => "erofs",
+        }
+    }
+
+    /// Whether the filesystem can only be mounted read-only
+    pub fn read_only(self) -> bool {
+        self != ImageFormat::Ext4
+    }
+
+    /// Host tool creating the image and the Debian/Ubuntu package providing it
+    pub fn tool(self) -> (&'static str, &'static str) {
+        match self {
+            ImageFormat::Ext4 => ("mkfs.ext4", "e2fsprogs"),
+            ImageFormat::Squashfs => ("mksquashfs", "squashfs-tools"),
+            ImageFormat::Erofs => ("mkfs.erofs", "erofs-utils"),
+        }
+    }
+
+    /// Kernel options for mounting the image as root, with the compression it is made with
+    pub fn kernel_options(self) -> &'static [&'static str] {
+        match self {
+            ImageFormat::Ext4 => &[],
+            ImageFormat::Squashfs => &["CONFIG_SQUASHFS", "CONFIG_SQUASHFS_ZLIB"],
+            ImageFormat::Erofs => &["CONFIG_EROFS_FS", "CONFIG_EROFS_FS_ZIP"],
+        }
+    }
+}
 
 /// Create init script in the rootfs directory
-pub fn create_init_script(rootfs_dir: &Path) {
+pub fn create_init_script(rootfs_dir: &Path) -> Result<(), BuildError> {
     let init_script = include_str!("../init/init");
 
     let init_path = rootfs_dir.join("init");
-    fs::write(&init_path, init_script).expect("Failed to create init script");
+    fs::write(&init_path, init_script).map_err(BuildError::io("failed to create init script"))?;
 
     // Make it executable
-    let status = Command::new("chmod")
-        .args(["+x", &init_path.to_string_lossy()])
-        .status()
-        .expect("Failed to make init script executable");
+    run(
+        Command::new("chmod").args(["+x", &init_path.to_string_lossy()]),
+        "making the init script executable",
+    )
+}
+
+/// Install boot hooks and guest tests into the rootfs
+pub fn install_init_scripts(rootfs_dir: &Path) -> Result<(), BuildError> {
+    for (src_dir, dest_dir) in [("init/hooks", "etc/hooks"), ("init/tests.d", "etc/tests.d")] {
+        let dest_dir = rootfs_dir.join(dest_dir);
+        fs::create_dir_all(&dest_dir)
+            .map_err(BuildError::io("failed to create script directory in rootfs"))?;
+
+        let Ok(entries) = fs::read_dir(src_dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
 
-    if !status.success() {
-        eprintln!("Failed to make init script executable");
+            let dest = dest_dir.join(path.file_name().unwrap());
+            fs::copy(&path, &dest).map_err(BuildError::io(format!(
+                "failed to copy {} into rootfs",
+                path.display()
+            )))?;
+
+            run(
+                Command::new("chmod").args(["+x", &dest.to_string_lossy()]),
+                &format!("making {} executable", dest.display()),
+            )?;
+        }
     }
+    Ok(())
+}
+
+/// Pack a rootfs directory into a gzipped newc cpio archive for use as an initramfs
+pub fn create_initramfs(rootfs_dir: &Path, output: &Path) -> bool {
+    pack_cpio(rootfs_dir, output, true)
+}
+
+/// Pack a rootfs directory into a newc cpio archive, optionally gzipped
+fn pack_cpio(rootfs_dir: &Path, output: &Path, gzip: bool) -> bool {
+    let output = match output.parent().map(|p| p.canonicalize()) {
+        Some(Ok(dir)) => dir.join(output.file_name().unwrap()),
+        _ => return false,
+    };
+
+    // Files are owned by root in the guest regardless of the building user; sorted, and
+    // without gzip's name and timestamp, the archive only depends on the tree
+    let compress = if gzip { " | gzip -9 -n" } else { "" };
+    let reproducible = if crate::repro::source_date_epoch().is_some() { " --reproducible" } else { "" };
+    let status = Command::new("bash")
+        .current_dir(rootfs_dir)
+        .args([
+            "-c",
+            &format!(
+                "set -o pipefail; find . | LC_ALL=C sort | cpio -o -H newc -R 0:0 --quiet{}{} > '{}'",
+                reproducible,
+                compress,
+                output.display()
+            ),
+        ])
+        .status();
+
+    matches!(status, Ok(status) if status.success())
+}
+
+/// Uncompressed archive the kernel build embeds for `initramfs_embed`
+pub fn embedded_initramfs_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("initramfs.cpio")
 }
 
 /// Calculate the size of rootfs directory in MB
@@ -64,129 +184,99 @@ pub fn calculate_rootfs_size(rootfs_dir: &Path) -> u64 {
 /// Create rootfs for a specific configuration
 pub fn create_rootfs_for_config(
     config_name: &str,
-    _arch: &str,
+    arch: &str,
     kernel_arch: &str,
     cross_compile_prefix: &Option<String>,
-) {
+    strict: bool,
+) -> Result<(), BuildError> {
     println!("Creating rootfs for configuration: {}", config_name);
 
     let rootfs_dir = PathBuf::from("build").join(config_name).join("rootfs");
-    let busybox_build_dir = PathBuf::from("build").join(config_name).join("busybox");
     let linux_build_dir = PathBuf::from("build").join(config_name).join("linux");
     let output_dir = PathBuf::from("build").join(config_name);
 
     // Clean and create rootfs directory
     if rootfs_dir.exists() {
-        fs::remove_dir_all(&rootfs_dir).expect("Failed to remove existing rootfs directory");
+        fs::remove_dir_all(&rootfs_dir)
+            .map_err(BuildError::io("failed to remove existing rootfs directory"))?;
     }
-    fs::create_dir_all(&rootfs_dir).expect("Failed to create rootfs directory");
+    fs::create_dir_all(&rootfs_dir).map_err(BuildError::io("failed to create rootfs directory"))?;
 
-    // Install busybox
+    // Install busybox from its staging directory
     println!("Installing busybox to rootfs...");
-    let mut make_args = vec![
-        format!("O={}", busybox_build_dir.canonicalize().unwrap().display()),
-        format!(
-            "CONFIG_PREFIX={}",
-            rootfs_dir.canonicalize().unwrap().display()
-        ),
-        "install".to_string(),
-    ];
-
-    if let Some(prefix) = cross_compile_prefix {
-        make_args.push(format!("CROSS_COMPILE={}", prefix));
-    }
+    run(
+        Command::new("cp")
+            .arg("-a")
+            .arg(crate::busybox::install_dir(config_name).join("."))
+            .arg(&rootfs_dir),
+        "installing busybox",
+    )?;
 
-    let status = Command::new("make")
-        .current_dir("busybox")
-        .args(&make_args)
-        .status()
-        .expect("Failed to install busybox");
-
-    if !status.success() {
-        eprintln!(
-            "Failed to install busybox for configuration: {}",
-            config_name
-        );
-        return;
-    }
-
-    // Debug: List contents of rootfs after busybox install
-    println!("Checking busybox installation...");
-    let _ = Command::new("ls")
-        .args(["-la", &rootfs_dir.to_string_lossy()])
-        .status();
+    log_rootfs_contents(&rootfs_dir, "after busybox install");
 
     // Create additional directories
     let dirs = [
-        "dev", "proc", "sys", "tmp", "var", "etc", "root", "home", "mnt",
+        "dev", "proc", "sys", "tmp", "var", "etc", "root", "home", "mnt", "run",
     ];
     for dir in &dirs {
-        fs::create_dir_all(rootfs_dir.join(dir)).expect("Failed to create directory in rootfs");
+        fs::create_dir_all(rootfs_dir.join(dir))
+            .map_err(BuildError::io(format!("failed to create /{} in rootfs", dir)))?;
     }
 
-    // Debug: List rootfs contents after creating directories
-    println!("Rootfs contents after setup:");
-    let _ = Command::new("bash")
-        .args([
-            "-c",
-            &format!("find {} -type f | head -20", rootfs_dir.display()),
-        ])
-        .status();
-
-    // Install kernel modules
-    println!("Installing kernel modules...");
-    let modules_dir = rootfs_dir.join("lib").join("modules");
-    fs::create_dir_all(&modules_dir).expect("Failed to create modules directory");
-
-    let mut make_args = vec![
-        format!("O={}", linux_build_dir.canonicalize().unwrap().display()),
-        format!("ARCH={}", kernel_arch),
-        format!(
-            "INSTALL_MOD_PATH={}",
-            rootfs_dir.canonicalize().unwrap().display()
-        ),
-        "modules_install".to_string(),
-    ];
+    log_rootfs_contents(&rootfs_dir, "after setup");
 
-    if let Some(prefix) = cross_compile_prefix {
-        make_args.push(format!("CROSS_COMPILE={}", prefix));
+    // Create init script
+    create_init_script(&rootfs_dir)?;
+    install_init_scripts(&rootfs_dir)?;
+
+    // Build and install guest test payloads
+    let manifest = crate::config::load_manifest(config_name).map_err(|e| {
+        BuildError::Config(format!(
+            "invalid manifest for configuration {}: {}",
+            config_name, e
+        ))
+    })?;
+    crate::modules::install_modules(
+        config_name,
+        &rootfs_dir,
+        kernel_arch,
+        cross_compile_prefix,
+        &manifest,
+    )?;
+    crate::etc::populate(&rootfs_dir, arch, &manifest)?;
+    if !crate::payload::install_payloads(
+        config_name,
+        &manifest,
+        &rootfs_dir,
+        kernel_arch,
+        cross_compile_prefix,
+    ) {
+        return Err(BuildError::Step("payload installation failed".to_string()));
     }
-
-    let status = Command::new("make")
-        .current_dir("linux")
-        .args(&make_args)
-        .status()
-        .expect("Failed to install kernel modules");
-
-    if !status.success() {
-        eprintln!(
-            "Failed to install kernel modules for configuration: {}",
-            config_name
-        );
-        // Continue anyway, modules might not be essential
+    if manifest.httpd {
+        crate::httpd::install_httpd(&rootfs_dir);
     }
-
-    // Create init script
-    create_init_script(&rootfs_dir);
+    if manifest.ssh {
+        crate::ssh::install_ssh(config_name, &rootfs_dir, cross_compile_prefix)?;
+    }
+    if let Some(timezone) = &manifest.timezone
+        && !crate::clock::install_timezone(&rootfs_dir, timezone)
+    {
+        return Err(BuildError::Step(format!("installing timezone {} failed", timezone)));
+    }
+    crate::guest_env::install(&rootfs_dir, &manifest)?;
+    crate::minimize::minimize_rootfs(&rootfs_dir, &manifest, cross_compile_prefix);
 
     // Locate kernel image and copy into rootfs boot directory
     println!("Locating kernel image and copying into rootfs boot directory...");
 
     // Determine possible kernel image paths
-    let possible_images = vec![
-        linux_build_dir
-            .join("arch")
-            .join("arm64")
-            .join("boot")
-            .join("Image"),
-        linux_build_dir
-            .join("arch")
-            .join("x86")
-            .join("boot")
-            .join("bzImage"),
-        linux_build_dir.join("Image"),
-        linux_build_dir.join("bzImage"),
-    ];
+    let mut possible_images = Vec::new();
+    if let Some(arch_info) = crate::arch::find_arch(arch) {
+        possible_images.push(arch_info.built_kernel_image(&linux_build_dir));
+    }
+    possible_images.push(linux_build_dir.join("Image"));
+    possible_images.push(linux_build_dir.join("bzImage"));
 
     let mut found_image: Option<PathBuf> = None;
     for img in possible_images {
@@ -216,114 +306,156 @@ pub fn create_rootfs_for_config(
         println!("No kernel image found in build directory to copy into rootfs");
     }
 
-    // Create rootfs image
-    create_rootfs_image(config_name, &rootfs_dir, &output_dir);
-}
+    // Signed last, so every file that ends up in the image carries a signature
+    if crate::ima::enabled(&manifest) && !crate::ima::sign_rootfs(config_name, &rootfs_dir) {
+        return Err(BuildError::Step("signing the rootfs failed".to_string()));
+    }
 
-/// Create rootfs image file
-fn create_rootfs_image(config_name: &str, rootfs_dir: &Path, output_dir: &Path) {
-    println!("Creating rootfs.img...");
-    let rootfs_img = output_dir.join("rootfs.img");
+    if !crate::audit::check_rootfs(&rootfs_dir, strict) {
+        return Err(BuildError::Step("rootfs audit failed".to_string()));
+    }
 
-    // Calculate size (add larger safety margin: 30% + 200MB minimum)
-    let base_size = calculate_rootfs_size(rootfs_dir);
-    let mut size_mb = (base_size as f64 * 1.3).ceil() as u64;
-    // Ensure at least base + 200MB extra
-    if size_mb < base_size + 200 {
-        size_mb = base_size + 200;
+    if manifest.initramfs {
+        return pack_initramfs(config_name, &manifest, &rootfs_dir);
     }
 
-    // Create empty image file
-    let status = Command::new("dd")
-        .args([
-            "if=/dev/zero",
-            &format!("of={}", rootfs_img.display()),
-            "bs=1M",
-            &format!("count={}", size_mb),
-        ])
-        .status()
-        .expect("Failed to create rootfs image file");
-
-    if !status.success() {
-        eprintln!(
-            "Failed to create rootfs image file for configuration: {}",
-            config_name
-        );
-        return;
+    // Create rootfs image
+    crate::repro::clamp_mtimes(&rootfs_dir).map_err(BuildError::Step)?;
+    match manifest.image_format {
+        ImageFormat::Ext4 => {
+            let reserve_mb = crate::swap::reserved_mb(&manifest);
+            create_rootfs_image(config_name, &rootfs_dir, &output_dir, reserve_mb)?
+        }
+        format => create_read_only_image(config_name, format, &rootfs_dir, &output_dir)?,
+    }
+    crate::manifest::update_manifest(config_name, |build| build.image_format = Some(manifest.image_format));
+    if manifest.verity && !crate::verity::append_hash_tree(config_name) {
+        return Err(BuildError::Step("appending the dm-verity hash tree failed".to_string()));
     }
+    if manifest.luks {
+        let passphrase = manifest
+            .luks_passphrase
+            .as_deref()
+            .unwrap_or(crate::luks::DEFAULT_PASSPHRASE);
+        if !crate::luks::encrypt_rootfs(config_name, passphrase) {
+            return Err(BuildError::Step("encrypting the rootfs failed".to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Pack a staged rootfs into the configuration's initramfs (and the archive to embed)
+pub fn pack_initramfs(config_name: &str, manifest: &ConfigManifest, rootfs_dir: &Path) -> Result<(), BuildError> {
+    let initramfs = crate::qemu::initramfs_path(config_name);
+    crate::repro::clamp_mtimes(rootfs_dir).map_err(BuildError::Step)?;
+    println!("Creating {}...", initramfs.display());
+    if !create_initramfs(rootfs_dir, &initramfs) {
+        return Err(BuildError::Step("packing the initramfs failed".to_string()));
+    }
+    // The kernel build compresses an embedded archive itself
+    if manifest.initramfs_embed
+        && !pack_cpio(rootfs_dir, &embedded_initramfs_path(config_name), false)
+    {
+        return Err(BuildError::Step("packing the initramfs failed".to_string()));
+    }
+    // A stale disk image would make the config look built for the other boot path
+    let _ = fs::remove_file(PathBuf::from("build").join(config_name).join("rootfs.img"));
+    crate::manifest::update_manifest(config_name, |build| build.image_format = None);
+    Ok(())
+}
 
-    // Format as ext4
-    let status = Command::new("mkfs.ext4")
-        .args(["-F", "-O", "^metadata_csum_seed", &rootfs_img.to_string_lossy()])
-        .status()
-        .expect("Failed to format rootfs image");
+/// Check whether mke2fs can populate a filesystem from a directory (`-d`, e2fsprogs 1.43+)
+fn mke2fs_can_populate() -> bool {
+    // Without arguments mke2fs prints its usage, which lists the supported options
+    Command::new("mke2fs")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stderr).contains("[-d "))
+        .unwrap_or(false)
+}
 
-    if !status.success() {
-        eprintln!(
-            "Failed to format rootfs image for configuration: {}",
-            config_name
-        );
-        return;
+/// Make every file in an image populated by `mke2fs -d` owned by root
+///
+/// mke2fs copies the build user's ownership, so fix it up with debugfs instead of chown.
+fn chown_image_to_root(rootfs_dir: &Path, rootfs_img: &Path) -> Result<(), BuildError> {
+    let mut script = String::new();
+    let mut pending = vec![rootfs_dir.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let metadata = fs::symlink_metadata(&path).map_err(BuildError::io(format!("failed to stat {}", path.display())))?;
+        let guest_path = Path::new("/").join(path.strip_prefix(rootfs_dir).unwrap());
+        let guest_path = guest_path.to_string_lossy();
+        if metadata.uid() != 0 {
+            script.push_str(&format!("set_inode_field \"{}\" uid 0\n", guest_path));
+        }
+        if metadata.gid() != 0 {
+            script.push_str(&format!("set_inode_field \"{}\" gid 0\n", guest_path));
+        }
+        if metadata.is_dir() {
+            let entries = fs::read_dir(&path).map_err(BuildError::io(format!("failed to read {}", path.display())))?;
+            pending.extend(entries.flatten().map(|entry| entry.path()));
+        }
     }
+    if script.is_empty() {
+        return Ok(());
+    }
+
+    println!("Changing ownership of all files to root:root...");
+    let script_path = rootfs_img.with_extension("debugfs");
+    fs::write(&script_path, script).map_err(BuildError::io("failed to write debugfs script"))?;
+    let result = run(
+        // debugfs echoes every command it runs
+        Command::new("debugfs")
+            .args(["-w", "-f", &script_path.to_string_lossy(), &rootfs_img.to_string_lossy()])
+            .envs(crate::repro::e2fsprogs_env())
+            .stdout(Stdio::null()),
+        "debugfs",
+    );
+    let _ = fs::remove_file(&script_path);
+    result
+}
 
+/// Copy the rootfs directory into a formatted image through a loop mount (needs sudo)
+fn copy_into_image(config_name: &str, rootfs_dir: &Path, rootfs_img: &Path) -> Result<(), BuildError> {
     // Mount and copy files
     let mount_point = PathBuf::from("/tmp").join(format!("rootfs_mount_{}", config_name));
-    fs::create_dir_all(&mount_point).expect("Failed to create mount point");
+    fs::create_dir_all(&mount_point).map_err(BuildError::io("failed to create mount point"))?;
 
-    let status = Command::new("sudo")
-        .args([
+    run(
+        Command::new("sudo").args([
             "mount",
             "-o",
             "loop",
             &rootfs_img.to_string_lossy(),
             &mount_point.to_string_lossy(),
-        ])
-        .status()
-        .expect("Failed to mount rootfs image");
-
-    if !status.success() {
-        eprintln!(
-            "Failed to mount rootfs image for configuration: {}",
-            config_name
-        );
-        return;
-    }
+        ]),
+        "mounting the rootfs image",
+    )?;
 
     // Copy rootfs contents
     println!("Copying rootfs contents to image...");
 
-    // Check if rootfs directory has content
-    if !rootfs_dir.exists()
-        || fs::read_dir(rootfs_dir)
-            .map(|mut d| d.next().is_none())
-            .unwrap_or(true)
-    {
-        eprintln!("Warning: rootfs directory is empty or doesn't exist");
-    }
-
     let copy_cmd = format!(
         "cd {} && sudo find . -mindepth 1 -maxdepth 1 -exec cp -a {{}} {} \\;",
         rootfs_dir.display(),
         mount_point.display()
     );
 
-    let status = Command::new("bash").args(["-c", &copy_cmd]).status();
+    let copied = run(
+        Command::new("bash").args(["-c", &copy_cmd]),
+        "copying files to the rootfs image",
+    );
 
     // Change ownership of all files to root:root
-    if status.is_ok() && status.as_ref().unwrap().success() {
+    if copied.is_ok() {
         println!("Changing ownership of all files to root:root...");
-        let chown_status = Command::new("sudo")
-            .args(["chown", "-R", "root:root", &mount_point.to_string_lossy()])
-            .status()
-            .expect("Failed to change ownership");
-
-        if !chown_status.success() {
-            eprintln!(
-                "Warning: Failed to change ownership to root:root for configuration: {}",
-                config_name
-            );
-        } else {
-            println!("Successfully changed ownership to root:root");
+        match run(
+            Command::new("sudo").args(["chown", "-R", "root:root", &mount_point.to_string_lossy()]),
+            "chown",
+        ) {
+            Ok(()) => println!("Successfully changed ownership to root:root"),
+            Err(e) => eprintln!(
+                "Warning: {} while changing ownership to root:root for configuration: {}",
+                e, config_name
+            ),
         }
     }
 
@@ -335,15 +467,141 @@ fn create_rootfs_image(config_name: &str, rootfs_dir: &Path, output_dir: &Path)
     // Clean up mount point
     let _ = fs::remove_dir(&mount_point);
 
-    if status.is_ok() && status.unwrap().success() {
-        println!(
-            "Rootfs image created successfully: {}",
-            rootfs_img.display()
-        );
+    copied
+}
+
+/// Log the top level of the rootfs tree and its file count (`RUST_LOG=debug`)
+fn log_rootfs_contents(rootfs_dir: &Path, when: &str) {
+    if !log::log_enabled!(log::Level::Debug) {
+        return;
+    }
+    let mut entries: Vec<String> = fs::read_dir(rootfs_dir)
+        .map(|dir| dir.flatten().map(|e| e.file_name().to_string_lossy().into_owned()).collect())
+        .unwrap_or_default();
+    entries.sort();
+    let mut files = 0;
+    let mut pending = vec![rootfs_dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => pending.push(entry.path()),
+                Ok(_) => files += 1,
+                Err(_) => {}
+            }
+        }
+    }
+    log::debug!("rootfs {} ({} files): {}", when, files, entries.join(" "));
+}
+
+/// mkfs.ext4 for the rootfs image, with a fixed UUID, hash seed and time under SOURCE_DATE_EPOCH
+fn mkfs_ext4(config_name: &str) -> Command {
+    let mut cmd = Command::new("mkfs.ext4");
+    cmd.args(["-F", "-O", "^metadata_csum_seed"]).envs(crate::repro::e2fsprogs_env());
+    if crate::repro::source_date_epoch().is_some() {
+        let uuid = crate::repro::fs_uuid(config_name);
+        cmd.args(["-U", &uuid, "-E", &format!("hash_seed={}", uuid)]);
+    }
+    cmd
+}
+
+/// Create rootfs image file, leaving `reserve_mb` more free for a swap file
+fn create_rootfs_image(
+    config_name: &str,
+    rootfs_dir: &Path,
+    output_dir: &Path,
+    reserve_mb: u64,
+) -> Result<(), BuildError> {
+    println!("Creating rootfs.img...");
+    let rootfs_img = output_dir.join("rootfs.img");
+
+    // Calculate size (add larger safety margin: 30% + 200MB minimum)
+    let base_size = calculate_rootfs_size(rootfs_dir);
+    let mut size_mb = (base_size as f64 * 1.3).ceil() as u64;
+    // Ensure at least base + 200MB extra
+    if size_mb < base_size + 200 {
+        size_mb = base_size + 200;
+    }
+    size_mb += reserve_mb;
+
+    // Create empty image file
+    run(
+        Command::new("dd").args([
+            "if=/dev/zero",
+            &format!("of={}", rootfs_img.display()),
+            "bs=1M",
+            &format!("count={}", size_mb),
+        ]),
+        "creating the rootfs image file",
+    )?;
+
+    // Check if rootfs directory has content
+    if !rootfs_dir.exists()
+        || fs::read_dir(rootfs_dir)
+            .map(|mut d| d.next().is_none())
+            .unwrap_or(true)
+    {
+        eprintln!("Warning: rootfs directory is empty or doesn't exist");
+    }
+
+    if mke2fs_can_populate() {
+        // Populate the ext4 image straight from the directory, no mount or root needed
+        println!("Copying rootfs contents to image...");
+        run(
+            mkfs_ext4(config_name).args([
+                "-d",
+                &rootfs_dir.to_string_lossy(),
+                &rootfs_img.to_string_lossy(),
+            ]),
+            "populating the rootfs image",
+        )?;
+        chown_image_to_root(rootfs_dir, &rootfs_img)?;
     } else {
-        eprintln!(
-            "Failed to copy files to rootfs image for configuration: {}",
-            config_name
-        );
+        println!("mke2fs cannot populate images (no -d), falling back to a loop mount");
+        run(
+            mkfs_ext4(config_name).arg(&rootfs_img),
+            "formatting the rootfs image",
+        )?;
+        copy_into_image(config_name, rootfs_dir, &rootfs_img)?;
+    }
+    println!(
+        "Rootfs image created successfully: {}",
+        rootfs_img.display()
+    );
+    Ok(())
+}
+
+/// Create a squashfs or erofs rootfs image, sized to its contents and owned by root
+fn create_read_only_image(config_name: &str, format: ImageFormat, rootfs_dir: &Path, output_dir: &Path) -> Result<(), BuildError> {
+    let (tool, package) = format.tool();
+    if !crate::doctor::has_tool(tool) {
+        return Err(BuildError::Step(format!(
+            "{} not found, needed for image_format = \"{}\" (apt install {})",
+            tool,
+            format.name(),
+            package
+        )));
     }
+    println!("Creating {} rootfs.img...", format.name());
+    let rootfs_img = output_dir.join("rootfs.img");
+    let _ = fs::remove_file(&rootfs_img);
+    let mut cmd = Command::new(tool);
+    match format {
+        ImageFormat::Squashfs => cmd
+            .arg(rootfs_dir)
+            .arg(&rootfs_img)
+            .args(["-noappend", "-all-root", "-comp", "gzip"]),
+        _ => cmd.args(["-zlz4hc", "--all-root"]).arg(&rootfs_img).arg(rootfs_dir),
+    };
+    // mksquashfs takes SOURCE_DATE_EPOCH from the environment itself
+    if format == ImageFormat::Erofs
+        && let Some(epoch) = crate::repro::source_date_epoch()
+    {
+        cmd.arg("-T").arg(epoch.to_string()).arg("-U").arg(crate::repro::fs_uuid(config_name));
+    }
+    run(&mut cmd, &format!("creating the {} image", format.name()))?;
+    println!(
+        "Rootfs image created successfully: {}",
+        rootfs_img.display()
+    );
+    Ok(())
 }
@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Root of the guest-test-linux checkout this binary was built from
+const REPO_ROOT: &str = env!("CARGO_MANIFEST_DIR");
+
+/// Run git in the tool's checkout and return its trimmed stdout on success
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(REPO_ROOT)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        eprintln!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Read the package version from the checkout's Cargo.toml
+fn checkout_version() -> Option<String> {
+    let content = fs::read_to_string(Path::new(REPO_ROOT).join("Cargo.toml")).ok()?;
+    let table: toml::Table = content.parse().ok()?;
+    table
+        .get("package")?
+        .get("version")?
+        .as_str()
+        .map(|v| v.to_string())
+}
+
+/// Fast-forward the tool's checkout to its upstream branch
+///
+/// `cargo xtask` rebuilds the tool from the updated sources on its next run.
+/// With `check` only reports whether an update is available.
+pub fn self_update(check: bool) -> bool {
+    let current = env!("CARGO_PKG_VERSION");
+
+    let Some(status) = git(&["status", "--porcelain", "--untracked-files=no"]) else {
+        return false;
+    };
+    if !status.is_empty() && !check {
+        eprintln!(
+            "{} has local changes, commit or stash them first",
+            REPO_ROOT
+        );
+        return false;
+    }
+
+    println!("Fetching updates for guest-test-linux {}...", current);
+    if git(&["fetch", "--quiet"]).is_none() {
+        return false;
+    }
+    let Some(behind) = git(&["rev-list", "--count", "HEAD..@{u}"]) else {
+        eprintln!("The checkout has no upstream branch to update from");
+        return false;
+    };
+    if behind == "0" {
+        println!("Already up to date.");
+        return true;
+    }
+
+    if check {
+        println!(
+            "{} new commit(s) available, run `cargo xtask self-update`",
+            behind
+        );
+        return true;
+    }
+
+    if git(&["merge", "--ff-only", "--quiet", "@{u}"]).is_none() {
+        eprintln!("Cannot fast-forward, the checkout has diverged from upstream");
+        return false;
+    }
+
+    let updated = checkout_version().unwrap_or_else(|| "unknown".to_string());
+    println!(
+        "Updated guest-test-linux {} -> {} ({} commit(s))",
+        current, updated, behind
+    );
+    true
+}
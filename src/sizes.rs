@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+/// Number of rootfs files listed as its largest contributors
+const LARGEST_FILES: usize = 20;
+
+/// Where a build keeps the previous kernel to compare against
+fn sizes_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("sizes")
+}
+
+/// Section sizes and largest rootfs files of a build, stored in the build manifest
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SizeReport {
+    /// Non-empty `vmlinux` sections and their sizes in bytes, largest first
+    pub sections: Vec<(String, u64)>,
+    /// bloat-o-meter's total against the previous build, e.g. "Total: Before=..., After=..., chg +0.12%"
+    pub bloat_total: Option<String>,
+    /// Largest rootfs files relative to the rootfs and their sizes in bytes
+    pub largest_files: Vec<(String, u64)>,
+}
+
+/// A binutils tool, cross-prefixed when cross-compiling
+fn tool(cross_compile_prefix: &Option<String>, name: &str) -> String {
+    format!("{}{}", cross_compile_prefix.as_deref().unwrap_or(""), name)
+}
+
+/// Sizes of the ELF sections of a kernel, from `size -A`
+fn section_sizes(vmlinux: &Path, size: &str) -> Result<Vec<(String, u64)>, String> {
+    let output = Command::new(size)
+        .arg("-A")
+        .arg(vmlinux)
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", size, e))?;
+    if !output.status.success() {
+        return Err(format!("{} -A {} failed", size, vmlinux.display()));
+    }
+    let mut sections: Vec<(String, u64)> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next().filter(|name| name.starts_with('.'))?;
+            let size = fields.next()?.parse().ok()?;
+            (size > 0).then(|| (name.to_string(), size))
+        })
+        .collect();
+    sections.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+    Ok(sections)
+}
+
+/// Compare a kernel against the previous build's with the kernel's `scripts/bloat-o-meter`,
+/// writing the full comparison next to it and returning the total line
+fn bloat_o_meter(
+    config_name: &str,
+    old: &Path,
+    new: &Path,
+    nm: &str,
+) -> Result<Option<String>, String> {
+    let script = crate::kernel::source_dir(config_name).join("scripts/bloat-o-meter");
+    if !old.exists() || !script.exists() {
+        return Ok(None);
+    }
+    let output = Command::new(&script)
+        .env("NM", nm)
+        .arg(old)
+        .arg(new)
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", script.display(), e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed", script.display()));
+    }
+    let report = sizes_dir(config_name).join("bloat-o-meter.txt");
+    fs::write(&report, &output.stdout).map_err(|e| format!("{}: {}", report.display(), e))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.starts_with("Total:"))
+        .map(str::to_string))
+}
+
+/// Largest regular files under the rootfs
+fn largest_files(rootfs_dir: &Path) -> Vec<(String, u64)> {
+    let mut files = Vec::new();
+    let mut dirs = vec![rootfs_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let path = entry.path();
+            if metadata.is_dir() {
+                dirs.push(path);
+            } else if metadata.is_file() {
+                let relative = path.strip_prefix(rootfs_dir).unwrap_or(&path);
+                files.push((format!("/{}", relative.display()), metadata.len()));
+            }
+        }
+    }
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    files.truncate(LARGEST_FILES);
+    files
+}
+
+/// Measure the kernel sections and rootfs contributors, compare with the previous build
+/// and record the report in the build manifest
+///
+/// Missing binutils only cost the kernel part of the report, they do not fail the build.
+pub fn report(config_name: &str, cross_compile_prefix: &Option<String>) -> Result<(), String> {
+    let dir = sizes_dir(config_name);
+    fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let vmlinux = PathBuf::from("build")
+        .join(config_name)
+        .join("linux")
+        .join("vmlinux");
+    let previous = dir.join("vmlinux.old");
+
+    let mut report = SizeReport::default();
+    match section_sizes(&vmlinux, &tool(cross_compile_prefix, "size")) {
+        Ok(sections) => report.sections = sections,
+        Err(e) => eprintln!("Warning: no kernel section sizes: {}", e),
+    }
+    match bloat_o_meter(
+        config_name,
+        &previous,
+        &vmlinux,
+        &tool(cross_compile_prefix, "nm"),
+    ) {
+        Ok(total) => report.bloat_total = total,
+        Err(e) => eprintln!("Warning: no comparison with the previous kernel: {}", e),
+    }
+    // Keep the symbols, not the debug info, for the next build's comparison
+    let kept = Command::new(tool(cross_compile_prefix, "objcopy"))
+        .arg("--strip-debug")
+        .arg(&vmlinux)
+        .arg(&previous)
+        .status();
+    if !matches!(kept, Ok(status) if status.success()) {
+        eprintln!("Warning: could not keep the kernel for the next size comparison");
+    }
+    report.largest_files = largest_files(&PathBuf::from("build").join(config_name).join("rootfs"));
+
+    for (name, size) in report.sections.iter().take(5) {
+        println!("  {:<16} {:>10}", name, size);
+    }
+    if let Some(total) = &report.bloat_total {
+        println!("  {}", total);
+    }
+    if let Some((path, size)) = report.largest_files.first() {
+        println!("  largest rootfs file: {} ({} bytes)", path, size);
+    }
+    crate::manifest::update_manifest(config_name, |build| build.size_report = Some(report));
+    Ok(())
+}
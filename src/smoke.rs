@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::load_manifest;
+use crate::httpd::DEFAULT_HTTPD_PORT;
+use crate::qemu::{LaunchOptions, is_built, launch};
+
+/// Marker printed by init as soon as it starts running
+pub const INIT_MARKER: &str = "init reached";
+
+/// Outcome of booting a single configuration
+struct SmokeResult {
+    config_name: String,
+    passed: bool,
+    detail: String,
+}
+
+/// List all configurations that have a kernel image and rootfs in `build/`
+pub fn built_configs() -> Vec<String> {
+    let mut configs = Vec::new();
+    let Ok(entries) = fs::read_dir("build") else {
+        return configs;
+    };
+
+    for entry in entries.flatten() {
+        if entry.path().is_dir() {
+            let config_name = entry.file_name().to_string_lossy().to_string();
+            if is_built(&config_name) {
+                configs.push(config_name);
+            }
+        }
+    }
+
+    configs.sort();
+    configs
+}
+
+/// Boot a configuration and wait for init to report in
+fn smoke_config(config_name: &str, timeout: Duration) -> SmokeResult {
+    let result = |passed: bool, detail: String| SmokeResult {
+        config_name: config_name.to_string(),
+        passed,
+        detail,
+    };
+
+    if !is_built(config_name) {
+        return result(false, "not built".to_string());
+    }
+    println!(
+        "Booting {} (timeout {}s)...",
+        config_name,
+        timeout.as_secs()
+    );
+    let start = Instant::now();
+    let mut session = match launch(config_name, &LaunchOptions::default()) {
+        Ok(session) => session,
+        Err(e) => return result(false, format!("failed to start QEMU: {}", e)),
+    };
+
+    let mut passed = session.expect(INIT_MARKER, timeout);
+
+    // Configs serving the httpd endpoint must also be reachable from the host
+    let mut http_error = None;
+    if passed
+        && let Ok(manifest) = load_manifest(config_name)
+        && manifest.httpd
+    {
+        let port = manifest.httpd_port.unwrap_or(DEFAULT_HTTPD_PORT);
+        if let Err(e) = crate::httpd::probe(port, timeout) {
+            http_error = Some(format!("httpd on port {}: {}", port, e));
+            passed = false;
+        }
+    }
+    session.kill();
+
+    // Keep the console output around for post-mortem debugging
+    let log_path = smoke_log_path(config_name);
+    if let Err(e) = fs::write(&log_path, session.output()) {
+        eprintln!("Failed to write smoke log {}: {}", log_path.display(), e);
+    }
+
+    if let Some(e) = http_error {
+        return result(false, e);
+    }
+    if passed {
+        result(true, format!("{:.1}s", start.elapsed().as_secs_f64()))
+    } else {
+        result(
+            false,
+            format!("marker not seen, see {}", log_path.display()),
+        )
+    }
+}
+
+/// Get the console log path for a smoke run
+fn smoke_log_path(config_name: &str) -> PathBuf {
+    Path::new("build").join(config_name).join("smoke.log")
+}
+
+/// Boot each configuration and print a pass/fail table, returning true if all passed
+pub fn smoke(configs: &[String], timeout: Duration) -> bool {
+    if configs.is_empty() {
+        println!("No built configurations found.");
+        return true;
+    }
+
+    let results: Vec<SmokeResult> = configs
+        .iter()
+        .map(|config_name| smoke_config(config_name, timeout))
+        .collect();
+
+    let width = results
+        .iter()
+        .map(|r| r.config_name.len())
+        .max()
+        .unwrap_or(0)
+        .max("CONFIG".len());
+
+    println!();
+    println!("{:<width$}  RESULT  DETAIL", "CONFIG");
+    for r in &results {
+        let status = if r.passed { "PASS" } else { "FAIL" };
+        println!("{:<width$}  {:<6}  {}", r.config_name, status, r.detail);
+    }
+
+    let failed = results.iter().filter(|r| !r.passed).count();
+    println!();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    failed == 0
+}
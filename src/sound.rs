@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// Frequency of the tone played by payloads/virtio-snd
+const TONE_HZ: f64 = 440.0;
+
+/// Peak amplitude below which the recording counts as silence
+const SILENCE_PEAK: i16 = 1000;
+
+/// WAV file QEMU's audio backend writes the guest's playback to
+pub fn wav_path(config_name: &str) -> PathBuf {
+    std::env::current_dir()
+        .expect("Failed to get current directory")
+        .join("build")
+        .join(config_name)
+        .join("virtio-snd.wav")
+}
+
+/// Check that the host backend recorded the guest's tone, returning a summary
+///
+/// The header sizes are only filled in when QEMU exits cleanly, so the data chunk
+/// is taken to run to the end of the file.
+pub fn verify_tone(config_name: &str) -> Result<String, String> {
+    let path = wav_path(config_name);
+    let data = fs::read(&path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+    if data.len() < 44 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(format!("{} is not a WAV file", path.display()));
+    }
+    let u16_at = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]);
+    let u32_at = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+
+    // QEMU writes a canonical header: fmt chunk at 12, data at 36
+    let channels = u16_at(22).max(1) as usize;
+    let rate = u32_at(24);
+    let bits = u16_at(34);
+    if bits != 16 || &data[36..40] != b"data" {
+        return Err(format!("unexpected WAV layout ({} bits per sample)", bits));
+    }
+
+    // First channel only; count sign changes between the first and last loud sample
+    let samples: Vec<i16> = data[44..]
+        .chunks_exact(2 * channels)
+        .map(|frame| i16::from_le_bytes([frame[0], frame[1]]))
+        .collect();
+    let loud = |s: &i16| s.unsigned_abs() >= SILENCE_PEAK.unsigned_abs();
+    let (Some(start), Some(end)) = (
+        samples.iter().position(loud),
+        samples.iter().rposition(loud),
+    ) else {
+        return Err(format!("{} frames recorded, all silent", samples.len()));
+    };
+    let audible = &samples[start..=end];
+    let peak = audible.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+
+    let crossings = audible
+        .windows(2)
+        .filter(|w| (w[0] < 0) != (w[1] < 0))
+        .count();
+    let seconds = audible.len() as f64 / rate.max(1) as f64;
+    let frequency = crossings as f64 / 2.0 / seconds;
+    // Underruns leave gaps of silence that lower the estimate a little
+    if !(TONE_HZ * 0.8..=TONE_HZ * 1.2).contains(&frequency) {
+        return Err(format!(
+            "recorded tone is {:.0} Hz, expected {} Hz",
+            frequency, TONE_HZ
+        ));
+    }
+    Ok(format!(
+        "{} frames at {} Hz, peak {}, tone ~{:.0} Hz",
+        samples.len(),
+        rate,
+        peak,
+        frequency
+    ))
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::download::{DEFAULT_SOURCES, DefaultSource};
+
+/// Exact commits and tarball checksums of the default source trees
+pub const LOCK_FILE: &str = "sources.lock";
+
+/// What `sources.lock` pins a source tree to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockedSource {
+    pub repo: String,
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    /// Commit the ref resolved to when the lock was updated
+    pub commit: String,
+    /// Release tarball of tarball mode and its SHA-256
+    pub tarball: Option<String>,
+    pub tarball_sha256: Option<String>,
+}
+
+/// The lock's entries by source name; none when there is no lock
+fn read_lock() -> Result<BTreeMap<String, LockedSource>, String> {
+    match fs::read_to_string(LOCK_FILE) {
+        Ok(content) => toml::from_str(&content).map_err(|e| format!("{}: {}", LOCK_FILE, e)),
+        Err(_) => Ok(BTreeMap::new()),
+    }
+}
+
+/// The lock's entry of a source, failing when it pins another repository or ref
+///
+/// A source without an entry only warns, so a checkout without a lock still builds.
+fn locked(source: &DefaultSource) -> Result<Option<LockedSource>, String> {
+    let Some(entry) = read_lock()?.remove(source.name) else {
+        eprintln!(
+            "Warning: {} does not pin {}, run `update-sources` to pin it",
+            LOCK_FILE, source.name
+        );
+        return Ok(None);
+    };
+    if entry.repo != source.repo || entry.git_ref != source.git_ref {
+        return Err(format!(
+            "{} pins {} {} from {}, but {} {} is fetched now; run `update-sources`",
+            LOCK_FILE, source.name, entry.git_ref, entry.repo, source.git_ref, source.repo
+        ));
+    }
+    Ok(Some(entry))
+}
+
+/// The default source fetched into `name`; other downloads such as dropbear are not locked
+fn default_source(name: &str) -> Option<&'static DefaultSource> {
+    DEFAULT_SOURCES.iter().find(|source| source.name == name)
+}
+
+/// Check that a cloned source tree is at the commit the lock pins
+///
+/// Trees given with `--kernel-src`/`--busybox-src` or unpacked from a tarball are not
+/// git checkouts and are left alone.
+pub fn verify_checkout(name: &str) -> Result<(), String> {
+    let dir = Path::new(name);
+    if dir.is_symlink() || !dir.join(".git").exists() {
+        return Ok(());
+    }
+    let Some(source) = default_source(name) else {
+        return Ok(());
+    };
+    let Some(entry) = locked(source)? else {
+        return Ok(());
+    };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    let head = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if head == entry.commit {
+        return Ok(());
+    }
+    Err(format!(
+        "{}/ is at {}, but {} pins {} {} to {}: the ref moved upstream or the clone was \
+         tampered with. Remove {}/ to fetch it again, or run `update-sources` if the new \
+         commit is expected",
+        name, head, LOCK_FILE, entry.repo, entry.git_ref, entry.commit, name
+    ))
+}
+
+/// Check a downloaded release tarball against the checksum the lock pins; tarballs of
+/// other sources only have their published checksum
+pub fn check_tarball(name: &str, url: &str, sha256: &str) -> Result<(), String> {
+    let Some(source) = default_source(name) else {
+        return Ok(());
+    };
+    let Some(entry) = locked(source)? else {
+        return Ok(());
+    };
+    match (entry.tarball.as_deref(), entry.tarball_sha256.as_deref()) {
+        (Some(tarball), Some(expected)) if tarball == url => {
+            if expected == sha256 {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} has SHA-256 {}, but {} pins {}",
+                    url, sha256, LOCK_FILE, expected
+                ))
+            }
+        }
+        _ => Err(format!(
+            "{} does not pin {}; run `update-sources`",
+            LOCK_FILE, url
+        )),
+    }
+}
+
+/// Commit a ref of a remote repository resolves to, peeling annotated tags
+fn remote_commit(repo: &str, git_ref: &str) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(["ls-remote", repo])
+        .arg(format!("refs/tags/{}", git_ref))
+        .arg(format!("refs/tags/{}^{{}}", git_ref))
+        .arg(format!("refs/heads/{}", git_ref))
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("git ls-remote {} failed", repo));
+    }
+    let refs: Vec<(String, String)> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (commit, name) = line.split_once('\t')?;
+            Some((commit.to_string(), name.to_string()))
+        })
+        .collect();
+    refs.iter()
+        .find(|(_, name)| name.ends_with("^{}"))
+        .or_else(|| refs.first())
+        .map(|(commit, _)| commit.clone())
+        .ok_or_else(|| format!("{} has no ref {}", repo, git_ref))
+}
+
+/// Resolve the refs of the default sources and the checksums of their tarballs anew and
+/// write them to `sources.lock`, showing what changed
+pub fn update_sources() -> bool {
+    if crate::download::offline() {
+        eprintln!("update-sources needs the network, which --offline forbids");
+        return false;
+    }
+    let mut lock = match read_lock() {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    for source in &DEFAULT_SOURCES {
+        let resolved = remote_commit(source.repo, source.git_ref).and_then(|commit| {
+            let sha256 = crate::download::published_checksum(source.tarball, source.sums_url)?;
+            Ok(LockedSource {
+                repo: source.repo.to_string(),
+                git_ref: source.git_ref.to_string(),
+                commit,
+                tarball: Some(source.tarball.to_string()),
+                tarball_sha256: Some(sha256),
+            })
+        });
+        let entry = match resolved {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("Failed to resolve {}: {}", source.name, e);
+                return false;
+            }
+        };
+        match lock.get(source.name) {
+            Some(old) if *old == entry => {
+                println!(
+                    "  {:<8} {} {} (unchanged)",
+                    source.name, entry.git_ref, entry.commit
+                )
+            }
+            Some(old) => println!(
+                "  {:<8} {} {} (was {} {})",
+                source.name, entry.git_ref, entry.commit, old.git_ref, old.commit
+            ),
+            None => println!(
+                "  {:<8} {} {} (new)",
+                source.name, entry.git_ref, entry.commit
+            ),
+        }
+        lock.insert(source.name.to_string(), entry);
+    }
+
+    let content = match toml::to_string(&lock) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Failed to serialize {}: {}", LOCK_FILE, e);
+            return false;
+        }
+    };
+    let header = "# Commits and tarball checksums the default sources are verified against.\n\
+                  # Refresh with `update-sources` and review the change before committing it.\n\n";
+    if let Err(e) = fs::write(LOCK_FILE, format!("{}{}", header, content)) {
+        eprintln!("{}: {}", LOCK_FILE, e);
+        return false;
+    }
+    println!("Wrote {}", LOCK_FILE);
+    true
+}
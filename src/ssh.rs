@@ -0,0 +1,262 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{BuildError, run};
+use crate::system::make_jobs;
+
+/// Host port forwarded to the guest's dropbear when the manifest does not pick one
+pub const DEFAULT_SSH_PORT: u16 = 2222;
+
+const DROPBEAR_TARBALL: &str = "https://matt.ucc.asn.au/dropbear/releases/dropbear-2024.86.tar.bz2";
+const DROPBEAR_SHA256SUMS: &str = "https://matt.ucc.asn.au/dropbear/releases/SHA256SUM.asc";
+
+/// Directory the dropbear source is unpacked into
+pub const DROPBEAR_SOURCE: &str = "dropbear";
+
+/// The only file the build puts into the guest's `/root/.ssh`
+pub const AUTHORIZED_KEYS: &str = "authorized_keys";
+
+/// ssh exits with this status when the connection itself failed
+const SSH_FAILURE: i32 = 255;
+
+/// Private key the harness logs in with, regenerated on every rootfs build
+pub fn key_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("ssh")
+        .join("id_ed25519")
+}
+
+/// Cross-build a static dropbear for the configuration
+fn build_dropbear(
+    config_name: &str,
+    cross_compile_prefix: &Option<String>,
+) -> Result<PathBuf, BuildError> {
+    if !Path::new(DROPBEAR_SOURCE).exists() {
+        println!("Fetching dropbear source...");
+        crate::download::fetch_tarball(DROPBEAR_TARBALL, DROPBEAR_SHA256SUMS, DROPBEAR_SOURCE)
+            .map_err(|e| BuildError::Step(format!("failed to fetch dropbear: {}", e)))?;
+    }
+    let source = Path::new(DROPBEAR_SOURCE)
+        .canonicalize()
+        .map_err(BuildError::io("failed to resolve the dropbear source"))?;
+    let build_dir = PathBuf::from("build").join(config_name).join("dropbear");
+    let binary = build_dir.join("dropbear");
+    if binary.exists() {
+        return Ok(binary);
+    }
+    fs::create_dir_all(&build_dir)
+        .map_err(BuildError::io("failed to create dropbear build directory"))?;
+
+    println!("Building dropbear for {}...", config_name);
+    let mut configure = Command::new(source.join("configure"));
+    configure.current_dir(&build_dir).args([
+        "--disable-zlib",
+        "--enable-static",
+        "--disable-wtmp",
+        "--disable-lastlog",
+    ]);
+    if let Some(prefix) = cross_compile_prefix {
+        configure
+            .arg(format!("--host={}", prefix.trim_end_matches('-')))
+            .env("CC", format!("{}gcc", prefix));
+    }
+    run(&mut configure, "configuring dropbear")?;
+    run(
+        Command::new("make")
+            .current_dir(&build_dir)
+            .args(["PROGRAMS=dropbear", "STATIC=1"])
+            .arg(format!("-j{}", make_jobs())),
+        "building dropbear",
+    )?;
+    Ok(binary)
+}
+
+/// Install dropbear, its boot hook and a freshly generated login key into the rootfs
+pub fn install_ssh(
+    config_name: &str,
+    rootfs_dir: &Path,
+    cross_compile_prefix: &Option<String>,
+) -> Result<(), BuildError> {
+    let binary = build_dropbear(config_name, cross_compile_prefix)?;
+    let sbin = rootfs_dir.join("usr/sbin");
+    fs::create_dir_all(&sbin).map_err(BuildError::io("failed to create /usr/sbin in rootfs"))?;
+    fs::copy(&binary, sbin.join("dropbear"))
+        .map_err(BuildError::io("failed to install dropbear into rootfs"))?;
+
+    // A new key per build, so a stale guest never accepts a key that leaked elsewhere
+    let key = key_path(config_name);
+    fs::create_dir_all(key.parent().unwrap())
+        .map_err(BuildError::io("failed to create ssh key directory"))?;
+    let _ = fs::remove_file(&key);
+    let _ = fs::remove_file(key.with_extension("pub"));
+    run(
+        Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "guest-test", "-f"])
+            .arg(&key),
+        "generating the ssh key",
+    )?;
+    let public_key = fs::read_to_string(key.with_extension("pub"))
+        .map_err(BuildError::io("failed to read the ssh public key"))?;
+
+    let ssh_dir = rootfs_dir.join("root/.ssh");
+    fs::create_dir_all(&ssh_dir).map_err(BuildError::io("failed to create /root/.ssh"))?;
+    fs::write(ssh_dir.join(AUTHORIZED_KEYS), public_key)
+        .map_err(BuildError::io("failed to write authorized_keys"))?;
+    // dropbear refuses keys in group- or world-writable locations
+    run(
+        Command::new("chmod")
+            .args(["-R", "go-rwx"])
+            .arg(rootfs_dir.join("root")),
+        "restricting /root permissions",
+    )?;
+
+    // dropbear looks up the login user, so root needs a passwd entry
+    let etc = rootfs_dir.join("etc");
+    for (file, entry) in [
+        ("passwd", "root:x:0:0:root:/root:/bin/sh\n"),
+        ("group", "root:x:0:\n"),
+    ] {
+        if !etc.join(file).exists() {
+            fs::write(etc.join(file), entry)
+                .map_err(BuildError::io(format!("failed to write /etc/{}", file)))?;
+        }
+    }
+
+    let hooks = etc.join("hooks");
+    fs::create_dir_all(&hooks).map_err(BuildError::io("failed to create hooks directory"))?;
+    let hook = hooks.join("80-ssh");
+    fs::write(&hook, include_str!("../init/ssh/hook"))
+        .map_err(BuildError::io("failed to write ssh hook"))?;
+    run(
+        Command::new("chmod").arg("+x").arg(&hook),
+        "making the ssh hook executable",
+    )?;
+    println!("Installed dropbear with key {}", key.display());
+    Ok(())
+}
+
+/// Run a command, killing it once `timeout` passes
+fn output_with_timeout(cmd: &mut Command, timeout: Duration) -> Result<Output, String> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run ssh: {}", e))?;
+    let readers: Vec<_> = [
+        Box::new(child.stdout.take().expect("stdout is piped")) as Box<dyn Read + Send>,
+        Box::new(child.stderr.take().expect("stderr is piped")),
+    ]
+    .into_iter()
+    .map(|mut stream| {
+        thread::spawn(move || {
+            let mut data = Vec::new();
+            let _ = stream.read_to_end(&mut data);
+            data
+        })
+    })
+    .collect();
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("command did not finish within {:?}", timeout));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+    let mut streams = readers.into_iter().map(|r| r.join().unwrap_or_default());
+    Ok(Output {
+        status,
+        stdout: streams.next().unwrap_or_default(),
+        stderr: streams.next().unwrap_or_default(),
+    })
+}
+
+/// Command channel to a guest's dropbear over a forwarded port
+pub struct SshChannel {
+    port: u16,
+    key: PathBuf,
+}
+
+impl SshChannel {
+    /// Wait until the guest accepts logins on `port`
+    pub fn connect(config_name: &str, port: u16, timeout: Duration) -> Result<SshChannel, String> {
+        let key = key_path(config_name);
+        if !key.exists() {
+            return Err(format!(
+                "no ssh key at {}, rebuild the rootfs",
+                key.display()
+            ));
+        }
+        let channel = SshChannel { port, key };
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match channel.exec("true", remaining.max(Duration::from_secs(1))) {
+                Ok(_) => return Ok(channel),
+                Err(e) if Instant::now() >= deadline => return Err(e),
+                Err(_) => thread::sleep(Duration::from_millis(500)),
+            }
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-q", "-T", "-i"])
+            .arg(&self.key)
+            .args(["-p", &self.port.to_string()])
+            // The host key changes with every boot
+            .args([
+                "-o",
+                "StrictHostKeyChecking=no",
+                "-o",
+                "UserKnownHostsFile=/dev/null",
+                "-o",
+                "BatchMode=yes",
+                "-o",
+                "ConnectTimeout=5",
+                "root@127.0.0.1",
+            ]);
+        cmd
+    }
+
+    /// Run a shell command, returning its raw output and exit status
+    pub fn exec(&self, command: &str, timeout: Duration) -> Result<(Vec<u8>, i32), String> {
+        let output = output_with_timeout(self.command().arg(command), timeout)?;
+        match output.status.code() {
+            Some(SSH_FAILURE) | None => Err(format!(
+                "ssh to port {} failed: {}",
+                self.port,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+            Some(code) => Ok((output.stdout, code)),
+        }
+    }
+
+    /// Copy a file out of the guest byte for byte
+    pub fn fetch(&self, remote: &str, local: &Path, timeout: Duration) -> Result<u64, String> {
+        let (data, status) = self.exec(&format!("cat -- {}", shell_quote(remote)), timeout)?;
+        if status != 0 {
+            return Err(format!("cannot read {} in the guest", remote));
+        }
+        fs::write(local, &data)
+            .map_err(|e| format!("failed to write {}: {}", local.display(), e))?;
+        Ok(data.len() as u64)
+    }
+}
+
+/// Quote an argument for the guest shell
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+/// A part of a build that `build --only` can select on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Stage {
+    /// Configure and build the kernel, then export its artifacts
+    Kernel,
+    /// Build BusyBox
+    Busybox,
+    /// Assemble the rootfs, or the bare init, and pack its images
+    Rootfs,
+}
+
+impl Stage {
+    /// Name of the stage as `--only` takes it
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Kernel => "kernel",
+            Stage::Busybox => "busybox",
+            Stage::Rootfs => "rootfs",
+        }
+    }
+}
+
+/// Stages a build runs: all of them unless some were selected with `--only`
+#[derive(Debug, Clone, Default)]
+pub struct StageSelection {
+    only: Vec<Stage>,
+}
+
+impl StageSelection {
+    pub fn only(stages: Vec<Stage>) -> Self {
+        StageSelection { only: stages }
+    }
+
+    pub fn runs(&self, stage: Stage) -> bool {
+        self.only.is_empty() || self.only.contains(&stage)
+    }
+
+    /// Warn about stages that are skipped although their outputs are missing
+    ///
+    /// The selected stages still run; they fail or come out incomplete further on.
+    pub fn check_inputs(&self, config_name: &str, bare: bool) {
+        let build_dir = PathBuf::from("build").join(config_name);
+        let mut missing = Vec::new();
+        if !self.runs(Stage::Kernel) {
+            if !crate::qemu::kernel_image_path(config_name).is_some_and(|p| p.exists()) {
+                missing.push((Stage::Kernel, "kernel image"));
+            }
+            if self.runs(Stage::Rootfs) && !build_dir.join("linux").join(".config").exists() {
+                missing.push((
+                    Stage::Kernel,
+                    "kernel build tree, needed for modules_install",
+                ));
+            }
+            if !crate::manifest::manifest_path(config_name).exists() {
+                missing.push((Stage::Kernel, "build manifest"));
+            }
+        }
+        if !bare
+            && self.runs(Stage::Rootfs)
+            && !self.runs(Stage::Busybox)
+            && !crate::busybox::install_dir(config_name).exists()
+        {
+            missing.push((Stage::Busybox, "BusyBox install"));
+        }
+        for (stage, output) in missing {
+            eprintln!(
+                "Warning: {} has no {} from an earlier build; include {} in --only or drop --only",
+                config_name,
+                output,
+                stage.name()
+            );
+        }
+    }
+}
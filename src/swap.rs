@@ -0,0 +1,189 @@
+use serde::Deserialize;
+
+use crate::config::ConfigManifest;
+use crate::disks::{Disk, DiskFormat};
+use crate::memory::{memory_size, parse_size};
+
+/// Name of the disk a swap partition lives on, as the guest sees it under `/dev/disk/by-name/`
+pub const DISK_NAME: &str = "swap";
+
+/// Where the guest swaps to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwapKind {
+    /// `/swapfile` on the root filesystem, created at boot
+    File,
+    /// A virtio-blk disk of its own, formatted with mkswap at boot
+    Partition,
+    /// A compressed RAM disk, which swaps without any I/O to the host
+    Zram,
+}
+
+impl SwapKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SwapKind::File => "file",
+            SwapKind::Partition => "partition",
+            SwapKind::Zram => "zram",
+        }
+    }
+}
+
+/// Compression algorithm of a zram device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZramCompressor {
+    Lzo,
+    /// The kernel's default
+    #[default]
+    #[serde(rename = "lzo-rle")]
+    LzoRle,
+    Lz4,
+    Lz4hc,
+    Zstd,
+    Deflate,
+}
+
+impl ZramCompressor {
+    /// Name written to `/sys/block/zram0/comp_algorithm`
+    pub fn name(self) -> &'static str {
+        match self {
+            ZramCompressor::Lzo => "lzo",
+            ZramCompressor::LzoRle => "lzo-rle",
+            ZramCompressor::Lz4 => "lz4",
+            ZramCompressor::Lz4hc => "lz4hc",
+            ZramCompressor::Zstd => "zstd",
+            ZramCompressor::Deflate => "deflate",
+        }
+    }
+
+    /// Kernel options for the algorithm: zram backends since 6.12, crypto API before
+    fn kernel_options(self) -> &'static [&'static str] {
+        match self {
+            ZramCompressor::Lzo | ZramCompressor::LzoRle => {
+                &["CONFIG_ZRAM_BACKEND_LZO", "CONFIG_CRYPTO_LZO"]
+            }
+            ZramCompressor::Lz4 => &["CONFIG_ZRAM_BACKEND_LZ4", "CONFIG_CRYPTO_LZ4"],
+            ZramCompressor::Lz4hc => &["CONFIG_ZRAM_BACKEND_LZ4HC", "CONFIG_CRYPTO_LZ4HC"],
+            ZramCompressor::Zstd => &["CONFIG_ZRAM_BACKEND_ZSTD", "CONFIG_CRYPTO_ZSTD"],
+            ZramCompressor::Deflate => &["CONFIG_ZRAM_BACKEND_DEFLATE", "CONFIG_CRYPTO_DEFLATE"],
+        }
+    }
+}
+
+/// Guest swap, e.g. `swap = { kind = "zram", size = "512M", compressor = "zstd" }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Swap {
+    pub kind: SwapKind,
+    /// Swap space; half the guest RAM by default
+    pub size: Option<String>,
+    /// `vm.swappiness`, 0 to 200; the kernel's default of 60 when unset
+    pub swappiness: Option<u32>,
+    /// zram only
+    pub compressor: Option<ZramCompressor>,
+}
+
+/// Swap space in whole MiB, rounded up
+fn size_mb(manifest: &ConfigManifest, swap: &Swap) -> Result<u64, String> {
+    let bytes = match &swap.size {
+        Some(size) => parse_size(size)?,
+        None => memory_size(manifest)? / 2,
+    };
+    Ok(bytes.div_ceil(1 << 20))
+}
+
+/// Check the swap settings of a manifest
+pub fn validate(manifest: &ConfigManifest) -> Result<(), String> {
+    let Some(swap) = &manifest.swap else {
+        return Ok(());
+    };
+    if manifest.bare {
+        return Err("swap needs the busybox init and cannot be combined with bare".to_string());
+    }
+    // Swap files need a block-mapped, writable filesystem
+    if swap.kind == SwapKind::File
+        && (manifest.initramfs || manifest.readonly_root || manifest.nfsroot)
+    {
+        return Err(
+            "swap kind \"file\" cannot be combined with initramfs, readonly_root or nfsroot; \
+             use \"partition\" or \"zram\""
+                .to_string(),
+        );
+    }
+    if swap.compressor.is_some() && swap.kind != SwapKind::Zram {
+        return Err("swap compressor requires kind = \"zram\"".to_string());
+    }
+    if swap.swappiness.is_some_and(|swappiness| swappiness > 200) {
+        return Err("swap swappiness must be between 0 and 200".to_string());
+    }
+    if swap.kind == SwapKind::Partition && manifest.disks.iter().any(|d| d.name == DISK_NAME) {
+        return Err(format!(
+            "disk {} is taken by the swap partition; rename it",
+            DISK_NAME
+        ));
+    }
+    if size_mb(manifest, swap)? == 0 {
+        return Err("swap has no size".to_string());
+    }
+    Ok(())
+}
+
+/// Kernel options needed by the swap settings
+pub fn kernel_options(manifest: &ConfigManifest) -> Vec<&'static str> {
+    let Some(swap) = &manifest.swap else {
+        return Vec::new();
+    };
+    let mut options = vec!["CONFIG_SWAP"];
+    if swap.kind == SwapKind::Zram {
+        options.extend(["CONFIG_ZSMALLOC", "CONFIG_ZRAM"]);
+        options.extend(swap.compressor.unwrap_or_default().kernel_options());
+    }
+    options
+}
+
+/// Kernel parameters telling the swap hook what to set up
+pub fn cmdline_params(manifest: &ConfigManifest) -> Vec<String> {
+    let Some(swap) = &manifest.swap else {
+        return Vec::new();
+    };
+    let Ok(size) = size_mb(manifest, swap) else {
+        return Vec::new();
+    };
+    let mut params = vec![
+        format!("guest_test.swap={}", swap.kind.name()),
+        format!("guest_test.swap_size={}", size),
+    ];
+    if swap.kind == SwapKind::Zram {
+        params.push(format!(
+            "guest_test.swap_comp={}",
+            swap.compressor.unwrap_or_default().name()
+        ));
+    }
+    if let Some(swappiness) = swap.swappiness {
+        params.push(format!("guest_test.swappiness={}", swappiness));
+    }
+    params
+}
+
+/// Space the rootfs image must leave free for a swap file, in MiB
+pub fn reserved_mb(manifest: &ConfigManifest) -> u64 {
+    match &manifest.swap {
+        Some(swap) if swap.kind == SwapKind::File => size_mb(manifest, swap).unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// The disk holding a swap partition
+pub fn disk(manifest: &ConfigManifest) -> Option<Disk> {
+    let swap = manifest
+        .swap
+        .as_ref()
+        .filter(|s| s.kind == SwapKind::Partition)?;
+    Some(Disk {
+        name: DISK_NAME.to_string(),
+        size: format!("{}M", size_mb(manifest, swap).ok()?),
+        format: DiskFormat::Raw,
+        persistent: false,
+    })
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Serialize;
+
+use crate::config::ConfigManifest;
+use crate::error::{BuildError, run};
+
+/// Guest kernel symbol table written to `build/<config>/symbols.json`
+#[derive(Debug, Serialize)]
+struct SymbolTable {
+    config: String,
+    symbols: Vec<Symbol>,
+}
+
+/// A single `System.map` entry
+#[derive(Debug, Serialize)]
+struct Symbol {
+    /// Link-time address as a hex string (JSON numbers cannot hold 64-bit addresses)
+    address: String,
+    /// `nm` symbol type, e.g. `T` for global text
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+}
+
+/// Parse the lines of a `System.map` into symbols
+fn parse_system_map(content: &str) -> Vec<Symbol> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let address = fields.next()?;
+            let kind = fields.next()?;
+            let name = fields.next()?;
+            Some(Symbol {
+                address: format!("0x{}", address),
+                kind: kind.to_string(),
+                name: name.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Extract the ORC unwind sections of vmlinux into a gzipped ELF
+fn export_orc(
+    output_dir: &Path,
+    kernel_build_dir: &Path,
+    cross_compile_prefix: &Option<String>,
+) -> Result<(), BuildError> {
+    let objcopy = format!(
+        "{}objcopy",
+        cross_compile_prefix.as_deref().unwrap_or_default()
+    );
+    let orc = output_dir.join("orc.elf");
+
+    run(
+        Command::new(&objcopy).args([
+            "--only-section=.orc_header",
+            "--only-section=.orc_unwind_ip",
+            "--only-section=.orc_unwind",
+            &kernel_build_dir.join("vmlinux").to_string_lossy(),
+            &orc.to_string_lossy(),
+        ]),
+        "extracting the ORC sections",
+    )?;
+    run(
+        Command::new("gzip").args(["-9", "-f"]).arg(&orc),
+        "compressing the ORC sections",
+    )?;
+    println!("ORC unwind info written: {}.gz", orc.display());
+    Ok(())
+}
+
+/// Export `System.map`, a JSON symbol table and optionally ORC unwind info
+/// into `build/<config>/` for hypervisor-side tracers
+pub fn export_symbols(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    kernel_build_dir: &Path,
+    cross_compile_prefix: &Option<String>,
+) -> Result<(), BuildError> {
+    let output_dir = PathBuf::from("build").join(config_name);
+    let system_map = output_dir.join("System.map");
+    fs::copy(kernel_build_dir.join("System.map"), &system_map)
+        .map_err(BuildError::io("failed to copy System.map"))?;
+
+    let content =
+        fs::read_to_string(&system_map).map_err(BuildError::io("failed to read System.map"))?;
+    let table = SymbolTable {
+        config: config_name.to_string(),
+        symbols: parse_system_map(&content),
+    };
+    let path = output_dir.join("symbols.json");
+    let json = serde_json::to_string(&table)
+        .map_err(|e| BuildError::Step(format!("failed to serialize the symbol table: {}", e)))?;
+    fs::write(&path, json).map_err(BuildError::io(format!(
+        "failed to write {}",
+        path.display()
+    )))?;
+    println!(
+        "Symbol table written: {} ({} symbols)",
+        path.display(),
+        table.symbols.len()
+    );
+
+    if manifest.orc {
+        export_orc(&output_dir, kernel_build_dir, cross_compile_prefix)?;
+    }
+    Ok(())
+}
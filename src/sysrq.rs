@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{load_manifest, parse_config_name};
+use crate::expect::SHELL_PARAM;
+use crate::qemu::{LaunchOptions, is_built, launch};
+
+/// How long to collect output after each sysrq command
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+
+/// Describe a sysrq key for the log
+fn describe(key: char) -> &'static str {
+    match key {
+        'c' => "trigger crash",
+        'l' => "backtrace of all active CPUs",
+        'm' => "memory info",
+        'p' => "registers and flags",
+        'q' => "armed timers",
+        't' => "task list",
+        'w' => "blocked tasks",
+        _ => "sysrq command",
+    }
+}
+
+/// Boot a configuration, send sysrq commands once it is up and log the output
+pub fn sysrq_debug(config_name: &str, keys: &[char], wait_for: &str, timeout: Duration) -> bool {
+    let manifest = match load_manifest(config_name) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return false;
+        }
+    };
+    if !manifest.profiles.iter().any(|p| p == "debug") {
+        println!(
+            "Warning: {} does not enable the debug profile, sysrq may be disabled",
+            config_name
+        );
+    }
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return false;
+    }
+
+    let (arch, _) = parse_config_name(config_name);
+    let console = manifest.console(&arch);
+    // Keep the guest at a shell after its tests, so a healthy guest does not power off
+    // while the commands are sent
+    let options = LaunchOptions {
+        extra_cmdline: vec![SHELL_PARAM.to_string()],
+        ..LaunchOptions::default()
+    };
+    let mut session = match launch(config_name, &options) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Failed to start QEMU: {}", e);
+            return false;
+        }
+    };
+
+    // A stuck guest may never print the marker; send the commands anyway
+    if !session.expect(wait_for, timeout) {
+        println!("'{}' not seen within {}s", wait_for, timeout.as_secs());
+    }
+
+    for &key in keys {
+        println!("Sending sysrq-{} ({})...", key, describe(key));
+        let start = session.output().len();
+        if !session.send_sysrq(console, key) {
+            eprintln!("Failed to write to the guest console");
+            break;
+        }
+        thread::sleep(SETTLE_TIME);
+        let output = session.output();
+        println!("{}", output[start..].trim_end());
+    }
+    session.kill();
+
+    let log_path = PathBuf::from("build").join(config_name).join("sysrq.log");
+    match fs::write(&log_path, session.output()) {
+        Ok(_) => {
+            println!("Console log written: {}", log_path.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", log_path.display(), e);
+            false
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::BackendKind;
+use crate::config::{load_manifest, parse_config_name};
+use crate::harness::GuestSession;
+use crate::qemu::{LaunchOptions, is_built, launch};
+use crate::qmp::Qmp;
+
+/// Printed by init right before the first guest test
+pub const TESTS_START_MARKER: &str = "Running guest tests...";
+
+/// Summary line printed by init when every guest test passed
+pub const ALL_PASSED_MARKER: &str = "All tests passed!";
+
+/// Summary line printed by init when a guest test failed
+pub const SOME_FAILED_MARKER: &str = "Some tests failed!";
+
+/// Prefix of benchmark lines printed by guest tests: `METRIC: <name> <value> [unit]`
+pub const METRIC_PREFIX: &str = "METRIC: ";
+
+/// Console expectations of a configuration, `[expect]` in its manifest
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Expectations {
+    /// Text that must show up on the console, in this order (e.g. "Run /init as init process")
+    pub markers: Vec<String>,
+    /// Text that must never show up (e.g. "Oops", "BUG:")
+    pub refute: Vec<String>,
+}
+
+impl Expectations {
+    /// Check the declared patterns of a manifest
+    pub fn validate(&self) -> Result<(), String> {
+        if self
+            .markers
+            .iter()
+            .chain(&self.refute)
+            .any(String::is_empty)
+        {
+            return Err("expect markers and refute patterns cannot be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// First marker missing from `console` when matching them one after another
+    fn first_missing(&self, console: &str) -> Option<&str> {
+        let mut rest = console;
+        for marker in &self.markers {
+            match rest.find(marker.as_str()) {
+                Some(pos) => rest = &rest[pos + marker.len()..],
+                None => return Some(marker),
+            }
+        }
+        None
+    }
+
+    /// Check a complete console log against the expectations
+    pub fn check(&self, console: &str) -> Result<String, String> {
+        if let Some(marker) = self.first_missing(console) {
+            return Err(format!("marker {:?} not seen in order", marker));
+        }
+        if let Some(pattern) = self.refute.iter().find(|p| console.contains(p.as_str())) {
+            return Err(format!("unexpected {:?} on the console", pattern));
+        }
+        Ok(format!(
+            "{} markers, {} refuted",
+            self.markers.len(),
+            self.refute.len()
+        ))
+    }
+
+    /// Keep reading the console until every marker showed up in order or `timeout` passes
+    fn wait(&self, session: &mut GuestSession, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while let Some(marker) = self.first_missing(session.output()).map(str::to_string) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !session.expect(&marker, remaining) {
+                break;
+            }
+        }
+    }
+}
+
+/// Outcome of one guest test or host-side check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+/// One guest test or host-side check, e.g. `00-mounts`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub status: TestStatus,
+    /// What a host-side check found, if it says more than pass/fail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// How long an in-guest test ran, as reported by init
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+}
+
+/// A benchmark value reported by a guest test
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metric {
+    pub value: f64,
+    #[serde(default)]
+    pub unit: String,
+}
+
+/// Results of one boot of a configuration's guest tests
+#[derive(Debug, Default)]
+pub struct TestRun {
+    pub passed: bool,
+    pub cases: Vec<TestCase>,
+    pub metrics: BTreeMap<String, Metric>,
+    pub duration: Duration,
+    /// Time from starting the guest until init began the tests
+    pub boot_secs: Option<f64>,
+    /// Console output of the guest
+    pub console: String,
+    /// Host-side traces of the run, such as QMP events and a memory dump, for failure bundles
+    pub evidence: Vec<PathBuf>,
+}
+
+impl TestRun {
+    /// Record the outcome of a host-side check
+    fn host_check(&mut self, name: &str, result: Result<String, String>) {
+        let (status, detail) = match result {
+            Ok(detail) => (TestStatus::Pass, detail),
+            Err(detail) => (TestStatus::Fail, detail),
+        };
+        let line = if detail.is_empty() {
+            name.to_string()
+        } else {
+            format!("{} ({})", name, detail)
+        };
+        match status {
+            TestStatus::Pass => println!("  PASS: {}", line),
+            _ => {
+                println!("  FAIL: {}", line);
+                self.passed = false;
+            }
+        }
+        self.cases.push(TestCase {
+            name: name.to_string(),
+            status,
+            detail: (!detail.is_empty()).then_some(detail),
+            duration_secs: None,
+        });
+    }
+}
+
+/// Parse a result line printed by init, e.g. `PASS: 00-mounts (0.12s)`
+pub fn parse_result(line: &str) -> Option<(&str, TestStatus, Option<f64>)> {
+    let (status, rest) = if let Some(rest) = line.strip_prefix("PASS: ") {
+        (TestStatus::Pass, rest)
+    } else if let Some(rest) = line.strip_prefix("FAIL: ") {
+        (TestStatus::Fail, rest)
+    } else {
+        (TestStatus::Skip, line.strip_prefix("SKIP: ")?)
+    };
+    let duration = rest
+        .rsplit_once(" (")
+        .and_then(|(name, time)| Some((name, time.strip_suffix("s)")?.parse().ok()?)));
+    Some(match duration {
+        Some((name, secs)) => (name, status, Some(secs)),
+        None => (rest, status, None),
+    })
+}
+
+/// Parse a `METRIC:` line into its name and value
+fn parse_metric(line: &str) -> Option<(String, Metric)> {
+    let mut fields = line.strip_prefix(METRIC_PREFIX)?.split_whitespace();
+    let name = fields.next()?.to_string();
+    let value = fields.next()?.parse().ok()?;
+    let unit = fields.next().unwrap_or("").to_string();
+    Some((name, Metric { value, unit }))
+}
+
+/// Kernel parameters passing `--filter`/`--exclude` to the guest's tests.d runner
+///
+/// Several patterns are combined into one alternation; the kernel command line
+/// cannot carry whitespace or quotes inside a parameter.
+pub fn selection_params(filter: &[String], exclude: &[String]) -> Result<Vec<String>, String> {
+    let mut params = Vec::new();
+    for (name, patterns) in [("filter", filter), ("exclude", exclude)] {
+        if patterns.is_empty() {
+            continue;
+        }
+        if let Some(bad) = patterns
+            .iter()
+            .find(|p| p.is_empty() || p.contains(|c: char| c.is_whitespace() || c == '"'))
+        {
+            return Err(format!("invalid --{} pattern {:?}", name, bad));
+        }
+        params.push(format!("guest_test.{}={}", name, patterns.join("|")));
+    }
+    Ok(params)
+}
+
+/// Record the QMP events of a run in the background, until QEMU exits
+fn record_events(config_name: &str, socket: PathBuf) -> JoinHandle<Option<PathBuf>> {
+    let path = PathBuf::from("build")
+        .join(config_name)
+        .join("qmp-events.jsonl");
+    thread::spawn(move || {
+        let recorded = Qmp::connect(&socket, Duration::from_secs(10)).and_then(|qmp| {
+            let mut file = fs::File::create(&path).map_err(|e| e.to_string())?;
+            qmp.record_events(&mut file)
+        });
+        match recorded {
+            Ok(()) => Some(path),
+            Err(e) => {
+                eprintln!("Warning: no QMP event trace: {}", e);
+                None
+            }
+        }
+    })
+}
+
+/// Boot a configuration and wait for init to report the guest test results
+///
+/// With `memory_dump`, a kernel panic ends the wait for the results and a failed run
+/// captures the guest memory and vCPU registers before the guest is stopped.
+pub fn run_tests(
+    config_name: &str,
+    options: &LaunchOptions,
+    timeout: Duration,
+    memory_dump: bool,
+) -> TestRun {
+    let mut run = TestRun::default();
+    if !is_built(config_name) {
+        eprintln!("{} has not been built", config_name);
+        return run;
+    }
+    let manifest = match load_manifest(config_name) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Invalid manifest for configuration {}: {}", config_name, e);
+            return run;
+        }
+    };
+    let has_profile = |name: &str| manifest.profiles.iter().any(|p| p == name);
+    let (arch, _) = parse_config_name(config_name);
+    let qmp_hotplug = has_profile("cpu-hotplug") && crate::cpu_hotplug::qmp_hotplug(&arch);
+
+    // Input events are injected, virtio-mem and the balloon are resized and vCPUs are
+    // plugged over QMP
+    let mut options = options.clone();
+    let qmp_socket = PathBuf::from("build").join(config_name).join("qmp.sock");
+    if has_profile("virtio-input")
+        || has_profile("virtio-mem")
+        || has_profile("oom")
+        || qmp_hotplug
+        || memory_dump
+    {
+        let _ = fs::remove_file(&qmp_socket);
+        options.qmp = Some(qmp_socket.clone());
+    }
+    // Only QEMU serves a second monitor; other backends run without an event trace
+    let events_socket = PathBuf::from("build")
+        .join(config_name)
+        .join("qmp-events.sock");
+    if options.backend == BackendKind::Qemu {
+        let _ = fs::remove_file(&events_socket);
+        options.qmp_events = Some(events_socket.clone());
+    }
+
+    let started = Instant::now();
+    let mut session = match launch(config_name, &options) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Failed to start the guest: {}", e);
+            return run;
+        }
+    };
+
+    let events = options
+        .qmp_events
+        .is_some()
+        .then(|| record_events(config_name, events_socket));
+
+    println!("Running guest tests for {}...", config_name);
+    let results = [ALL_PASSED_MARKER, SOME_FAILED_MARKER];
+    let mut input_error = None;
+    let mut early_result = None;
+    if has_profile("virtio-input") {
+        // The tests may end before the payload gets ready, e.g. when it fails early
+        match session.expect_any(
+            &[crate::input::READY_MARKER, results[0], results[1]],
+            timeout,
+        ) {
+            Some(0) => {
+                input_error = Qmp::connect(&qmp_socket, timeout)
+                    .and_then(|mut qmp| crate::input::inject_test_events(&mut qmp))
+                    .err();
+            }
+            other => early_result = Some(other.map(|index| index - 1)),
+        }
+    }
+    let mut resize_error = None;
+    if has_profile("virtio-mem") && early_result.is_none() {
+        let mut qmp = None;
+        for &(marker, size) in crate::virtio_mem::RESIZE_STEPS {
+            match session.expect_any(&[marker, results[0], results[1]], timeout) {
+                Some(0) => {
+                    if qmp.is_none() {
+                        match Qmp::connect(&qmp_socket, timeout) {
+                            Ok(connection) => qmp = Some(connection),
+                            Err(e) => {
+                                resize_error = Some(e);
+                                break;
+                            }
+                        }
+                    }
+                    if let Some(qmp) = &mut qmp
+                        && let Err(e) = crate::virtio_mem::resize(qmp, size)
+                    {
+                        resize_error = Some(e);
+                        break;
+                    }
+                }
+                other => {
+                    early_result = Some(other.map(|index| index - 1));
+                    break;
+                }
+            }
+        }
+    }
+
+    // The balloon shrinks the guest for its global OOM and must hold through it
+    let mut balloon_check = None;
+    if has_profile("oom") && early_result.is_none() {
+        let memory =
+            crate::memory::memory_size(&manifest).unwrap_or(crate::memory::DEFAULT_MEMORY);
+        let inflated = memory - crate::oom::BALLOON_SIZE;
+        let mut qmp = None;
+        for (marker, target) in [
+            (crate::oom::INFLATE_MARKER, inflated),
+            (crate::oom::DEFLATE_MARKER, memory),
+        ] {
+            match session.expect_any(&[marker, results[0], results[1]], timeout) {
+                Some(0) => {
+                    if qmp.is_none() {
+                        match Qmp::connect(&qmp_socket, timeout) {
+                            Ok(connection) => qmp = Some(connection),
+                            Err(e) => {
+                                balloon_check = Some(Err(e));
+                                break;
+                            }
+                        }
+                    }
+                    if let Some(qmp) = &mut qmp {
+                        let held = if target == memory {
+                            crate::oom::check_held(qmp, inflated)
+                        } else {
+                            Ok(())
+                        };
+                        if let Err(e) = held.and_then(|()| crate::oom::resize(qmp, target)) {
+                            balloon_check = Some(Err(e));
+                            break;
+                        }
+                    }
+                    if target == memory {
+                        balloon_check = Some(Ok(format!(
+                            "held {} through the OOM",
+                            crate::memory::format_size(crate::oom::BALLOON_SIZE)
+                        )));
+                    }
+                }
+                other => {
+                    early_result = Some(other.map(|index| index - 1));
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut hotplug_error = None;
+    if qmp_hotplug && early_result.is_none() {
+        let mut added = Vec::new();
+        let markers = [
+            crate::cpu_hotplug::READY_MARKER,
+            crate::cpu_hotplug::ADDED_MARKER,
+        ];
+        for (step, marker) in markers.into_iter().enumerate() {
+            match session.expect_any(&[marker, results[0], results[1]], timeout) {
+                Some(0) => {
+                    let outcome = Qmp::connect(&qmp_socket, timeout).and_then(|mut qmp| {
+                        if step == 0 {
+                            added = crate::cpu_hotplug::add_cpus(&mut qmp)?;
+                            Ok(())
+                        } else {
+                            crate::cpu_hotplug::remove_cpus(&mut qmp, &added)
+                        }
+                    });
+                    if let Err(e) = outcome {
+                        hotplug_error = Some(e);
+                        break;
+                    }
+                }
+                other => {
+                    early_result = Some(other.map(|index| index - 1));
+                    break;
+                }
+            }
+        }
+    }
+
+    // A panicked guest would only be dumped once the timeout ran out
+    let mut endings = results.to_vec();
+    if memory_dump {
+        endings.push(crate::coredump::PANIC_SIGNATURE);
+    }
+    let result = early_result.unwrap_or_else(|| session.expect_any(&endings, timeout));
+    run.passed = result == Some(0);
+    if !run.passed && memory_dump {
+        match result {
+            None => println!("Guest tests timed out, capturing the guest state..."),
+            Some(2) => println!("Guest kernel panicked, capturing the guest state..."),
+            _ => {}
+        }
+        run.evidence
+            .extend(crate::coredump::capture(config_name, &qmp_socket));
+    }
+    // Expected markers may come after the test results, e.g. a final shell prompt
+    let has_expectations =
+        !manifest.expect.markers.is_empty() || !manifest.expect.refute.is_empty();
+    if has_expectations {
+        manifest.expect.wait(&mut session, timeout);
+    }
+
+    // Host-side half of the virtio-snd test; QEMU finalizes the WAV file on exit
+    let sound_check = (run.passed && has_profile("virtio-snd")).then(|| {
+        session.wait_exit(Duration::from_secs(10));
+        crate::sound::verify_tone(config_name)
+    });
+    session.kill();
+    if let Some(events) = events
+        && let Ok(Some(path)) = events.join()
+    {
+        run.evidence.push(path);
+    }
+    run.duration = started.elapsed();
+    run.console = session.output().to_string();
+    run.boot_secs = session
+        .seen_at(TESTS_START_MARKER)
+        .map(|seen| seen.duration_since(started).as_secs_f64());
+
+    for line in run.console.lines() {
+        let line = line.trim();
+        if let Some((name, status, duration_secs)) = parse_result(line) {
+            if status != TestStatus::Skip {
+                println!("  {}", line);
+            }
+            run.cases.push(TestCase {
+                name: name.to_string(),
+                status,
+                detail: None,
+                duration_secs,
+            });
+        } else if let Some((name, metric)) = parse_metric(line) {
+            run.metrics.insert(name, metric);
+        }
+    }
+    let pstore_records = crate::pstore::records(&run.console);
+    if !pstore_records.is_empty() {
+        println!(
+            "  Crash logs from an earlier boot (pstore): {}",
+            crate::pstore::record_names(&pstore_records)
+        );
+    }
+    let count = |status| run.cases.iter().filter(|c| c.status == status).count();
+    let skipped = count(TestStatus::Skip);
+    if skipped > 0 {
+        println!(
+            "  {} selected, {} skipped by --filter/--exclude",
+            count(TestStatus::Pass) + count(TestStatus::Fail),
+            skipped
+        );
+    }
+    if let Some(e) = input_error {
+        run.host_check("virtio-input event injection", Err(e));
+    }
+    if let Some(e) = resize_error {
+        run.host_check("virtio-mem resize", Err(e));
+    }
+    if let Some(e) = hotplug_error {
+        run.host_check("vCPU hotplug", Err(e));
+    }
+    if let Some(result) = balloon_check {
+        run.host_check("virtio-balloon", result);
+    }
+    if let Some(result) = sound_check {
+        run.host_check("virtio-snd host backend", result);
+    }
+    if has_expectations {
+        let result = manifest.expect.check(&run.console);
+        run.host_check("console expectations", result);
+    }
+    if let Some(boot_secs) = run.boot_secs {
+        run.metrics.insert(
+            "boot_time".to_string(),
+            Metric {
+                value: boot_secs,
+                unit: "s".to_string(),
+            },
+        );
+        let result = crate::budget::check_boot(config_name, &manifest, boot_secs);
+        if manifest.budget.boot_secs.is_some() {
+            run.host_check("boot time budget", result);
+        }
+    }
+    println!(
+        "Guest tests {} for {}",
+        if run.passed { "passed" } else { "failed" },
+        config_name
+    );
+    run
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::config::ConfigManifest;
+
+/// How long swtpm gets to create its control socket
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Register interface of the emulated TPM 2.0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TpmInterface {
+    /// TPM Interface Specification, FIFO registers; available on every machine
+    Tis,
+    /// Command Response Buffer, found through ACPI (x86 only)
+    Crb,
+}
+
+impl TpmInterface {
+    /// Kernel options for the interface's driver
+    pub fn kernel_options(self) -> &'static [&'static str] {
+        match self {
+            TpmInterface::Tis => &["CONFIG_TCG_TPM", "CONFIG_TCG_TIS"],
+            TpmInterface::Crb => &["CONFIG_TCG_TPM", "CONFIG_ACPI", "CONFIG_TCG_CRB"],
+        }
+    }
+}
+
+/// Check the TPM settings of a manifest
+pub fn validate(manifest: &ConfigManifest, arch: &str) -> Result<(), String> {
+    if manifest.tpm == Some(TpmInterface::Crb) && arch != "x86" {
+        return Err(format!("tpm = \"crb\" is not supported on {}", arch));
+    }
+    Ok(())
+}
+
+/// swtpm state directory of a configuration; the TPM's NVRAM persists across boots
+fn state_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("tpm")
+}
+
+/// Control socket QEMU talks to swtpm over
+fn socket_path(config_name: &str) -> PathBuf {
+    state_dir(config_name).join("swtpm.sock")
+}
+
+/// QEMU arguments connecting the configuration's swtpm as the guest's TPM
+pub fn qemu_args(config_name: &str, manifest: &ConfigManifest) -> Vec<String> {
+    let Some(interface) = manifest.tpm else {
+        return Vec::new();
+    };
+    let (arch, _) = crate::config::parse_config_name(config_name);
+    // On devicetree machines the TIS is a sysbus device QEMU adds to the DT
+    let devicetree = crate::arch::find_arch(&arch).is_some_and(|a| a.devicetree);
+    let device = match interface {
+        TpmInterface::Tis if devicetree => "tpm-tis-device",
+        TpmInterface::Tis => "tpm-tis",
+        TpmInterface::Crb => "tpm-crb",
+    };
+    vec![
+        "-chardev".to_string(),
+        format!(
+            "socket,id=chrtpm,path={}",
+            socket_path(config_name).display()
+        ),
+        "-tpmdev".to_string(),
+        "emulator,id=tpm0,chardev=chrtpm".to_string(),
+        "-device".to_string(),
+        format!("{},tpmdev=tpm0", device),
+    ]
+}
+
+/// Kernel parameters telling the guest's TPM test to run
+pub fn cmdline_params(manifest: &ConfigManifest) -> Vec<String> {
+    match manifest.tpm {
+        Some(_) => vec!["guest_test.tpm".to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// Start swtpm for a configuration that requests a TPM, once its socket is up
+pub fn start_swtpm(config_name: &str, manifest: &ConfigManifest) -> Result<Option<Child>, String> {
+    if manifest.tpm.is_none() {
+        return Ok(None);
+    }
+
+    let dir = state_dir(config_name);
+    let socket = socket_path(config_name);
+    fs::create_dir_all(&dir).map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    let _ = fs::remove_file(&socket);
+
+    println!("Starting swtpm with state in {}...", dir.display());
+    // --terminate makes swtpm exit once QEMU disconnects
+    let mut child = Command::new("swtpm")
+        .args(["socket", "--tpm2", "--terminate"])
+        .arg("--tpmstate")
+        .arg(format!("dir={}", dir.display()))
+        .arg("--ctrl")
+        .arg(format!("type=unixio,path={}", socket.display()))
+        .arg("--log")
+        .arg(format!("file={},level=1", dir.join("swtpm.log").display()))
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to run swtpm ({}); install swtpm", e))?;
+
+    let start = Instant::now();
+    while !socket.exists() {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("swtpm exited early: {}", status));
+        }
+        if start.elapsed() > SOCKET_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("swtpm did not create {}", socket.display()));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(Some(child))
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::qemu::rootfs_image_path;
+
+/// dm-verity data and hash block size
+const BLOCK_SIZE: u64 = 4096;
+
+/// Kernel options for a dm-verity root set up from the command line
+pub const VERITY_KERNEL_OPTIONS: &[&str] = &[
+    "CONFIG_MD",
+    "CONFIG_BLK_DEV_DM",
+    "CONFIG_DM_INIT",
+    "CONFIG_DM_VERITY",
+];
+
+/// Parameters of the hash tree appended to a rootfs image
+struct VerityInfo {
+    root_hash: String,
+    salt: String,
+    algorithm: String,
+    /// Filesystem blocks covered by the tree; the tree starts right after them
+    data_blocks: u64,
+}
+
+/// File recording the verity parameters next to the image
+fn info_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("rootfs.verity")
+}
+
+/// Root hash alone, for attestation and boot loaders
+fn root_hash_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build")
+        .join(config_name)
+        .join("rootfs.roothash")
+}
+
+/// Field of `veritysetup format` output such as `Root hash:      abcd...`
+fn field(output: &str, name: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == name).then(|| value.trim().to_string())
+    })
+}
+
+/// Append a dm-verity hash tree to the rootfs image and record its root hash
+///
+/// The tree has no superblock, so the kernel only needs the table on its command
+/// line and the image stays a single disk.
+pub fn append_hash_tree(config_name: &str) -> bool {
+    let image = rootfs_image_path(config_name);
+    let size = match fs::metadata(&image) {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            eprintln!("Cannot protect {}: {}", image.display(), e);
+            return false;
+        }
+    };
+    if !size.is_multiple_of(BLOCK_SIZE) {
+        eprintln!("{} is not a whole number of blocks", image.display());
+        return false;
+    }
+
+    println!("Appending dm-verity hash tree to {}...", image.display());
+    let output = Command::new("veritysetup")
+        .args([
+            "format",
+            "--no-superblock",
+            &format!("--data-blocks={}", size / BLOCK_SIZE),
+            &format!("--hash-offset={}", size),
+        ])
+        .arg(&image)
+        .arg(&image)
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+        Ok(output) => {
+            eprintln!(
+                "veritysetup format failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Failed to run veritysetup (install cryptsetup): {}", e);
+            return false;
+        }
+    };
+
+    let (Some(root_hash), Some(salt), Some(algorithm)) = (
+        field(&output, "Root hash"),
+        field(&output, "Salt"),
+        field(&output, "Hash algorithm"),
+    ) else {
+        eprintln!("Unexpected veritysetup output:\n{}", output);
+        return false;
+    };
+
+    let info = format!(
+        "root_hash={}\nsalt={}\nalgorithm={}\ndata_blocks={}\n",
+        root_hash,
+        salt,
+        algorithm,
+        size / BLOCK_SIZE
+    );
+    if let Err(e) = fs::write(info_path(config_name), info)
+        .and_then(|_| fs::write(root_hash_path(config_name), format!("{}\n", root_hash)))
+    {
+        eprintln!("Failed to record the verity root hash: {}", e);
+        return false;
+    }
+    println!("dm-verity root hash: {}", root_hash);
+    true
+}
+
+/// Read the verity parameters recorded by the build
+fn read_info(config_name: &str) -> Result<VerityInfo, String> {
+    let path = info_path(config_name);
+    let content = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let value = |key: &str| {
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(str::to_string)
+            .ok_or_else(|| format!("{}: missing {}", path.display(), key))
+    };
+    Ok(VerityInfo {
+        root_hash: value("root_hash")?,
+        salt: value("salt")?,
+        algorithm: value("algorithm")?,
+        data_blocks: value("data_blocks")?
+            .parse()
+            .map_err(|_| format!("{}: bad data_blocks", path.display()))?,
+    })
+}
+
+/// Kernel parameters mapping `device` through dm-verity and booting from the result
+pub fn cmdline_params(config_name: &str, device: &str) -> Vec<String> {
+    let info = match read_info(config_name) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("No dm-verity parameters, build the config first: {}", e);
+            return vec![format!("root={}", device)];
+        }
+    };
+    let sectors = info.data_blocks * BLOCK_SIZE / 512;
+    let table = format!(
+        "0 {} verity 1 {} {} {} {} {} {} {} {} {}",
+        sectors,
+        device,
+        device,
+        BLOCK_SIZE,
+        BLOCK_SIZE,
+        info.data_blocks,
+        info.data_blocks,
+        info.algorithm,
+        info.root_hash,
+        info.salt
+    );
+    vec![
+        format!("dm-mod.create=\"vroot,,,ro,{}\"", table),
+        format!("dm-mod.waitfor={}", device),
+        "root=/dev/dm-0".to_string(),
+    ]
+}
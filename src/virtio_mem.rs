@@ -0,0 +1,36 @@
+use serde_json::json;
+
+use crate::qmp::Qmp;
+
+/// Hotpluggable memory behind the virtio-mem device, on top of the boot memory
+pub const REGION_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Memory plugged by the test; payloads/virtio-mem expects the same amount
+pub const PLUG_SIZE: u64 = 256 * 1024 * 1024;
+
+/// QOM path of the virtio-mem device
+const DEVICE_PATH: &str = "/machine/peripheral/vmem0";
+
+/// Guest markers after which the harness resizes the device, and the new size
+pub const RESIZE_STEPS: &[(&str, u64)] =
+    &[("virtio-mem: ready", PLUG_SIZE), ("virtio-mem: plugged", 0)];
+
+/// QEMU arguments adding the virtio-mem device; `-m` needs `maxmem` to make room for it
+pub fn qemu_args() -> Vec<String> {
+    vec![
+        "-object".to_string(),
+        format!("memory-backend-ram,id=vmem,size={}", REGION_SIZE),
+        "-device".to_string(),
+        "virtio-mem-pci,id=vmem0,memdev=vmem,requested-size=0".to_string(),
+    ]
+}
+
+/// Ask the guest to plug or unplug memory until the device provides `size` bytes
+pub fn resize(qmp: &mut Qmp, size: u64) -> Result<(), String> {
+    println!("Resizing virtio-mem to {} bytes...", size);
+    qmp.execute(
+        "qom-set",
+        json!({ "path": DEVICE_PATH, "property": "requested-size", "value": size }),
+    )
+    .map(|_| ())
+}
@@ -0,0 +1,164 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cmdline::kernel_cmdline;
+use crate::config::{ConfigManifest, parse_config_name};
+use crate::cpu_hotplug::BOOT_CPUS;
+use crate::error::BuildError;
+use crate::memory::{ARM64_RAM_BASE, RISCV64_RAM_BASE, memory_regions, memory_size};
+
+/// Alignment of the kernel, DTB and ramdisk load addresses
+const LOAD_ALIGN: u64 = 0x20_0000;
+
+/// Memory region flags: read, write, execute
+const MEM_RWX: u64 = 0x7;
+/// Memory region mapping: guest RAM allocated by the hypervisor
+const MAP_ALLOC: u64 = 0;
+
+/// Emulated device types of the hypervisor for the virtio devices it can provide
+const VIRTIO_DEVICES: [(&str, u64); 3] = [("blk", 0xe1), ("net", 0xe2), ("console", 0xe3)];
+
+/// virtio-mmio windows of QEMU's virt layout: first base, stride, first interrupt
+fn virtio_mmio_layout(arch: &str) -> Option<(u64, u64, u64)> {
+    match arch {
+        // SPI 16 onwards, GIC interrupt 48
+        "arm64" => Some((0x0a00_0000, 0x200, 48)),
+        "riscv64" => Some((0x1000_1000, 0x1000, 1)),
+        _ => None,
+    }
+}
+
+/// Path of the ArceOS hypervisor VM config of a configuration
+pub fn vm_config_path(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("vm.toml")
+}
+
+fn align_up(value: u64) -> u64 {
+    value.div_ceil(LOAD_ALIGN) * LOAD_ALIGN
+}
+
+/// A TOML string literal
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// File name of an artifact in `build/<config>/`
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Write the VM definition the ArceOS hypervisor boots the built guest from, `build/<config>/vm.toml`
+pub fn write_vm_config(config_name: &str, manifest: &ConfigManifest) -> Result<(), BuildError> {
+    let (arch, _) = parse_config_name(config_name);
+    let ram_base = match arch.as_str() {
+        "arm64" => ARM64_RAM_BASE,
+        "riscv64" => RISCV64_RAM_BASE,
+        _ => {
+            println!("No ArceOS VM config for {} guests", arch);
+            return Ok(());
+        }
+    };
+    let build = crate::manifest::read_manifest(config_name)
+        .ok_or_else(|| BuildError::Step("the build manifest is missing".to_string()))?;
+
+    let mut regions = memory_regions(manifest).map_err(BuildError::Config)?;
+    if regions.is_empty() {
+        regions.push((ram_base, memory_size(manifest).map_err(BuildError::Config)?));
+    }
+    let (first_base, first_size) = regions[0];
+
+    // Kernel, DTB and ramdisk follow each other at the start of the first region,
+    // after the 2 MiB that firmware such as OpenSBI occupies
+    let kernel = crate::qemu::kernel_image_path(config_name)
+        .ok_or_else(|| BuildError::Config(format!("unsupported architecture: {}", arch)))?;
+    let kernel_size = fs::metadata(&kernel)
+        .map_err(BuildError::io(format!(
+            "failed to read {}",
+            kernel.display()
+        )))?
+        .len();
+    let kernel_load = first_base + LOAD_ALIGN;
+    let mut next_load = align_up(kernel_load + kernel_size);
+
+    let mut toml = format!(
+        "# ArceOS hypervisor VM config of {}, generated by guest-test-linux\n\
+         # Paths are relative to build/{}/\n\n\
+         [base]\nid = 1\nname = {}\nvm_type = 1\ncpu_num = {}\n\n\
+         [kernel]\nentry_point = {:#x}\nimage_location = \"fs\"\nkernel_path = {}\nkernel_load_addr = {:#x}\n",
+        config_name,
+        config_name,
+        quote(config_name),
+        manifest.vcpus.unwrap_or(BOOT_CPUS),
+        kernel_load,
+        quote(&file_name(&kernel)),
+        kernel_load
+    );
+
+    if let Some(dtb) = crate::dtb::guest_dtb_path(config_name, manifest) {
+        toml.push_str(&format!(
+            "dtb_path = {}\ndtb_load_addr = {:#x}\n",
+            quote(&file_name(&dtb)),
+            next_load
+        ));
+        next_load += LOAD_ALIGN;
+    }
+    // An embedded initramfs is already part of the kernel image
+    if manifest.initramfs && !manifest.initramfs_embed {
+        let initramfs = crate::qemu::initramfs_path(config_name);
+        toml.push_str(&format!(
+            "ramdisk_path = {}\nramdisk_load_addr = {:#x}\n",
+            quote(&file_name(&initramfs)),
+            next_load
+        ));
+        next_load += fs::metadata(&initramfs).map(|m| m.len()).unwrap_or(0);
+    }
+    if next_load > first_base + first_size {
+        return Err(BuildError::Step(format!(
+            "the kernel, DTB and ramdisk do not fit in the first memory region ({:#x}+{:#x})",
+            first_base, first_size
+        )));
+    }
+
+    toml.push_str(&format!(
+        "cmdline = {}\nmemory_regions = [\n",
+        quote(&kernel_cmdline(config_name, manifest))
+    ));
+    for (base, size) in &regions {
+        toml.push_str(&format!(
+            "    [{:#x}, {:#x}, {:#x}, {}], # System RAM\n",
+            base, size, MEM_RWX, MAP_ALLOC
+        ));
+    }
+    toml.push_str("]\n\n[devices]\npassthrough_devices = []\nemu_devices = [\n");
+
+    // The guest probes virtio-mmio devices at the slots QEMU's virt machine uses
+    let virtio = &build.hypervisor.virtio;
+    if let Some((base, stride, irq)) = virtio_mmio_layout(&arch)
+        && virtio.iter().any(|transport| transport == "mmio")
+    {
+        let devices = VIRTIO_DEVICES
+            .iter()
+            .filter(|(device, _)| virtio.iter().any(|v| v == device));
+        for (slot, (device, emu_type)) in (0u64..).zip(devices) {
+            toml.push_str(&format!(
+                "    [{}, {:#x}, {:#x}, {}, {:#x}, []],\n",
+                quote(&format!("virtio-{}", device)),
+                base + slot * stride,
+                stride,
+                irq + slot,
+                emu_type
+            ));
+        }
+    }
+    toml.push_str("]\n");
+
+    let path = vm_config_path(config_name);
+    fs::write(&path, toml).map_err(BuildError::io(format!(
+        "failed to write {}",
+        path.display()
+    )))?;
+    println!("ArceOS VM config written to {}", path.display());
+    Ok(())
+}
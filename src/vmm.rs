@@ -0,0 +1,118 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::arch::{Arch, find_arch};
+use crate::cmdline::kernel_cmdline_with;
+use crate::config::{ConfigManifest, Console, parse_config_name};
+use crate::cpu_hotplug::BOOT_CPUS;
+use crate::error::BuildError;
+use crate::memory::memory_size;
+
+/// cloud-hypervisor and crosvm both attach the root disk as the first virtio-blk device
+const ROOT_DEVICE: &str = "/dev/vda";
+
+/// Directory the reference-VMM boot artifacts of a configuration are written to
+pub fn vmm_dir(config_name: &str) -> PathBuf {
+    PathBuf::from("build").join(config_name).join("vmm")
+}
+
+/// Kernel options for booting under cloud-hypervisor and crosvm
+pub fn kernel_options(arch: &str) -> Vec<&'static str> {
+    let mut options = vec![
+        "CONFIG_VIRTIO_PCI",
+        "CONFIG_VIRTIO_BLK",
+        "CONFIG_VIRTIO_CONSOLE",
+        "CONFIG_HVC_DRIVER",
+    ];
+    // x86 direct boot enters the uncompressed ELF kernel through its PVH note
+    if arch == "x86" {
+        options.push("CONFIG_PVH");
+    }
+    options
+}
+
+/// File name of the VMM kernel: the ELF `vmlinux` on x86, the raw image elsewhere
+fn kernel_name(arch: &str, arch_info: &Arch) -> String {
+    if arch == "x86" {
+        return "vmlinux".to_string();
+    }
+    Path::new(arch_info.kernel_image)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| arch_info.image_name().to_string())
+}
+
+/// Kernel the reference VMMs boot, if the configuration was built with `vmm_artifacts`
+pub fn kernel_path(config_name: &str) -> Option<PathBuf> {
+    let (arch, _) = parse_config_name(config_name);
+    let arch_info = find_arch(&arch)?;
+    let path = vmm_dir(config_name).join(kernel_name(&arch, arch_info));
+    path.exists().then_some(path)
+}
+
+/// Kernel command line for the reference VMMs: virtio-blk root and the virtio console
+pub fn cmdline(config_name: &str, manifest: &ConfigManifest) -> String {
+    kernel_cmdline_with(config_name, manifest, ROOT_DEVICE, Console::Virtio.device())
+}
+
+/// Write the kernel, separate initramfs, command line and a description to `build/<config>/vmm/`
+pub fn write_artifacts(
+    config_name: &str,
+    manifest: &ConfigManifest,
+    kernel_build_dir: &Path,
+) -> Result<(), BuildError> {
+    let (arch, _) = parse_config_name(config_name);
+    let arch_info = find_arch(&arch)
+        .ok_or_else(|| BuildError::Config(format!("unsupported architecture: {}", arch)))?;
+    let dir = vmm_dir(config_name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(BuildError::io("failed to clear vmm directory"))?;
+    }
+    fs::create_dir_all(&dir).map_err(BuildError::io("failed to create vmm directory"))?;
+
+    let kernel = kernel_name(&arch, arch_info);
+    let kernel_source = if arch == "x86" {
+        kernel_build_dir.join("vmlinux")
+    } else {
+        kernel_build_dir.join(arch_info.kernel_image)
+    };
+    fs::copy(&kernel_source, dir.join(&kernel)).map_err(BuildError::io(format!(
+        "failed to copy {}",
+        kernel_source.display()
+    )))?;
+
+    // An embedded initramfs is already part of the kernel
+    let initramfs = (manifest.initramfs && !manifest.initramfs_embed).then(|| {
+        let name = "initramfs.cpio.gz";
+        fs::copy(crate::qemu::initramfs_path(config_name), dir.join(name)).map(|_| name)
+    });
+    let initramfs = initramfs
+        .transpose()
+        .map_err(BuildError::io("failed to copy the initramfs"))?;
+    let rootfs = (!manifest.initramfs).then_some("../rootfs.img");
+
+    let cmdline = cmdline(config_name, manifest);
+    fs::write(dir.join("cmdline"), format!("{}\n", cmdline))
+        .map_err(BuildError::io("failed to write the vmm command line"))?;
+    let description = json!({
+        "kernel": kernel,
+        "kernel_format": if arch == "x86" { "elf-pvh" } else { "raw" },
+        "initramfs": initramfs,
+        "rootfs": rootfs,
+        "rootfs_readonly": manifest.readonly_root,
+        "cmdline": cmdline,
+        "console": Console::Virtio.device(),
+        "memory": memory_size(manifest).map_err(BuildError::Config)?,
+        "vcpus": manifest.vcpus.unwrap_or(BOOT_CPUS),
+        "firmware": crate::firmware::files(config_name, manifest).map(|files| json!({
+            "code": files.code,
+            "vars": files.vars,
+        })),
+    });
+    let json = serde_json::to_string_pretty(&description).expect("Failed to serialize boot.json");
+    fs::write(dir.join("boot.json"), json).map_err(BuildError::io("failed to write boot.json"))?;
+    println!("Reference VMM artifacts written to {}", dir.display());
+    Ok(())
+}
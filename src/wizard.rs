@@ -0,0 +1,427 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::arch::{ARCHES, find_arch};
+use crate::firmware::FirmwareKind;
+
+/// How the guest gets its root filesystem, as offered by the wizard
+const ROOTFS_TYPES: &[(&str, &str)] = &[
+    ("disk", "ext4 image on a virtio disk"),
+    ("initramfs", "initramfs loaded next to the kernel"),
+    ("embedded", "initramfs built into the kernel image"),
+    ("bare", "static init in an initramfs, no BusyBox"),
+    ("nfs", "root over NFS from build/<config>/rootfs"),
+];
+
+/// Questions answered on stdin; an empty answer takes the default, and so does
+/// the end of input, which makes `wizard < /dev/null` generate the defaults
+struct Prompt<R> {
+    input: R,
+    eof: bool,
+}
+
+impl<R: BufRead> Prompt<R> {
+    fn ask(&mut self, question: &str, default: &str) -> String {
+        print!("{} [{}]: ", question, default);
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        if self.eof || matches!(self.input.read_line(&mut line), Ok(0) | Err(_)) {
+            self.eof = true;
+            println!();
+            return default.to_string();
+        }
+        match line.trim() {
+            "" => default.to_string(),
+            answer => answer.to_string(),
+        }
+    }
+
+    /// One of `options`, the first being the default
+    fn choose(&mut self, question: &str, options: &[&str]) -> Result<String, String> {
+        loop {
+            let answer = self.ask(&format!("{} ({})", question, options.join("/")), options[0]);
+            if options.contains(&answer.as_str()) {
+                return Ok(answer);
+            }
+            self.retry(&format!("answer one of {}", options.join(", ")))?;
+        }
+    }
+
+    /// Any number of `options`, comma-separated
+    fn pick(&mut self, question: &str, options: &[String]) -> Result<Vec<String>, String> {
+        if options.is_empty() {
+            return Ok(Vec::new());
+        }
+        println!("{}: {}", question, options.join(", "));
+        loop {
+            let answer = self.ask("  comma-separated", "none");
+            if answer == "none" {
+                return Ok(Vec::new());
+            }
+            let picked: Vec<String> = answer
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect();
+            match picked.iter().find(|item| !options.contains(item)) {
+                None => return Ok(picked),
+                Some(unknown) => self.retry(&format!("{} is not one of them", unknown))?,
+            }
+        }
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> Result<bool, String> {
+        loop {
+            let answer = self.ask(
+                &format!("{} (y/n)", question),
+                if default { "y" } else { "n" },
+            );
+            match answer.to_lowercase().as_str() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => self.retry("answer y or n")?,
+            }
+        }
+    }
+
+    /// Ask again after an invalid answer, unless there is no more input to ask
+    fn retry(&self, hint: &str) -> Result<(), String> {
+        if self.eof {
+            return Err(format!("no valid answer: {}", hint));
+        }
+        eprintln!("  Please {}.", hint);
+        Ok(())
+    }
+}
+
+/// Everything the generated files are made from
+struct Answers {
+    arch: String,
+    name: String,
+    kernel_ref: Option<String>,
+    firmware: Option<FirmwareKind>,
+    console: String,
+    rootfs: String,
+    payloads: Vec<String>,
+    profiles: Vec<String>,
+    ssh: bool,
+    overlay: bool,
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Directories under `payloads/`
+fn payload_names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir("payloads")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+fn ask_questions<R: BufRead>(prompt: &mut Prompt<R>) -> Result<Answers, String> {
+    let arches: Vec<&str> = ARCHES.iter().map(|arch| arch.name).collect();
+    let arch = prompt.choose("Architecture", &arches)?;
+    let arch_info = find_arch(&arch).ok_or_else(|| format!("unsupported architecture {}", arch))?;
+    let config_dir = Path::new("config").join(&arch);
+
+    let default_name = (1..)
+        .map(|n| {
+            if n == 1 {
+                "custom".to_string()
+            } else {
+                format!("custom-{}", n)
+            }
+        })
+        .find(|name| !config_dir.join(name).exists())
+        .unwrap_or_default();
+    let name = loop {
+        let name = prompt.ask("Config name, built as <arch>-<name>", &default_name);
+        if !is_valid_name(&name) {
+            prompt.retry("use lowercase letters, digits and dashes")?;
+        } else if config_dir.join(&name).exists() {
+            prompt.retry(&format!(
+                "pick a name other than the existing {}-{}",
+                arch, name
+            ))?;
+        } else {
+            break name;
+        }
+    };
+
+    let kernel_ref = prompt.ask(
+        "Kernel tag, branch or commit (\"release\" for the one in linux/)",
+        "release",
+    );
+    let kernel_ref = (kernel_ref != "release").then_some(kernel_ref);
+
+    let mut boot_modes = vec!["direct"];
+    boot_modes.extend(
+        [FirmwareKind::Edk2, FirmwareKind::Seabios]
+            .into_iter()
+            .filter(|kind| kind.available(&arch))
+            .map(FirmwareKind::name),
+    );
+    let boot_mode = prompt.choose(
+        "Boot mode: direct kernel boot or through firmware",
+        &boot_modes,
+    )?;
+    let firmware = [FirmwareKind::Edk2, FirmwareKind::Seabios]
+        .into_iter()
+        .find(|kind| kind.name() == boot_mode);
+
+    let consoles: Vec<&str> = arch_info
+        .consoles
+        .iter()
+        .map(|console| console.name())
+        .collect();
+    let console = prompt.choose("Console", &consoles)?;
+
+    for (rootfs, description) in ROOTFS_TYPES {
+        println!("  {:<10} {}", rootfs, description);
+    }
+    let rootfs_types: Vec<&str> = ROOTFS_TYPES.iter().map(|(rootfs, _)| *rootfs).collect();
+    let rootfs = prompt.choose("Root filesystem", &rootfs_types)?;
+
+    // A bare guest has no shell to install payloads into or run test profiles from
+    let (payloads, profiles, ssh) = if rootfs == "bare" {
+        (Vec::new(), Vec::new(), false)
+    } else {
+        let payloads = prompt.pick("Payloads to install", &payload_names())?;
+        let profile_names: Vec<String> = crate::profile::PROFILES
+            .iter()
+            .map(|profile| profile.name.to_string())
+            .collect();
+        let profiles = prompt.pick("Test profiles (see `cargo xtask profiles`)", &profile_names)?;
+        let ssh = prompt.confirm("Run guest commands over SSH", false)?;
+        (payloads, profiles, ssh)
+    };
+
+    let overlay = arch_info.devicetree
+        && prompt.confirm(
+            "Add a devicetree overlay for hypervisor-emulated devices",
+            false,
+        )?;
+
+    Ok(Answers {
+        arch,
+        name,
+        kernel_ref,
+        firmware,
+        console,
+        rootfs,
+        payloads,
+        profiles,
+        ssh,
+        overlay,
+    })
+}
+
+/// A TOML string literal
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// A TOML array of strings
+fn quote_list(values: &[String]) -> String {
+    let quoted: Vec<String> = values.iter().map(|value| quote(value)).collect();
+    format!("[{}]", quoted.join(", "))
+}
+
+fn extra_fragment_name(name: &str) -> String {
+    format!("{}-extra.config", name)
+}
+
+fn overlay_name(name: &str) -> String {
+    format!("{}.dtso", name)
+}
+
+/// `config/<arch>/<name>.toml`
+fn manifest_text(answers: &Answers) -> String {
+    let config_name = format!("{}-{}", answers.arch, answers.name);
+    // ARCH=x86 defconfig follows the host, which is i386 on some
+    let base_config = if answers.arch == "x86" {
+        "x86_64_defconfig"
+    } else {
+        "defconfig"
+    };
+    let mut toml = format!(
+        "# Settings for {config}, generated by `cargo xtask wizard`; each variant builds as {config}@<variant>\n\
+         console = {console}\n\
+         memory = \"1G\"\n\
+         # config/{arch}/{name} is a fragment on top of the arch defconfig\n\
+         base_config = {base_config}\n\
+         # Local experiments, merged last\n\
+         fragments = [{extra}]\n",
+        config = config_name,
+        console = quote(&answers.console),
+        arch = answers.arch,
+        name = answers.name,
+        base_config = quote(base_config),
+        extra = quote(&extra_fragment_name(&answers.name)),
+    );
+    if let Some(kernel_ref) = &answers.kernel_ref {
+        toml.push_str(&format!("kernel_ref = {}\n", quote(kernel_ref)));
+    }
+    if let Some(firmware) = answers.firmware {
+        toml.push_str(&format!("firmware = {}\n", quote(firmware.name())));
+    }
+    match answers.rootfs.as_str() {
+        "initramfs" => toml.push_str("initramfs = true\n"),
+        "embedded" => toml.push_str("initramfs = true\ninitramfs_embed = true\n"),
+        "bare" => toml.push_str("initramfs = true\nbare = true\n"),
+        "nfs" => toml.push_str("nfsroot = true\n"),
+        _ => {}
+    }
+    if !answers.payloads.is_empty() {
+        toml.push_str(&format!("payloads = {}\n", quote_list(&answers.payloads)));
+    }
+    if !answers.profiles.is_empty() {
+        toml.push_str(&format!("profiles = {}\n", quote_list(&answers.profiles)));
+    }
+    if answers.ssh {
+        toml.push_str("ssh = true\n");
+    }
+    if answers.overlay {
+        toml.push_str(&format!(
+            "# Compiled into build/{}/overlays/\noverlays = [{}]\n",
+            config_name,
+            quote(&overlay_name(&answers.name))
+        ));
+    }
+    toml.push_str(
+        "\n# Checked by `test` on top of the guest tests\n\
+         [expect]\n\
+         markers = [\"Running guest tests...\"]\n\
+         refute = [\"Kernel panic\", \"BUG: \", \"Oops\"]\n",
+    );
+    toml
+}
+
+/// `config/<arch>/<name>`; console, initramfs and profile options come from the manifest
+fn fragment_text(answers: &Answers) -> String {
+    format!(
+        "#\n\
+         # {}-{}, merged onto the arch defconfig (base_config in {}.toml)\n\
+         #\n\
+         CONFIG_DEVTMPFS=y\n\
+         CONFIG_DEVTMPFS_MOUNT=y\n\
+         CONFIG_VIRTIO_MENU=y\n\
+         CONFIG_VIRTIO=y\n\
+         CONFIG_VIRTIO_PCI=y\n\
+         CONFIG_VIRTIO_MMIO=y\n\
+         CONFIG_VIRTIO_BLK=y\n\
+         CONFIG_VIRTIO_NET=y\n\
+         CONFIG_EXT4_FS=y\n\
+         CONFIG_TMPFS=y\n",
+        answers.arch, answers.name, answers.name
+    )
+}
+
+fn extra_fragment_text(answers: &Answers) -> String {
+    format!(
+        "#\n\
+         # Extra options for {}-{}, merged after config/{}/{}\n\
+         #\n",
+        answers.arch, answers.name, answers.arch, answers.name
+    )
+}
+
+fn overlay_text(answers: &Answers) -> String {
+    format!(
+        "// SPDX-License-Identifier: GPL-2.0\n\
+         /*\n \
+         * Devicetree overlay of {}-{}: describe the devices the hypervisor\n \
+         * emulates for the guest here. See config/arm64/shmem.dtso for an example.\n \
+         */\n\
+         /dts-v1/;\n\
+         /plugin/;\n\
+         \n\
+         &{{/}} {{\n\
+         }};\n",
+        answers.arch, answers.name
+    )
+}
+
+/// Ask for the settings of a new configuration and write its config, manifest,
+/// extra fragment and overlay under `config/<arch>/`; returns false on failure
+pub fn run() -> bool {
+    let mut prompt = Prompt {
+        input: io::stdin().lock(),
+        eof: false,
+    };
+    let answers = match ask_questions(&mut prompt) {
+        Ok(answers) => answers,
+        Err(e) => {
+            eprintln!("Wizard aborted: {}", e);
+            return false;
+        }
+    };
+
+    let config_dir = PathBuf::from("config").join(&answers.arch);
+    let mut files = vec![
+        (config_dir.join(&answers.name), fragment_text(&answers)),
+        (
+            config_dir.join(format!("{}.toml", answers.name)),
+            manifest_text(&answers),
+        ),
+        (
+            config_dir.join(extra_fragment_name(&answers.name)),
+            extra_fragment_text(&answers),
+        ),
+    ];
+    if answers.overlay {
+        files.push((
+            config_dir.join(overlay_name(&answers.name)),
+            overlay_text(&answers),
+        ));
+    }
+    if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+        eprintln!("{} already exists, not overwriting it", path.display());
+        return false;
+    }
+
+    let remove_all = |files: &[(PathBuf, String)]| {
+        for (path, _) in files {
+            let _ = fs::remove_file(path);
+        }
+    };
+    if let Err(e) = fs::create_dir_all(&config_dir) {
+        eprintln!("Failed to create {}: {}", config_dir.display(), e);
+        return false;
+    }
+    for (path, content) in &files {
+        if let Err(e) = fs::write(path, content) {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            remove_all(&files);
+            return false;
+        }
+    }
+
+    // The generated manifest has to load like a hand-written one
+    let config_name = format!("{}-{}", answers.arch, answers.name);
+    if let Err(e) = crate::config::load_manifest(&config_name) {
+        eprintln!("Generated manifest is invalid, removing it: {}", e);
+        remove_all(&files);
+        return false;
+    }
+
+    println!();
+    for (path, _) in &files {
+        println!("Created {}", path.display());
+    }
+    println!("Build and test it with:");
+    println!("  cargo xtask build {}", config_name);
+    println!("  cargo xtask test {}", config_name);
+    true
+}